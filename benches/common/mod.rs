@@ -0,0 +1,52 @@
+// iNES images built in memory, the benches don't depend on roms being around
+#![allow(dead_code)]
+
+const PRG_ROM_PAGE_SIZE: usize = 16384;
+const CHR_ROM_PAGE_SIZE: usize = 8192;
+
+/// NROM image with vertical mirroring
+pub fn ines_image(prg_rom: &[u8], chr_rom: &[u8]) -> Vec<u8> {
+    assert_eq!(prg_rom.len() % PRG_ROM_PAGE_SIZE, 0);
+    assert_eq!(chr_rom.len() % CHR_ROM_PAGE_SIZE, 0);
+    let mut image = vec![
+        0x4E,
+        0x45,
+        0x53,
+        0x1A,
+        (prg_rom.len() / PRG_ROM_PAGE_SIZE) as u8,
+        (chr_rom.len() / CHR_ROM_PAGE_SIZE) as u8,
+        0x01,
+    ];
+    image.resize(16, 0);
+    image.extend_from_slice(prg_rom);
+    image.extend_from_slice(chr_rom);
+    image
+}
+
+/// Turns rendering and NMI on and spins, the NMI handler counts frames at $01
+pub fn spinning_rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    let reset = [
+        0x78,             // SEI
+        0xd8,             // CLD
+        0xa9, 0x80,       // LDA #$80
+        0x8d, 0x00, 0x20, // STA $2000
+        0xa9, 0x1e,       // LDA #$1E
+        0x8d, 0x01, 0x20, // STA $2001
+        0xe6, 0x00,       // loop: INC $00
+        0x4c, 0x0c, 0x80, // JMP loop
+    ];
+    #[rustfmt::skip]
+    let nmi = [
+        0xe6, 0x01,       // INC $01
+        0x40,             // RTI
+    ];
+    let mut prg = vec![0xea; 2 * PRG_ROM_PAGE_SIZE];
+    prg[..reset.len()].copy_from_slice(&reset);
+    prg[0x20..0x20 + nmi.len()].copy_from_slice(&nmi);
+    // NMI, RESET, IRQ vectors
+    prg[0x7ffa..].copy_from_slice(&[0x20, 0x80, 0x00, 0x80, 0x20, 0x80]);
+
+    let chr: Vec<u8> = (0..CHR_ROM_PAGE_SIZE).map(|i| (i * 7) as u8).collect();
+    ines_image(&prg, &chr)
+}
@@ -0,0 +1,64 @@
+// Headless emulation throughput: `cargo bench --bench emulator`
+//
+// Whole frames (CPU, PPU, rendering) with no frontend. Runs nestest in automation mode when
+// test_rom/nestest.nes is around (it's not in the repo), a generated rom that keeps
+// rendering on otherwise.
+// Snapshot/restore are taken every frame by rollback and run-ahead, they have to stay cheap.
+mod common;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rustness::rom::Rom;
+use rustness::{Emulator, Inputs};
+use std::fs;
+
+const NESTEST: &str = "test_rom/nestest.nes";
+
+fn run_frames(c: &mut Criterion, name: &str, mut emulator: Emulator) {
+    let inputs = Inputs::default();
+    let mut group = c.benchmark_group("emulator");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function(name, |b| {
+        b.iter(|| {
+            emulator.run_frame(&inputs).unwrap();
+        })
+    });
+    group.finish();
+}
+
+fn spinning(c: &mut Criterion) {
+    let rom = Rom::load(&common::spinning_rom()).unwrap();
+    run_frames(c, "frame", Emulator::builder(rom).build());
+}
+
+fn snapshots(c: &mut Criterion) {
+    let rom = Rom::load(&common::spinning_rom()).unwrap();
+    let mut emulator = Emulator::builder(rom).build();
+    emulator.run_frame(&Inputs::default()).unwrap();
+    let snapshot = emulator.snapshot();
+    let mut group = c.benchmark_group("emulator");
+    group.bench_function("snapshot", |b| b.iter(|| emulator.snapshot()));
+    group.bench_function("restore", |b| {
+        b.iter(|| emulator.restore(&snapshot).unwrap())
+    });
+    group.finish();
+}
+
+fn nestest(c: &mut Criterion) {
+    let data = match fs::read(NESTEST) {
+        Ok(data) => data,
+        Err(_) => {
+            println!("{} not found, skipping", NESTEST);
+            return;
+        }
+    };
+    let rom = Rom::load(&data).unwrap();
+    // nestest ends with an infinite loop once the tests are done, the frames keep going
+    run_frames(
+        c,
+        "nestest frame",
+        Emulator::builder(rom).start_pc(0xc000).build(),
+    );
+}
+
+criterion_group!(benches, spinning, snapshots, nestest);
+criterion_main!(benches);
@@ -0,0 +1,63 @@
+// Opcode dispatch benchmark: `cargo bench --bench opcodes`
+//
+// Compares the static opcode table with the HashMap lookup it replaced, and measures
+// how fast the CPU steps through a small loop on the mock bus.
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use rustness::bus::MockBus;
+use rustness::cpu::cpu::CPU;
+use rustness::cpu::opscode::{self, OpsCode};
+use std::collections::HashMap;
+
+fn lookups(c: &mut Criterion) {
+    let map: HashMap<u8, &'static OpsCode> = opscode::CPU_OPS_CODES
+        .iter()
+        .map(|ops| (ops.code, ops))
+        .collect();
+
+    let mut group = c.benchmark_group("lookup");
+    group.throughput(Throughput::Elements(256));
+    group.bench_function("HashMap", |b| {
+        b.iter(|| {
+            let mut cycles = 0usize;
+            for code in 0..=255u8 {
+                cycles += map.get(&black_box(code)).unwrap().cycles as usize;
+            }
+            cycles
+        })
+    });
+    group.bench_function("static table", |b| {
+        b.iter(|| {
+            let mut cycles = 0usize;
+            for code in 0..=255u8 {
+                cycles += opscode::lookup(black_box(code)).unwrap().cycles as usize;
+            }
+            cycles
+        })
+    });
+    group.finish();
+}
+
+fn cpu_steps(c: &mut Criterion) {
+    #[rustfmt::skip]
+    let program = [
+        0xa2, 0x00,       // LDX #$00
+        0xe8,             // loop: INX
+        0xb5, 0x10,       // LDA $10,X
+        0x69, 0x01,       // ADC #$01
+        0x9d, 0x00, 0x02, // STA $0200,X
+        0xd0, 0xf6,       // BNE loop
+        0x4c, 0x00, 0x80, // JMP $8000
+    ];
+    let mut bus = MockBus::new();
+    bus.space[0x8000..0x8000 + program.len()].copy_from_slice(&program);
+    let mut cpu = CPU::new(Box::from(bus));
+    cpu.program_counter = 0x8000;
+
+    let mut group = c.benchmark_group("cpu");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("step", |b| b.iter(|| cpu.step()));
+    group.finish();
+}
+
+criterion_group!(benches, lookups, cpu_steps);
+criterion_main!(benches);
@@ -0,0 +1,55 @@
+// Renderer benchmark: `cargo bench --bench render [--features simd]`
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use rustness::ppu::ppu::{NesPPU, PPU};
+use rustness::rom::Mirroring;
+use rustness::screen::tile;
+
+fn tile_rows(c: &mut Criterion) {
+    let palette = [(1, 2, 3), (4, 5, 6), (7, 8, 9), (10, 11, 12)];
+    let mut out = [0u8; 24];
+
+    let mut group = c.benchmark_group("map row");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("scalar", |b| {
+        b.iter(|| tile::map_row_scalar(black_box(0x1b6c), &palette, &mut out))
+    });
+    // SSSE3 when built with the simd feature
+    group.bench_function("map_row", |b| {
+        b.iter(|| tile::map_row(black_box(0x1b6c), &palette, &mut out))
+    });
+    group.finish();
+}
+
+fn frames(c: &mut Criterion) {
+    let chr: Vec<u8> = (0..0x2000).map(|i| (i * 7) as u8).collect();
+    let mut ppu = NesPPU::new(chr, Mirroring::VERTICAL);
+    for (i, byte) in ppu.vram.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+
+    let mut group = c.benchmark_group("ppu frame");
+    group.throughput(Throughput::Elements(1));
+    // every tile is different and the background is redrawn every frame
+    group.bench_function("full redraw", |b| {
+        b.iter(|| {
+            ppu.dirty_tiles.mark_all();
+            while !ppu.tick(341) {}
+            while ppu.line != 0 {
+                ppu.tick(341);
+            }
+        })
+    });
+    // static screen: only sprites and the tiles under them
+    group.bench_function("unchanged background", |b| {
+        b.iter(|| {
+            while !ppu.tick(341) {}
+            while ppu.line != 0 {
+                ppu.tick(341);
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, tile_rows, frames);
+criterion_main!(benches);
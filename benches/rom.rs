@@ -0,0 +1,19 @@
+// ROM parsing benchmark: `cargo bench --bench rom`
+mod common;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use rustness::rom::{self, Rom};
+
+fn parsing(c: &mut Criterion) {
+    let image = common::spinning_rom();
+
+    let mut group = c.benchmark_group("rom");
+    group.throughput(Throughput::Bytes(image.len() as u64));
+    group.bench_function("load", |b| b.iter(|| Rom::load(black_box(&image)).unwrap()));
+    // game db lookups hash the whole rom
+    group.bench_function("crc32", |b| b.iter(|| rom::crc32(black_box(&image))));
+    group.finish();
+}
+
+criterion_group!(benches, parsing);
+criterion_main!(benches);
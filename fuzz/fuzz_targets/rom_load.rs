@@ -0,0 +1,14 @@
+// cargo +nightly fuzz run rom_load
+//
+// Any input has to be either a rom or an error, never a panic.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rustness::rom::Rom;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(rom) = Rom::load(data) {
+        assert!(!rom.prg_rom.is_empty());
+        let _ = rom.crc32();
+        let _ = rom.rom_flags.mirroring();
+    }
+});
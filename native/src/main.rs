@@ -1,25 +1,120 @@
-use rustness::bus::Bus;
+use rustness::achievements::Achievements;
+use rustness::audio::AudioSink;
+use rustness::bus::{Bus, DynamicBusWrapper};
+use rustness::cheats::{Cheat, Cheats};
+use rustness::clock::{Clock, FramePacer, FrameStats, RealClock, Stage};
 use rustness::cpu::cpu::CPU;
 use rustness::cpu::mem::Mem;
+use rustness::cpu::trace_filter::TraceFilter;
+use rustness::cpu::trace_writer::TraceWriter;
+use rustness::cpu::TraceFormat;
+use rustness::debugger::breakpoint::Breakpoint;
+use rustness::debugger::crash_report::{self, ExecutionHistory};
+use rustness::debugger::monitor::{Action, Monitor};
+use rustness::debugger::remote::RemoteServer;
+use rustness::debugger::watch::Watch;
+use rustness::debugger::{Debugger, StepMode};
 use rustness::input;
+use rustness::movie::Movie;
 use rustness::ppu::ppu::NesPPU;
+use rustness::config_file::ConfigFile;
+use rustness::rom::battery;
+use rustness::rom::db::GameDb;
+use rustness::rom::settings::GameSettings;
 use rustness::rom::Rom;
+use rustness::rewind::Rewind;
+use rustness::save_state;
 use rustness::screen::render;
-use rustness::screen::frame::Frame;
+use rustness::screen::frame::{Frame, PixelSink};
+use rustness::screen::ghost::Ghost;
+use rustness::screen::osd::Osd;
+use rustness::screen::overscan::Overscan;
+use rustness::screen::palette::ColorVision;
+use rustness::screen::phosphor::Phosphor;
+use rustness::symbols::Symbols;
+use rustness::{Emulator, Region};
 
+use sdl2::audio::AudioQueue;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::rect::Rect;
-use std::fs::File;
-use std::io::Read;
-use std::time::Duration;
-use std::time::SystemTime;
+use sdl2::render::Texture;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Read, Write};
 
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::env;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+const FAST_FORWARD_SKIP: usize = 8;
+// extra scanlines of CPU time when overclock is switched on without --overclock
+const OVERCLOCK_LINES: usize = 100;
+
+struct TextureSink<'a, 'r>(&'a mut Texture<'r>);
+
+impl PixelSink for TextureSink<'_, '_> {
+    fn blit(&mut self, data: &[u8], pitch: usize) {
+        self.0.update(None, data, pitch).unwrap();
+    }
+}
+
+// todo: not connected yet, there is no APU producing samples
+#[allow(dead_code)]
+struct QueueSink(AudioQueue<f32>);
+
+impl AudioSink for QueueSink {
+    fn push_samples(&mut self, samples: &[f32]) {
+        // false on a closed device, the sound is just lost then
+        let _ = self.0.queue(samples);
+    }
+}
+
+// F1..F10
+fn state_slot(keycode: Keycode) -> Option<u8> {
+    let slot = match keycode {
+        Keycode::F1 => 1,
+        Keycode::F2 => 2,
+        Keycode::F3 => 3,
+        Keycode::F4 => 4,
+        Keycode::F5 => 5,
+        Keycode::F6 => 6,
+        Keycode::F7 => 7,
+        Keycode::F8 => 8,
+        Keycode::F9 => 9,
+        Keycode::F10 => 10,
+        _ => return None,
+    };
+    Some(slot)
+}
+
+// Escape or closing the window: the battery RAM (or the EEPROM) goes to the .sav file, with
+// --auto-resume the state is saved for the next launch
+fn quit(cpu: &CPU, bus: &RefCell<Bus<NesPPU>>, rom_path: &Path, auto_resume: bool) -> ! {
+    let mut bus = bus.borrow_mut();
+    if bus.has_battery() {
+        if let Err(e) = battery::save(&battery::path(rom_path), bus.battery_ram()) {
+            println!("{}", e);
+        }
+    }
+    if let Some(eeprom) = bus.eeprom() {
+        if let Err(e) = eeprom.save(&battery::path(rom_path)) {
+            println!("{}", e);
+        }
+    }
+    drop(bus);
+    if auto_resume {
+        match save_state::save_resume(cpu, rom_path) {
+            Ok(path) => println!("session saved to {}", path.display()),
+            Err(e) => println!("{}", e),
+        }
+    }
+    std::process::exit(0)
+}
 
 fn main() {
     let mut key_map = HashMap::new();
@@ -32,16 +127,175 @@ fn main() {
     key_map.insert(Keycode::A, input::JoypadButton::BUTTON_A);
     key_map.insert(Keycode::S, input::JoypadButton::BUTTON_B);
 
-    let mut file = File::open(dbg!(env::args().collect::<Vec<String>>()).get(1).unwrap()).unwrap();
+    let args = dbg!(env::args().collect::<Vec<String>>());
+    let rom_path = args.iter().skip(1).find(|arg| !arg.starts_with("--")).unwrap();
+
+    // --config=<file> (TOML, see rustness::config_file) is loaded if it exists, otherwise it's
+    // created with the defaults. The command line options below override it
+    let config = match args.iter().find(|arg| arg.starts_with("--config=")) {
+        Some(arg) => {
+            let path = Path::new(&arg["--config=".len()..]);
+            if path.exists() {
+                ConfigFile::load(path).unwrap()
+            } else {
+                let config = ConfigFile::new();
+                config.save(path).unwrap();
+                println!("default config written to {}", path.display());
+                config
+            }
+        }
+        None => ConfigFile::new(),
+    };
+    let scale = config.video.scale;
+    // save states, the resume and the battery RAM files are named after it
+    let save_base = config.paths.save_base(Path::new(rom_path));
+    if let Some(saves) = config.paths.saves.as_ref() {
+        fs::create_dir_all(saves).unwrap();
+    }
+
+    // --overscan hides top/bottom 8 lines, --crop-sides additionally hides 8 pixels on the left/right
+    let mut overscan = config.video.overscan;
+    if args.iter().any(|arg| arg == "--overscan") {
+        overscan = Overscan::NTSC;
+    }
+    if args.iter().any(|arg| arg == "--crop-sides") {
+        overscan = overscan.with_sides(8);
+    }
+    // --phosphor=0.5 blends every frame with the previous one, against sprite flicker
+    let phosphor_decay = match args.iter().find(|arg| arg.starts_with("--phosphor=")) {
+        Some(arg) => arg["--phosphor=".len()..].parse::<f32>().unwrap(),
+        None => config.video.phosphor,
+    };
+    let mut phosphor = Phosphor::new(phosphor_decay);
+
+    let mut file = File::open(rom_path).unwrap();
     let mut data = Vec::new();
     file.read_to_end(&mut data).unwrap();
 
     let rom = Rom::load(&data).unwrap();
 
+    // --region=ntsc|pal|dendy overrides the rom header
+    let region = match args.iter().find(|arg| arg.starts_with("--region=")) {
+        Some(arg) => arg["--region=".len()..].parse::<Region>().unwrap(),
+        None => config.region.unwrap_or_else(|| Region::from_rom(&rom)),
+    };
+
+    // --palette=deuteranopia|protanopia for color-vision deficiency, C cycles through them
+    let mut color_vision = match args.iter().find(|arg| arg.starts_with("--palette=")) {
+        Some(arg) => arg["--palette=".len()..].parse::<ColorVision>().unwrap(),
+        None => config.video.color_vision,
+    };
+    // the config's palette file, until C is pressed
+    let palette = if args.iter().any(|arg| arg.starts_with("--palette=")) {
+        color_vision.palette()
+    } else {
+        config.palette().unwrap()
+    };
+
+    // --ghost=<movie.fm2> races a recorded run: the ghost's buttons and, with
+    // --ghost-pos=<x addr>,<y addr> (e.g. 0086,00CE for Super Mario Bros.), its player position
+    let mut ghost = args.iter().find(|arg| arg.starts_with("--ghost=")).map(|arg| {
+        let movie = Movie::load(Path::new(&arg["--ghost=".len()..])).unwrap();
+        let emulator = Emulator::builder(Rom::load(&data).unwrap())
+            .region(region)
+            .build();
+        let ghost = Ghost::new(emulator, movie);
+        match args.iter().find(|arg| arg.starts_with("--ghost-pos=")) {
+            Some(arg) => {
+                let addrs: Vec<u16> = arg["--ghost-pos=".len()..]
+                    .split(',')
+                    .map(|addr| u16::from_str_radix(addr, 16).unwrap())
+                    .collect();
+                ghost.position(addrs[0], addrs[1])
+            }
+            None => ghost,
+        }
+    });
+
+    // --gamedb=<file> with "<crc32> <title>" lines, used to show the game name in the window title
+    let game_db = match args.iter().find(|arg| arg.starts_with("--gamedb=")) {
+        Some(arg) => GameDb::load(Path::new(&arg["--gamedb=".len()..])).unwrap(),
+        None => match config.paths.gamedb.as_ref() {
+            Some(path) => GameDb::load(Path::new(path)).unwrap(),
+            None => GameDb::new(),
+        },
+    };
+    let title = game_db.title(&rom, Path::new(rom_path));
+    println!("{} (crc32: {:08X})", title, rom.crc32());
+
+    // --game-settings=<file> with "<crc32> sprite_limit=off" lines, see rustness::rom::settings.
+    // --no-sprite-limit draws all the sprites (no flicker) whatever the game, L toggles it
+    let game_settings = match args.iter().find(|arg| arg.starts_with("--game-settings=")) {
+        Some(arg) => GameSettings::load(Path::new(&arg["--game-settings=".len()..])).unwrap(),
+        None => match config.paths.game_settings.as_ref() {
+            Some(path) => GameSettings::load(Path::new(path)).unwrap(),
+            None => GameSettings::new(),
+        },
+    };
+    let mut sprite_limit = !args.iter().any(|arg| arg == "--no-sprite-limit")
+        && game_settings.lookup(&rom).sprite_limit.unwrap_or(config.sprite_limit);
+
+    // --overclock=<lines>: extra scanlines of CPU time per frame against slowdown (e.g. Gradius),
+    //   O toggles it (with OVERCLOCK_LINES when none are set)
+    let overclock_lines = match args.iter().find(|arg| arg.starts_with("--overclock=")) {
+        Some(arg) => arg["--overclock=".len()..].parse::<usize>().unwrap(),
+        None => config.overclock,
+    };
+    let mut overclock = overclock_lines > 0;
+    let overclock_lines = if overclock { overclock_lines } else { OVERCLOCK_LINES };
+
+    // --rewind=<MB>: memory for the states Backspace goes back through, 0 turns rewind off
+    let rewind_budget = match args.iter().find(|arg| arg.starts_with("--rewind=")) {
+        Some(arg) => arg["--rewind=".len()..].parse::<usize>().unwrap(),
+        None => config.rewind,
+    };
+    let mut rewind = if rewind_budget > 0 {
+        Some(Rewind::new(rewind_budget << 20))
+    } else {
+        None
+    };
+
+    // RAM freeze cheats from game.cht next to the rom, plus --cheat=<addr:value> (e.g. --cheat=0075:09)
+    let cheats_path = Cheats::path(Path::new(rom_path));
+    let mut cheats = if cheats_path.exists() {
+        Cheats::load(&cheats_path).unwrap()
+    } else {
+        Cheats::new()
+    };
+    for arg in args.iter().filter(|arg| arg.starts_with("--cheat=")) {
+        cheats.add(Cheat::parse(&arg["--cheat=".len()..]).unwrap());
+    }
+    if !cheats.is_empty() {
+        println!("{} cheat(s):\n{}", cheats.len(), cheats);
+    }
+
+    // --achievements=<file> with memory triggers, see rustness::achievements
+    let mut achievements = match args.iter().find(|arg| arg.starts_with("--achievements=")) {
+        Some(arg) => Achievements::load(Path::new(&arg["--achievements=".len()..])).unwrap(),
+        None => Achievements::new(),
+    };
+
+    // --hot-reload: the rom is loaded again when the file changes (rebuilt with cc65/asm6), the
+    //   machine keeps running from its state; --hot-reload=reset power cycles it instead
+    let hot_reload = args
+        .iter()
+        .find(|arg| *arg == "--hot-reload" || arg.starts_with("--hot-reload="))
+        .map(|arg| arg == "--hot-reload=reset");
+    let mut rom_modified = fs::metadata(rom_path).and_then(|meta| meta.modified()).ok();
+    let mut frames_since_check = 0;
+
+    // --auto-resume: the state is saved on exit and offered on the next launch of the same rom
+    let auto_resume = args.iter().any(|arg| arg == "--auto-resume");
+    let rom_crc32 = rom.crc32();
+
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let window = video_subsystem
-        .window("rust nes demo", (256.0 * 3.0) as u32, (240.0 * 3.0) as u32)
+        .window(
+            &title,
+            overscan.width() as u32 * scale,
+            overscan.height() as u32 * scale,
+        )
         .position_centered()
         .build()
         .unwrap();
@@ -59,35 +313,203 @@ fn main() {
 
     let mut canvas = window.into_canvas().present_vsync().build().unwrap();
     canvas.present();
-    let mut event_pump = sdl_context.event_pump().unwrap();
+    // shared with the debugger: keys are polled while the emulation is paused
+    let event_pump = Rc::from(RefCell::from(sdl_context.event_pump().unwrap()));
+    let event_pump_rc = event_pump.clone();
 
     let creator = canvas.texture_creator();
     let mut texture = creator
         .create_texture_target(PixelFormatEnum::RGB24, 256, 240)
         .unwrap();
 
-    canvas.set_scale(3.0, 3.0).unwrap();
-    let mut prev_time = SystemTime::now();
+    canvas.set_scale(scale as f32, scale as f32).unwrap();
+    let mut pacer = FramePacer::new(RealClock::new(), region.frame_duration());
+    // I shows frame timings: fps on the OSD, the averages over the last second in the terminal
+    let mut stats = FrameStats::new(60);
+    let mut show_stats = false;
+    let mut frame_number = 0u64;
 
-    let trace = Rc::from(RefCell::from(false));
+    // D toggles tracing, --trace turns it on from the start
+    let trace = Rc::from(RefCell::from(
+        TraceWriter::new(io::stdout()).enabled(args.iter().any(|arg| arg == "--trace")),
+    ));
 
     let trace_rc = trace.clone();
 
-    let frame = Frame::new();
-    let func = move |z: &NesPPU, joypad: &mut input::Joypad| {
-        for event in event_pump.poll_iter() {
+    // Pause key stops in the debugger (F1..F10 are state slots)
+    let pause = Rc::from(RefCell::from(false));
+    let pause_rc = pause.clone();
+    // the state can only be saved from the cpu callback
+    let quit_requested = Rc::from(RefCell::from(false));
+    let quit_requested_rc = quit_requested.clone();
+    // F12 pauses and opens the monitor console in the terminal
+    let console = Rc::from(RefCell::from(false));
+    let console_rc = console.clone();
+
+    // Shift+F1..F10 saves the state to slot 1..10 (game.state1 next to the rom), F1..F10 loads it.
+    // Only the cpu callback has access to the machine, so the key handler just records the request
+    let slot_request: Rc<RefCell<Option<(u8, bool)>>> = Rc::from(RefCell::from(None));
+    let slot_request_rc = slot_request.clone();
+    // R is the reset button, Shift+R power cycles (RAM cleared), same deal as the slots
+    let reset_request: Rc<RefCell<Option<bool>>> = Rc::from(RefCell::from(None));
+    let reset_request_rc = reset_request.clone();
+    // Backspace held - rewind, a frame back per frame
+    let rewinding = Rc::from(RefCell::from(false));
+    let rewinding_rc = rewinding.clone();
+    let osd = Rc::from(RefCell::from(Osd::new()));
+    let osd_rc = osd.clone();
+
+    let mut frame = Frame::new();
+    // Tab held - fast-forward: no frame pacing, 1 of FAST_FORWARD_SKIP frames is rendered
+    let mut fast_forward = false;
+    let mut switch_palette = false;
+    let mut switch_sprite_limit = false;
+    let mut switch_overclock = false;
+    // called once per frame from the cpu loop at the start of vblank: shows the frame, waits for
+    // its time, then polls the events and the controllers
+    let mut on_frame = move |bus: &mut Bus<NesPPU>| {
+        stats.mark(Stage::Emulation, pacer.clock().now());
+        cheats.apply(bus);
+        for achievement in achievements.check(bus) {
+            println!("achievement unlocked: {}", achievement.title);
+            // 3 seconds
+            osd_rc.borrow_mut().show(&achievement.title, 180);
+        }
+        if show_stats {
+            if let Some(last) = stats.last() {
+                let busy = last.busy().as_secs_f64() * 1000.0;
+                osd_rc.borrow_mut().show(&format!("{:.1} fps {:.1}ms", stats.fps(), busy), 1);
+            }
+        }
+
+        // render::render(bus.ppu(), &mut frame);
+        if let Some(ghost) = ghost.as_mut() {
+            if let Err(e) = ghost.advance() {
+                println!("ghost: {}", e);
+            }
+        }
+        if osd_rc.borrow().is_visible() || ghost.is_some() || phosphor_decay > 0.0 {
+            bus.ppu().blit(&mut frame);
+            phosphor.apply(&mut frame);
+            if let Some(ghost) = ghost.as_mut() {
+                ghost.draw(&mut frame);
+            }
+            osd_rc.borrow_mut().draw(&mut frame);
+            TextureSink(&mut texture).blit(&frame.data, Frame::WIDTH * 3);
+        } else {
+            bus.ppu().blit(&mut TextureSink(&mut texture));
+        }
+        stats.mark(Stage::Render, pacer.clock().now());
+        canvas.clear();
+
+        canvas
+            .copy(
+                &texture,
+                Some(Rect::new(
+                    overscan.left as i32,
+                    overscan.top as i32,
+                    overscan.width() as u32,
+                    overscan.height() as u32,
+                )),
+                Some(Rect::new(0, 0, overscan.width() as u32, overscan.height() as u32)),
+            )
+            .unwrap();
+        canvas.set_scale(scale as f32, scale as f32).unwrap();
+        canvas.present();
+        stats.mark(Stage::Present, pacer.clock().now());
+
+        bus.ppu_mut().set_frame_skip(if fast_forward { FAST_FORWARD_SKIP } else { 1 });
+        if !fast_forward {
+            pacer.wait();
+        }
+        stats.end_frame(pacer.clock().now());
+        if show_stats && stats.frames().count() == 60 && frame_number % 60 == 0 {
+            println!("{}", stats.summary());
+        }
+        frame_number += 1;
+
+        // the controllers are read right before the game does (NMI handler), after the wait:
+        // the presses during the frame pacing make it into this frame, not the next one
+        let joypad = bus.joypad1_mut();
+        for event in event_pump_rc.borrow_mut().poll_iter() {
             match event {
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => fast_forward = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => fast_forward = false,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => {
+                    rewinding_rc.replace(true);
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => {
+                    rewinding_rc.replace(false);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::C),
+                    ..
+                } => switch_palette = true,
+                Event::KeyDown {
+                    keycode: Some(Keycode::L),
+                    ..
+                } => switch_sprite_limit = true,
+                Event::KeyDown {
+                    keycode: Some(Keycode::O),
+                    ..
+                } => switch_overclock = true,
+                Event::KeyDown {
+                    keycode: Some(Keycode::I),
+                    ..
+                } => show_stats = !show_stats,
+                Event::KeyDown {
+                    keycode: Some(Keycode::R),
+                    keymod,
+                    ..
+                } => {
+                    let hard = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                    reset_request_rc.replace(Some(hard));
+                }
                 Event::Quit { .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
-                } => std::process::exit(0),
+                } => {
+                    quit_requested_rc.replace(true);
+                }
                 Event::KeyDown {
                     keycode: Some(Keycode::D),
                     ..
                 } => {
-                    let upd = !*trace_rc.borrow();
-                    trace_rc.replace(upd);
+                    trace_rc.borrow_mut().toggle().unwrap();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Pause),
+                    ..
+                } => {
+                    pause_rc.replace(true);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => {
+                    pause_rc.replace(true);
+                    console_rc.replace(true);
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    ..
+                } if state_slot(keycode).is_some() => {
+                    let save = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                    slot_request_rc.replace(state_slot(keycode).map(|slot| (slot, save)));
                 }
 
                 Event::KeyDown { keycode, .. } => {
@@ -158,42 +580,348 @@ fn main() {
             }
         }
 
-        // render::render(z, &mut frame);
-        texture.update(None, &z.frame.borrow().data, 256 * 3).unwrap();
-        canvas.clear();
+        if switch_palette {
+            switch_palette = false;
+            color_vision = color_vision.next();
+            bus.ppu_mut().set_palette(color_vision.palette());
+            osd_rc.borrow_mut().show(&format!("palette: {:?}", color_vision), 120);
+        }
+        if switch_sprite_limit {
+            switch_sprite_limit = false;
+            sprite_limit = !sprite_limit;
+            bus.ppu_mut().set_sprite_limit(sprite_limit);
+            let state = if sprite_limit { "on" } else { "off" };
+            osd_rc.borrow_mut().show(&format!("sprite limit: {}", state), 120);
+        }
+        if switch_overclock {
+            switch_overclock = false;
+            overclock = !overclock;
+            bus.ppu_mut().set_overclock(if overclock { overclock_lines } else { 0 });
+            let state = if overclock { "on" } else { "off" };
+            osd_rc.borrow_mut().show(&format!("overclock: {}", state), 120);
+        }
+    };
 
-        canvas
-            .copy(&texture, None, Some(Rect::new(0, 0, 256, 240)))
-            .unwrap();
-        canvas.set_scale(3.0, 3.0).unwrap();
-        canvas.present();
+    let bus = Rc::from(RefCell::from(Bus::<NesPPU>::new(rom)));
+    config.ram_init.fill(&mut bus.borrow_mut().ram);
+    bus.borrow_mut().set_region(region);
+    bus.borrow_mut().ppu_mut().set_palette(palette);
+    bus.borrow_mut().ppu_mut().set_sprite_limit(sprite_limit);
+    bus.borrow_mut().ppu_mut().set_overclock(if overclock { overclock_lines } else { 0 });
+    // --render-thread draws the picture on a separate thread
+    if config.render_thread || args.iter().any(|arg| arg == "--render-thread") {
+        bus.borrow_mut().ppu_mut().set_render_thread(true);
+    }
+    // the game saves of the battery backed carts from game.sav, written back on exit
+    if bus.borrow().has_battery() {
+        let path = battery::path(&save_base);
+        if battery::load(&path, bus.borrow_mut().battery_ram()).unwrap() {
+            println!("battery RAM loaded from {}", path.display());
+        }
+    }
+    if let Some(eeprom) = bus.borrow_mut().eeprom() {
+        let path = battery::path(&save_base);
+        if path.exists() {
+            eeprom.load(&path).unwrap();
+            println!("EEPROM loaded from {}", path.display());
+        }
+    }
+
+    let pc = Mem::read_u16(&mut *bus.borrow_mut(), 0xfffc);
+    println!("ROM Start address: {}", pc);
+    let mut cpu = CPU::new(Box::from(DynamicBusWrapper::new(bus.clone())));
+    cpu.program_counter = pc;
 
-        let elapsed_time = SystemTime::now()
-            .duration_since(prev_time)
-            .unwrap()
-            .as_nanos();
+    if auto_resume {
+        if let Some(state) = save_state::read_resume(&save_base, rom_crc32).unwrap() {
+            print!("Resume the previous session? [Y/n] ");
+            io::stdout().flush().unwrap();
+            let mut answer = String::new();
+            io::stdin().lock().read_line(&mut answer).unwrap();
+            if !answer.trim().eq_ignore_ascii_case("n") {
+                if let Err(e) = cpu.load_state(&state) {
+                    println!("{}", e);
+                }
+            }
+        }
+    }
 
-        let wait = if elapsed_time < 1_000_000_000u128 / 60 {
-            1_000_000_000u32 / 60 - (elapsed_time as u32)
-        } else {
-            0
-        };
-        ::std::thread::sleep(Duration::new(0, wait));
-        prev_time = SystemTime::now();
+    // --symbols=<file> with FCEUX (.nl) or ca65 (.dbg) labels, used in traces and breakpoints
+    let symbols = match args.iter().find(|arg| arg.starts_with("--symbols=")) {
+        Some(arg) => Symbols::load(Path::new(&arg["--symbols=".len()..])).unwrap(),
+        None => Symbols::new(),
     };
 
-    let mut bus = Bus::<'_, NesPPU>::new(rom, func);
+    // --break=<spec>, e.g. --break=8057, --break=reset_handler, --break=write:0200-02ff, --break=ppu:2002, --break=scanline:241,
+    //   --break=stack (mismatched RTS/RTI)
+    let mut debugger = Debugger::new();
+    for arg in args.iter().filter(|arg| arg.starts_with("--break=")) {
+        debugger.add_breakpoint(Breakpoint::parse(&arg["--break=".len()..], &symbols).unwrap());
+    }
 
-    let pc = Mem::read_u16(&mut bus, 0xfffc);
-    println!("ROM Start address: {}", pc);
-    let mut cpu = CPU::new(Box::from(bus));
-    cpu.program_counter = pc;
+    // --watch=<expr>, e.g. --watch='$00FE', --watch='word($0010)', --watch='A + X'
+    // printed every frame and on every debugger stop
+    for arg in args.iter().filter(|arg| arg.starts_with("--watch=")) {
+        debugger.add_watch(Watch::parse(&arg["--watch=".len()..], &symbols).unwrap());
+    }
+    let mut last_scanline = 0;
+
+    // --trace-pc=8000-80ff, --trace-op=STA,LDA, --trace-ppu (only accesses to $2000-$2007)
+    // --trace-format=json: a JSON object per line instead of the nestest-like text, no symbols
+    let trace_format = match args.iter().find(|arg| arg.starts_with("--trace-format=")) {
+        Some(arg) => arg["--trace-format=".len()..].parse::<TraceFormat>().unwrap(),
+        None => TraceFormat::Text,
+    };
+    let mut trace_filter = TraceFilter::new();
+    for arg in args.iter() {
+        if arg.starts_with("--trace-pc=") {
+            trace_filter.pc_range =
+                Some(TraceFilter::parse_pc_range(&arg["--trace-pc=".len()..]).unwrap());
+        } else if arg.starts_with("--trace-op=") {
+            trace_filter.mnemonics = TraceFilter::parse_mnemonics(&arg["--trace-op=".len()..]);
+        } else if arg == "--trace-ppu" {
+            trace_filter.ppu_registers_only = true;
+        }
+    }
+
+    // --crash-report=<file>: on panic the last 100 instructions are dumped to the file
+    // --crash-dump=<file>: on a bus/PPU fault the machine state and the last 100 instructions are
+    //   written to the file as JSON
+    let crash_dump = args
+        .iter()
+        .find(|arg| arg.starts_with("--crash-dump="))
+        .map(|arg| PathBuf::from(&arg["--crash-dump=".len()..]));
+    let history = match args.iter().find(|arg| arg.starts_with("--crash-report=")) {
+        Some(arg) => {
+            let history = Arc::new(Mutex::new(ExecutionHistory::new(100)));
+            crash_report::install_panic_hook(
+                history.clone(),
+                PathBuf::from(&arg["--crash-report=".len()..]),
+            );
+            Some(history)
+        }
+        None if crash_dump.is_some() => Some(Arc::new(Mutex::new(ExecutionHistory::new(100)))),
+        None => None,
+    };
+
+    let mut monitor = Monitor::new(&symbols);
+    let stdin = io::stdin();
+
+    // --remote=127.0.0.1:6502: the debugger over TCP for external tools (JSON lines, see
+    //   rustness::debugger::remote). Requests are served once a frame, and on every stop while a
+    //   client is attached
+    let mut remote = args
+        .iter()
+        .find(|arg| arg.starts_with("--remote="))
+        .map(|arg| RemoteServer::bind(&arg["--remote=".len()..], &symbols).unwrap());
 
     let trace_rc2 = trace.clone();
     cpu.interpret_fn(0xffff, |cpu| {
-        if *trace_rc2.borrow() {
+        if bus.borrow_mut().poll_frame_complete() {
+            trace_rc2.borrow_mut().flush().unwrap();
+            on_frame(&mut bus.borrow_mut());
+            if let Some(remote) = &mut remote {
+                remote.poll(cpu, &mut debugger);
+            }
+            if let Some(reset) = hot_reload {
+                frames_since_check += 1;
+                // twice a second
+                if frames_since_check == 30 {
+                    frames_since_check = 0;
+                    let modified = fs::metadata(rom_path).and_then(|meta| meta.modified()).ok();
+                    if modified != rom_modified {
+                        rom_modified = modified;
+                        let rom = fs::read(rom_path)
+                            .map_err(|e| e.to_string())
+                            .and_then(|data| Rom::load(&data).map_err(|e| e.to_string()));
+                        match rom {
+                            Ok(rom) => {
+                                bus.borrow_mut().swap_rom(rom);
+                                // the states are bound to the old rom
+                                if let Some(rewind) = &mut rewind {
+                                    rewind.clear();
+                                }
+                                if reset {
+                                    reset_request.replace(Some(true));
+                                }
+                                println!("{} reloaded", rom_path);
+                                osd.borrow_mut().show("ROM RELOADED", 120);
+                            }
+                            // half written by the assembler, the next write brings it back
+                            Err(e) => println!("failed to reload {}: {}", rom_path, e),
+                        }
+                    }
+                }
+            }
+            if let Some(rewind) = &mut rewind {
+                if *rewinding.borrow() {
+                    if let Some(snapshot) = rewind.pop() {
+                        cpu.restore(&snapshot).unwrap();
+                    }
+                } else {
+                    rewind.push(&cpu.snapshot());
+                }
+            }
+        }
+        if let Some(history) = &history {
+            history.lock().unwrap().record(cpu);
+        }
+        if let Some(error) = cpu.bus.take_error() {
+            println!("{}", error);
+            if let (Some(path), Some(history)) = (&crash_dump, &history) {
+                let history = history.lock().unwrap();
+                let dump =
+                    crash_report::crash_dump(cpu, &bus.borrow(), &history, &error.to_string());
+                match fs::write(path, dump) {
+                    Ok(_) => println!("crash dump is written to {}", path.display()),
+                    Err(e) => println!("failed to write crash dump {}: {}", path.display(), e),
+                }
+            }
+        }
+        if *quit_requested.borrow() {
+            trace_rc2.borrow_mut().flush().unwrap();
+            quit(cpu, &bus, &save_base, auto_resume);
+        }
+        if pause.replace(false) {
+            debugger.pause();
+        }
+        if let Some(hard) = reset_request.replace(None) {
+            // the bus borrow has to end before the cpu reads the reset vector through it
+            if hard {
+                {
+                    let mut bus = bus.borrow_mut();
+                    bus.power_on();
+                    config.ram_init.fill(&mut bus.ram);
+                }
+                cpu.power_on();
+            } else {
+                bus.borrow_mut().reset();
+                cpu.reset();
+            }
+            osd.borrow_mut().show(if hard { "POWER CYCLE" } else { "RESET" }, 120);
+        }
+        if let Some((slot, save)) = slot_request.replace(None) {
+            let (result, done, failed) = if save {
+                (save_state::save_slot(cpu, &save_base, slot), "SAVED", "SAVE FAILED")
+            } else {
+                (save_state::load_slot(cpu, &save_base, slot), "LOADED", "LOAD FAILED")
+            };
+            let message = match result {
+                Ok(path) => {
+                    println!("state {} {}", done.to_lowercase(), path.display());
+                    format!("STATE {} {}", slot, done)
+                }
+                Err(e) => {
+                    println!("{}", e);
+                    format!("STATE {} {}", slot, failed)
+                }
+            };
+            // 2 seconds
+            osd.borrow_mut().show(&message, 120);
+        }
+        if !debugger.watches().is_empty() {
+            // vblank start
+            let scanline = cpu.bus.trace().ppu_scanline;
+            if scanline == 241 && last_scanline != 241 {
+                println!("{}", debugger.format_watches(cpu).join(", "));
+            }
+            last_scanline = scanline;
+        }
+        if let Some(reason) = debugger.check(cpu) {
+            // the trace so far goes before the debugger output
+            trace_rc2.borrow_mut().flush().unwrap();
+            println!("{}", reason);
+            println!("{}", rustness::cpu::trace_with_symbols(cpu, &symbols));
+            for watch in debugger.format_watches(cpu) {
+                println!("  {}", watch);
+            }
+            print!("{}", debugger.call_stack().format_backtrace(&symbols));
+            if let Some(remote) = remote.as_mut().filter(|remote| remote.is_attached()) {
+                // the client is in control until it continues or disconnects
+                if let Some(mode) = remote.stopped(&reason, cpu, &mut debugger) {
+                    debugger.schedule_step(cpu, mode);
+                }
+            } else {
+                // F5/Pause - continue, F10 - step over, F11 - step into, Shift+F11 - step out, F12 - console
+                loop {
+                    if *console.borrow() {
+                        print!("> ");
+                        io::stdout().flush().unwrap();
+                        let mut line = String::new();
+                        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+                            // stdin is closed, back to hotkeys
+                            console.replace(false);
+                            continue;
+                        }
+                        match monitor.execute(&line, cpu, &mut debugger) {
+                            Ok(Action::Output(output)) if output.is_empty() => {}
+                            Ok(Action::Output(output)) => println!("{}", output),
+                            Ok(Action::Go) => {
+                                console.replace(false);
+                                break;
+                            }
+                            Ok(Action::Step(mode)) => {
+                                debugger.schedule_step(cpu, mode);
+                                break;
+                            }
+                            Err(e) => println!("{}", e),
+                        }
+                        continue;
+                    }
+                    match event_pump.borrow_mut().wait_event() {
+                        Event::Quit { .. }
+                        | Event::KeyDown {
+                            keycode: Some(Keycode::Escape),
+                            ..
+                        } => quit(cpu, &bus, &save_base, auto_resume),
+                        Event::KeyDown {
+                            keycode: Some(Keycode::F5),
+                            ..
+                        }
+                        | Event::KeyDown {
+                            keycode: Some(Keycode::Pause),
+                            ..
+                        } => break,
+                        Event::KeyDown {
+                            keycode: Some(Keycode::F12),
+                            ..
+                        } => {
+                            println!("{}", Monitor::help());
+                            console.replace(true);
+                        }
+                        Event::KeyDown {
+                            keycode: Some(Keycode::F10),
+                            ..
+                        } => {
+                            debugger.schedule_step(cpu, StepMode::Over);
+                            break;
+                        }
+                        Event::KeyDown {
+                            keycode: Some(Keycode::F11),
+                            keymod,
+                            ..
+                        } => {
+                            let mode = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                                StepMode::Out
+                            } else {
+                                StepMode::Into
+                            };
+                            debugger.schedule_step(cpu, mode);
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        let mut trace = trace_rc2.borrow_mut();
+        if trace.is_enabled() && trace_filter.matches(cpu) {
             // ::std::thread::sleep(Duration::new(0, 10000));
-            println!("{}", rustness::cpu::trace(cpu));
+            let line = match trace_format {
+                TraceFormat::Text => rustness::cpu::trace_with_symbols(cpu, &symbols),
+                TraceFormat::Json => rustness::cpu::trace_json(cpu),
+            };
+            trace.write_line(&line).unwrap();
         }
     });
 }
@@ -0,0 +1,276 @@
+// Achievements in the spirit of RetroAchievements: declarative triggers on the game memory,
+// checked once per frame.
+//
+// File format, one achievement per line:
+//   once   $075F == 1 && $0086 > 200 : Reach the end of world 2
+//   repeat $075A >= 5 : Five lives
+//   # comment
+// `once` unlocks the first time the conditions hold, `repeat` fires every time they become true
+// (after being false). Conditions compare a byte of RAM ($0000-$1FFF) or PRG RAM ($6000-$7FFF)
+// with a value (decimal, or hex with $) using ==, !=, <, <=, > or >=, all of them have to hold.
+//
+//   let fired = achievements.check(&mut *emulator.cpu_mut().bus);
+//   for achievement in fired { println!("unlocked: {}", achievement.title); }
+use crate::bus;
+use crate::cpu::mem::Mem;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub addr: u16,
+    pub comparison: Comparison,
+    pub value: u8,
+}
+
+impl Condition {
+    fn parse(s: &str) -> Result<Condition, String> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        if words.len() != 3 {
+            return Err(format!(
+                "bad condition '{}', expected <addr> <op> <value>",
+                s
+            ));
+        }
+        let addr = u16::from_str_radix(words[0].trim_start_matches('$'), 16)
+            .map_err(|_| format!("bad address '{}'", words[0]))?;
+        if !bus::is_ram(addr) {
+            return Err(format!("${:04X} is not in RAM", addr));
+        }
+        let comparison = match words[1] {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            "<=" => Comparison::LessOrEqual,
+            ">" => Comparison::Greater,
+            ">=" => Comparison::GreaterOrEqual,
+            op => return Err(format!("bad comparison '{}'", op)),
+        };
+        let value = match words[2].strip_prefix('$') {
+            Some(hex) => u8::from_str_radix(hex, 16),
+            None => words[2].parse::<u8>(),
+        }
+        .map_err(|_| format!("bad value '{}'", words[2]))?;
+        Ok(Condition {
+            addr,
+            comparison,
+            value,
+        })
+    }
+
+    fn holds<M: Mem + ?Sized>(&self, mem: &mut M) -> bool {
+        let actual = mem.read(self.addr);
+        match self.comparison {
+            Comparison::Equal => actual == self.value,
+            Comparison::NotEqual => actual != self.value,
+            Comparison::Less => actual < self.value,
+            Comparison::LessOrEqual => actual <= self.value,
+            Comparison::Greater => actual > self.value,
+            Comparison::GreaterOrEqual => actual >= self.value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Achievement {
+    pub title: String,
+    pub conditions: Vec<Condition>,
+    /// Fires every time the conditions become true, not only the first one
+    pub repeat: bool,
+    pub unlocked: bool,
+    // the conditions held at the last check
+    active: bool,
+}
+
+pub struct Achievements {
+    achievements: Vec<Achievement>,
+}
+
+impl Achievements {
+    pub fn new() -> Self {
+        Achievements {
+            achievements: vec![],
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Achievements, String> {
+        let mut achievements = Achievements::new();
+        for (line_num, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |e: String| format!("line {}: {}", line_num + 1, e);
+            let mut parts = line.splitn(2, ':');
+            let trigger = parts.next().unwrap_or("").trim();
+            let title = parts.next().unwrap_or("").trim();
+            if title.is_empty() {
+                return Err(error("missing title".to_string()));
+            }
+            let mut words = trigger.splitn(2, char::is_whitespace);
+            let repeat = match words.next().unwrap_or("") {
+                "once" => false,
+                "repeat" => true,
+                mode => {
+                    return Err(error(format!(
+                        "bad mode '{}', expected once or repeat",
+                        mode
+                    )))
+                }
+            };
+            let conditions = words
+                .next()
+                .unwrap_or("")
+                .split("&&")
+                .map(Condition::parse)
+                .collect::<Result<Vec<Condition>, String>>()
+                .map_err(error)?;
+            achievements.add(title, conditions, repeat);
+        }
+        Ok(achievements)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load(path: &Path) -> Result<Achievements, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Achievements::parse(&content)
+    }
+
+    pub fn add(&mut self, title: &str, conditions: Vec<Condition>, repeat: bool) {
+        self.achievements.push(Achievement {
+            title: title.to_string(),
+            conditions,
+            repeat,
+            unlocked: false,
+            active: false,
+        });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Achievement> {
+        self.achievements.iter()
+    }
+
+    /// Checks the triggers against the memory, once per frame (e.g. after `run_frame`).
+    /// Returns the achievements fired by this check.
+    pub fn check<M: Mem + ?Sized>(&mut self, mem: &mut M) -> Vec<&Achievement> {
+        let mut fired = vec![];
+        for (idx, achievement) in self.achievements.iter_mut().enumerate() {
+            if achievement.unlocked && !achievement.repeat {
+                continue;
+            }
+            let active = achievement.conditions.iter().all(|c| c.holds(mem));
+            if active && !achievement.active {
+                achievement.unlocked = true;
+                fired.push(idx);
+            }
+            achievement.active = active;
+        }
+        let achievements = &self.achievements;
+        fired.into_iter().map(|idx| &achievements[idx]).collect()
+    }
+}
+
+impl Default for Achievements {
+    fn default() -> Self {
+        Achievements::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::MockBus;
+
+    const FILE: &str = "# smb\n\
+                        once $075F == 1 && $0086 > 200 : End of world 2\n\
+                        repeat $075A >= 5 : Five lives\n";
+
+    fn titles(fired: Vec<&Achievement>) -> Vec<&str> {
+        fired.iter().map(|a| a.title.as_str()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let achievements = Achievements::parse(FILE).unwrap();
+        let achievements: Vec<&Achievement> = achievements.iter().collect();
+        assert_eq!(achievements.len(), 2);
+        assert_eq!(
+            achievements[0].conditions,
+            vec![
+                Condition {
+                    addr: 0x075f,
+                    comparison: Comparison::Equal,
+                    value: 1
+                },
+                Condition {
+                    addr: 0x0086,
+                    comparison: Comparison::Greater,
+                    value: 200
+                }
+            ]
+        );
+        assert!(!achievements[0].repeat);
+        assert_eq!(achievements[1].title, "Five lives");
+        assert!(achievements[1].repeat);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Achievements::parse("always $10 == 1 : x").err(),
+            Some(String::from(
+                "line 1: bad mode 'always', expected once or repeat"
+            ))
+        );
+        assert!(Achievements::parse("once $10 == 1").is_err());
+        assert!(Achievements::parse("once $10 = 1 : x").is_err());
+        assert!(Achievements::parse("once $10 == 256 : x").is_err());
+        assert!(Achievements::parse("once $10 == : x").is_err());
+        assert_eq!(
+            Achievements::parse("once $2002 == 1 : x").err(),
+            Some(String::from("line 1: $2002 is not in RAM"))
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let mut achievements = Achievements::parse(FILE).unwrap();
+        let mut mem = MockBus::new();
+        assert!(achievements.check(&mut mem).is_empty());
+
+        mem.write(0x075a, 5);
+        assert_eq!(titles(achievements.check(&mut mem)), vec!["Five lives"]);
+        // still true, already fired
+        assert!(achievements.check(&mut mem).is_empty());
+        mem.write(0x075a, 4);
+        assert!(achievements.check(&mut mem).is_empty());
+        mem.write(0x075a, 6);
+        assert_eq!(titles(achievements.check(&mut mem)), vec!["Five lives"]);
+
+        mem.write(0x075f, 1);
+        mem.write(0x0086, 200);
+        assert!(achievements.check(&mut mem).is_empty());
+        mem.write(0x0086, 201);
+        assert_eq!(titles(achievements.check(&mut mem)), vec!["End of world 2"]);
+        assert!(achievements.iter().next().unwrap().unlocked);
+
+        // once only
+        mem.write(0x0086, 0);
+        achievements.check(&mut mem);
+        mem.write(0x0086, 201);
+        assert!(achievements.check(&mut mem).is_empty());
+    }
+}
@@ -0,0 +1,360 @@
+// Famicom Disk System expansion sound: one wavetable channel with a frequency modulation unit.
+// https://wiki.nesdev.com/w/index.php/FDS_audio
+//
+//   $4040-$407F  wavetable, 64 6-bit samples (writable while $4089.7 is set)
+//   $4080        volume envelope: 7 - direct gain, 6 - increase, 0-5 speed or gain
+//   $4082/$4083  12-bit wave frequency; $4083.7 halts the wave, $4083.6 halts the envelopes
+//   $4084        modulation envelope, same layout as $4080
+//   $4085        modulation counter (7-bit signed)
+//   $4086/$4087  12-bit modulation frequency; $4087.7 halts the modulation (table writes allowed)
+//   $4088        modulation table: 3-bit steps appended to a 64-entry ring, each one twice
+//   $4089        7 - wavetable write enable, 0-1 master volume (2/2, 2/3, 2/4, 2/5)
+//   $408A        envelope speed multiplier
+//   $4090/$4092  volume/modulation gain (read only)
+//
+// todo: mixing into the APU output, once there is an APU (and the FDS itself: disk drive, IRQs)
+use super::ExpansionAudio;
+
+// modulation table steps, 4 resets the counter
+const MOD_STEPS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+const MOD_RESET: u8 = 4;
+// gain above 32 is stored but the output is capped
+const MAX_GAIN: u8 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Envelope {
+    // direct gain, no envelope
+    disabled: bool,
+    increase: bool,
+    speed: u8,
+    gain: u8,
+    // CPU cycles till the next step
+    timer: u32,
+}
+
+impl Envelope {
+    fn new() -> Self {
+        Envelope {
+            disabled: true,
+            increase: false,
+            speed: 0,
+            gain: 0,
+            timer: 0,
+        }
+    }
+
+    fn write(&mut self, data: u8, master_speed: u8) {
+        self.disabled = data & 0x80 != 0;
+        self.increase = data & 0x40 != 0;
+        self.speed = data & 0x3f;
+        if self.disabled {
+            self.gain = self.speed;
+        }
+        self.timer = self.period(master_speed);
+    }
+
+    fn period(&self, master_speed: u8) -> u32 {
+        8 * (self.speed as u32 + 1) * master_speed as u32
+    }
+
+    fn tick(&mut self, master_speed: u8) {
+        if self.disabled || master_speed == 0 {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period(master_speed);
+        if self.increase && self.gain < MAX_GAIN {
+            self.gain += 1;
+        } else if !self.increase && self.gain > 0 {
+            self.gain -= 1;
+        }
+    }
+}
+
+pub struct FdsAudio {
+    wave: [u8; 64],
+    wave_write: bool,
+    wave_halt: bool,
+    // position in the wavetable in the top 6 bits
+    wave_acc: u32,
+    freq: u16,
+    volume: Envelope,
+    envelopes_halt: bool,
+    master_speed: u8,
+    master_volume: u8,
+    // the output holds while the wavetable is written
+    level: u16,
+
+    modulation: Envelope,
+    mod_table: [u8; 64],
+    // position in the modulation table in the top 6 bits
+    mod_acc: u32,
+    mod_freq: u16,
+    mod_halt: bool,
+    // 7-bit signed
+    mod_counter: i8,
+}
+
+impl FdsAudio {
+    pub fn new() -> Self {
+        FdsAudio {
+            wave: [0; 64],
+            wave_write: false,
+            wave_halt: true,
+            wave_acc: 0,
+            freq: 0,
+            volume: Envelope::new(),
+            envelopes_halt: false,
+            master_speed: 0xe8,
+            master_volume: 0,
+            level: 0,
+            modulation: Envelope::new(),
+            mod_table: [0; 64],
+            mod_acc: 0,
+            mod_freq: 0,
+            mod_halt: true,
+            mod_counter: 0,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4040..=0x407f if self.wave_write => {
+                self.wave[(addr - 0x4040) as usize] = data & 0x3f;
+            }
+            0x4080 => self.volume.write(data, self.master_speed),
+            0x4082 => self.freq = self.freq & 0xf00 | data as u16,
+            0x4083 => {
+                self.freq = self.freq & 0xff | (data as u16 & 0x0f) << 8;
+                self.wave_halt = data & 0x80 != 0;
+                self.envelopes_halt = data & 0x40 != 0;
+                if self.wave_halt {
+                    self.wave_acc = 0;
+                }
+            }
+            0x4084 => self.modulation.write(data, self.master_speed),
+            0x4085 => self.mod_counter = sign_extend_7(data),
+            0x4086 => self.mod_freq = self.mod_freq & 0xf00 | data as u16,
+            0x4087 => {
+                self.mod_freq = self.mod_freq & 0xff | (data as u16 & 0x0f) << 8;
+                self.mod_halt = data & 0x80 != 0;
+                if self.mod_halt {
+                    // the fraction is lost, the table position stays
+                    self.mod_acc &= 0x3f_0000;
+                }
+            }
+            0x4088 if self.mod_halt => {
+                let pos = (self.mod_acc >> 16) as usize;
+                self.mod_table[pos] = data & 0x07;
+                self.mod_table[(pos + 1) % 64] = data & 0x07;
+                self.mod_acc = (self.mod_acc + (2 << 16)) & 0x3f_ffff;
+            }
+            0x4089 => {
+                self.wave_write = data & 0x80 != 0;
+                self.master_volume = data & 0x03;
+            }
+            0x408a => self.master_speed = data,
+            _ => {}
+        }
+    }
+
+    /// Reads of the wavetable and the gain registers, the rest is open bus (0 here)
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x4040..=0x407f => self.wave[(addr - 0x4040) as usize],
+            0x4090 => self.volume.gain,
+            0x4092 => self.modulation.gain,
+            _ => 0,
+        }
+    }
+
+    /// One CPU cycle
+    pub fn tick(&mut self) {
+        if !self.envelopes_halt && !self.wave_halt {
+            self.volume.tick(self.master_speed);
+            self.modulation.tick(self.master_speed);
+        }
+
+        if !self.mod_halt && self.mod_freq > 0 {
+            let before = self.mod_acc;
+            self.mod_acc = (self.mod_acc + self.mod_freq as u32) & 0x3f_ffff;
+            // a step every 2^16
+            if self.mod_acc >> 16 != before >> 16 {
+                self.step_modulation();
+            }
+        }
+
+        if !self.wave_halt {
+            let pitch = self.pitch();
+            if pitch > 0 {
+                self.wave_acc = (self.wave_acc + pitch as u32) & 0x3f_ffff;
+            }
+        }
+
+        if !self.wave_write {
+            let sample = self.wave[(self.wave_acc >> 16) as usize & 0x3f] as u16;
+            self.level = sample * self.volume.gain.min(MAX_GAIN) as u16;
+        }
+    }
+
+    fn step_modulation(&mut self) {
+        let step = self.mod_table[(self.mod_acc >> 16) as usize & 0x3f];
+        self.mod_counter = if step == MOD_RESET {
+            0
+        } else {
+            sign_extend_7(self.mod_counter.wrapping_add(MOD_STEPS[step as usize]) as u8)
+        };
+    }
+
+    // wave frequency bent by the modulation unit, the hardware's rounding included
+    fn pitch(&self) -> i32 {
+        let freq = self.freq as i32;
+        if self.mod_halt {
+            return freq;
+        }
+        let counter = self.mod_counter as i32;
+        let mut temp = counter * self.modulation.gain as i32;
+        let remainder = temp & 0x0f;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        temp *= freq;
+        let remainder = temp & 0x3f;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        freq + temp
+    }
+
+    /// 0.0..=1.0, after the master volume
+    pub fn output(&self) -> f32 {
+        // 63 * 32 at the full gain
+        let level = self.level as f32 / 2016.0;
+        level * 2.0 / (self.master_volume as f32 + 2.0)
+    }
+}
+
+impl ExpansionAudio for FdsAudio {
+    fn tick(&mut self) {
+        FdsAudio::tick(self);
+    }
+
+    fn output(&self) -> f32 {
+        FdsAudio::output(self)
+    }
+}
+
+impl Default for FdsAudio {
+    fn default() -> Self {
+        FdsAudio::new()
+    }
+}
+
+fn sign_extend_7(value: u8) -> i8 {
+    ((value << 1) as i8) >> 1
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // ramp 0..63, direct full gain
+    fn audio() -> FdsAudio {
+        let mut audio = FdsAudio::new();
+        audio.write(0x4089, 0x80);
+        for idx in 0..64 {
+            audio.write(0x4040 + idx, idx as u8);
+        }
+        audio.write(0x4089, 0x00);
+        audio.write(0x4080, 0x80 | 32);
+        audio
+    }
+
+    #[test]
+    fn test_wavetable() {
+        let mut audio = FdsAudio::new();
+        audio.write(0x4040, 0x3f);
+        assert_eq!(audio.read(0x4040), 0);
+        audio.write(0x4089, 0x80);
+        audio.write(0x4040, 0xff);
+        assert_eq!(audio.read(0x4040), 0x3f);
+    }
+
+    #[test]
+    fn test_wave_pitch() {
+        let mut audio = audio();
+        // a sample every 64 cycles
+        audio.write(0x4082, 0x00);
+        audio.write(0x4083, 0x04);
+        for _ in 0..64 * 10 {
+            audio.tick();
+        }
+        assert_eq!(audio.level, 10 * 32);
+        assert!((audio.output() - 320.0 / 2016.0).abs() < 1e-6);
+
+        // 2/5 master volume
+        audio.write(0x4089, 0x03);
+        assert!((audio.output() - 128.0 / 2016.0).abs() < 1e-6);
+
+        // halted: back to the start of the table
+        audio.write(0x4083, 0x84);
+        audio.tick();
+        assert_eq!(audio.level, 0);
+    }
+
+    #[test]
+    fn test_envelope() {
+        let mut audio = audio();
+        audio.write(0x4083, 0x04);
+        // increase, speed 0: a step every 8 * 1 * $E8 cycles
+        audio.write(0x4080, 0x40);
+        assert_eq!(audio.read(0x4090), 32);
+        audio.write(0x4080, 0x80);
+        assert_eq!(audio.read(0x4090), 0);
+        audio.write(0x4080, 0x40);
+        for _ in 0..(8 * 0xe8 + 1) * 3 {
+            audio.tick();
+        }
+        assert_eq!(audio.read(0x4090), 3);
+    }
+
+    #[test]
+    fn test_modulation() {
+        let mut audio = audio();
+        audio.write(0x4087, 0x80);
+        // +1 steps
+        for _ in 0..32 {
+            audio.write(0x4088, 1);
+        }
+        audio.write(0x4084, 0x80 | 0x3f);
+        audio.write(0x4085, 0x00);
+        // a step every 32 cycles
+        audio.write(0x4086, 0x00);
+        audio.write(0x4087, 0x08);
+        for _ in 0..32 * 5 {
+            audio.tick();
+        }
+        assert_eq!(audio.mod_counter, 5);
+
+        audio.write(0x4082, 0x00);
+        audio.write(0x4083, 0x01);
+        // 5 * 63 / 16 = 19.6 -> 21, 256 * 21 / 64 = 84
+        assert_eq!(audio.pitch(), 256 + 84);
+
+        // wraps at 7 bits
+        audio.write(0x4085, 0x3f);
+        assert_eq!(audio.mod_counter, 63);
+        audio.step_modulation();
+        assert_eq!(audio.mod_counter, -64);
+    }
+}
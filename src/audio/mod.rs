@@ -0,0 +1,210 @@
+// Audio output. The APU (todo) pushes mono samples in -1.0..1.0 into an `AudioSink`,
+// frontends pick the backend: SDL2 audio queue (native/), WAV file, ...
+pub mod fds;
+pub mod vrc6;
+
+use crate::prelude::*;
+#[cfg(feature = "std")]
+use std::io::{self, Seek, SeekFrom, Write};
+
+pub trait AudioSink {
+    fn push_samples(&mut self, samples: &[f32]);
+}
+
+/// Sound channels on the cartridge (FDS, VRC6, ...), mixed with the APU output. The mixer
+/// clocks them, see `Mapper::expansion_audio`
+pub trait ExpansionAudio: Send {
+    /// One CPU cycle
+    fn tick(&mut self);
+    /// 0.0..=1.0
+    fn output(&self) -> f32;
+}
+
+/// Clocks a sound source every CPU cycle and pushes its output at the sample rate of the sink
+/// (point sampling, no filtering): the expansion channels are heard without the APU mixer (todo),
+/// e.g. `Sampler::new(region.cpu_clock_hz(), 44100)`
+pub struct Sampler {
+    cpu_clock_hz: u64,
+    sample_rate: u64,
+    // grows by sample_rate every cycle, a sample is taken each time it reaches cpu_clock_hz
+    phase: u64,
+    buffer: Vec<f32>,
+}
+
+impl Sampler {
+    pub fn new(cpu_clock_hz: u64, sample_rate: u32) -> Self {
+        Sampler {
+            cpu_clock_hz,
+            sample_rate: sample_rate as u64,
+            phase: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Runs `source` for `cycles` CPU cycles, the samples go to `sink` in one batch
+    pub fn run(
+        &mut self,
+        source: &mut dyn ExpansionAudio,
+        cycles: usize,
+        sink: &mut dyn AudioSink,
+    ) {
+        self.buffer.clear();
+        for _ in 0..cycles {
+            source.tick();
+            self.phase += self.sample_rate;
+            if self.phase >= self.cpu_clock_hz {
+                self.phase -= self.cpu_clock_hz;
+                self.buffer.push(source.output());
+            }
+        }
+        if !self.buffer.is_empty() {
+            sink.push_samples(&self.buffer);
+        }
+    }
+}
+
+/// Records samples into a 16 bit mono PCM WAV file,
+/// sizes in the header are filled in by `finish`
+#[cfg(feature = "std")]
+pub struct WavWriter<W: Write + Seek> {
+    output: W,
+    samples: u32,
+    // the first write failure, `push_samples` can't report it
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "std")]
+impl<W: Write + Seek> WavWriter<W> {
+    const HEADER_LEN: u32 = 44;
+
+    pub fn new(mut output: W, sample_rate: u32) -> io::Result<Self> {
+        // http://soundfile.sapp.org/doc/WaveFormat/
+        output.write_all(b"RIFF")?;
+        output.write_all(&0u32.to_le_bytes())?; // patched in `finish`
+        output.write_all(b"WAVEfmt ")?;
+        output.write_all(&16u32.to_le_bytes())?;
+        output.write_all(&1u16.to_le_bytes())?; // PCM
+        output.write_all(&1u16.to_le_bytes())?; // mono
+        output.write_all(&sample_rate.to_le_bytes())?;
+        output.write_all(&(sample_rate * 2).to_le_bytes())?; // byte rate
+        output.write_all(&2u16.to_le_bytes())?; // block align
+        output.write_all(&16u16.to_le_bytes())?; // bits per sample
+        output.write_all(b"data")?;
+        output.write_all(&0u32.to_le_bytes())?; // patched in `finish`
+        Ok(WavWriter {
+            output,
+            samples: 0,
+            error: None,
+        })
+    }
+
+    /// Writes the final sizes into the header
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let data_len = self.samples * 2;
+        self.output.seek(SeekFrom::Start(4))?;
+        self.output
+            .write_all(&(Self::HEADER_LEN - 8 + data_len).to_le_bytes())?;
+        self.output.seek(SeekFrom::Start(40))?;
+        self.output.write_all(&data_len.to_le_bytes())?;
+        self.output.seek(SeekFrom::End(0))?;
+        self.output.flush()?;
+        Ok(self.output)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write + Seek> AudioSink for WavWriter<W> {
+    fn push_samples(&mut self, samples: &[f32]) {
+        if self.error.is_some() {
+            return;
+        }
+        let mut data = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            data.extend(&sample.to_le_bytes());
+        }
+        match self.output.write_all(&data) {
+            Ok(_) => self.samples += samples.len() as u32,
+            Err(e) => self.error = Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "std")]
+mod test {
+    use super::fds::FdsAudio;
+    use super::*;
+    use crate::region::Region;
+    use std::io::Cursor;
+
+    struct Samples(Vec<f32>);
+
+    impl AudioSink for Samples {
+        fn push_samples(&mut self, samples: &[f32]) {
+            self.0.extend_from_slice(samples);
+        }
+    }
+
+    #[test]
+    fn test_sampler() {
+        // a generated square wave in the FDS wavetable, one period every 64 * 8 = 512 cycles
+        let mut fds = FdsAudio::new();
+        fds.write(0x4089, 0x80);
+        for idx in 0..64 {
+            fds.write(0x4040 + idx, if idx < 32 { 0x3f } else { 0 });
+        }
+        fds.write(0x4089, 0x00);
+        fds.write(0x4080, 0x80 | 32);
+        fds.write(0x4082, 0x00);
+        fds.write(0x4083, 0x08);
+
+        let cpu_clock_hz = Region::Ntsc.cpu_clock_hz();
+        let mut sampler = Sampler::new(cpu_clock_hz, 44100);
+        let mut samples = Samples(vec![]);
+        // a second, in frames
+        for _ in 0..60 {
+            sampler.run(&mut fds, cpu_clock_hz as usize / 60, &mut samples);
+        }
+        let samples = samples.0;
+        assert!(
+            (44099..=44100).contains(&samples.len()),
+            "{}",
+            samples.len()
+        );
+        assert!(samples.iter().all(|sample| (0.0..=1.0).contains(sample)));
+        // the square wave: both levels, switching about every 256 cycles (11 samples)
+        let high = samples.iter().filter(|sample| **sample > 0.5).count();
+        assert!(
+            high > samples.len() / 3 && high < samples.len() * 2 / 3,
+            "{}",
+            high
+        );
+
+        let mut wav = WavWriter::new(Cursor::new(vec![]), 44100).unwrap();
+        sampler.run(&mut fds, 1000, &mut wav);
+        let data = wav.finish().unwrap().into_inner();
+        assert!(data.len() > 44 && data[44..].iter().any(|byte| *byte != 0));
+    }
+
+    #[test]
+    fn test_wav_writer() {
+        let mut wav = WavWriter::new(Cursor::new(vec![]), 44100).unwrap();
+        wav.push_samples(&[0.0, 1.0]);
+        wav.push_samples(&[-1.0, 2.0]);
+        let data = wav.finish().unwrap().into_inner();
+
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(&data[4..8], &(36u32 + 8).to_le_bytes());
+        assert_eq!(&data[24..28], &44100u32.to_le_bytes());
+        assert_eq!(&data[40..44], &8u32.to_le_bytes());
+        assert_eq!(
+            &data[44..],
+            &[0x00, 0x00, 0xff, 0x7f, 0x01, 0x80, 0xff, 0x7f]
+        );
+    }
+}
@@ -0,0 +1,265 @@
+// Konami VRC6 expansion sound: two pulse channels with 8 duty cycles and a sawtooth.
+// https://wiki.nesdev.com/w/index.php/VRC6_audio
+//
+//   $9000/$A000  pulse: 7 - constant volume (ignores the duty), 4-6 duty, 0-3 volume
+//   $9001/$A001  pulse period, low 8 bits
+//   $9002/$A002  7 - enable, 0-3 pulse period, high 4 bits
+//   $9003        0 - halt all, 1 - periods / 16, 2 - periods / 256
+//   $B000        sawtooth accumulator rate, 6 bits
+//   $B001/$B002  sawtooth period, same layout as the pulse ones
+//
+// The addresses are VRC6a (Akumajou Densetsu) ones, the mapper swaps A0/A1 for VRC6b.
+use super::ExpansionAudio;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Pulse {
+    constant: bool,
+    duty: u8,
+    volume: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    // 15 down to 0
+    step: u8,
+}
+
+impl Pulse {
+    fn new() -> Self {
+        Pulse {
+            constant: false,
+            duty: 0,
+            volume: 0,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            step: 15,
+        }
+    }
+
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.constant = data & 0x80 != 0;
+                self.duty = (data >> 4) & 0x07;
+                self.volume = data & 0x0f;
+            }
+            1 => self.period = self.period & 0xf00 | data as u16,
+            _ => {
+                self.period = self.period & 0xff | (data as u16 & 0x0f) << 8;
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = self.step.checked_sub(1).unwrap_or(15);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Saw {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    // 14 steps, the accumulator adds up on every second one
+    step: u8,
+    accumulator: u8,
+}
+
+impl Saw {
+    fn new() -> Self {
+        Saw {
+            rate: 0,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            step: 0,
+            accumulator: 0,
+        }
+    }
+
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => self.rate = data & 0x3f,
+            1 => self.period = self.period & 0xf00 | data as u16,
+            _ => {
+                self.period = self.period & 0xff | (data as u16 & 0x0f) << 8;
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> shift;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step & 1 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    // the top 5 bits
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+pub struct Vrc6Audio {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    saw: Saw,
+    halt: bool,
+    // $9003 period divider: 0, 4 or 8 bits off
+    shift: u8,
+}
+
+impl Vrc6Audio {
+    pub fn new() -> Self {
+        Vrc6Audio {
+            pulse1: Pulse::new(),
+            pulse2: Pulse::new(),
+            saw: Saw::new(),
+            halt: false,
+            shift: 0,
+        }
+    }
+
+    /// The sound registers of $9000-$B002, VRC6a addresses
+    pub fn write(&mut self, addr: u16, data: u8) {
+        let reg = addr & 0x03;
+        match addr & 0xf000 {
+            0x9000 if reg == 3 => {
+                self.halt = data & 0x01 != 0;
+                self.shift = match data & 0x06 {
+                    0 => 0,
+                    0x02 => 4,
+                    // 8 bits wins over 4
+                    _ => 8,
+                };
+            }
+            0x9000 => self.pulse1.write(reg, data),
+            0xa000 if reg < 3 => self.pulse2.write(reg, data),
+            0xb000 if reg < 3 => self.saw.write(reg, data),
+            _ => {}
+        }
+    }
+}
+
+impl Default for Vrc6Audio {
+    fn default() -> Self {
+        Vrc6Audio::new()
+    }
+}
+
+impl ExpansionAudio for Vrc6Audio {
+    fn tick(&mut self) {
+        if self.halt {
+            return;
+        }
+        self.pulse1.tick(self.shift);
+        self.pulse2.tick(self.shift);
+        self.saw.tick(self.shift);
+    }
+
+    fn output(&self) -> f32 {
+        // 15 + 15 + 31 at the full volume
+        let level = self.pulse1.output() + self.pulse2.output() + self.saw.output();
+        level as f32 / 61.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pulse_duty() {
+        let mut audio = Vrc6Audio::new();
+        // duty 3: 4 of 16 steps high, a step every 11 cycles
+        audio.write(0x9000, 0x3f);
+        audio.write(0x9001, 10);
+        audio.write(0x9002, 0x80);
+        let mut high = 0;
+        for _ in 0..16 * 11 {
+            audio.tick();
+            if audio.output() > 0.0 {
+                assert_eq!(audio.output(), 15.0 / 61.0);
+                high += 1;
+            }
+        }
+        assert_eq!(high, 4 * 11);
+
+        // constant volume
+        audio.write(0x9000, 0x85);
+        assert_eq!(audio.pulse1.output(), 5);
+        audio.write(0x9002, 0x00);
+        assert_eq!(audio.output(), 0.0);
+    }
+
+    #[test]
+    fn test_saw() {
+        let mut audio = Vrc6Audio::new();
+        audio.write(0xb000, 0x08);
+        audio.write(0xb001, 0);
+        audio.write(0xb002, 0x80);
+        let levels: Vec<u8> = (0..14)
+            .map(|_| {
+                audio.tick();
+                audio.saw.output()
+            })
+            .collect();
+        assert_eq!(&levels, &[0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 0]);
+    }
+
+    #[test]
+    fn test_halt_and_frequency_shift() {
+        let mut audio = Vrc6Audio::new();
+        audio.write(0x9000, 0x0f);
+        audio.write(0xa001, 0x00);
+        audio.write(0x9001, 0x00);
+        audio.write(0x9002, 0x81);
+        audio.write(0x9003, 0x01);
+        audio.tick();
+        assert_eq!(audio.pulse1.step, 15);
+
+        // $100 / 256: a step every 2 cycles
+        audio.write(0x9003, 0x04);
+        for _ in 0..4 {
+            audio.tick();
+        }
+        assert_eq!(audio.pulse1.step, 13);
+    }
+}
@@ -1,10 +1,20 @@
 use crate::cpu::mem::Mem;
+use crate::error::{BusError, PpuError, RustnessError};
+use crate::events::{EmulatorEvents, Event};
 use crate::input;
 use crate::ppu::ppu::NesPPU;
+#[cfg(feature = "save-state")]
+use crate::ppu::ppu::PpuState;
 use crate::ppu::ppu::PPU;
-use crate::rom::Rom;
-use std::cell::RefCell;
-use std::rc::Rc;
+use crate::prelude::*;
+use crate::region::Region;
+use crate::rom::mapper::{self, Mapper};
+use crate::rom::eeprom::Eeprom;
+use crate::rom::{Rom, RomFlags};
+#[cfg(feature = "save-state")]
+use serde::{Deserialize, Serialize};
+use alloc::rc::Rc;
+use core::cell::RefCell;
 
 // # Memory Map http://nesdev.com/NESDoc.pdf
 //
@@ -43,23 +53,43 @@ const STACK: u16 = 0x0100;
 #[allow(dead_code)]
 const RAM: u16 = 0x0200;
 const RAM_MIRRORS: u16 = 0x0800;
-#[allow(dead_code)]
 const RAM_MIRRORS_END: u16 = 0x1FFF;
 #[allow(dead_code)]
 const IO_REGISTERS: u16 = 0x2000;
 const IO_MIRRORS: u16 = 0x2008;
 const IO_MIRRORS_END: u16 = 0x3FFF;
+// 8KB of cartridge RAM, every rom gets it: iNES 1.0 headers often don't declare it
+const PRG_RAM: u16 = 0x6000;
+const PRG_RAM_END: u16 = 0x7FFF;
 const PRG_ROM: u16 = 0x8000;
 const PRG_ROM_END: u16 = 0xFFFF;
 
-pub struct Bus<'call, T: PPU + 'call> {
+pub struct Bus<T: PPU> {
     pub ram: [u8; 0x800],
+    pub prg_ram: [u8; 0x2000],
     pub rom: Rom,
+    // all the cartridge accesses but the PRG RAM go through it
+    mapper: Box<dyn Mapper>,
     pub nmi_interrupt: Option<u8>,
+    // the cartridge IRQ output, sampled on every tick
+    irq_line: bool,
     cycles: usize,
     ppu: T,
-    interrupt_fn: Box<dyn FnMut(&T, &mut input::Joypad) + 'call>,
+    region: Region,
+    // fraction of a PPU dot left from the last tick (PAL runs 3.2 dots per CPU cycle)
+    ppu_dots_remainder: u8,
+    // set at the start of vblank, see `poll_frame_complete`
+    frame_complete: bool,
+    // completed frames since power on, for `Event::FrameCompleted`
+    frames: usize,
     joypad1: input::Joypad,
+    joypad2: input::Joypad,
+    // a DMC sample fetch waiting for the next CPU read, see `request_dmc_fetch`
+    dmc_fetch: Option<u16>,
+    dmc_sample: Option<u8>,
+    // the first fault since the last `take_error`
+    error: Option<RustnessError>,
+    subscribers: Vec<Box<dyn EmulatorEvents + Send>>,
 }
 
 fn map_mirrors(pos: u16) -> u16 {
@@ -70,22 +100,42 @@ fn map_mirrors(pos: u16) -> u16 {
     }
 }
 
+/// Internal RAM (with the mirrors) or the cartridge PRG RAM: reading it has no side effects,
+/// unlike the io registers
+pub fn is_ram(pos: u16) -> bool {
+    pos <= RAM_MIRRORS_END || (PRG_RAM..=PRG_RAM_END).contains(&pos)
+}
+
 #[allow(dead_code)]
-impl<'a, T: PPU> Bus<'a, T> {
-    pub fn new<'call, F>(rom: Rom, interrupt_fn: F) -> Bus<'call, NesPPU>
-    where
-        F: FnMut(&NesPPU, &mut input::Joypad) + 'call,
-    {
-        let chr_rom_copy = rom.chr_rom.clone(); // todo: this will bite me with mappers
-        let mirroring = rom.rom_flags.mirroring();
+impl<T: PPU> Bus<T> {
+    pub fn new(rom: Rom) -> Bus<NesPPU> {
+        let mut mapper = mapper::create(&rom);
+        let chr = mapper::chr_window(mapper.as_mut(), &rom);
+        let mirroring = mapper
+            .mirroring()
+            .unwrap_or_else(|| rom.rom_flags.mirroring());
+        let region = Region::from_rom(&rom);
+        let mut ppu = NesPPU::new(chr, mirroring);
+        ppu.set_region(region);
         Bus {
             ram: [0; 2048],
+            prg_ram: [0; 0x2000],
             rom: rom,
+            mapper,
             nmi_interrupt: None,
+            irq_line: false,
             cycles: 7, //todo implement reset
-            ppu: NesPPU::new(chr_rom_copy, mirroring),
-            interrupt_fn: Box::from(interrupt_fn),
+            ppu,
+            region,
+            ppu_dots_remainder: 0,
+            frame_complete: false,
+            frames: 0,
             joypad1: input::Joypad::new(),
+            joypad2: input::Joypad::new(),
+            dmc_fetch: None,
+            dmc_sample: None,
+            error: None,
+            subscribers: vec![],
         }
     }
 
@@ -102,7 +152,7 @@ impl<'a, T: PPU> Bus<'a, T> {
                 self.ppu.write_to_mask(data);
             }
 
-            0x2002 => panic!("attempt to write to PPU status register"),
+            0x2002 => self.fault(BusError::PpuStatusWrite(data)),
 
             0x2003 => {
                 self.ppu.write_to_oam_addr(data);
@@ -119,13 +169,23 @@ impl<'a, T: PPU> Bus<'a, T> {
             }
             0x2007 => {
                 self.ppu.write_to_data(data);
+                if let Some((addr, data)) = self.ppu.take_chr_write() {
+                    if addr >= 0x2000 {
+                        self.mapper.ppu_write(&self.rom, addr, data);
+                        self.sync_cartridge(addr);
+                    } else if self.mapper.ppu_write(&self.rom, addr, data) {
+                        self.ppu.write_chr(addr, data);
+                    } else {
+                        self.fault(PpuError::ChrRomWrite { addr, data });
+                    }
+                }
             }
             // https://wiki.nesdev.com/w/index.php/PPU_programmer_reference#OAM_DMA_.28.244014.29_.3E_write
             0x4014 => {
                 let mut buffer: [u8; 256] = [0; 256];
                 let hi: u16 = (data as u16) << 8;
                 for i in 0..256u16 {
-                    buffer[i as usize] = self.read(hi + i);
+                    buffer[i as usize] = self.dma_read(hi + i);
                 }
 
                 self.ppu.write_oam_dma(&buffer);
@@ -145,27 +205,48 @@ impl<'a, T: PPU> Bus<'a, T> {
                 //ignore APU for now
             }
 
+            // the strobe goes to both controllers
             0x4016 => {
                 self.joypad1.write(data);
+                self.joypad2.write(data);
             }
 
             0x4017 => {
-                // self.joypad2.write(data);
+                //todo: APU frame counter
+            }
+
+            PRG_RAM..=PRG_RAM_END => {
+                if self.mapper.prg_ram_write(pos, data) {
+                    self.sync_cartridge(pos);
+                } else {
+                    self.prg_ram[(pos - PRG_RAM) as usize] = data;
+                }
             }
 
-            PRG_ROM..=PRG_ROM_END => {
-                panic!("attempt to write to a ROM section: {:x}", pos); //sram?
+            0x4020..=0x5fff | PRG_ROM..=PRG_ROM_END => {
+                if self.mapper.cpu_write(&self.rom, pos, data) {
+                    self.sync_cartridge(pos);
+                } else if pos >= PRG_ROM {
+                    self.fault(BusError::PrgRomWrite { addr: pos, data });
+                } else {
+                    self.fault(BusError::UnmappedWrite { addr: pos, data });
+                }
             }
             // 0x4020 ..=0x5FFF => {
             //     //ignore exapnsion rom for now
             // }
             _ => {
-                unimplemented!("attempting to write to {:x}", pos);
+                //todo: sram
+                self.fault(BusError::UnmappedWrite { addr: pos, data });
             }
         }
     }
 
     pub fn read(&mut self, pos: u16) -> u8 {
+        if let Some(addr) = self.dmc_fetch.take() {
+            self.dmc_sample = Some(self.dma_read(addr));
+            self.dmc_dma_conflict(pos);
+        }
         match pos {
             0x0..=RAM_MIRRORS_END => {
                 let pos = map_mirrors(pos);
@@ -194,10 +275,16 @@ impl<'a, T: PPU> Bus<'a, T> {
 
             0x4016 => self.joypad1.read(),
 
-            0x4017 => 0, //self.joypad2.read(),
+            0x4017 => self.joypad2.read(),
 
-            //todo 0x4000 - 0x8000
-            PRG_ROM..=PRG_ROM_END => self.read_prg_rom(pos),
+            PRG_RAM..=PRG_RAM_END => match self.mapper.prg_ram_read(pos) {
+                Some(data) => data,
+                None => self.prg_ram[(pos - PRG_RAM) as usize],
+            },
+
+            0x4020..=0x5fff | PRG_ROM..=PRG_ROM_END => {
+                self.mapper.cpu_read(&self.rom, pos).unwrap_or(0)
+            }
 
             // 0x4020 ..=0x5FFF => {
             //     0
@@ -212,35 +299,252 @@ impl<'a, T: PPU> Bus<'a, T> {
         }
     }
 
+    /// DMC sample fetch (DMA) of the byte at `addr`: it stalls the CPU on its next read, the
+    /// byte is then available from `take_dmc_sample`. For the DMC channel of the APU (todo)
+    /// https://wiki.nesdev.com/w/index.php/APU_DMC#Memory_reader
+    pub fn request_dmc_fetch(&mut self, addr: u16) {
+        self.dmc_fetch = Some(addr);
+    }
+
+    pub fn take_dmc_sample(&mut self) -> Option<u8> {
+        self.dmc_sample.take()
+    }
+
+    // A DMC sample fetch stalled the CPU on a read of `pos`: the stall repeats the read, the
+    // extra read clocks the controller shift register and a button bit is lost. Games read the
+    // pads until two reads in a row agree to get around it.
+    // https://wiki.nesdev.com/w/index.php/APU_DMC#Conflict_with_controller_and_PPU_read
+    fn dmc_dma_conflict(&mut self, pos: u16) {
+        match pos {
+            0x4016 => {
+                self.joypad1.read();
+            }
+            0x4017 => {
+                self.joypad2.read();
+            }
+            _ => {}
+        }
+    }
+
     pub fn tick(&mut self, cycles: u16) -> bool {
         self.cycles += cycles as usize;
-        let render = self.ppu.tick(cycles * 3); //todo: oh my..
+        self.mapper.tick(cycles);
+        let dots = self.region.ppu_dots(cycles, &mut self.ppu_dots_remainder);
+        let line = self.ppu.scanline();
+        let frame_complete = self.ppu.tick(dots);
+        if self.ppu.scanline() != line {
+            self.mapper
+                .scanline(self.ppu.scanline(), self.ppu.rendering_enabled());
+        }
         self.nmi_interrupt = self.ppu.poll_nmi_interrupt();
-        render
+        self.irq_line = self.mapper.irq();
+        if frame_complete {
+            self.frame_complete = true;
+            self.frames += 1;
+        }
+        frame_complete
+    }
+
+    /// true once per frame, when the picture is complete: that's the time to show the frame
+    /// and to update the controllers, before the game reads them in its NMI handler
+    pub fn poll_frame_complete(&mut self) -> bool {
+        core::mem::replace(&mut self.frame_complete, false)
+    }
+
+    // OAM DMA source: memory the same as the CPU sees it, the io registers read as open bus
+    // (0 here) without the side effects of a CPU read: vblank flag, $2007 buffer, controllers.
+    // The same for the cartridge registers at $4020-$5FFF (MMC5 $5204 acknowledges the IRQ)
+    fn dma_read(&mut self, pos: u16) -> u8 {
+        match pos {
+            0x0..=RAM_MIRRORS_END => self.ram[map_mirrors(pos) as usize],
+            PRG_RAM..=PRG_RAM_END => match self.mapper.prg_ram_read(pos) {
+                Some(data) => data,
+                None => self.prg_ram[(pos - PRG_RAM) as usize],
+            },
+            PRG_ROM..=PRG_ROM_END => self.mapper.cpu_read(&self.rom, pos).unwrap_or(0),
+            _ => 0,
+        }
     }
 
-    fn read_prg_rom(&self, mut pos: u16) -> u8 {
-        //todo: mapper
-        pos -= 0x8000;
-        if self.rom.prg_rom.len() == 0x4000 && pos >= 0x4000 {
-            //mirror if needed
-            pos = pos % 0x4000;
+    // after a write to the cartridge register at `addr`: the PPU gets the switched pattern
+    // tables, the nametable layout and the board nametables, the subscribers the switched bank
+    fn sync_cartridge(&mut self, addr: u16) {
+        if let Some(bank) = self.mapper.bank_register(addr) {
+            self.notify(Event::MapperBankSwitched { addr, bank });
+        }
+        if self.mapper.take_chr_switched() {
+            self.ppu
+                .set_chr(mapper::chr_window(self.mapper.as_mut(), &self.rom));
+        }
+        if let Some(mirroring) = self.mapper.mirroring() {
+            self.ppu.set_mirroring(mirroring);
         }
-        self.rom.prg_rom[pos as usize]
+        if self.mapper.take_video_changed() {
+            self.ppu.set_cartridge_video(self.mapper.cartridge_video());
+        }
+    }
+
+    // the PPU side of the cartridge from scratch: power on, loaded states, another rom
+    fn reload_cartridge(&mut self) {
+        self.mapper.take_chr_switched();
+        self.ppu
+            .set_chr(mapper::chr_window(self.mapper.as_mut(), &self.rom));
+        let mirroring = self
+            .mapper
+            .mirroring()
+            .unwrap_or_else(|| self.rom.rom_flags.mirroring());
+        self.ppu.set_mirroring(mirroring);
+        self.mapper.take_video_changed();
+        let video = self.mapper.cartridge_video();
+        // the 4KB banks of the extended attributes and the split come from the whole CHR ROM
+        self.ppu.set_chr_banks(if video.is_some() {
+            self.rom.chr_rom.clone()
+        } else {
+            Vec::new()
+        });
+        self.ppu.set_cartridge_video(video);
     }
 
     pub fn poll_nmi_status(&mut self) -> Option<u8> {
         self.nmi_interrupt.take()
     }
+
+    /// IRQ is level triggered: it stays asserted till the game acknowledges it on the board
+    pub fn poll_irq_status(&self) -> bool {
+        self.irq_line
+    }
+
+    fn fault<E: Into<RustnessError>>(&mut self, error: E) {
+        if self.error.is_none() {
+            self.error = Some(error.into());
+        }
+    }
+
+    pub fn ppu(&self) -> &T {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut T {
+        &mut self.ppu
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// The cart keeps `battery_ram` with the power off, the frontend persists it (`rom::battery`)
+    pub fn has_battery(&self) -> bool {
+        self.rom.rom_flags.contains(RomFlags::BATTERY_RAM)
+    }
+
+    /// The RAM to persist when `has_battery`: `prg_ram`, or the bigger RAM of the board (MMC5)
+    pub fn battery_ram(&mut self) -> &mut [u8] {
+        match self.mapper.battery_ram() {
+            Some(ram) => ram,
+            None => &mut self.prg_ram,
+        }
+    }
+
+    /// The save memory of the boards with a serial EEPROM instead (Bandai), persisted the same
+    pub fn eeprom(&mut self) -> Option<&mut Eeprom> {
+        self.mapper.eeprom()
+    }
+
+    /// TV system timing, `Region::from_rom` by default
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.ppu.set_region(region);
+    }
+
+    /// Reset button: PPU registers are cleared, RAM is kept (no APU yet)
+    pub fn reset(&mut self) {
+        self.ppu.reset();
+        self.nmi_interrupt = None;
+    }
+
+    /// Power cycle: PPU and bus state back to the power on values. RAM is left to the caller
+    /// (the power on pattern is a setting, see `RamInit`), PRG RAM is kept: it's battery backed
+    /// on the carts that have it.
+    pub fn power_on(&mut self) {
+        self.ppu.power_on();
+        self.mapper.power_on();
+        self.reload_cartridge();
+        self.nmi_interrupt = None;
+        self.irq_line = false;
+        self.cycles = 7;
+        self.ppu_dots_remainder = 0;
+        self.frame_complete = false;
+        self.joypad1 = input::Joypad::new();
+        self.joypad2 = input::Joypad::new();
+        self.dmc_fetch = None;
+        self.dmc_sample = None;
+        self.error = None;
+    }
+
+    pub fn joypad1_mut(&mut self) -> &mut input::Joypad {
+        &mut self.joypad1
+    }
+
+    pub fn joypad2_mut(&mut self) -> &mut input::Joypad {
+        &mut self.joypad2
+    }
+
+    pub fn subscribe(&mut self, subscriber: Box<dyn EmulatorEvents + Send>) {
+        self.subscribers.push(subscriber);
+    }
+
+    fn notify(&mut self, event: Event) {
+        for subscriber in self.subscribers.iter_mut() {
+            subscriber.on_event(&event);
+        }
+    }
 }
 
 pub trait CpuBus: Mem {
     fn poll_nmi_status(&mut self) -> Option<u8>;
+    /// Called only when the cpu takes IRQs (I flag is clear), true - it enters the IRQ handler
+    fn poll_irq_status(&mut self) -> bool;
     fn tick(&mut self, cycles: u8);
     fn trace(&self) -> BusTrace;
+    /// RAM, PPU and controllers state, rom data is not included
+    #[cfg(feature = "save-state")]
+    fn save_state(&self) -> Result<Vec<u8>, String>;
+    #[cfg(feature = "save-state")]
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String>;
+    /// crc32 of the loaded rom, save states are bound to it
+    fn rom_crc32(&self) -> u32;
+    /// The first bus or PPU fault since the last call, see `error`
+    fn take_error(&mut self) -> Option<RustnessError> {
+        None
+    }
 }
 
-impl Mem for Bus<'_, NesPPU> {
+#[cfg(feature = "save-state")]
+#[derive(Serialize, Deserialize)]
+struct BusState {
+    ram: Vec<u8>,
+    prg_ram: Vec<u8>,
+    cycles: usize,
+    ppu_dots_remainder: u8,
+    nmi_interrupt: Option<u8>,
+    ppu: PpuState,
+    mapper: Vec<u8>,
+    joypad1: input::Joypad,
+    joypad2: input::Joypad,
+}
+
+impl Bus<NesPPU> {
+    /// Puts another build of the game into the slot, the machine state stays: RAM, PPU and the
+    /// cpu keep running from where they were (call `power_on` after it for a clean start).
+    /// Save states made before it are bound to the old rom
+    pub fn swap_rom(&mut self, rom: Rom) {
+        self.mapper = mapper::create(&rom);
+        self.rom = rom;
+        self.reload_cartridge();
+    }
+}
+
+impl Mem for Bus<NesPPU> {
     fn write(&mut self, pos: u16, data: u8) {
         Bus::write(self, pos, data);
     }
@@ -255,17 +559,42 @@ pub struct BusTrace {
     pub ppu_scanline: usize,
 }
 
-impl CpuBus for Bus<'_, NesPPU> {
+impl CpuBus for Bus<NesPPU> {
     fn poll_nmi_status(&mut self) -> Option<u8> {
-        Bus::poll_nmi_status(self)
+        let nmi = Bus::poll_nmi_status(self);
+        if nmi.is_some() {
+            self.notify(Event::NmiFired);
+        }
+        nmi
+    }
+
+    fn poll_irq_status(&mut self) -> bool {
+        let irq = Bus::poll_irq_status(self);
+        if irq {
+            self.notify(Event::IrqFired);
+        }
+        irq
     }
 
     fn tick(&mut self, cycles: u8) {
-        let nmi_before = self.nmi_interrupt.is_some();
-        let _render = Bus::<NesPPU>::tick(self, cycles as u16);
-        let nmi_after = self.nmi_interrupt.is_some();
-        if !nmi_before && nmi_after {
-            (self.interrupt_fn)(&self.ppu, &mut self.joypad1);
+        let line = self.ppu.line;
+        let frame_complete = Bus::<NesPPU>::tick(self, cycles as u16);
+        if self.subscribers.is_empty() {
+            return;
+        }
+        // an instruction takes far less than a scanline, at most one line change per tick
+        if self.ppu.line != line {
+            self.notify(Event::ScanlineStarted { y: self.ppu.line });
+            for subscriber in self.subscribers.iter_mut() {
+                subscriber.on_scanline(self.ppu.line);
+            }
+        }
+        if frame_complete {
+            self.notify(Event::FrameCompleted { n: self.frames });
+            let frame = self.ppu.frame();
+            for subscriber in self.subscribers.iter_mut() {
+                subscriber.on_vblank(frame);
+            }
         }
     }
 
@@ -276,6 +605,54 @@ impl CpuBus for Bus<'_, NesPPU> {
             ppu_scanline: self.ppu.line,
         }
     }
+
+    #[cfg(feature = "save-state")]
+    fn save_state(&self) -> Result<Vec<u8>, String> {
+        let state = BusState {
+            ram: self.ram.to_vec(),
+            prg_ram: self.prg_ram.to_vec(),
+            cycles: self.cycles,
+            ppu_dots_remainder: self.ppu_dots_remainder,
+            nmi_interrupt: self.nmi_interrupt,
+            ppu: self.ppu.save_state(),
+            mapper: self.mapper.save_state(),
+            joypad1: self.joypad1.clone(),
+            joypad2: self.joypad2.clone(),
+        };
+        bincode::serialize(&state).map_err(|e| e.to_string())
+    }
+
+    #[cfg(feature = "save-state")]
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let state: BusState =
+            bincode::deserialize(data).map_err(|e| format!("corrupted bus state: {}", e))?;
+        if state.ram.len() != self.ram.len() || state.prg_ram.len() != self.prg_ram.len() {
+            return Err("corrupted bus state: wrong RAM size".to_string());
+        }
+        self.ppu.load_state(state.ppu)?;
+        self.mapper.load_state(&state.mapper)?;
+        self.reload_cartridge();
+        self.ram.copy_from_slice(&state.ram);
+        self.prg_ram.copy_from_slice(&state.prg_ram);
+        self.cycles = state.cycles;
+        self.ppu_dots_remainder = state.ppu_dots_remainder;
+        self.nmi_interrupt = state.nmi_interrupt;
+        self.irq_line = self.mapper.irq();
+        self.joypad1 = state.joypad1;
+        self.joypad2 = state.joypad2;
+        Ok(())
+    }
+
+    fn rom_crc32(&self) -> u32 {
+        self.rom.crc32()
+    }
+
+    fn take_error(&mut self) -> Option<RustnessError> {
+        match self.error.take() {
+            Some(error) => Some(error),
+            None => self.ppu.take_error().map(RustnessError::from),
+        }
+    }
 }
 
 pub struct DynamicBusWrapper {
@@ -305,10 +682,14 @@ impl Mem for DynamicBusWrapper {
 }
 
 impl CpuBus for DynamicBusWrapper {
-    fn poll_nmi_status(&mut self) -> std::option::Option<u8> {
+    fn poll_nmi_status(&mut self) -> Option<u8> {
         self.bus.borrow_mut().poll_nmi_status()
     }
 
+    fn poll_irq_status(&mut self) -> bool {
+        self.bus.borrow_mut().poll_irq_status()
+    }
+
     fn tick(&mut self, cycles: u8) {
         self.bus.borrow_mut().tick(cycles);
     }
@@ -316,11 +697,30 @@ impl CpuBus for DynamicBusWrapper {
     fn trace(&self) -> BusTrace {
         self.bus.borrow().trace()
     }
+
+    #[cfg(feature = "save-state")]
+    fn save_state(&self) -> Result<Vec<u8>, String> {
+        self.bus.borrow().save_state()
+    }
+
+    #[cfg(feature = "save-state")]
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        self.bus.borrow_mut().load_state(data)
+    }
+
+    fn rom_crc32(&self) -> u32 {
+        self.bus.borrow().rom_crc32()
+    }
+
+    fn take_error(&mut self) -> Option<RustnessError> {
+        self.bus.borrow_mut().take_error()
+    }
 }
 
 pub struct MockBus {
     pub space: [u8; 0x10000],
     pub nmi_interrupt: Option<u8>,
+    pub irq_line: bool,
     pub cycles: usize,
 }
 
@@ -339,6 +739,10 @@ impl CpuBus for MockBus {
         self.nmi_interrupt.take()
     }
 
+    fn poll_irq_status(&mut self) -> bool {
+        self.irq_line
+    }
+
     fn tick(&mut self, cycles: u8) {
         self.cycles += cycles as usize;
     }
@@ -350,6 +754,30 @@ impl CpuBus for MockBus {
             ppu_scanline: 0,
         }
     }
+
+    #[cfg(feature = "save-state")]
+    fn save_state(&self) -> Result<Vec<u8>, String> {
+        bincode::serialize(&(self.space.to_vec(), self.nmi_interrupt, self.cycles))
+            .map_err(|e| e.to_string())
+    }
+
+    #[cfg(feature = "save-state")]
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let (space, nmi_interrupt, cycles): (Vec<u8>, Option<u8>, usize) =
+            bincode::deserialize(data).map_err(|e| format!("corrupted bus state: {}", e))?;
+        if space.len() != self.space.len() {
+            return Err("corrupted bus state: wrong memory size".to_string());
+        }
+        self.space.copy_from_slice(&space);
+        self.nmi_interrupt = nmi_interrupt;
+        self.cycles = cycles;
+        Ok(())
+    }
+
+    // no rom
+    fn rom_crc32(&self) -> u32 {
+        0
+    }
 }
 
 impl MockBus {
@@ -357,6 +785,7 @@ impl MockBus {
         MockBus {
             space: [0; 0x10000],
             nmi_interrupt: None,
+            irq_line: false,
             cycles: 0,
         }
     }
@@ -368,17 +797,162 @@ mod test {
     use crate::ppu::ppu::test;
     use crate::ppu::ppu::test::MockPPU;
     use crate::rom::test_ines_rom;
+    use crate::rom::Mirroring;
 
-    fn stub_bus() -> Bus<'static, MockPPU> {
-        let func = |_: &MockPPU, _: &mut input::Joypad| {};
+    fn stub_bus() -> Bus<MockPPU> {
+        let rom = test_ines_rom::test_rom();
         Bus {
             ram: [0; 0x800],
-            rom: test_ines_rom::test_rom(),
+            prg_ram: [0; 0x2000],
+            mapper: mapper::create(&rom),
+            rom,
             nmi_interrupt: None,
+            irq_line: false,
             cycles: 0,
             ppu: test::stub_ppu(),
-            interrupt_fn: Box::from(func),
+            region: Region::Ntsc,
+            ppu_dots_remainder: 0,
+            frame_complete: false,
+            frames: 0,
             joypad1: input::Joypad::new(),
+            joypad2: input::Joypad::new(),
+            dmc_fetch: None,
+            dmc_sample: None,
+            error: None,
+            subscribers: vec![],
+        }
+    }
+
+    #[test]
+    fn test_swap_rom() {
+        let mut bus = Bus::<NesPPU>::new(test_ines_rom::test_rom());
+        bus.write(0x10, 0x42);
+        let mut rom = test_ines_rom::test_rom();
+        rom.prg_rom[0] = 0xea;
+        rom.chr_rom[0] = 0x55;
+        let crc32 = rom.crc32();
+
+        bus.swap_rom(rom);
+        assert_eq!(bus.read(0x8000), 0xea);
+        assert_eq!(bus.ppu().chr_rom[0], 0x55);
+        assert_eq!(bus.read(0x10), 0x42);
+        assert_eq!(CpuBus::rom_crc32(&bus), crc32);
+    }
+
+    // a register at $8000 switching between 2 banks of CHR RAM, horizontal mirroring
+    struct TestMapper {
+        chr: Vec<u8>,
+        bank: usize,
+        switched: bool,
+    }
+
+    impl Mapper for TestMapper {
+        fn cpu_read(&mut self, _rom: &Rom, addr: u16) -> Option<u8> {
+            Some((addr >> 8) as u8)
+        }
+        fn cpu_write(&mut self, _rom: &Rom, addr: u16, data: u8) -> bool {
+            self.bank = data as usize & 1;
+            self.switched = true;
+            addr == 0x8000
+        }
+        fn ppu_read(&mut self, _rom: &Rom, addr: u16) -> u8 {
+            self.chr[self.bank * 0x2000 + addr as usize]
+        }
+        fn ppu_write(&mut self, _rom: &Rom, addr: u16, data: u8) -> bool {
+            self.chr[self.bank * 0x2000 + addr as usize] = data;
+            true
+        }
+        fn mirroring(&self) -> Option<Mirroring> {
+            Some(Mirroring::HORIZONTAL)
+        }
+        fn take_chr_switched(&mut self) -> bool {
+            std::mem::replace(&mut self.switched, false)
+        }
+    }
+
+    #[test]
+    fn test_mapper() {
+        let mut bus = Bus::<NesPPU>::new(test_ines_rom::test_rom());
+        let mut chr = vec![0; 0x4000];
+        chr[0x2000] = 0x77;
+        bus.mapper = Box::new(TestMapper {
+            chr,
+            bank: 0,
+            switched: false,
+        });
+        assert_eq!(bus.read(0x9100), 0x91);
+        assert_eq!(bus.read(0x5000), 0x50);
+
+        bus.write(0x8000, 1);
+        assert_eq!(bus.ppu().chr_rom[0], 0x77);
+        assert_eq!(bus.ppu().mirroring, Mirroring::HORIZONTAL);
+        bus.write(0x9000, 1);
+        assert_eq!(
+            bus.error.take(),
+            Some(RustnessError::Bus(BusError::PrgRomWrite {
+                addr: 0x9000,
+                data: 1
+            }))
+        );
+
+        // CHR RAM
+        bus.write(0x2006, 0x00);
+        bus.write(0x2006, 0x10);
+        bus.write(0x2007, 0x42);
+        assert_eq!(bus.ppu().chr_rom[0x10], 0x42);
+        bus.write(0x8000, 0);
+        assert_eq!(bus.ppu().chr_rom[0x10], 0);
+        bus.write(0x8000, 1);
+        assert_eq!(bus.ppu().chr_rom[0x10], 0x42);
+        assert!(bus.error.is_none());
+    }
+
+    #[test]
+    fn test_chr_rom_write() {
+        let mut bus = Bus::<NesPPU>::new(test_ines_rom::test_rom());
+        bus.write(0x2006, 0x00);
+        bus.write(0x2006, 0x10);
+        bus.write(0x2007, 0x42);
+        assert_eq!(
+            CpuBus::take_error(&mut bus),
+            Some(RustnessError::Ppu(PpuError::ChrRomWrite {
+                addr: 0x10,
+                data: 0x42
+            }))
+        );
+        assert_eq!(bus.ppu().chr_rom[0x10], 2);
+    }
+
+    #[test]
+    fn test_battery() {
+        let mut rom = test_ines_rom::test_rom();
+        assert!(!Bus::<NesPPU>::new(test_ines_rom::test_rom()).has_battery());
+        rom.rom_flags.insert(RomFlags::BATTERY_RAM);
+        assert!(Bus::<NesPPU>::new(rom).has_battery());
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let mut rom = test_ines_rom::test_rom();
+        rom.mapper = 0;
+        rom.chr_rom = vec![];
+        rom.chr_ram = 0x2000;
+        let mut bus = Bus::<NesPPU>::new(rom);
+        assert_eq!(bus.ppu().chr_rom.len(), 0x2000);
+        bus.write(0x2006, 0x1f);
+        bus.write(0x2006, 0xff);
+        bus.write(0x2007, 0x42);
+        assert_eq!(CpuBus::take_error(&mut bus), None);
+        assert_eq!(bus.ppu().chr_rom[0x1fff], 0x42);
+
+        #[cfg(feature = "save-state")]
+        {
+            let state = CpuBus::save_state(&bus).unwrap();
+            bus.write(0x2006, 0x1f);
+            bus.write(0x2006, 0xff);
+            bus.write(0x2007, 0);
+            CpuBus::load_state(&mut bus, &state).unwrap();
+            assert_eq!(bus.ppu().chr_rom[0x1fff], 0x42);
         }
     }
 
@@ -397,6 +971,65 @@ mod test {
         assert_eq!(bus.read(0x1005), 0x55);
     }
 
+    #[test]
+    fn test_prg_ram() {
+        let mut bus = stub_bus();
+
+        bus.write(0x6000, 0x80);
+        bus.write(0x7fff, 0x42);
+        assert_eq!(bus.read(0x6000), 0x80);
+        assert_eq!(bus.read(0x7fff), 0x42);
+        assert_eq!(bus.prg_ram[0x1fff], 0x42);
+        assert!(bus.error.is_none());
+    }
+
+    #[test]
+    fn test_joypad2() {
+        let mut bus = stub_bus();
+        bus.joypad2_mut()
+            .set_button_pressed_status(input::JoypadButton::BUTTON_A, true);
+
+        bus.write(0x4016, 1);
+        bus.write(0x4016, 0);
+        assert_eq!(bus.read(0x4016), 0);
+        assert_eq!(bus.read(0x4017), 1);
+        assert_eq!(bus.read(0x4017), 0);
+    }
+
+    #[test]
+    fn test_dmc_dma_conflict() {
+        let mut bus = stub_bus();
+        bus.joypad1_mut()
+            .set_button_pressed_status(input::JoypadButton::BUTTON_B, true);
+        let read_pad = |bus: &mut Bus<MockPPU>, fetch_at: Option<u8>| {
+            bus.write(0x4016, 1);
+            bus.write(0x4016, 0);
+            let mut buttons = 0u8;
+            for bit in 0..8 {
+                if fetch_at == Some(bit) {
+                    bus.request_dmc_fetch(0xc000);
+                }
+                buttons |= (bus.read(0x4016) & 1) << bit;
+            }
+            buttons
+        };
+
+        assert_eq!(read_pad(&mut bus, None), 0b10);
+        assert_eq!(bus.take_dmc_sample(), None);
+        // the stalled read shifts a button out, the last read gets the 1 of an empty register
+        assert_eq!(read_pad(&mut bus, Some(1)), 0b1000_0000);
+        assert_eq!(bus.take_dmc_sample(), Some(1));
+        assert_eq!(read_pad(&mut bus, Some(0)), 0b1000_0001);
+        // the double read: the next read disagrees, the game reads again
+        assert_ne!(read_pad(&mut bus, Some(0)), read_pad(&mut bus, None));
+        assert_eq!(read_pad(&mut bus, None), read_pad(&mut bus, None));
+
+        // no conflict on other reads, the sample is fetched all the same
+        bus.request_dmc_fetch(0xc000);
+        assert_eq!(bus.read(0x0000), 0);
+        assert_eq!(bus.take_dmc_sample(), Some(1));
+    }
+
     #[test]
     fn test_ppu_register_mirrors() {
         let mut bus = stub_bus();
@@ -427,4 +1060,78 @@ mod test {
             "oam data arrrays are not equal"
         );
     }
+    #[test]
+    fn test_oam_dma_from_ram_mirror() {
+        let mut bus = stub_bus();
+        for i in 0..=255u8 {
+            bus.write(0x0700 + i as u16, i ^ 0x5a);
+        }
+        bus.write(0x4014, 0x1f);
+        let expected: Vec<u8> = (0..=255u8).map(|b| b ^ 0x5a).collect();
+        assert_eq!(bus.ppu.oam.to_vec(), expected);
+    }
+
+    #[test]
+    fn test_oam_dma_from_rom() {
+        let mut bus = stub_bus();
+        // 16KB, mirrored at $C000
+        bus.rom.prg_rom.truncate(0x4000);
+        for (i, byte) in bus.rom.prg_rom[0x100..0x200].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let expected: Vec<u8> = (0..=255u8).collect();
+        bus.write(0x4014, 0x81);
+        assert_eq!(bus.ppu.oam.to_vec(), expected);
+        bus.ppu.oam = [0; 256];
+        bus.write(0x4014, 0xc1);
+        assert_eq!(bus.ppu.oam.to_vec(), expected);
+        assert!(bus.error.is_none());
+    }
+
+    #[test]
+    fn test_oam_dma_from_io_registers() {
+        let mut bus = stub_bus();
+        bus.ppu.oam = [0xff; 256];
+        bus.joypad1_mut()
+            .set_button_pressed_status(input::JoypadButton::BUTTON_A, true);
+        bus.write(0x4016, 1);
+        bus.write(0x4016, 0);
+
+        bus.write(0x4014, 0x40);
+        bus.write(0x4014, 0x20);
+        assert_eq!(bus.ppu.oam, [0; 256]);
+        // the controller wasn't clocked
+        assert_eq!(bus.read(0x4016), 1);
+        assert_eq!(bus.read(0x4016), 0);
+    }
+
+    #[test]
+    fn test_oam_dma_from_mapper_prg_ram() {
+        let mut rom = test_ines_rom::test_rom();
+        rom.mapper = 159;
+        let mut bus = Bus::<NesPPU>::new(rom);
+        // the EEPROM data line released: bit 4 of every $6000-$7FFF read
+        bus.write(0x800d, 0x40);
+        bus.write(0x4014, 0x60);
+        assert_eq!(bus.ppu.oam_data, [0x10; 256]);
+    }
+
+    #[test]
+    fn test_oam_dma_from_cartridge_registers() {
+        let mut rom = test_ines_rom::test_rom();
+        rom.mapper = 5;
+        let mut bus = Bus::<NesPPU>::new(rom);
+        bus.write(0x2001, 0b0000_1000);
+        bus.write(0x5203, 1);
+        bus.write(0x5204, 0x80);
+        for _ in 0..3 {
+            bus.tick(100);
+        }
+        assert!(bus.poll_irq_status());
+
+        bus.write(0x4014, 0x52);
+        assert_eq!(bus.ppu.oam_data, [0; 256]);
+        // the MMC5 IRQ wasn't acknowledged
+        assert_eq!(bus.read(0x5204) & 0x80, 0x80);
+    }
 }
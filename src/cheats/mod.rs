@@ -0,0 +1,254 @@
+// RAM freeze cheats (Pro Action Replay style): the value is written to the address once per
+// frame at vblank, so whatever the game stores there gets overwritten before the next frame.
+//
+// Accepted codes:
+//   0075:09   address:value
+//   007509    Pro Action Replay, AAAAVV
+// Only the internal RAM ($0000-$1FFF) and the cartridge PRG RAM ($6000-$7FFF) can be frozen.
+//
+// Cheats file (game.cht next to the rom), one cheat per line:
+//   0075:09 infinite lives
+//   -00A5:FF disabled cheat
+//   # comment
+pub mod search;
+
+use crate::bus;
+use crate::cpu::mem::Mem;
+use std::fmt;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Cheat {
+    pub addr: u16,
+    pub value: u8,
+    pub enabled: bool,
+    pub description: String,
+}
+
+impl Cheat {
+    pub fn new(addr: u16, value: u8) -> Result<Self, String> {
+        if !bus::is_ram(addr) {
+            return Err(format!("${:04X} is not in RAM", addr));
+        }
+        Ok(Cheat {
+            addr,
+            value,
+            enabled: true,
+            description: String::new(),
+        })
+    }
+
+    pub fn parse(code: &str) -> Result<Self, String> {
+        let bad_code = || format!("bad cheat code '{}'", code);
+        let (addr, value) = match code.find(':') {
+            Some(i) => (&code[..i], &code[i + 1..]),
+            None if code.len() == 6 => (&code[..4], &code[4..]),
+            None => return Err(bad_code()),
+        };
+        let addr = u16::from_str_radix(addr.trim_start_matches('$'), 16).map_err(|_| bad_code())?;
+        let value = u8::from_str_radix(value, 16).map_err(|_| bad_code())?;
+        Cheat::new(addr, value)
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = description.to_string();
+        self
+    }
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.enabled {
+            write!(f, "-")?;
+        }
+        write!(f, "{:04X}:{:02X}", self.addr, self.value)?;
+        if !self.description.is_empty() {
+            write!(f, " {}", self.description)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Cheats { cheats: vec![] }
+    }
+
+    pub fn parse(content: &str) -> Result<Cheats, String> {
+        let mut cheats = Cheats::new();
+        for (line_num, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (enabled, line) = match line.strip_prefix('-') {
+                Some(rest) => (false, rest),
+                None => (true, line),
+            };
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let code = parts.next().unwrap_or("");
+            let description = parts.next().unwrap_or("").trim();
+            let mut cheat = Cheat::parse(code)
+                .map_err(|e| format!("line {}: {}", line_num + 1, e))?
+                .description(description);
+            cheat.enabled = enabled;
+            cheats.add(cheat);
+        }
+        Ok(cheats)
+    }
+
+    /// Cheats file of the rom: `game.nes` -> `game.cht`
+    #[cfg(feature = "std")]
+    pub fn path(rom_path: &Path) -> PathBuf {
+        rom_path.with_extension("cht")
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load(path: &Path) -> Result<Cheats, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Cheats::parse(&content)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_string())
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+
+    pub fn add(&mut self, cheat: Cheat) {
+        self.cheats.push(cheat);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        if index < self.cheats.len() {
+            Some(self.cheats.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> Result<(), String> {
+        match self.cheats.get_mut(index) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                Ok(())
+            }
+            None => Err(format!("no cheat #{}", index)),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cheat> {
+        self.cheats.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cheats.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    /// Writes the enabled cheats to memory, called once per frame at vblank
+    pub fn apply<M: Mem + ?Sized>(&self, mem: &mut M) {
+        for cheat in self.cheats.iter().filter(|c| c.enabled) {
+            mem.write(cheat.addr, cheat.value);
+        }
+    }
+}
+
+impl Default for Cheats {
+    fn default() -> Self {
+        Cheats::new()
+    }
+}
+
+impl fmt::Display for Cheats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for cheat in self.cheats.iter() {
+            writeln!(f, "{}", cheat)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::MockBus;
+
+    #[test]
+    fn test_parse_code() {
+        assert_eq!(
+            Cheat::parse("0075:09").unwrap(),
+            Cheat::new(0x75, 0x09).unwrap()
+        );
+        assert_eq!(
+            Cheat::parse("$07FF:1").unwrap(),
+            Cheat::new(0x7ff, 0x01).unwrap()
+        );
+        assert_eq!(
+            Cheat::parse("6010ff").unwrap(),
+            Cheat::new(0x6010, 0xff).unwrap()
+        );
+        assert!(Cheat::parse("0075").is_err());
+        assert!(Cheat::parse("zz75:09").is_err());
+        assert!(Cheat::parse("0075:100").is_err());
+        // rom and registers can't be frozen
+        assert_eq!(
+            Cheat::parse("8000:01"),
+            Err(String::from("$8000 is not in RAM"))
+        );
+        assert!(Cheat::parse("2000:01").is_err());
+    }
+
+    #[test]
+    fn test_parse_file() {
+        let cheats =
+            Cheats::parse("# smb\n\n0075:09 infinite lives\n-00A5:FF  disabled \n").unwrap();
+        let cheats: Vec<&Cheat> = cheats.iter().collect();
+        assert_eq!(cheats.len(), 2);
+        assert_eq!(cheats[0].description, "infinite lives");
+        assert!(cheats[0].enabled);
+        assert_eq!(cheats[1].addr, 0xa5);
+        assert_eq!(cheats[1].description, "disabled");
+        assert!(!cheats[1].enabled);
+
+        assert_eq!(
+            Cheats::parse("0075:09\n9000:01").err(),
+            Some(String::from("line 2: $9000 is not in RAM"))
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let content = "0075:09 infinite lives\n-00A5:FF\n";
+        assert_eq!(Cheats::parse(content).unwrap().to_string(), content);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut cheats = Cheats::new();
+        cheats.add(Cheat::new(0x10, 0x42).unwrap());
+        cheats.add(Cheat::new(0x11, 0x43).unwrap());
+        cheats.set_enabled(1, false).unwrap();
+        assert!(cheats.set_enabled(2, false).is_err());
+
+        let mut bus = MockBus::new();
+        cheats.apply(&mut bus);
+        assert_eq!(bus.read(0x10), 0x42);
+        assert_eq!(bus.read(0x11), 0);
+
+        assert_eq!(cheats.remove(0).unwrap().addr, 0x10);
+        assert_eq!(cheats.remove(5), None);
+        assert_eq!(cheats.len(), 1);
+    }
+}
@@ -0,0 +1,172 @@
+// Cheat search (RAM watch): the classic way to find where a game keeps its lives counter.
+//
+//   let mut search = CheatSearch::new(&mut *emulator.cpu_mut().bus);  // 3 lives
+//   ... lose a life ...
+//   search.filter(&mut *emulator.cpu_mut().bus, Filter::ChangedBy(-1));
+//   search.filter(&mut *emulator.cpu_mut().bus, Filter::EqualTo(2));
+//   ... repeat till a few candidates are left, then freeze one with a `Cheat`
+//
+// Every filter compares the current memory with the snapshot taken by the previous step
+// (or with a constant) and takes a new snapshot.
+use crate::cpu::mem::Mem;
+
+// internal RAM (without the mirrors) and the cartridge PRG RAM
+const AREAS: [(u16, u16); 2] = [(0x0000, 0x07FF), (0x6000, 0x7FFF)];
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Filter {
+    // against the previous snapshot
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+    /// current - previous, wrapping: ChangedBy(-1) matches $00 -> $FF
+    ChangedBy(i16),
+    // against a constant
+    EqualTo(u8),
+    GreaterThan(u8),
+    LessThan(u8),
+}
+
+impl Filter {
+    fn matches(&self, previous: u8, current: u8) -> bool {
+        match *self {
+            Filter::Equal => current == previous,
+            Filter::NotEqual => current != previous,
+            Filter::Greater => current > previous,
+            Filter::Less => current < previous,
+            Filter::ChangedBy(delta) => current == previous.wrapping_add(delta as u8),
+            Filter::EqualTo(value) => current == value,
+            Filter::GreaterThan(value) => current > value,
+            Filter::LessThan(value) => current < value,
+        }
+    }
+
+    /// `=`, `!=`, `>`, `<` against the previous values, `= 3`, `> $10` against a constant,
+    /// `+1`, `-2` changed by
+    pub fn parse(s: &str) -> Result<Filter, String> {
+        let s = s.trim();
+        let op_len = s.find(|c: char| !"=!<>".contains(c)).unwrap_or(s.len());
+        let (op, value) = (&s[..op_len], s[op_len..].trim());
+        if op.is_empty() {
+            return value
+                .parse::<i16>()
+                .ok()
+                .filter(|_| value.starts_with('+') || value.starts_with('-'))
+                .map(Filter::ChangedBy)
+                .ok_or_else(|| format!("bad filter '{}'", s));
+        }
+        let value = if value.is_empty() {
+            None
+        } else if let Some(hex) = value.strip_prefix('$') {
+            Some(u8::from_str_radix(hex, 16).map_err(|_| format!("bad value '{}'", value))?)
+        } else {
+            Some(
+                value
+                    .parse::<u8>()
+                    .map_err(|_| format!("bad value '{}'", value))?,
+            )
+        };
+        match (op, value) {
+            ("=", None) => Ok(Filter::Equal),
+            ("!=", None) => Ok(Filter::NotEqual),
+            (">", None) => Ok(Filter::Greater),
+            ("<", None) => Ok(Filter::Less),
+            ("=", Some(value)) => Ok(Filter::EqualTo(value)),
+            (">", Some(value)) => Ok(Filter::GreaterThan(value)),
+            ("<", Some(value)) => Ok(Filter::LessThan(value)),
+            _ => Err(format!("bad filter '{}'", s)),
+        }
+    }
+}
+
+pub struct CheatSearch {
+    // (address, value at the last snapshot)
+    candidates: Vec<(u16, u8)>,
+}
+
+impl CheatSearch {
+    /// Starts a new search: every RAM address is a candidate
+    pub fn new<M: Mem + ?Sized>(mem: &mut M) -> Self {
+        let candidates = AREAS
+            .iter()
+            .flat_map(|&(start, end)| start..=end)
+            .map(|addr| (addr, mem.read(addr)))
+            .collect();
+        CheatSearch { candidates }
+    }
+
+    /// Keeps the addresses matching `filter`, returns how many are left
+    pub fn filter<M: Mem + ?Sized>(&mut self, mem: &mut M, filter: Filter) -> usize {
+        self.candidates
+            .retain(|&(addr, previous)| filter.matches(previous, mem.read(addr)));
+        for (addr, value) in self.candidates.iter_mut() {
+            *value = mem.read(*addr);
+        }
+        self.candidates.len()
+    }
+
+    /// Remaining addresses with their values at the last step
+    pub fn results(&self) -> &[(u16, u8)] {
+        &self.candidates
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::MockBus;
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!(Filter::parse("="), Ok(Filter::Equal));
+        assert_eq!(Filter::parse("!="), Ok(Filter::NotEqual));
+        assert_eq!(Filter::parse(" > "), Ok(Filter::Greater));
+        assert_eq!(Filter::parse("<"), Ok(Filter::Less));
+        assert_eq!(Filter::parse("= 3"), Ok(Filter::EqualTo(3)));
+        assert_eq!(Filter::parse(">$10"), Ok(Filter::GreaterThan(0x10)));
+        assert_eq!(Filter::parse("< 200"), Ok(Filter::LessThan(200)));
+        assert_eq!(Filter::parse("-1"), Ok(Filter::ChangedBy(-1)));
+        assert_eq!(Filter::parse("+16"), Ok(Filter::ChangedBy(16)));
+        assert!(Filter::parse("16").is_err());
+        assert!(Filter::parse("!= 3").is_err());
+        assert!(Filter::parse("= 300").is_err());
+        assert!(Filter::parse("=>").is_err());
+    }
+
+    #[test]
+    fn test_search() {
+        let mut mem = MockBus::new();
+        mem.write(0x0010, 3);
+        mem.write(0x0020, 3);
+        mem.write(0x6000, 3);
+        let mut search = CheatSearch::new(&mut mem);
+        assert_eq!(search.len(), 0x800 + 0x2000);
+
+        assert_eq!(search.filter(&mut mem, Filter::EqualTo(3)), 3);
+
+        // lost a life
+        mem.write(0x0010, 2);
+        mem.write(0x0020, 4);
+        assert_eq!(search.filter(&mut mem, Filter::NotEqual), 2);
+        assert_eq!(search.filter(&mut mem, Filter::Equal), 2);
+
+        mem.write(0x0010, 1);
+        mem.write(0x0020, 3);
+        assert_eq!(search.filter(&mut mem, Filter::ChangedBy(-1)), 2);
+        assert_eq!(search.filter(&mut mem, Filter::Less), 0);
+
+        let mut search = CheatSearch::new(&mut mem);
+        mem.write(0x0010, 0xff);
+        assert_eq!(search.filter(&mut mem, Filter::ChangedBy(-2)), 1);
+        assert_eq!(search.results(), &[(0x0010, 0xff)]);
+    }
+}
@@ -0,0 +1,311 @@
+// Time source for frame pacing. Frontends use the monotonic `RealClock`, tests and headless
+// runs use `VirtualClock`: sleeping only moves its time forward, nothing waits.
+// `FrameStats` keeps per-frame timings measured with the same clocks, for performance overlays
+// and slowdown reports.
+use std::collections::VecDeque;
+use std::time::Duration;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+pub trait Clock {
+    /// Time since an arbitrary starting point
+    fn now(&self) -> Duration;
+    fn sleep(&mut self, duration: Duration);
+}
+
+#[cfg(feature = "std")]
+pub struct RealClock {
+    start: Instant,
+}
+
+#[cfg(feature = "std")]
+impl RealClock {
+    pub fn new() -> Self {
+        RealClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for RealClock {
+    fn default() -> Self {
+        RealClock::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for RealClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+pub struct VirtualClock {
+    now: Duration,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        VirtualClock {
+            now: Duration::from_secs(0),
+        }
+    }
+
+    /// Simulates time spent on work (e.g. emulating and rendering a frame)
+    pub fn advance(&mut self, duration: Duration) {
+        self.now += duration;
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        VirtualClock::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        self.now
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        self.now += duration;
+    }
+}
+
+/// Keeps frames at a fixed rate: sleeps the rest of the frame time after each frame
+pub struct FramePacer<C: Clock> {
+    clock: C,
+    frame_time: Duration,
+    prev: Duration,
+}
+
+impl<C: Clock> FramePacer<C> {
+    pub fn new(clock: C, frame_time: Duration) -> Self {
+        let prev = clock.now();
+        FramePacer {
+            clock,
+            frame_time,
+            prev,
+        }
+    }
+
+    /// Called once per frame, returns the time slept
+    pub fn wait(&mut self) -> Duration {
+        let elapsed = self.clock.now() - self.prev;
+        let wait = if elapsed < self.frame_time {
+            self.frame_time - elapsed
+        } else {
+            Duration::from_secs(0)
+        };
+        self.clock.sleep(wait);
+        self.prev = self.clock.now();
+        wait
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    pub fn clock_mut(&mut self) -> &mut C {
+        &mut self.clock
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Stage {
+    Emulation,
+    Render,
+    Present,
+}
+
+/// Time spent on one frame
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct FrameTimes {
+    pub emulation: Duration,
+    pub render: Duration,
+    pub present: Duration,
+    /// From the end of the previous frame, pacing included
+    pub interval: Duration,
+    /// Audio buffer fill in percent, as reported by the frontend.
+    /// todo: always empty so far, there is no APU
+    pub audio_fill: Option<u8>,
+}
+
+impl FrameTimes {
+    /// Time spent working, pacing excluded
+    pub fn busy(&self) -> Duration {
+        self.emulation + self.render + self.present
+    }
+}
+
+/// Rolling window of the last frames. The frontend marks the end of every stage with the clock
+/// time and closes the frame with `end_frame`; a stage marked twice adds up.
+pub struct FrameStats {
+    frames: VecDeque<FrameTimes>,
+    capacity: usize,
+    current: FrameTimes,
+    // the previous mark
+    lap: Option<Duration>,
+    frame_end: Option<Duration>,
+}
+
+impl FrameStats {
+    pub fn new(capacity: usize) -> Self {
+        FrameStats {
+            frames: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+            current: FrameTimes::default(),
+            lap: None,
+            frame_end: None,
+        }
+    }
+
+    pub fn mark(&mut self, stage: Stage, now: Duration) {
+        let elapsed = now.saturating_sub(self.lap.unwrap_or(now));
+        match stage {
+            Stage::Emulation => self.current.emulation += elapsed,
+            Stage::Render => self.current.render += elapsed,
+            Stage::Present => self.current.present += elapsed,
+        }
+        self.lap = Some(now);
+    }
+
+    pub fn set_audio_fill(&mut self, percent: u8) {
+        self.current.audio_fill = Some(percent.min(100));
+    }
+
+    /// Adds the frame to the window, the time from `now` on goes to the next frame
+    pub fn end_frame(&mut self, now: Duration) {
+        self.current.interval = now.saturating_sub(self.frame_end.unwrap_or(now));
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(self.current);
+        self.current = FrameTimes::default();
+        self.lap = Some(now);
+        self.frame_end = Some(now);
+    }
+
+    /// Oldest first
+    pub fn frames(&self) -> impl Iterator<Item = &FrameTimes> {
+        self.frames.iter()
+    }
+
+    pub fn last(&self) -> Option<&FrameTimes> {
+        self.frames.back()
+    }
+
+    pub fn average(&self) -> FrameTimes {
+        let count = self.frames.len().max(1) as u32;
+        let sum =
+            |time: fn(&FrameTimes) -> Duration| self.frames.iter().map(time).sum::<Duration>();
+        let fills: Vec<u32> = self
+            .frames
+            .iter()
+            .filter_map(|f| f.audio_fill)
+            .map(u32::from)
+            .collect();
+        FrameTimes {
+            emulation: sum(|f| f.emulation) / count,
+            render: sum(|f| f.render) / count,
+            present: sum(|f| f.present) / count,
+            interval: sum(|f| f.interval) / count,
+            audio_fill: if fills.is_empty() {
+                None
+            } else {
+                Some((fills.iter().sum::<u32>() / fills.len() as u32) as u8)
+            },
+        }
+    }
+
+    /// The frame that took the longest to emulate, render and present
+    pub fn worst(&self) -> Option<&FrameTimes> {
+        self.frames.iter().max_by_key(|f| f.busy())
+    }
+
+    pub fn fps(&self) -> f64 {
+        let interval = self.average().interval;
+        if interval.as_nanos() == 0 {
+            0.0
+        } else {
+            1.0 / interval.as_secs_f64()
+        }
+    }
+
+    /// One line for an overlay or a bug report, e.g.
+    /// "60.0 fps, emu 3.10ms, render 0.80ms, present 1.20ms, worst 6.02ms"
+    pub fn summary(&self) -> String {
+        let ms = |time: Duration| time.as_secs_f64() * 1000.0;
+        let average = self.average();
+        let worst = self.worst().map(|f| f.busy()).unwrap_or_default();
+        let mut summary = format!(
+            "{:.1} fps, emu {:.2}ms, render {:.2}ms, present {:.2}ms, worst {:.2}ms",
+            self.fps(),
+            ms(average.emulation),
+            ms(average.render),
+            ms(average.present),
+            ms(worst)
+        );
+        if let Some(fill) = average.audio_fill {
+            summary.push_str(&format!(", audio {}%", fill));
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame_pacer() {
+        let frame = Duration::from_millis(16);
+        let mut pacer = FramePacer::new(VirtualClock::new(), frame);
+
+        pacer.clock_mut().advance(Duration::from_millis(10));
+        assert_eq!(pacer.wait(), Duration::from_millis(6));
+        assert_eq!(pacer.clock().now(), frame);
+
+        // a slow frame is not compensated by the next one
+        pacer.clock_mut().advance(Duration::from_millis(20));
+        assert_eq!(pacer.wait(), Duration::from_secs(0));
+        pacer.clock_mut().advance(Duration::from_millis(1));
+        assert_eq!(pacer.wait(), Duration::from_millis(15));
+        assert_eq!(pacer.clock().now(), Duration::from_millis(52));
+    }
+
+    #[test]
+    fn test_frame_stats() {
+        let ms = Duration::from_millis;
+        let mut clock = VirtualClock::new();
+        let mut stats = FrameStats::new(2);
+        stats.end_frame(clock.now());
+        for (emulation, render) in [(10, 2), (4, 1), (6, 3)].iter() {
+            clock.advance(ms(*emulation));
+            stats.mark(Stage::Emulation, clock.now());
+            clock.advance(ms(*render));
+            stats.mark(Stage::Render, clock.now());
+            stats.mark(Stage::Present, clock.now());
+            clock.sleep(ms(16) - ms(emulation + render));
+            stats.end_frame(clock.now());
+        }
+
+        // the window keeps the last 2 frames
+        assert_eq!(stats.frames().count(), 2);
+        assert_eq!(stats.last().unwrap().render, ms(3));
+        let average = stats.average();
+        assert_eq!((average.emulation, average.render), (ms(5), ms(2)));
+        assert_eq!(average.interval, ms(16));
+        assert_eq!(stats.worst().unwrap().busy(), ms(9));
+        assert_eq!(
+            stats.summary(),
+            "62.5 fps, emu 5.00ms, render 2.00ms, present 0.00ms, worst 9.00ms"
+        );
+    }
+}
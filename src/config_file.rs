@@ -0,0 +1,499 @@
+// Emulator configuration file, shared by the frontends and `EmulatorBuilder::config_file`.
+// A TOML subset: [sections], `key = value` with strings, integers, floats and booleans, comments.
+// Written by hand: the library has no TOML dependency.
+//
+//   [emulation]
+//   region = "pal"              # ntsc, pal or dendy; from the rom header when missing
+//   ram_init = "alternating"    # or a byte, e.g. 0 or 255
+//   sprite_limit = true         # false - no sprite flicker
+//   overclock = 0               # scanlines of extra CPU time per frame, against slowdown
+//   rewind = 64                 # MB of states to rewind through, 0 - off
+//   render_thread = false
+//
+//   [video]
+//   palette = "palettes/fceux.pal"
+//   color_vision = "normal"     # deuteranopia, protanopia (the system palette only)
+//   scale = 3
+//   overscan_top = 8
+//   overscan_bottom = 8
+//   overscan_left = 0
+//   overscan_right = 0
+//   phosphor = 0.0              # share of the previous frame blended in, against flicker
+//
+//   [audio]
+//   enabled = true
+//   sample_rate = 44100
+//   volume = 1.0
+//
+//   [paths]
+//   saves = "saves"             # save states and resume files, next to the rom when missing
+//   gamedb = "gamedb.txt"
+//   game_settings = "games.txt"
+//
+// Missing keys keep their defaults. Paths are used as written: relative ones are resolved
+// against the working directory.
+use crate::emulator::{Config, RamInit};
+use crate::region::Region;
+use crate::screen::overscan::Overscan;
+#[cfg(feature = "std")]
+use crate::screen::palette;
+use crate::screen::palette::ColorVision;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+// everything after a `#` outside of a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..idx],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_string(raw: &str) -> Result<String, String> {
+    let inner = &raw[1..raw.len() - 1];
+    let mut value = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('"') => value.push('"'),
+                Some('\\') => value.push('\\'),
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                other => return Err(format!("bad escape '\\{}'", other.unwrap_or(' '))),
+            },
+            '"' => return Err(format!("bad string {}", raw)),
+            c => value.push(c),
+        }
+    }
+    Ok(value)
+}
+
+fn parse_value(raw: &str) -> Result<Value, String> {
+    match raw {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => {}
+    }
+    if raw.len() >= 2 && raw.starts_with('"') && raw.ends_with('"') {
+        return parse_string(raw).map(Value::Str);
+    }
+    let number = raw.replace('_', "");
+    if let Ok(value) = number.parse::<i64>() {
+        return Ok(Value::Int(value));
+    }
+    if let Ok(value) = number.parse::<f64>() {
+        return Ok(Value::Float(value));
+    }
+    Err(format!("bad value '{}'", raw))
+}
+
+fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Video {
+    /// .pal file instead of the built-in palette
+    pub palette: Option<String>,
+    pub color_vision: ColorVision,
+    /// window size: the picture times `scale`
+    pub scale: u32,
+    pub overscan: Overscan,
+    /// see `Phosphor::new`, 0.0 - off
+    pub phosphor: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Audio {
+    pub enabled: bool,
+    pub sample_rate: u32,
+    /// 0.0..=1.0
+    pub volume: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Paths {
+    /// save states and resume files, next to the rom when not set
+    pub saves: Option<String>,
+    /// see `rom::db`
+    pub gamedb: Option<String>,
+    /// see `rom::settings`
+    pub game_settings: Option<String>,
+}
+
+impl Paths {
+    /// Path the save files of the rom are named after (`save_state::slot_path` and co.)
+    #[cfg(feature = "std")]
+    pub fn save_base(&self, rom_path: &Path) -> PathBuf {
+        match (self.saves.as_ref(), rom_path.file_name()) {
+            (Some(saves), Some(file_name)) => Path::new(saves).join(file_name),
+            _ => rom_path.to_path_buf(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigFile {
+    /// TV system timing, taken from the rom header when not set
+    pub region: Option<Region>,
+    pub ram_init: RamInit,
+    pub sprite_limit: bool,
+    /// Extra scanlines of CPU time per frame, see `NesPPU::set_overclock`
+    pub overclock: usize,
+    /// Memory for the rewind states in MB, 0 - off. See `rewind::Rewind`
+    pub rewind: usize,
+    pub render_thread: bool,
+    pub video: Video,
+    pub audio: Audio,
+    pub paths: Paths,
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        ConfigFile {
+            region: None,
+            ram_init: RamInit::Fill(0),
+            sprite_limit: true,
+            overclock: 0,
+            rewind: 64,
+            render_thread: false,
+            video: Video {
+                palette: None,
+                color_vision: ColorVision::Normal,
+                scale: 3,
+                overscan: Overscan::NONE,
+                phosphor: 0.0,
+            },
+            audio: Audio {
+                enabled: true,
+                sample_rate: 44100,
+                volume: 1.0,
+            },
+            paths: Paths::default(),
+        }
+    }
+}
+
+impl ConfigFile {
+    pub fn new() -> Self {
+        ConfigFile::default()
+    }
+
+    pub fn parse(content: &str) -> Result<ConfigFile, String> {
+        let mut config = ConfigFile::new();
+        let mut section = String::new();
+        for (line_num, line) in content.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", line_num + 1, message);
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].trim().to_string();
+                if !["emulation", "video", "audio", "paths"].contains(&section.as_str()) {
+                    return Err(error(format!("unknown section [{}]", section)));
+                }
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(value) => parse_value(value.trim()).map_err(error)?,
+                None => return Err(error(format!("expected key = value, got '{}'", line))),
+            };
+            config.set(&section, key, value).map_err(error)?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, section: &str, key: &str, value: Value) -> Result<(), String> {
+        let name = format!("{}.{}", section, key);
+        let string = |value: Value| match value {
+            Value::Str(s) => Ok(s),
+            _ => Err(format!("{} should be a string", name)),
+        };
+        let boolean = |value: Value| match value {
+            Value::Bool(b) => Ok(b),
+            _ => Err(format!("{} should be true or false", name)),
+        };
+        let integer = |value: Value, max: i64| match value {
+            Value::Int(n) if n >= 0 && n <= max => Ok(n),
+            _ => Err(format!("{} should be a number in 0..={}", name, max)),
+        };
+
+        match (section, key) {
+            ("emulation", "region") => self.region = Some(string(value)?.parse()?),
+            ("emulation", "ram_init") => {
+                self.ram_init = match value {
+                    Value::Str(s) if s == "alternating" => RamInit::Alternating,
+                    Value::Int(n) if (0..=0xff).contains(&n) => RamInit::Fill(n as u8),
+                    _ => return Err(format!("{} should be \"alternating\" or a byte", name)),
+                }
+            }
+            ("emulation", "sprite_limit") => self.sprite_limit = boolean(value)?,
+            ("emulation", "overclock") => self.overclock = integer(value, 1000)? as usize,
+            ("emulation", "rewind") => self.rewind = integer(value, 4096)? as usize,
+            ("emulation", "render_thread") => self.render_thread = boolean(value)?,
+            ("video", "palette") => self.video.palette = Some(string(value)?),
+            ("video", "color_vision") => self.video.color_vision = string(value)?.parse()?,
+            ("video", "scale") => self.video.scale = integer(value, 16)?.max(1) as u32,
+            ("video", "overscan_top") => self.video.overscan.top = integer(value, 64)? as usize,
+            ("video", "overscan_bottom") => {
+                self.video.overscan.bottom = integer(value, 64)? as usize
+            }
+            ("video", "overscan_left") => self.video.overscan.left = integer(value, 64)? as usize,
+            ("video", "overscan_right") => self.video.overscan.right = integer(value, 64)? as usize,
+            ("video", "phosphor") => {
+                self.video.phosphor = match value {
+                    Value::Float(v) if (0.0..1.0).contains(&v) => v as f32,
+                    Value::Int(0) => 0.0,
+                    _ => return Err(format!("{} should be in 0.0..1.0", name)),
+                }
+            }
+            ("audio", "enabled") => self.audio.enabled = boolean(value)?,
+            ("audio", "sample_rate") => self.audio.sample_rate = integer(value, 192_000)? as u32,
+            ("audio", "volume") => {
+                self.audio.volume = match value {
+                    Value::Float(v) if (0.0..=1.0).contains(&v) => v as f32,
+                    Value::Int(v) if (0..=1).contains(&v) => v as f32,
+                    _ => return Err(format!("{} should be in 0.0..=1.0", name)),
+                }
+            }
+            ("paths", "saves") => self.paths.saves = Some(string(value)?),
+            ("paths", "gamedb") => self.paths.gamedb = Some(string(value)?),
+            ("paths", "game_settings") => self.paths.game_settings = Some(string(value)?),
+            ("", _) => return Err(format!("'{}' is outside of a section", key)),
+            _ => return Err(format!("unknown key '{}' in [{}]", key, section)),
+        }
+        Ok(())
+    }
+
+    /// TOML text `parse` reads back, unset options are left out
+    pub fn to_toml(&self) -> String {
+        let mut out = String::from("[emulation]\n");
+        if let Some(region) = self.region {
+            out += &format!("region = \"{}\"\n", format!("{:?}", region).to_lowercase());
+        }
+        match self.ram_init {
+            RamInit::Fill(value) => out += &format!("ram_init = {}\n", value),
+            RamInit::Alternating => out += "ram_init = \"alternating\"\n",
+        }
+        out += &format!("sprite_limit = {}\n", self.sprite_limit);
+        out += &format!("overclock = {}\n", self.overclock);
+        out += &format!("rewind = {}\n", self.rewind);
+        out += &format!("render_thread = {}\n", self.render_thread);
+
+        out += "\n[video]\n";
+        if let Some(palette) = self.video.palette.as_ref() {
+            out += &format!("palette = {}\n", quote(palette));
+        }
+        let color_vision = format!("{:?}", self.video.color_vision).to_lowercase();
+        out += &format!("color_vision = \"{}\"\n", color_vision);
+        out += &format!("scale = {}\n", self.video.scale);
+        out += &format!("overscan_top = {}\n", self.video.overscan.top);
+        out += &format!("overscan_bottom = {}\n", self.video.overscan.bottom);
+        out += &format!("overscan_left = {}\n", self.video.overscan.left);
+        out += &format!("overscan_right = {}\n", self.video.overscan.right);
+        out += &format!("phosphor = {:?}\n", self.video.phosphor);
+
+        out += "\n[audio]\n";
+        out += &format!("enabled = {}\n", self.audio.enabled);
+        out += &format!("sample_rate = {}\n", self.audio.sample_rate);
+        out += &format!("volume = {:?}\n", self.audio.volume);
+
+        out += "\n[paths]\n";
+        let paths = [
+            ("saves", &self.paths.saves),
+            ("gamedb", &self.paths.gamedb),
+            ("game_settings", &self.paths.game_settings),
+        ];
+        for (key, path) in paths.iter() {
+            if let Some(path) = path {
+                out += &format!("{} = {}\n", key, quote(path));
+            }
+        }
+        out
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load(path: &Path) -> Result<ConfigFile, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        ConfigFile::parse(&content).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    #[cfg(feature = "std")]
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_toml())
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+
+    /// The palette the picture is drawn with: the palette file or the built-in one adjusted for
+    /// `color_vision`
+    pub fn palette(&self) -> Result<[(u8, u8, u8); 64], String> {
+        match self.video.palette.as_ref() {
+            #[cfg(feature = "std")]
+            Some(path) => {
+                let data = fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+                palette::from_pal(&data).map_err(|e| format!("{}: {}", path, e))
+            }
+            #[cfg(not(feature = "std"))]
+            Some(_) => Err(String::from("palette files need the std feature")),
+            None => Ok(self.video.color_vision.palette()),
+        }
+    }
+
+    /// Overrides the emulation options of `config`, the rest (start address, frame skip) stays
+    pub fn apply(&self, config: &mut Config) -> Result<(), String> {
+        config.palette = self.palette()?;
+        if self.region.is_some() {
+            config.region = self.region;
+        }
+        config.ram_init = self.ram_init;
+        config.sprite_limit = self.sprite_limit;
+        config.overclock = self.overclock;
+        config.render_thread = self.render_thread;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = ConfigFile::parse(
+            "# comment\n\
+             [emulation]\n\
+             region = \"PAL\"   # trailing comment\n\
+             ram_init = \"alternating\"\n\
+             sprite_limit = false\n\
+             overclock = 100\n\
+             rewind = 16\n\
+             \n\
+             [video]\n\
+             palette = \"pal # files/fceux.pal\"\n\
+             scale = 2\n\
+             overscan_top = 8\n\
+             phosphor = 0.5\n\
+             [audio]\n\
+             sample_rate = 48_000\n\
+             volume = 0.5\n\
+             [paths]\n\
+             saves = \"C:\\\\saves\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.region, Some(Region::Pal));
+        assert_eq!(config.ram_init, RamInit::Alternating);
+        assert!(!config.sprite_limit);
+        assert_eq!(config.overclock, 100);
+        assert_eq!(config.rewind, 16);
+        assert_eq!(
+            config.video.palette.as_deref(),
+            Some("pal # files/fceux.pal")
+        );
+        assert_eq!(config.video.scale, 2);
+        assert_eq!(config.video.overscan, Overscan::new(8, 0, 0, 0));
+        assert_eq!(config.video.phosphor, 0.5);
+        assert_eq!(config.audio.sample_rate, 48000);
+        assert_eq!(config.audio.volume, 0.5);
+        assert!(config.audio.enabled);
+        assert_eq!(config.paths.saves.as_deref(), Some("C:\\saves"));
+        assert_eq!(config.paths.gamedb, None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |content: &str| ConfigFile::parse(content).err().unwrap();
+        assert_eq!(
+            error("[video]\nsize = 3"),
+            "line 2: unknown key 'size' in [video]"
+        );
+        assert_eq!(error("[cheats]"), "line 1: unknown section [cheats]");
+        assert_eq!(
+            error("scale = 3"),
+            "line 1: 'scale' is outside of a section"
+        );
+        assert_eq!(
+            error("[emulation]\nsprite_limit = 1"),
+            "line 2: emulation.sprite_limit should be true or false"
+        );
+        assert_eq!(
+            error("[video]\nphosphor = 1.0"),
+            "line 2: video.phosphor should be in 0.0..1.0"
+        );
+        assert_eq!(
+            error("[video]\nscale"),
+            "line 2: expected key = value, got 'scale'"
+        );
+        assert!(error("[emulation]\nregion = \"secam\"").starts_with("line 2: unknown region"));
+        assert!(error("[video]\nscale = three").starts_with("line 2: bad value"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut config = ConfigFile::new();
+        assert_eq!(ConfigFile::parse(&config.to_toml()), Ok(config.clone()));
+
+        config.region = Some(Region::Dendy);
+        config.ram_init = RamInit::Fill(0xff);
+        config.video.palette = Some(String::from("my \"best\" palette.pal"));
+        config.video.color_vision = ColorVision::Protanopia;
+        config.video.overscan = Overscan::NTSC.with_sides(8);
+        config.video.phosphor = 0.75;
+        config.audio.volume = 0.25;
+        config.paths.game_settings = Some(String::from("games.txt"));
+        assert_eq!(ConfigFile::parse(&config.to_toml()), Ok(config));
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut file = ConfigFile::new();
+        file.region = Some(Region::Pal);
+        file.sprite_limit = false;
+        file.video.color_vision = ColorVision::Deuteranopia;
+        let mut config = Config {
+            start_pc: Some(0xc000),
+            ..Config::default()
+        };
+        file.apply(&mut config).unwrap();
+        assert_eq!(config.region, Some(Region::Pal));
+        assert!(!config.sprite_limit);
+        assert_eq!(config.palette[..], ColorVision::Deuteranopia.palette()[..]);
+        assert_eq!(config.start_pc, Some(0xc000));
+
+        file.video.palette = Some(String::from("no/such/file.pal"));
+        assert!(file.apply(&mut config).is_err());
+    }
+}
@@ -3,9 +3,13 @@
 use crate::bus::CpuBus;
 use crate::cpu::mem::AddressingMode;
 use crate::cpu::opscode;
+use crate::prelude::*;
+#[cfg(feature = "save-state")]
+use crate::save_state::Snapshot;
+#[cfg(feature = "std")]
 use hex;
+#[cfg(feature = "save-state")]
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 
 bitflags! {
 /// # Status Register (P) http://wiki.nesdev.com/w/index.php/Status_flags
@@ -20,7 +24,7 @@ bitflags! {
 ///  | +--------------- Overflow Flag
 ///  +----------------- Negative Flag
 ///
-    #[derive(Serialize, Deserialize)]
+    #[cfg_attr(feature = "save-state", derive(Serialize, Deserialize))]
     pub struct CpuFlags: u8 {
         const CARRY             = 0b00000001;
         const ZERO              = 0b00000010;
@@ -45,7 +49,7 @@ mod interrupt {
     #[derive(PartialEq, Eq)]
     pub enum InterruptType {
         BRK,
-        IRQ, //todo: will be needed for APU
+        IRQ,
         NMI,
     }
 
@@ -64,8 +68,6 @@ mod interrupt {
         cpu_cycles: 1,
     };
 
-    #[allow(dead_code)]
-    //todo: will be needed for APU
     pub(super) const IRQ: Interrupt = Interrupt {
         itype: InterruptType::IRQ,
         vector_addr: 0xfffe,
@@ -81,21 +83,46 @@ mod interrupt {
     };
 }
 
-pub struct CPU<'a> {
+#[cfg(feature = "save-state")]
+#[derive(Serialize, Deserialize)]
+struct SaveState {
+    register_a: u8,
+    register_x: u8,
+    register_y: u8,
+    stack_pointer: u8,
+    program_counter: u16,
+    flags: CpuFlags,
+    irq_inhibit: bool,
+    bus: Vec<u8>,
+}
+
+/// The bus is a trait object by default, `CPU<Bus<NesPPU>>` avoids the dynamic dispatch
+/// and is `Send` (see `Emulator`)
+pub struct CPU<B: ?Sized = dyn CpuBus> {
     pub(super) register_a: u8,
     pub(super) register_x: u8,
     pub(super) register_y: u8,
     pub(super) stack_pointer: u8,
     pub program_counter: u16,
     pub(super) flags: CpuFlags,
-    pub bus: Box<dyn CpuBus + 'a>,
+    // the I flag as the IRQ poll sees it: CLI, SEI and PLP change it after the poll of the next
+    // instruction, RTI right away
+    irq_inhibit: bool,
+    pub bus: Box<B>,
 }
 
-impl<'a> CPU<'a> {
+impl CPU {
+    pub fn new(bus: Box<dyn CpuBus>) -> CPU {
+        CPU::with_bus(bus)
+    }
+
+    #[cfg(feature = "std")]
     pub fn transform(s: &str) -> Vec<u8> {
         hex::decode(s.replace(' ', "")).expect("Decoding failed")
     }
+}
 
+impl<B: CpuBus + ?Sized> CPU<B> {
     /// note: ignoring decimal mode
     /// http://www.righto.com/2012/12/the-6502-overflow-flag-explained.html
     fn add_to_register_a(&mut self, data: u8) {
@@ -235,6 +262,14 @@ impl<'a> CPU<'a> {
         self.bus.read_u16(pos)
     }
 
+    pub(super) fn mem_read_u16_zero_page(&mut self, ptr: u8) -> u16 {
+        self.bus.read_u16_wrap_zero_page(ptr)
+    }
+
+    pub(super) fn mem_read_u16_bug(&mut self, pos: u16) -> u16 {
+        self.bus.read_u16_bug(pos)
+    }
+
     pub(super) fn mem_write(&mut self, pos: u16, data: u8) {
         self.bus.write(pos, data);
     }
@@ -353,7 +388,7 @@ impl<'a> CPU<'a> {
 
     pub fn test_interpret_fn<F>(&mut self, program: &[u8], mem_start: u16, callback_opt: F)
     where
-        F: FnMut(&mut CPU),
+        F: FnMut(&mut CPU<B>),
     {
         self.program_counter = mem_start;
         let mut pos = self.program_counter;
@@ -367,26 +402,138 @@ impl<'a> CPU<'a> {
     pub fn interpret_fn<F>(&mut self, program_end: usize, mut callback_opt: F)
     //todo: program end is not needed
     where
-        F: FnMut(&mut CPU),
+        F: FnMut(&mut CPU<B>),
     {
-        let ref opscodes: HashMap<u8, &'static opscode::OpsCode> = *opscode::OPSCODES_MAP;
         while (self.program_counter as usize) < program_end {
             callback_opt(self);
-            self.execute_next_op(program_end, &opscodes);
+            self.execute_next_op(program_end);
+        }
+    }
+
+    pub fn stack_pointer(&self) -> u8 {
+        self.stack_pointer
+    }
+
+    pub fn register_a(&self) -> u8 {
+        self.register_a
+    }
+
+    pub fn register_x(&self) -> u8 {
+        self.register_x
+    }
+
+    pub fn register_y(&self) -> u8 {
+        self.register_y
+    }
+
+    pub fn status(&self) -> u8 {
+        self.flags.bits()
+    }
+
+    /// Snapshot of the whole machine: cpu registers and everything behind the bus (RAM, PPU,
+    /// mapper registers, controllers).
+    /// todo: the APU, once it is implemented
+    /// The data starts with a `save_state::Header`
+    #[cfg(feature = "save-state")]
+    pub fn save_state(&self) -> Result<Vec<u8>, String> {
+        let snapshot = Snapshot {
+            rom_crc32: self.bus.rom_crc32(),
+            state: self.encode_state()?,
+        };
+        Ok(snapshot.to_bytes())
+    }
+
+    /// Restores a state produced by `save_state` with the same rom loaded
+    #[cfg(feature = "save-state")]
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        self.restore(&Snapshot::from_bytes(data)?)
+    }
+
+    /// In-memory copy of the machine state, see `save_state::Snapshot`
+    #[cfg(feature = "save-state")]
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            rom_crc32: self.bus.rom_crc32(),
+            state: self
+                .encode_state()
+                .expect("serialization into memory doesn't fail"),
         }
     }
 
-    fn execute_next_op(
-        &mut self,
-        program_end: usize,
-        opscodes: &HashMap<u8, &'static opscode::OpsCode>,
-    ) {
+    /// Restores a snapshot taken from this machine
+    #[cfg(feature = "save-state")]
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), String> {
+        snapshot.validate(self.bus.rom_crc32())?;
+        self.decode_state(&snapshot.state)
+    }
+
+    #[cfg(feature = "save-state")]
+    fn encode_state(&self) -> Result<Vec<u8>, String> {
+        let state = SaveState {
+            register_a: self.register_a,
+            register_x: self.register_x,
+            register_y: self.register_y,
+            stack_pointer: self.stack_pointer,
+            program_counter: self.program_counter,
+            flags: self.flags,
+            irq_inhibit: self.irq_inhibit,
+            bus: self.bus.save_state()?,
+        };
+        bincode::serialize(&state).map_err(|e| e.to_string())
+    }
+
+    #[cfg(feature = "save-state")]
+    fn decode_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let state: SaveState =
+            bincode::deserialize(data).map_err(|e| format!("corrupted save state: {}", e))?;
+        self.bus.load_state(&state.bus)?;
+        self.register_a = state.register_a;
+        self.register_x = state.register_x;
+        self.register_y = state.register_y;
+        self.stack_pointer = state.stack_pointer;
+        self.program_counter = state.program_counter;
+        self.flags = state.flags;
+        self.irq_inhibit = state.irq_inhibit;
+        Ok(())
+    }
+
+    /// Reset button, RAM and registers other than SP and P are not changed
+    /// https://wiki.nesdev.com/w/index.php/CPU_power_up_state#After_reset
+    pub fn reset(&mut self) {
+        self.stack_pointer = self.stack_pointer.wrapping_sub(3);
+        self.flags.insert(CpuFlags::INTERRUPT_DISABLE);
+        self.irq_inhibit = true;
+        self.program_counter = self.mem_read_u16(0xfffc);
+    }
+
+    /// Power on state, then the reset sequence
+    /// https://wiki.nesdev.com/w/index.php/CPU_power_up_state
+    pub fn power_on(&mut self) {
+        self.register_a = 0;
+        self.register_x = 0;
+        self.register_y = 0;
+        self.stack_pointer = STACK_RESET;
+        self.flags = CpuFlags::from_bits_truncate(0b100100);
+        self.irq_inhibit = true;
+        self.program_counter = self.mem_read_u16(0xfffc);
+    }
+
+    /// executes single instruction (including pending NMI/IRQ handling)
+    pub fn step(&mut self) {
+        // same as frontends: the program runs till the end of address space
+        self.execute_next_op(0xffff);
+    }
+
+    fn execute_next_op(&mut self, program_end: usize) {
         if let Some(_nmi) = self.bus.poll_nmi_status() {
             self.interrupt(interrupt::NMI);
+        } else if !self.irq_inhibit && self.bus.poll_irq_status() {
+            self.interrupt(interrupt::IRQ);
         }
+        self.irq_inhibit = self.flags.contains(CpuFlags::INTERRUPT_DISABLE);
 
         let code = self.mem_read(self.program_counter);
-        let ops = opscodes.get(&code).unwrap();
+        let ops = opscode::lookup(code).unwrap();
 
         self.program_counter += 1;
         let program_counter_state = self.program_counter;
@@ -559,21 +706,11 @@ impl<'a> CPU<'a> {
             /* JMP Indirect */
             0x6c => {
                 let mem_address = self.mem_read_u16(self.program_counter);
-                // let indirect_ref = self.mem_read_u16(mem_address);
                 //6502 bug mode with with page boundary:
                 //  if address $3000 contains $40, $30FF contains $80, and $3100 contains $50,
                 // the result of JMP ($30FF) will be a transfer of control to $4080 rather than $5080 as you intended
                 // i.e. the 6502 took the low byte of the address from $30FF and the high byte from $3000
-
-                let indirect_ref = if mem_address & 0x00FF == 0x00FF {
-                    let lo = self.mem_read(mem_address);
-                    let hi = self.mem_read(mem_address & 0xFF00);
-                    (hi as u16) << 8 | (lo as u16)
-                } else {
-                    self.mem_read_u16(mem_address)
-                };
-
-                self.program_counter = indirect_ref;
+                self.program_counter = self.mem_read_u16_bug(mem_address);
             }
 
             /* JSR */
@@ -593,6 +730,7 @@ impl<'a> CPU<'a> {
                 self.flags.bits = self.stack_pop();
                 self.flags.remove(CpuFlags::BREAK);
                 self.flags.insert(CpuFlags::BREAK2);
+                self.irq_inhibit = self.flags.contains(CpuFlags::INTERRUPT_DISABLE);
 
                 self.program_counter = self.stack_pop_u16();
             }
@@ -902,7 +1040,7 @@ impl<'a> CPU<'a> {
             /* AHX  Indirect Y */
             0x93 => {
                 let pos: u8 = self.mem_read(self.program_counter);
-                let mem_address = self.mem_read_u16(pos as u16) + self.register_y as u16;
+                let mem_address = self.mem_read_u16_zero_page(pos) + self.register_y as u16;
                 let data = self.register_a & self.register_x & (mem_address >> 8) as u8;
                 ops.mode.write_u8(self, data);
             }
@@ -946,7 +1084,7 @@ impl<'a> CPU<'a> {
         }
     }
 
-    pub fn new<'b>(bus: Box<dyn CpuBus + 'b>) -> CPU<'b> {
+    pub fn with_bus(bus: Box<B>) -> Self {
         return CPU {
             register_a: 0,
             register_x: 0,
@@ -954,6 +1092,7 @@ impl<'a> CPU<'a> {
             stack_pointer: STACK_RESET,
             program_counter: 0,
             flags: CpuFlags::from_bits_truncate(0b100100),
+            irq_inhibit: true,
             bus: bus,
         };
     }
@@ -1396,6 +1535,40 @@ mod test {
         assert_eq!(cpu.program_counter, 0xbafc);
     }
 
+    #[test]
+    fn test_0x6c_jmp_indirect_page_bug() {
+        let mem = MockBus::new();
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.mem_write(0x02ff, 0x80);
+        cpu.mem_write(0x0200, 0x40);
+        cpu.mem_write(0x0300, 0x50);
+        cpu.interpret(&CPU::transform("6c ff 02"), 100);
+        assert_eq!(cpu.program_counter, 0x4080);
+    }
+
+    #[test]
+    fn test_indirect_y_pointer_wraps_in_zero_page() {
+        let mem = MockBus::new();
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.mem_write(0x00ff, 0x34);
+        cpu.mem_write(0x0000, 0x12);
+        cpu.mem_write(0x0100, 0x56);
+        cpu.mem_write(0x1235, 0x42);
+        cpu.interpret(&CPU::transform("a0 01 b1 ff"), 100);
+        assert_eq!(cpu.register_a, 0x42);
+    }
+
+    #[test]
+    fn test_0x93_ahx_pointer_wraps_in_zero_page() {
+        let mem = MockBus::new();
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.mem_write(0x00ff, 0x00);
+        cpu.mem_write(0x0000, 0x02);
+        cpu.mem_write(0x0100, 0x05);
+        cpu.interpret(&CPU::transform("a9 ff a2 ff a0 00 93 ff"), 100);
+        assert_eq!(cpu.mem_read(0x0200), 0x02);
+    }
+
     #[test]
     fn test_0x4c_jmp_absolute() {
         let mem = MockBus::new();
@@ -1719,6 +1892,228 @@ mod test {
         assert_eq!(bus.borrow().cycles, 21);
     }
 
+    #[test]
+    fn test_irq() {
+        let mut mem = MockBus::new();
+        mem.irq_line = true;
+        mem.space[0xfffe] = 0x00;
+        mem.space[0xffff] = 0x07;
+        // 0600: INX; CLI; INX; INX
+        // 0700: irq: LDY #$05
+        mem.space[0x600..0x604].copy_from_slice(&CPU::transform("e8 58 e8 e8"));
+        mem.space[0x700..0x702].copy_from_slice(&CPU::transform("a0 05"));
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.program_counter = 0x600;
+        cpu.flags.insert(CpuFlags::INTERRUPT_DISABLE);
+
+        // masked by the I flag
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x602);
+
+        // CLI takes effect after the next instruction: the second INX runs first
+        cpu.step();
+        assert_eq!(cpu.register_x, 2);
+        assert_eq!(cpu.register_y, 0);
+
+        // the IRQ handler runs instead of the third INX
+        cpu.step();
+        assert_eq!(cpu.register_y, 5);
+        assert_eq!(cpu.register_x, 2);
+        assert!(cpu.flags.contains(CpuFlags::INTERRUPT_DISABLE));
+        assert_eq!(cpu.mem_read_u16(0x01fc), 0x603);
+    }
+
+    #[test]
+    fn test_mapper_irq() {
+        use crate::bus::Bus;
+        use crate::ppu::ppu::NesPPU;
+        use crate::rom::test_ines_rom;
+
+        // VRC6: IRQ vector in the fixed last bank points to the handler at $0700
+        let mut rom = test_ines_rom::test_rom();
+        rom.mapper = 24;
+        let len = rom.prg_rom.len();
+        rom.prg_rom[len - 2] = 0x00;
+        rom.prg_rom[len - 1] = 0x07;
+        let mut cpu = CPU::new(Box::from(Bus::<NesPPU>::new(rom)));
+        /*
+            LDA #$FB
+            STA $F000 ; IRQ latch
+            LDA #$07
+            STA $F001 ; IRQ on, cpu cycle mode
+            CLI
+            loop: JMP loop
+
+            irq:
+            INC $10
+            STA $F002 ; acknowledge
+            RTI
+        */
+        let program = CPU::transform("a9 fb 8d 00 f0 a9 07 8d 01 f0 58 4c 0b 06");
+        let handler = CPU::transform("e6 10 8d 02 f0 40");
+        for (i, byte) in program.iter().enumerate() {
+            cpu.bus.write(0x600 + i as u16, *byte);
+        }
+        for (i, byte) in handler.iter().enumerate() {
+            cpu.bus.write(0x700 + i as u16, *byte);
+        }
+        cpu.program_counter = 0x600;
+
+        // the counter overflows before CLI, the IRQ waits for the I flag
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert_eq!(cpu.bus.read(0x10), 0);
+        // CLI, then the JMP right after it still runs before the handler
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.bus.read(0x10), 0);
+        cpu.step();
+        assert_eq!(cpu.bus.read(0x10), 1);
+        // acknowledged, the latch reloads the counter: it fires again
+        for _ in 0..20 {
+            cpu.step();
+        }
+        assert!(cpu.bus.read(0x10) > 1);
+    }
+
+    #[test]
+    #[cfg(feature = "save-state")]
+    fn test_save_load_state() {
+        use crate::bus::Bus;
+        use crate::cpu::trace;
+        use crate::ppu::ppu::NesPPU;
+        use crate::rom::test_ines_rom;
+
+        let bus = Bus::<NesPPU>::new(test_ines_rom::test_rom());
+        let mut cpu = CPU::new(Box::from(bus));
+        cpu.program_counter = 0x8000;
+        cpu.bus.write(0x0010, 0x42);
+        // $2105 in VRAM
+        cpu.bus.write(0x2006, 0x21);
+        cpu.bus.write(0x2006, 0x05);
+        cpu.bus.write(0x2007, 0x55);
+        for _ in 0..1000 {
+            cpu.step();
+        }
+
+        let state = cpu.save_state().unwrap();
+        let run = |cpu: &mut CPU| -> Vec<String> {
+            (0..3000)
+                .map(|_| {
+                    let line = trace(cpu);
+                    cpu.step();
+                    line
+                })
+                .collect()
+        };
+        let expected = run(&mut cpu);
+        cpu.bus.write(0x0010, 0x00);
+        cpu.bus.write(0x2006, 0x21);
+        cpu.bus.write(0x2006, 0x05);
+        cpu.bus.write(0x2007, 0x00);
+
+        cpu.load_state(&state).unwrap();
+        assert_eq!(run(&mut cpu), expected);
+
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.bus.read(0x0010), 0x42);
+        cpu.bus.write(0x2006, 0x21);
+        cpu.bus.write(0x2006, 0x05);
+        cpu.bus.read(0x2007);
+        assert_eq!(cpu.bus.read(0x2007), 0x55);
+
+        assert!(cpu.load_state(&state[..state.len() / 2]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "save-state")]
+    fn test_snapshot_restore() {
+        use crate::bus::Bus;
+        use crate::cpu::trace;
+        use crate::ppu::ppu::NesPPU;
+        use crate::rom::test_ines_rom;
+
+        let bus = Bus::<NesPPU>::new(test_ines_rom::test_rom());
+        let mut cpu = CPU::new(Box::from(bus));
+        cpu.program_counter = 0x8000;
+        for _ in 0..1000 {
+            cpu.step();
+        }
+
+        let snapshot = cpu.snapshot();
+        let run = |cpu: &mut CPU| -> Vec<String> {
+            (0..3000)
+                .map(|_| {
+                    let line = trace(cpu);
+                    cpu.step();
+                    line
+                })
+                .collect()
+        };
+        let expected = run(&mut cpu);
+        let after = cpu.snapshot();
+        assert_ne!(after, snapshot);
+
+        // branch from the same point twice
+        for _ in 0..2 {
+            cpu.restore(&snapshot).unwrap();
+            assert_eq!(run(&mut cpu), expected);
+            assert_eq!(cpu.snapshot(), after);
+        }
+    }
+
+    // cycles of one instruction at $0600: absolute operands point at $02F0, the zero page
+    // pointer at $10 to $80F0, so X/Y = $20 crosses a page and X/Y = 0 doesn't
+    fn op_cycles(program: &str, index: u8) -> usize {
+        let bus = Rc::from(RefCell::from(MockBus::new()));
+        bus.borrow_mut().space[0x10] = 0xf0;
+        bus.borrow_mut().space[0x11] = 0x80;
+        for (i, byte) in CPU::transform(program).iter().enumerate() {
+            bus.borrow_mut().space[0x600 + i] = *byte;
+        }
+        let mut cpu = CPU::new(Box::from(DynamicBusWrapper::new(bus.clone())));
+        cpu.program_counter = 0x600;
+        cpu.register_x = index;
+        cpu.register_y = index;
+        let before = bus.borrow().cycles;
+        cpu.step();
+        let cycles = bus.borrow().cycles - before;
+        cycles
+    }
+
+    #[test]
+    fn test_unofficial_page_cross_cycles() {
+        let cases = [
+            // (program, cycles, cycles with a page cross)
+            ("bf f0 02", 4, 5), // LAX abs,Y
+            ("b3 10", 5, 6),    // LAX (zp),Y
+            ("bb f0 02", 4, 5), // LAS abs,Y
+            ("1c f0 02", 4, 5), // NOP abs,X
+            ("fc f0 02", 4, 5), // NOP abs,X
+            ("7f f0 02", 7, 7), // RRA abs,X
+            ("7b f0 02", 7, 7), // RRA abs,Y
+            ("73 10", 8, 8),    // RRA (zp),Y
+            ("ff f0 02", 7, 7), // ISB abs,X
+            ("fb f0 02", 7, 7), // ISB abs,Y
+            ("f3 10", 8, 8),    // ISB (zp),Y
+            ("df f0 02", 7, 7), // DCP abs,X
+            ("1b f0 02", 7, 7), // SLO abs,Y
+            ("93 10", 6, 6),    // AHX (zp),Y
+            ("9f f0 02", 5, 5), // AHX abs,Y
+            ("9e f0 02", 5, 5), // SHX abs,Y
+            ("9c f0 02", 5, 5), // SHY abs,X
+            ("9b f0 02", 5, 5), // TAS abs,Y
+        ];
+        for (program, cycles, crossed) in cases.iter() {
+            assert_eq!(op_cycles(program, 0), *cycles, "{}", program);
+            assert_eq!(op_cycles(program, 0x20), *crossed, "{} page cross", program);
+        }
+        assert_eq!(op_cycles("ab 00", 0), 2); // LXA #
+        assert_eq!(op_cycles("8b 00", 0), 2); // XAA #
+    }
+
     #[test]
     fn test_ololo() {
         let mem = MockBus::new();
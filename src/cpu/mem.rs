@@ -1,3 +1,4 @@
+use crate::bus::CpuBus;
 use crate::cpu::cpu::CPU;
 
 const ZERO_PAGE: u16 = 0x0;
@@ -8,19 +9,34 @@ pub trait Mem {
         let hi = (data >> 8) as u8;
         let lo = (data & 0xff) as u8;
         self.write(pos, lo);
-        self.write(pos + 1, hi);
+        self.write(pos.wrapping_add(1), hi);
     }
 
     fn read(&mut self, pos: u16) -> u8;
 
     fn read_u16(&mut self, pos: u16) -> u16 {
         let lo = self.read(pos) as u16;
-        let hi = self.read(pos + 1) as u16;
+        let hi = self.read(pos.wrapping_add(1)) as u16;
         (hi << 8) | (lo as u16)
     }
+
+    /// Pointer stored in the zero page: ($FF) takes the high byte from $00, not $0100
+    fn read_u16_wrap_zero_page(&mut self, ptr: u8) -> u16 {
+        let lo = self.read(ptr as u16) as u16;
+        let hi = self.read(ptr.wrapping_add(1) as u16) as u16;
+        (hi << 8) | lo
+    }
+
+    /// JMP indirect bug: the high byte is read from the same page,
+    /// JMP ($30FF) takes the low byte from $30FF and the high byte from $3000
+    fn read_u16_bug(&mut self, pos: u16) -> u16 {
+        let lo = self.read(pos) as u16;
+        let hi = self.read((pos & 0xFF00) | (pos.wrapping_add(1) & 0x00FF)) as u16;
+        (hi << 8) | lo
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[allow(non_camel_case_types)]
 pub enum AddressingMode {
     Immediate,
@@ -40,7 +56,11 @@ pub enum AddressingMode {
 }
 
 impl AddressingMode {
-    pub fn get_absolute_addr(&self, cpu: &mut CPU, base: u16) -> (bool, u16) {
+    pub fn get_absolute_addr<B: CpuBus + ?Sized>(
+        &self,
+        cpu: &mut CPU<B>,
+        base: u16,
+    ) -> (bool, u16) {
         match self {
             AddressingMode::ZeroPage => (false, ZERO_PAGE + base),
             AddressingMode::ZeroPage_X => {
@@ -65,16 +85,10 @@ impl AddressingMode {
 
             AddressingMode::Indirect_X => {
                 let ptr: u8 = (base as u8).wrapping_add(cpu.register_x);
-                let lo = cpu.mem_read(ptr as u16);
-                let hi = cpu.mem_read(ptr.wrapping_add(1) as u16);
-                (false, (hi as u16) << 8 | (lo as u16))
+                (false, cpu.mem_read_u16_zero_page(ptr))
             }
             AddressingMode::Indirect_Y | AddressingMode::Indirect_Y_PageCross => {
-                let lo = cpu.mem_read(base as u16);
-                let hi = cpu.mem_read((base as u8).wrapping_add(1) as u16);
-                // let deref = ((hi as u16) << 8 | (lo as u16)).wrapping_add(cpu.register_y as u16);
-
-                let deref_base = (hi as u16) << 8 | (lo as u16);
+                let deref_base = cpu.mem_read_u16_zero_page(base as u8);
                 let deref = deref_base.wrapping_add(cpu.register_y as u16);
                 (page_cross(deref_base, deref), deref)
             }
@@ -86,7 +100,7 @@ impl AddressingMode {
         }
     }
 
-    pub fn read_u8<'a>(&self, cpu: &mut CPU) -> u8 {
+    pub fn read_u8<B: CpuBus + ?Sized>(&self, cpu: &mut CPU<B>) -> u8 {
         if let AddressingMode::Accumulator = self {
             return cpu.register_a;
         }
@@ -111,7 +125,7 @@ impl AddressingMode {
         cpu.mem_read(addr)
     }
 
-    pub fn write_u8(&self, cpu: &mut CPU, data: u8) {
+    pub fn write_u8<B: CpuBus + ?Sized>(&self, cpu: &mut CPU<B>, data: u8) {
         if let AddressingMode::Accumulator = self {
             cpu.set_register_a(data);
             return;
@@ -143,3 +157,37 @@ fn page_cross_mode(mode: &AddressingMode) -> bool {
 fn page_cross(addr1: u16, addr2: u16) -> bool {
     addr1 & 0xFF00 != addr2 & 0xFF00
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::MockBus;
+
+    #[test]
+    fn test_read_u16_wraps_at_end_of_memory() {
+        let mut mem = MockBus::new();
+        mem.write(0xffff, 0x34);
+        mem.write(0x0000, 0x12);
+        assert_eq!(mem.read_u16(0xffff), 0x1234);
+    }
+
+    #[test]
+    fn test_read_u16_wrap_zero_page() {
+        let mut mem = MockBus::new();
+        mem.write(0x00ff, 0x34);
+        mem.write(0x0100, 0x56);
+        mem.write(0x0000, 0x12);
+        assert_eq!(mem.read_u16_wrap_zero_page(0xff), 0x1234);
+        assert_eq!(mem.read_u16(0x00ff), 0x5634);
+    }
+
+    #[test]
+    fn test_read_u16_bug() {
+        let mut mem = MockBus::new();
+        mem.write(0x30ff, 0x80);
+        mem.write(0x3000, 0x40);
+        mem.write(0x3100, 0x50);
+        assert_eq!(mem.read_u16_bug(0x30ff), 0x4080);
+        assert_eq!(mem.read_u16_bug(0x30fe), mem.read_u16(0x30fe));
+    }
+}
@@ -1,39 +1,81 @@
+use crate::bus::CpuBus;
 use crate::cpu::mem::AddressingMode;
+use crate::prelude::*;
+#[cfg(feature = "std")]
+use crate::symbols::Symbols;
+use core::str::FromStr;
 use cpu::CPU;
-use std::collections::HashMap;
 
 pub mod cpu;
 pub mod mem;
 pub mod opscode;
+#[cfg(feature = "std")]
+pub mod trace_filter;
+#[cfg(feature = "std")]
+pub mod trace_writer;
 
-lazy_static! {
-    pub static ref NON_READABLE_ADDR: Vec<u16> =
-        vec!(0x2001, 0x2002, 0x2003, 0x2004, 0x2005, 0x2006, 0x2007, 0x4016, 0x4017);
-}
-
-pub fn trace(cpu: &mut CPU) -> String {
-    let ref opscodes: HashMap<u8, &'static opscode::OpsCode> = *opscode::OPSCODES_MAP;
-    let ref non_readable_addr = *NON_READABLE_ADDR;
+pub const NON_READABLE_ADDR: [u16; 9] =
+    [0x2001, 0x2002, 0x2003, 0x2004, 0x2005, 0x2006, 0x2007, 0x4016, 0x4017];
 
-    let code = cpu.mem_read(cpu.program_counter);
-    let ops = opscodes.get(&code).unwrap();
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MemAccessKind {
+    Read,
+    Write,
+    ReadWrite,
+}
 
-    let begin = cpu.program_counter;
-    let mut hex_dump = vec![];
-    hex_dump.push(code);
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct MemAccess {
+    pub addr: u16,
+    pub kind: MemAccessKind,
+}
 
-    let (mem_addr, stored_value) = match ops.mode {
+/// address of the data the instruction at `pc` operates on (none for immediate/implied/relative modes)
+fn effective_addr<B: CpuBus + ?Sized>(cpu: &mut CPU<B>, ops: &opscode::OpsCode) -> Option<u16> {
+    match ops.mode {
         AddressingMode::Immediate
         | AddressingMode::NoneAddressing
-        | AddressingMode::Accumulator => (0, 0),
+        | AddressingMode::Accumulator => None,
         _ => {
+            let begin = cpu.program_counter;
             let address = if ops.len == 2 {
                 cpu.mem_read(begin + 1) as u16
             } else {
                 cpu.mem_read_u16(begin + 1)
             };
             let (_, addr) = ops.mode.get_absolute_addr(cpu, address);
-            if !non_readable_addr.contains(&addr) {
+            Some(addr)
+        }
+    }
+}
+
+/// Data memory access the next instruction is about to make. Stack and opcode fetches are not reported.
+pub fn next_mem_access<B: CpuBus + ?Sized>(cpu: &mut CPU<B>) -> Option<MemAccess> {
+    let code = cpu.mem_read(cpu.program_counter);
+    let ops = opscode::lookup(code)?;
+    let addr = effective_addr(cpu, ops)?;
+
+    let kind = match ops.mnemonic.trim_start_matches('*') {
+        "STA" | "STX" | "STY" | "SAX" | "AHX" | "SHX" | "SHY" | "TAS" => MemAccessKind::Write,
+        "ASL" | "LSR" | "ROL" | "ROR" | "INC" | "DEC" | "DCP" | "RLA" | "SLO" | "SRE" | "RRA"
+        | "ISB" => MemAccessKind::ReadWrite,
+        _ => MemAccessKind::Read,
+    };
+    Some(MemAccess { addr, kind })
+}
+
+pub fn trace<B: CpuBus + ?Sized>(cpu: &mut CPU<B>) -> String {
+    let code = cpu.mem_read(cpu.program_counter);
+    let ops = opscode::lookup(code).unwrap();
+
+    let begin = cpu.program_counter;
+    let mut hex_dump = vec![];
+    hex_dump.push(code);
+
+    let (mem_addr, stored_value) = match effective_addr(cpu, ops) {
+        None => (0, 0),
+        Some(addr) => {
+            if !NON_READABLE_ADDR.contains(&addr) {
                 (addr, cpu.mem_read(addr))
             } else {
                 (addr, 0)
@@ -101,15 +143,7 @@ pub fn trace(cpu: &mut CPU) -> String {
                 AddressingMode::NoneAddressing => {
                     if ops.code == 0x6c {
                         //jmp indirect
-                        let jmp_addr = if address & 0x00FF == 0x00FF {
-                            let lo = cpu.mem_read(address);
-                            let hi = cpu.mem_read(address & 0xFF00);
-                            (hi as u16) << 8 | (lo as u16)
-                        } else {
-                            cpu.mem_read_u16(address)
-                        };
-
-                        // let jmp_addr = cpu.mem_read_u16(address);
+                        let jmp_addr = cpu.mem_read_u16_bug(address);
                         format!("(${:04x}) = {:04x}", address, jmp_addr)
                     } else {
                         format!("${:04x}", address)
@@ -160,6 +194,106 @@ pub fn trace(cpu: &mut CPU) -> String {
     .to_ascii_uppercase()
 }
 
+/// The `trace` data as a JSON object on one line, for jq/pandas rather than eyes:
+///   {"pc":49152,"opcode":76,"mnemonic":"JMP","operands":[245,197],"addr":null,"a":0,"x":0,
+///    "y":0,"p":36,"sp":253,"cycles":7,"scanline":0,"dot":21}
+/// `addr` is the address of the data the instruction reads or writes, `dot`/`scanline` the PPU
+/// position. Numbers are decimal, as JSON has no hex.
+pub fn trace_json<B: CpuBus + ?Sized>(cpu: &mut CPU<B>) -> String {
+    let pc = cpu.program_counter;
+    let code = cpu.mem_read(pc);
+    let ops = opscode::lookup(code).unwrap();
+    let operands: Vec<String> = (1..ops.len as u16)
+        .map(|idx| cpu.mem_read(pc.wrapping_add(idx)).to_string())
+        .collect();
+    let addr = match effective_addr(cpu, ops) {
+        Some(addr) => addr.to_string(),
+        None => String::from("null"),
+    };
+    let bus_trace = cpu.bus.trace();
+    format!(
+        "{{\"pc\":{},\"opcode\":{},\"mnemonic\":\"{}\",\"operands\":[{}],\"addr\":{},\
+         \"a\":{},\"x\":{},\"y\":{},\"p\":{},\"sp\":{},\"cycles\":{},\"scanline\":{},\"dot\":{}}}",
+        pc,
+        code,
+        ops.mnemonic,
+        operands.join(","),
+        addr,
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        cpu.flags.bits(),
+        cpu.stack_pointer,
+        bus_trace.cpu_cycles,
+        bus_trace.ppu_scanline,
+        bus_trace.ppu_cycles
+    )
+}
+
+/// Line format of the instruction trace
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TraceFormat {
+    /// nestest.log like, see `trace`
+    Text,
+    /// JSON lines, see `trace_json`
+    Json,
+}
+
+impl TraceFormat {
+    pub fn line<B: CpuBus + ?Sized>(&self, cpu: &mut CPU<B>) -> String {
+        match self {
+            TraceFormat::Text => trace(cpu),
+            TraceFormat::Json => trace_json(cpu),
+        }
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TraceFormat, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(TraceFormat::Text),
+            "json" => Ok(TraceFormat::Json),
+            _ => Err(format!(
+                "unknown trace format '{}', expected text or json",
+                s
+            )),
+        }
+    }
+}
+
+/// `trace` line followed by symbol names of the current pc and of the address the instruction refers to
+#[cfg(feature = "std")]
+pub fn trace_with_symbols<B: CpuBus + ?Sized>(cpu: &mut CPU<B>, symbols: &Symbols) -> String {
+    let line = trace(cpu);
+
+    let pc = cpu.program_counter;
+    let ops = opscode::lookup(cpu.mem_read(pc)).unwrap();
+    let operand_addr = match (ops.len, &ops.mode) {
+        // JMP/JSR (pointer address for indirect JMP)
+        (3, AddressingMode::NoneAddressing) => Some(cpu.mem_read_u16(pc + 1)),
+        // branches
+        (2, AddressingMode::NoneAddressing) => {
+            Some((pc + 2).wrapping_add((cpu.mem_read(pc + 1) as i8) as u16))
+        }
+        _ => effective_addr(cpu, ops),
+    };
+
+    let mut names = vec![];
+    if let Some(name) = symbols.name(pc) {
+        names.push(format!("{}:", name));
+    }
+    if let Some(name) = operand_addr.and_then(|addr| symbols.name(addr)) {
+        names.push(name.to_string());
+    }
+    if names.is_empty() {
+        line
+    } else {
+        format!("{} ; {}", line, names.join(" "))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -195,6 +329,97 @@ mod test {
         ); //zero flag
     }
 
+    #[test]
+    fn test_trace_with_symbols() {
+        let mut mem = MockBus::new();
+        // JSR $0070
+        mem.space[100] = 0x20;
+        mem.space[101] = 0x70;
+        mem.space[102] = 0x00;
+        // STA $2000
+        mem.space[0x70] = 0x8d;
+        mem.space[0x71] = 0x00;
+        mem.space[0x72] = 0x20;
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.program_counter = 0x64;
+
+        let mut symbols = Symbols::new();
+        symbols.insert(0x64, "reset_handler");
+        symbols.insert(0x70, "init_ppu");
+        symbols.insert(0x2000, "PPU_CTRL");
+
+        let mut result: Vec<String> = vec![];
+        cpu.interpret_fn(0x73, |cpu| {
+            result.push(trace_with_symbols(cpu, &symbols));
+        });
+        assert!(result[0].ends_with(" ; reset_handler: init_ppu"));
+        assert!(result[1].ends_with(" ; init_ppu: PPU_CTRL"));
+    }
+
+    #[test]
+    fn test_next_mem_access() {
+        let mut mem = MockBus::new();
+        // STA $0200,X
+        mem.space[100] = 0x9d;
+        mem.space[101] = 0x00;
+        mem.space[102] = 0x02;
+        // INC $10
+        mem.space[103] = 0xe6;
+        mem.space[104] = 0x10;
+        // LDA #$01
+        mem.space[105] = 0xa9;
+        mem.space[106] = 0x01;
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.program_counter = 0x64;
+        cpu.register_x = 5;
+
+        let mut result = vec![];
+        cpu.interpret_fn(0x64 + 7, |cpu| {
+            result.push(next_mem_access(cpu));
+        });
+        assert_eq!(
+            result,
+            vec![
+                Some(MemAccess {
+                    addr: 0x0205,
+                    kind: MemAccessKind::Write
+                }),
+                Some(MemAccess {
+                    addr: 0x10,
+                    kind: MemAccessKind::ReadWrite
+                }),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trace_json() {
+        let mut mem = MockBus::new();
+        // STA $0200,X
+        mem.space[100] = 0x9d;
+        mem.space[101] = 0x00;
+        mem.space[102] = 0x02;
+        // DEX
+        mem.space[103] = 0xca;
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.program_counter = 0x64;
+        cpu.register_x = 5;
+
+        let mut result: Vec<String> = vec![];
+        cpu.interpret_fn(0x64 + 4, |cpu| {
+            result.push(TraceFormat::Json.line(cpu));
+        });
+        assert_eq!(
+            result[0],
+            "{\"pc\":100,\"opcode\":157,\"mnemonic\":\"STA\",\"operands\":[0,2],\"addr\":517,\
+             \"a\":0,\"x\":5,\"y\":0,\"p\":36,\"sp\":253,\"cycles\":0,\"scanline\":0,\"dot\":0}"
+        );
+        assert!(result[1].contains("\"mnemonic\":\"DEX\",\"operands\":[],\"addr\":null"));
+        assert_eq!("json".parse::<TraceFormat>(), Ok(TraceFormat::Json));
+        assert!("xml".parse::<TraceFormat>().is_err());
+    }
+
     #[test]
     fn test_format_mem_access() {
         let mut mem = MockBus::new();
@@ -2,8 +2,8 @@
 // http://www.6502.org/tutorials/6502opcodes.html
 //
 use crate::cpu::mem::AddressingMode;
-use std::collections::HashMap;
 
+#[derive(Clone, Copy)]
 pub struct OpsCode {
     pub code: u8,
     pub mnemonic: &'static str,
@@ -13,19 +13,44 @@ pub struct OpsCode {
 }
 
 impl OpsCode {
-    fn new(code: u8, mnemonic: &'static str, len: u8, cycles: u8, mode: AddressingMode) -> Self {
+    const fn new(
+        code: u8,
+        mnemonic: &'static str,
+        len: u8,
+        cycles: u8,
+        mode: AddressingMode,
+    ) -> Self {
         OpsCode {
-            code: code,
-            mnemonic: mnemonic,
-            len: len,
-            cycles: cycles,
-            mode: mode,
+            code,
+            mnemonic,
+            len,
+            cycles,
+            mode,
         }
     }
 }
 
-lazy_static! {
-   pub static ref CPU_OPS_CODES: Vec<OpsCode> = vec![
+/// Opcode metadata; a direct array index, no hashing on the hot path
+pub fn lookup(code: u8) -> Option<&'static OpsCode> {
+    OPSCODES[code as usize].as_ref()
+}
+
+// indexed by the opcode byte; built at compile time
+pub static OPSCODES: [Option<OpsCode>; 256] = table(CPU_OPS_CODES);
+
+const fn table(ops: &[OpsCode]) -> [Option<OpsCode>; 256] {
+    let mut table = [None; 256];
+    let mut i = 0;
+    while i < ops.len() {
+        // later entries win, same as inserting into a map
+        table[ops[i].code as usize] = Some(ops[i]);
+        i += 1;
+    }
+    table
+}
+
+#[rustfmt::skip]
+pub const CPU_OPS_CODES: &[OpsCode] = &[
        OpsCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing),
        OpsCode::new(0xea, "NOP", 1, 2, AddressingMode::NoneAddressing),
 
@@ -186,12 +211,12 @@ lazy_static! {
         OpsCode::new(0xd4, "*NOP", 2, 4, AddressingMode::ZeroPage_X),
         OpsCode::new(0xf4, "*NOP", 2, 4, AddressingMode::ZeroPage_X),
         OpsCode::new(0x0c, "*NOP", 3, 4, AddressingMode::Absolute),
-        OpsCode::new(0x1c, "*NOP", 3, 4 /*or 5*/, AddressingMode::Absolute_X_PageCross),
-        OpsCode::new(0x3c, "*NOP", 3, 4 /*or 5*/, AddressingMode::Absolute_X_PageCross),
-        OpsCode::new(0x5c, "*NOP", 3, 4 /*or 5*/, AddressingMode::Absolute_X_PageCross),
-        OpsCode::new(0x7c, "*NOP", 3, 4 /*or 5*/, AddressingMode::Absolute_X_PageCross),
-        OpsCode::new(0xdc, "*NOP", 3, 4 /* or 5*/, AddressingMode::Absolute_X_PageCross),
-        OpsCode::new(0xfc, "*NOP", 3, 4 /* or 5*/, AddressingMode::Absolute_X_PageCross),
+        OpsCode::new(0x1c, "*NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X_PageCross),
+        OpsCode::new(0x3c, "*NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X_PageCross),
+        OpsCode::new(0x5c, "*NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X_PageCross),
+        OpsCode::new(0x7c, "*NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X_PageCross),
+        OpsCode::new(0xdc, "*NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X_PageCross),
+        OpsCode::new(0xfc, "*NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X_PageCross),
 
         OpsCode::new(0x67, "*RRA", 2, 5, AddressingMode::ZeroPage),
         OpsCode::new(0x77, "*RRA", 2, 6, AddressingMode::ZeroPage_X),
@@ -231,22 +256,25 @@ lazy_static! {
         // OpsCode::new(0xea, "NOP", 1,2, AddressingMode::NoneAddressing),
         OpsCode::new(0xfa, "*NOP", 1,2, AddressingMode::NoneAddressing),
 
-        OpsCode::new(0xab, "*LXA", 2, 3, AddressingMode::Immediate), //todo: highly unstable and not used
+        // no page cross penalty for the unofficial read-modify-write and store opcodes, same as
+        // for the official ones: they always take the extra cycle. LAX and LAS reads do get it
+        // http://www.oxyron.de/html/opcodes02.html
+        OpsCode::new(0xab, "*LXA", 2, 2, AddressingMode::Immediate), //todo: highly unstable and not used
         //http://visual6502.org/wiki/index.php?title=6502_Opcode_8B_%28XAA,_ANE%29
-        OpsCode::new(0x8b, "*XAA", 2, 3, AddressingMode::Immediate), //todo: highly unstable and not used
-        OpsCode::new(0xbb, "*LAS", 3, 2, AddressingMode::Absolute_Y), //todo: highly unstable and not used
-        OpsCode::new(0x9b, "*TAS", 3, 2, AddressingMode::Absolute_Y), //todo: highly unstable and not used
-        OpsCode::new(0x93, "*AHX", 2, /* guess */ 8, AddressingMode::Indirect_Y), //todo: highly unstable and not used
-        OpsCode::new(0x9f, "*AHX", 3, /* guess */ 4/* or 5*/, AddressingMode::Absolute_Y_PageCross), //todo: highly unstable and not used
-        OpsCode::new(0x9e, "*SHX", 3, /* guess */ 4/* or 5*/, AddressingMode::Absolute_Y_PageCross), //todo: highly unstable and not used
-        OpsCode::new(0x9c, "*SHY", 3, /* guess */ 4/* or 5*/, AddressingMode::Absolute_X_PageCross), //todo: highly unstable and not used
+        OpsCode::new(0x8b, "*XAA", 2, 2, AddressingMode::Immediate), //todo: highly unstable and not used
+        OpsCode::new(0xbb, "*LAS", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y_PageCross), //todo: highly unstable and not used
+        OpsCode::new(0x9b, "*TAS", 3, 5, AddressingMode::Absolute_Y), //todo: highly unstable and not used
+        OpsCode::new(0x93, "*AHX", 2, 6, AddressingMode::Indirect_Y), //todo: highly unstable and not used
+        OpsCode::new(0x9f, "*AHX", 3, 5, AddressingMode::Absolute_Y), //todo: highly unstable and not used
+        OpsCode::new(0x9e, "*SHX", 3, 5, AddressingMode::Absolute_Y), //todo: highly unstable and not used
+        OpsCode::new(0x9c, "*SHY", 3, 5, AddressingMode::Absolute_X), //todo: highly unstable and not used
 
         OpsCode::new(0xa7, "*LAX", 2, 3, AddressingMode::ZeroPage),
         OpsCode::new(0xb7, "*LAX", 2, 4, AddressingMode::ZeroPage_Y),
         OpsCode::new(0xaf, "*LAX", 3, 4, AddressingMode::Absolute),
-        OpsCode::new(0xbf, "*LAX", 3, 4, AddressingMode::Absolute_Y_PageCross),
+        OpsCode::new(0xbf, "*LAX", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y_PageCross),
         OpsCode::new(0xa3, "*LAX", 2, 6, AddressingMode::Indirect_X),
-        OpsCode::new(0xb3, "*LAX", 2, 5, AddressingMode::Indirect_Y_PageCross),
+        OpsCode::new(0xb3, "*LAX", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y_PageCross),
 
         OpsCode::new(0x87, "*SAX", 2, 3, AddressingMode::ZeroPage),
         OpsCode::new(0x97, "*SAX", 2, 4, AddressingMode::ZeroPage_Y),
@@ -359,14 +387,17 @@ lazy_static! {
        OpsCode::new(0x68, "PLA", 1, 4, AddressingMode::NoneAddressing),
        OpsCode::new(0x08, "PHP", 1, 3, AddressingMode::NoneAddressing),
        OpsCode::new(0x28, "PLP", 1, 4, AddressingMode::NoneAddressing),
-   ];
+];
 
-   pub static ref OPSCODES_MAP: HashMap<u8, &'static OpsCode> = {
-       let mut map = HashMap::new();
-       for cpuop in &*CPU_OPS_CODES {
-           map.insert(cpuop.code, cpuop);
-       }
-       map
-   };
+#[cfg(test)]
+mod test {
+    use super::*;
 
+    #[test]
+    fn test_table_is_indexed_by_code() {
+        for code in 0..=0xffu8 {
+            assert_eq!(lookup(code).unwrap().code, code);
+        }
+        assert_eq!(lookup(0xa9).unwrap().mnemonic, "LDA");
+    }
 }
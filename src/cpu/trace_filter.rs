@@ -0,0 +1,141 @@
+// Filters for `trace` based logging: full traces of a game are gigabytes,
+// when hunting a specific issue only a small part of them is interesting.
+use crate::bus::CpuBus;
+use crate::cpu::cpu::CPU;
+use crate::cpu::next_mem_access;
+use crate::cpu::opscode;
+use std::ops::RangeInclusive;
+
+// PPU registers $2000-$2007 and their mirrors
+const PPU_REGISTERS: RangeInclusive<u16> = 0x2000..=0x3fff;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    pub pc_range: Option<RangeInclusive<u16>>,
+    // empty - any instruction. Unofficial opcodes are matched without '*' prefix
+    pub mnemonics: Vec<String>,
+    pub ppu_registers_only: bool,
+}
+
+impl TraceFilter {
+    pub fn new() -> Self {
+        TraceFilter::default()
+    }
+
+    /// "8000-80ff" or a single address "8057"
+    pub fn parse_pc_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+        let parse = |addr: &str| {
+            u16::from_str_radix(addr.trim().trim_start_matches('$'), 16)
+                .map_err(|_| format!("bad address '{}'", addr))
+        };
+        let range = match s.find('-') {
+            Some(idx) => parse(&s[..idx])?..=parse(&s[idx + 1..])?,
+            None => parse(s)?..=parse(s)?,
+        };
+        if range.is_empty() {
+            return Err(format!("bad range '{}'", s));
+        }
+        Ok(range)
+    }
+
+    /// "STA,LDA,inc"
+    pub fn parse_mnemonics(s: &str) -> Vec<String> {
+        s.split(',')
+            .map(|m| m.trim().to_ascii_uppercase())
+            .filter(|m| !m.is_empty())
+            .collect()
+    }
+
+    /// Has to be called before the instruction at pc is executed, same as `trace`
+    pub fn matches<B: CpuBus + ?Sized>(&self, cpu: &mut CPU<B>) -> bool {
+        let pc = cpu.program_counter;
+        if let Some(range) = &self.pc_range {
+            if !range.contains(&pc) {
+                return false;
+            }
+        }
+
+        if !self.mnemonics.is_empty() {
+            let code = cpu.bus.read(pc);
+            let mnemonic = match opscode::lookup(code) {
+                Some(ops) => ops.mnemonic.trim_start_matches('*'),
+                None => return false,
+            };
+            if !self.mnemonics.iter().any(|m| m == mnemonic) {
+                return false;
+            }
+        }
+
+        if self.ppu_registers_only {
+            match next_mem_access(cpu) {
+                Some(access) if PPU_REGISTERS.contains(&access.addr) => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::MockBus;
+
+    #[test]
+    fn test_filters() {
+        let mut mem = MockBus::new();
+        // LDA #$80; STA $2000; STA $0200; LDA $2002; INX
+        let program = CPU::transform("a9 80 8d 00 20 8d 00 02 ad 02 20 e8");
+        mem.space[0x600..0x600 + program.len()].copy_from_slice(&program);
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.program_counter = 0x600;
+
+        let by_pc = TraceFilter {
+            pc_range: Some(TraceFilter::parse_pc_range("0605-0608").unwrap()),
+            ..TraceFilter::new()
+        };
+        let by_mnemonic = TraceFilter {
+            mnemonics: TraceFilter::parse_mnemonics("sta, INX"),
+            ..TraceFilter::new()
+        };
+        let ppu = TraceFilter {
+            ppu_registers_only: true,
+            ..TraceFilter::new()
+        };
+        let ppu_writes = TraceFilter {
+            mnemonics: vec!["STA".to_string()],
+            ppu_registers_only: true,
+            ..TraceFilter::new()
+        };
+
+        let mut result: Vec<(u16, bool, bool, bool, bool)> = vec![];
+        cpu.interpret_fn(0x60c, |cpu| {
+            let pc = cpu.program_counter;
+            result.push((
+                pc,
+                by_pc.matches(cpu),
+                by_mnemonic.matches(cpu),
+                ppu.matches(cpu),
+                ppu_writes.matches(cpu),
+            ));
+        });
+        assert_eq!(
+            result,
+            vec![
+                (0x600, false, false, false, false),
+                (0x602, false, true, true, true),
+                (0x605, true, true, false, false),
+                (0x608, true, false, true, false),
+                (0x60b, false, true, false, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_pc_range() {
+        assert_eq!(TraceFilter::parse_pc_range("8000-80ff"), Ok(0x8000..=0x80ff));
+        assert_eq!(TraceFilter::parse_pc_range("$c000"), Ok(0xc000..=0xc000));
+        assert!(TraceFilter::parse_pc_range("80ff-8000").is_err());
+        assert!(TraceFilter::parse_pc_range("zz").is_err());
+    }
+}
@@ -0,0 +1,103 @@
+// Output side of `trace` based logging. A trace is a line per instruction (millions of
+// lines per second of emulated time), flushing each of them is what makes traced runs slow.
+use std::io::{self, BufWriter, Write};
+
+// a crash loses at most this many lines of the log
+const FLUSH_EVERY_LINES: usize = 4096;
+
+pub struct TraceWriter<W: Write> {
+    out: BufWriter<W>,
+    enabled: bool,
+    flush_every: usize,
+    pending: usize,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W) -> Self {
+        TraceWriter {
+            out: BufWriter::new(out),
+            enabled: true,
+            flush_every: FLUSH_EVERY_LINES,
+            pending: 0,
+        }
+    }
+
+    pub fn flush_every(mut self, lines: usize) -> Self {
+        self.flush_every = lines.max(1);
+        self
+    }
+
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Turns the trace on/off at runtime, returns the new state.
+    /// Whatever was logged before switching off gets flushed.
+    pub fn toggle(&mut self) -> io::Result<bool> {
+        self.set_enabled(!self.enabled)?;
+        Ok(self.enabled)
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) -> io::Result<()> {
+        if self.enabled && !enabled {
+            self.flush()?;
+        }
+        self.enabled = enabled;
+        Ok(())
+    }
+
+    /// Does nothing while the trace is off. Callers check `is_enabled` first
+    /// to skip formatting the line altogether.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        self.out.write_all(line.as_bytes())?;
+        self.out.write_all(b"\n")?;
+        self.pending += 1;
+        if self.pending >= self.flush_every {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.pending = 0;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_flushes_periodically() {
+        let mut writer = TraceWriter::new(Vec::new()).flush_every(2);
+        writer.write_line("C000  4C F5 C5  JMP $C5F5").unwrap();
+        assert!(writer.out.get_ref().is_empty());
+
+        writer.write_line("C5F5  A2 00     LDX #$00").unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(writer.out.get_ref()),
+            "C000  4C F5 C5  JMP $C5F5\nC5F5  A2 00     LDX #$00\n"
+        );
+    }
+
+    #[test]
+    fn test_toggle() {
+        let mut writer = TraceWriter::new(Vec::new()).enabled(false);
+        writer.write_line("skipped").unwrap();
+        assert!(writer.toggle().unwrap());
+        writer.write_line("logged").unwrap();
+        assert!(!writer.toggle().unwrap());
+        writer.write_line("skipped").unwrap();
+
+        assert_eq!(String::from_utf8_lossy(writer.out.get_ref()), "logged\n");
+    }
+}
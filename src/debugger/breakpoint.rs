@@ -0,0 +1,198 @@
+use crate::debugger::call_stack::StackMismatch;
+use crate::symbols::Symbols;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+// PPU registers $2000-$2007 are mirrored every 8 bytes up to $3FFF
+pub(super) fn ppu_register(addr: u16) -> Option<u16> {
+    match addr {
+        0x2000..=0x3fff => Some(addr & 0b10000000000111),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Breakpoint {
+    Pc(u16),
+    Read(RangeInclusive<u16>),
+    Write(RangeInclusive<u16>),
+    /// any access (read or write) to a PPU register $2000-$2007 (mirrors included)
+    PpuRegister(u16),
+    Scanline(usize),
+    /// RTS/RTI returning to an address different from the shadow call stack
+    StackMismatch,
+}
+
+// same format as accepted by `Breakpoint::parse`
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Pc(pc) => write!(f, "pc:${:04X}", pc),
+            Breakpoint::Read(range) => {
+                write!(f, "read:${:04X}-${:04X}", range.start(), range.end())
+            }
+            Breakpoint::Write(range) => {
+                write!(f, "write:${:04X}-${:04X}", range.start(), range.end())
+            }
+            Breakpoint::PpuRegister(register) => write!(f, "ppu:${:04X}", register),
+            Breakpoint::Scanline(scanline) => write!(f, "scanline:{}", scanline),
+            Breakpoint::StackMismatch => write!(f, "stack"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BreakReason {
+    Pc(u16),
+    Read { pc: u16, addr: u16 },
+    Write { pc: u16, addr: u16 },
+    PpuRegister { pc: u16, register: u16 },
+    Scanline { pc: u16, scanline: usize },
+    Step(u16),
+    StackMismatch(StackMismatch),
+}
+
+impl fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BreakReason::Pc(pc) => write!(f, "breakpoint at ${:04X}", pc),
+            BreakReason::Read { pc, addr } => {
+                write!(f, "read from ${:04X} at ${:04X}", addr, pc)
+            }
+            BreakReason::Write { pc, addr } => write!(f, "write to ${:04X} at ${:04X}", addr, pc),
+            BreakReason::PpuRegister { pc, register } => {
+                write!(f, "access to PPU register ${:04X} at ${:04X}", register, pc)
+            }
+            BreakReason::Scanline { pc, scanline } => {
+                write!(f, "scanline {} reached at ${:04X}", scanline, pc)
+            }
+            BreakReason::Step(pc) => write!(f, "stopped at ${:04X}", pc),
+            BreakReason::StackMismatch(mismatch) => write!(f, "{}", mismatch),
+        }
+    }
+}
+
+// a symbol name takes priority over hex (e.g. label "beef"), unless the address is prefixed with '$'
+pub(super) fn parse_addr(s: &str, symbols: &Symbols) -> Result<u16, String> {
+    if let Some(addr) = symbols.addr(s) {
+        return Ok(addr);
+    }
+    u16::from_str_radix(s.trim_start_matches('$'), 16).map_err(|_| format!("bad address '{}'", s))
+}
+
+fn parse_range(s: &str, symbols: &Symbols) -> Result<RangeInclusive<u16>, String> {
+    match s.find('-') {
+        Some(idx) => {
+            let from = parse_addr(&s[..idx], symbols)?;
+            let to = parse_addr(&s[idx + 1..], symbols)?;
+            if from > to {
+                return Err(format!("bad range '{}'", s));
+            }
+            Ok(from..=to)
+        }
+        None => {
+            let addr = parse_addr(s, symbols)?;
+            Ok(addr..=addr)
+        }
+    }
+}
+
+impl Breakpoint {
+    /// Breakpoint specs:
+    ///   8057 | pc:8057 | reset_handler
+    ///   read:0200 | read:0200-02ff
+    ///   write:0200 | write:0200-02ff
+    ///   ppu:2002
+    ///   scanline:241
+    ///   stack (mismatched RTS/RTI)
+    /// addresses can be given as names from the symbol file
+    pub fn parse(spec: &str, symbols: &Symbols) -> Result<Breakpoint, String> {
+        let spec = spec.trim();
+        if spec == "stack" {
+            return Ok(Breakpoint::StackMismatch);
+        }
+        let (kind, value) = match spec.find(':') {
+            Some(idx) => (&spec[..idx], &spec[idx + 1..]),
+            None => ("pc", spec),
+        };
+        match kind {
+            "pc" => Ok(Breakpoint::Pc(parse_addr(value, symbols)?)),
+            "read" => Ok(Breakpoint::Read(parse_range(value, symbols)?)),
+            "write" => Ok(Breakpoint::Write(parse_range(value, symbols)?)),
+            "ppu" => {
+                let register = ppu_register(parse_addr(value, symbols)?)
+                    .ok_or_else(|| format!("${} is not a PPU register", value))?;
+                Ok(Breakpoint::PpuRegister(register))
+            }
+            "scanline" => value
+                .parse::<usize>()
+                .map(Breakpoint::Scanline)
+                .map_err(|_| format!("bad scanline '{}'", value)),
+            _ => Err(format!("unknown breakpoint type '{}'", kind)),
+        }
+    }
+}
+
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        Breakpoint::parse(spec, &Symbols::new())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("8057".parse(), Ok(Breakpoint::Pc(0x8057)));
+        assert_eq!("pc:$C000".parse(), Ok(Breakpoint::Pc(0xc000)));
+        assert_eq!("read:0200-02ff".parse(), Ok(Breakpoint::Read(0x200..=0x2ff)));
+        assert_eq!("write:10".parse(), Ok(Breakpoint::Write(0x10..=0x10)));
+        assert_eq!("ppu:3456".parse(), Ok(Breakpoint::PpuRegister(0x2006)));
+        assert_eq!("scanline:241".parse(), Ok(Breakpoint::Scanline(241)));
+        assert_eq!("stack".parse(), Ok(Breakpoint::StackMismatch));
+
+        assert!("ppu:0200".parse::<Breakpoint>().is_err());
+        assert!("read:02ff-0200".parse::<Breakpoint>().is_err());
+        assert!("jump:0200".parse::<Breakpoint>().is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        for spec in [
+            "pc:$8057",
+            "read:$0200-$02FF",
+            "ppu:$2002",
+            "scanline:241",
+            "stack",
+        ] {
+            let bp: Breakpoint = spec.parse().unwrap();
+            assert_eq!(bp.to_string(), spec);
+        }
+    }
+
+    #[test]
+    fn test_parse_with_symbols() {
+        let mut symbols = Symbols::new();
+        symbols.insert(0xc000, "reset_handler");
+        symbols.insert(0x0300, "beef");
+        symbols.insert(0x2002, "PPU_STATUS");
+
+        assert_eq!(Breakpoint::parse("reset_handler", &symbols), Ok(Breakpoint::Pc(0xc000)));
+        assert_eq!(Breakpoint::parse("beef", &symbols), Ok(Breakpoint::Pc(0x0300)));
+        assert_eq!(Breakpoint::parse("$beef", &symbols), Ok(Breakpoint::Pc(0xbeef)));
+        assert_eq!(
+            Breakpoint::parse("write:beef-03ff", &symbols),
+            Ok(Breakpoint::Write(0x300..=0x3ff))
+        );
+        assert_eq!(
+            Breakpoint::parse("ppu:PPU_STATUS", &symbols),
+            Ok(Breakpoint::PpuRegister(0x2002))
+        );
+        assert!(Breakpoint::parse("nmi_handler", &symbols).is_err());
+    }
+}
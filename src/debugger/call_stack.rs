@@ -0,0 +1,309 @@
+// Shadow call stack built from JSR/RTS, BRK/NMI and RTI.
+//
+// NMI is handled by the cpu together with the first instruction of the handler, so it's detected
+// after the fact: the address of the instruction that was about to run is found on the stack.
+// Returns are matched by stack pointer. An RTS with the stack pointer below the current frame is
+// not a return (e.g. "push address-1; RTS" jump tables). Returning to an unexpected address
+// (overwritten return address, PLA PLA RTS skipping frames) is reported as a mismatch.
+use crate::cpu::cpu::CPU;
+use crate::symbols::Symbols;
+use std::fmt;
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+const BRK: u8 = 0x00;
+
+const STACK: u16 = 0x0100;
+const NMI_VECTOR: u16 = 0xfffa;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CallKind {
+    Jsr,
+    Brk,
+    Nmi,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct CallFrame {
+    pub kind: CallKind,
+    // subroutine or interrupt handler address
+    pub entry: u16,
+    // JSR/BRK address or the instruction interrupted by NMI
+    pub call_site: u16,
+    // stack pointer before the call
+    pub stack_pointer: u8,
+}
+
+impl CallFrame {
+    pub fn return_addr(&self) -> u16 {
+        match self.kind {
+            CallKind::Jsr => self.call_site.wrapping_add(3),
+            CallKind::Brk => self.call_site.wrapping_add(2),
+            CallKind::Nmi => self.call_site,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct StackMismatch {
+    // RTS/RTI address
+    pub pc: u16,
+    pub expected: u16,
+    pub actual: u16,
+}
+
+impl fmt::Display for StackMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "mismatched return at ${:04X}: expected ${:04X}, returned to ${:04X}",
+            self.pc, self.expected, self.actual
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Executed {
+    pc: u16,
+    stack_pointer: u8,
+    opcode: u8,
+}
+
+pub struct CallStack {
+    frames: Vec<CallFrame>,
+    previous: Option<Executed>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        CallStack {
+            frames: vec![],
+            previous: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = CallStack::new();
+    }
+
+    /// Has to be called before every instruction (e.g. from `CPU::interpret_fn` callback)
+    pub fn observe(&mut self, cpu: &mut CPU) -> Option<StackMismatch> {
+        let current = Executed {
+            pc: cpu.program_counter,
+            stack_pointer: cpu.stack_pointer(),
+            opcode: cpu.bus.read(cpu.program_counter),
+        };
+        // called twice for the same instruction (e.g. resuming after a breakpoint)
+        let previous = match self.previous.replace(current) {
+            Some(previous) if previous != current => previous,
+            _ => return None,
+        };
+
+        if is_interrupted(cpu, &previous) {
+            self.frames.push(CallFrame {
+                kind: CallKind::Nmi,
+                entry: read_u16(cpu, NMI_VECTOR),
+                call_site: previous.pc,
+                stack_pointer: previous.stack_pointer,
+            });
+            return None;
+        }
+
+        let kind = match previous.opcode {
+            JSR => CallKind::Jsr,
+            // BRK with interrupts disabled is a 2 byte NOP
+            BRK if previous.stack_pointer.wrapping_sub(current.stack_pointer) == 3 => {
+                CallKind::Brk
+            }
+            RTS | RTI => return self.pop(previous.pc, current),
+            _ => return None,
+        };
+        self.frames.push(CallFrame {
+            kind,
+            entry: current.pc,
+            call_site: previous.pc,
+            stack_pointer: previous.stack_pointer,
+        });
+        None
+    }
+
+    fn pop(&mut self, return_pc: u16, current: Executed) -> Option<StackMismatch> {
+        // nested frames have lower stack pointers
+        let mut returned = None;
+        while let Some(frame) = self.frames.last() {
+            if frame.stack_pointer > current.stack_pointer {
+                break;
+            }
+            returned = self.frames.pop();
+        }
+        match returned {
+            Some(frame) if frame.return_addr() != current.pc => Some(StackMismatch {
+                pc: return_pc,
+                expected: frame.return_addr(),
+                actual: current.pc,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Innermost frame first
+    pub fn backtrace(&self) -> Vec<CallFrame> {
+        self.frames.iter().rev().cloned().collect()
+    }
+
+    pub fn format_backtrace(&self, symbols: &Symbols) -> String {
+        let name = |addr: u16| match symbols.name(addr) {
+            Some(name) => name.to_string(),
+            None => format!("${:04X}", addr),
+        };
+        let mut out = String::new();
+        for (idx, frame) in self.backtrace().iter().enumerate() {
+            let call = match frame.kind {
+                CallKind::Jsr => "called from",
+                CallKind::Brk => "BRK at",
+                CallKind::Nmi => "NMI at",
+            };
+            out.push_str(&format!(
+                "#{} {} {} {}\n",
+                idx,
+                name(frame.entry),
+                call,
+                name(frame.call_site)
+            ));
+        }
+        out
+    }
+}
+
+impl Default for CallStack {
+    fn default() -> Self {
+        CallStack::new()
+    }
+}
+
+fn read_u16(cpu: &mut CPU, addr: u16) -> u16 {
+    let lo = cpu.bus.read(addr) as u16;
+    let hi = cpu.bus.read(addr.wrapping_add(1)) as u16;
+    hi << 8 | lo
+}
+
+// NMI pushes pc of the instruction that didn't get executed, JSR and BRK push pc+2
+fn is_interrupted(cpu: &mut CPU, previous: &Executed) -> bool {
+    // pc and status are pushed, then the first handler instruction could push/pull a byte
+    let pushed = previous
+        .stack_pointer
+        .wrapping_sub(cpu.stack_pointer());
+    if !(2..=4).contains(&pushed) {
+        return false;
+    }
+    let hi = cpu.bus.read(STACK + previous.stack_pointer as u16);
+    let lo = cpu.bus.read(STACK + previous.stack_pointer.wrapping_sub(1) as u16);
+    (hi as u16) << 8 | lo as u16 == previous.pc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::MockBus;
+
+    fn cpu_with_program(program: &str) -> CPU {
+        let mut mem = MockBus::new();
+        let program = CPU::transform(program);
+        mem.space[0x600..0x600 + program.len()].copy_from_slice(&program);
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.program_counter = 0x600;
+        cpu
+    }
+
+    #[test]
+    fn test_backtrace() {
+        // 0600: JSR outer; BRK
+        // 0604: outer: JSR inner; RTS
+        // 0608: inner: INX; RTS
+        let mut cpu = cpu_with_program("20 04 06 00 20 08 06 60 e8 60");
+        let mut call_stack = CallStack::new();
+        let mut backtraces = vec![];
+        cpu.interpret_fn(0xffff, |cpu| {
+            assert_eq!(call_stack.observe(cpu), None);
+            if cpu.program_counter == 0x608 {
+                backtraces.push(call_stack.backtrace());
+            }
+        });
+
+        assert_eq!(
+            backtraces,
+            vec![vec![
+                CallFrame {
+                    kind: CallKind::Jsr,
+                    entry: 0x608,
+                    call_site: 0x604,
+                    stack_pointer: 0xfb,
+                },
+                CallFrame {
+                    kind: CallKind::Jsr,
+                    entry: 0x604,
+                    call_site: 0x600,
+                    stack_pointer: 0xfd,
+                },
+            ]]
+        );
+        assert!(call_stack.backtrace().is_empty());
+
+        let mut symbols = Symbols::new();
+        symbols.insert(0x604, "outer");
+        call_stack.frames = backtraces[0].iter().rev().cloned().collect();
+        assert_eq!(
+            call_stack.format_backtrace(&symbols),
+            "#0 $0608 called from outer\n#1 outer called from $0600\n"
+        );
+    }
+
+    #[test]
+    fn test_mismatched_rts() {
+        // 0600: JSR sub; BRK
+        // 0604: sub: PLA; PLA; LDA #$07; PHA; LDA #$05; PHA; RTS  (returns to $0606)
+        let mut cpu = cpu_with_program("20 04 06 00 68 68 a9 07 48 a9 05 48 60");
+        let mut call_stack = CallStack::new();
+        let mut mismatches = vec![];
+        cpu.interpret_fn(0xffff, |cpu| {
+            if let Some(mismatch) = call_stack.observe(cpu) {
+                mismatches.push(mismatch);
+            }
+        });
+        assert_eq!(
+            mismatches,
+            vec![StackMismatch {
+                pc: 0x60c,
+                expected: 0x603,
+                actual: 0x706,
+            }]
+        );
+        assert!(call_stack.backtrace().is_empty());
+    }
+
+    #[test]
+    fn test_nmi() {
+        let mut mem = MockBus::new();
+        // 0600: INX; BRK
+        // 0700: nmi: PHA; PLA; RTI
+        mem.space[0x600..0x602].copy_from_slice(&CPU::transform("e8 00"));
+        mem.space[0x700..0x703].copy_from_slice(&CPU::transform("48 68 40"));
+        mem.space[0xfffa] = 0x00;
+        mem.space[0xfffb] = 0x07;
+        mem.nmi_interrupt = Some(1);
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.program_counter = 0x600;
+
+        let mut call_stack = CallStack::new();
+        let mut backtraces = vec![];
+        cpu.interpret_fn(0xffff, |cpu| {
+            assert_eq!(call_stack.observe(cpu), None);
+            backtraces.push((cpu.program_counter, call_stack.backtrace().len()));
+        });
+        assert_eq!(
+            backtraces,
+            vec![(0x600, 0), (0x701, 1), (0x702, 1), (0x600, 0), (0x601, 0)]
+        );
+    }
+}
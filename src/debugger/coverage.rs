@@ -0,0 +1,128 @@
+// Opcode coverage: which opcodes (and so mnemonic/addressing mode combinations) were executed
+// during a run. Shows how much of the cpu core a test rom actually exercises.
+use crate::cpu::cpu::CPU;
+use crate::cpu::opscode;
+use std::collections::BTreeSet;
+
+pub struct Coverage {
+    counts: [usize; 256],
+}
+
+fn is_official(ops: &opscode::OpsCode) -> bool {
+    !ops.mnemonic.starts_with('*')
+}
+
+// page crossing variants differ only in timing
+fn mode_name(ops: &opscode::OpsCode) -> String {
+    format!("{:?}", ops.mode)
+        .trim_end_matches("_PageCross")
+        .to_string()
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage { counts: [0; 256] }
+    }
+
+    pub fn reset(&mut self) {
+        self.counts = [0; 256];
+    }
+
+    /// Has to be called before every instruction (e.g. from `CPU::interpret_fn` callback)
+    pub fn record(&mut self, cpu: &mut CPU) {
+        let code = cpu.bus.read(cpu.program_counter);
+        self.counts[code as usize] += 1;
+    }
+
+    pub fn count(&self, code: u8) -> usize {
+        self.counts[code as usize]
+    }
+
+    pub fn executed(&self) -> Vec<u8> {
+        (0..=0xffu8).filter(|&code| self.count(code) > 0).collect()
+    }
+
+    /// Official opcodes that were never executed
+    pub fn missing(&self) -> Vec<u8> {
+        (0..=0xffu8)
+            .filter(|&code| self.count(code) == 0 && is_official(opscode::lookup(code).unwrap()))
+            .collect()
+    }
+
+    pub fn summary(&self) -> String {
+        let official = |code: &u8| is_official(opscode::lookup(*code).unwrap());
+        let all: Vec<u8> = (0..=0xffu8).collect();
+        let executed = self.executed();
+
+        let combinations = |codes: &[u8]| {
+            codes
+                .iter()
+                .map(|code| (opscode::lookup(*code).unwrap().mnemonic, mode_name(opscode::lookup(*code).unwrap())))
+                .collect::<BTreeSet<(&str, String)>>()
+                .len()
+        };
+
+        let mut out = format!(
+            "opcodes: {}/{} (official: {}/{})\n",
+            executed.len(),
+            all.len(),
+            executed.iter().filter(|code| official(code)).count(),
+            all.iter().filter(|code| official(code)).count()
+        );
+        out.push_str(&format!(
+            "mnemonic/addressing mode combinations: {}/{}\n",
+            combinations(&executed),
+            combinations(&all)
+        ));
+
+        let missing = self.missing();
+        if !missing.is_empty() {
+            out.push_str("not executed:\n");
+            for code in missing {
+                let ops = opscode::lookup(code).unwrap();
+                out.push_str(&format!(
+                    "  {:02X} {} {}\n",
+                    code,
+                    ops.mnemonic,
+                    mode_name(ops)
+                ));
+            }
+        }
+        out
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::MockBus;
+
+    #[test]
+    fn test_coverage() {
+        let mut mem = MockBus::new();
+        // LDA #$01; LDA #$02; STA $10; *NOP; INX
+        let program = CPU::transform("a9 01 a9 02 85 10 1a e8");
+        mem.space[0x600..0x600 + program.len()].copy_from_slice(&program);
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.program_counter = 0x600;
+
+        let mut coverage = Coverage::new();
+        cpu.interpret_fn(0x608, |cpu| coverage.record(cpu));
+
+        assert_eq!(coverage.count(0xa9), 2);
+        assert_eq!(coverage.executed(), vec![0x1a, 0x85, 0xa9, 0xe8]);
+        assert!(!coverage.missing().contains(&0x85));
+        assert!(coverage.missing().contains(&0x8d));
+        assert!(!coverage.missing().contains(&0x3a));
+
+        let summary = coverage.summary();
+        assert!(summary.starts_with("opcodes: 4/256 (official: 3/151)\n"));
+        assert!(summary.contains("  8D STA Absolute\n"));
+    }
+}
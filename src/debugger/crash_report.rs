@@ -0,0 +1,197 @@
+// Execution history ring buffer and an opt-in panic hook that dumps it to a crash report file.
+// Recording formats a trace line per instruction, so it slows emulation down noticeably.
+//
+// `crash_dump` is the machine-readable version for bus/PPU faults (see `EmulatorBuilder::
+// crash_dump`), one JSON object:
+//   {"reason": "...", "cpu": {"pc", "a", "x", "y", "sp", "p", "cycles"},
+//    "ppu": {"ctrl", "mask", "status", "oam_addr", "scanline", "cycle"},
+//    "mapper": {"number", "mirroring", "prg_rom", "chr_rom"}, "bus_access": "Write $2002",
+//    "ram": [...], "prg_ram": [...], "vram": [...], "oam": [...], "palette": [...],
+//    "trace": ["C000  A9 01 ...", ...]}
+use crate::bus::{Bus, CpuBus};
+use crate::cpu::cpu::CPU;
+use crate::cpu::{next_mem_access, trace, MemAccess};
+use crate::dump::{bytes, string};
+use crate::ppu::ppu::NesPPU;
+use crate::rom::Mirroring;
+use std::collections::VecDeque;
+use std::fs;
+use std::panic;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+pub struct ExecutionHistory {
+    lines: VecDeque<String>,
+    capacity: usize,
+    // memory access of the most recent instruction, i.e. the one that was executing during a crash
+    last_access: Option<MemAccess>,
+    cpu_cycles: usize,
+    ppu_cycles: usize,
+    ppu_scanline: usize,
+}
+
+impl ExecutionHistory {
+    pub fn new(capacity: usize) -> Self {
+        ExecutionHistory {
+            lines: VecDeque::with_capacity(capacity),
+            capacity,
+            last_access: None,
+            cpu_cycles: 0,
+            ppu_cycles: 0,
+            ppu_scanline: 0,
+        }
+    }
+
+    /// Has to be called before every instruction (e.g. from `CPU::interpret_fn` callback)
+    pub fn record<B: CpuBus + ?Sized>(&mut self, cpu: &mut CPU<B>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(trace(cpu));
+        self.last_access = next_mem_access(cpu);
+        let bus = cpu.bus.trace();
+        self.cpu_cycles = bus.cpu_cycles;
+        self.ppu_cycles = bus.ppu_cycles;
+        self.ppu_scanline = bus.ppu_scanline;
+    }
+
+    /// oldest first
+    pub fn lines(&self) -> impl Iterator<Item = &String> {
+        self.lines.iter()
+    }
+
+    pub fn report(&self, reason: &str) -> String {
+        let mut report = String::new();
+        report.push_str(&format!("crash: {}\n\n", reason));
+        report.push_str(&format!(
+            "last instruction:\n  {}\n",
+            self.lines.back().map(|l| l.as_str()).unwrap_or("-")
+        ));
+        match self.last_access {
+            Some(access) => report.push_str(&format!(
+                "bus access: {:?} ${:04X}\n",
+                access.kind, access.addr
+            )),
+            None => report.push_str("bus access: -\n"),
+        }
+        report.push_str(&format!(
+            "cpu cycles: {}, ppu scanline: {}, ppu cycle: {}\n\n",
+            self.cpu_cycles, self.ppu_scanline, self.ppu_cycles
+        ));
+        report.push_str(&format!("last {} instructions:\n", self.lines.len()));
+        for line in self.lines.iter() {
+            report.push_str(&format!("  {}\n", line));
+        }
+        report
+    }
+}
+
+/// Registers, memory, mapper state and the recent trace as JSON
+pub fn crash_dump<B: CpuBus + ?Sized>(
+    cpu: &CPU<B>,
+    bus: &Bus<NesPPU>,
+    history: &ExecutionHistory,
+    reason: &str,
+) -> String {
+    let ppu = bus.ppu();
+    let timing = bus.trace();
+    let mirroring = match ppu.mirroring {
+        Mirroring::VERTICAL => "vertical",
+        Mirroring::HORIZONTAL => "horizontal",
+        Mirroring::SINGLE_SCREEN_LOWER => "single screen lower",
+        Mirroring::SINGLE_SCREEN_UPPER => "single screen upper",
+        Mirroring::CUSTOM(_) => "custom",
+    };
+    let bus_access = match history.last_access {
+        Some(access) => string(&format!("{:?} ${:04X}", access.kind, access.addr)),
+        None => String::from("null"),
+    };
+    let trace: Vec<String> = history.lines().map(|line| string(line)).collect();
+    format!(
+        "{{\"reason\":{},\
+         \"cpu\":{{\"pc\":{},\"a\":{},\"x\":{},\"y\":{},\"sp\":{},\"p\":{},\"cycles\":{}}},\
+         \"ppu\":{{\"ctrl\":{},\"mask\":{},\"status\":{},\"oam_addr\":{},\"scanline\":{},\"cycle\":{}}},\
+         \"mapper\":{{\"number\":{},\"mirroring\":\"{}\",\"prg_rom\":{},\"chr_rom\":{}}},\
+         \"bus_access\":{},\"ram\":{},\"prg_ram\":{},\"vram\":{},\"oam\":{},\"palette\":{},\
+         \"trace\":[{}]}}",
+        string(reason),
+        cpu.program_counter,
+        cpu.register_a(),
+        cpu.register_x(),
+        cpu.register_y(),
+        cpu.stack_pointer(),
+        cpu.status(),
+        timing.cpu_cycles,
+        ppu.ctrl.bits(),
+        ppu.mask.bits(),
+        ppu.status.snapshot(),
+        ppu.oam_addr,
+        timing.ppu_scanline,
+        timing.ppu_cycles,
+        bus.rom.mapper,
+        mirroring,
+        bus.rom.prg_rom.len(),
+        bus.rom.chr_rom.len(),
+        bus_access,
+        bytes(bus.ram.iter().copied()),
+        bytes(bus.prg_ram.iter().copied()),
+        bytes(ppu.vram.iter().copied()),
+        bytes(ppu.oam_data.iter().copied()),
+        bytes(ppu.palette_table.iter().copied()),
+        trace.join(",")
+    )
+}
+
+/// Writes a crash report with the execution history to `path` when the emulator panics.
+/// The previous panic hook is still called afterwards.
+pub fn install_panic_hook(history: Arc<Mutex<ExecutionHistory>>, path: PathBuf) {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        // the lock could be poisoned or held by the panicking code itself
+        let history = match history.try_lock() {
+            Ok(history) => Some(history),
+            Err(std::sync::TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+            Err(std::sync::TryLockError::WouldBlock) => None,
+        };
+        if let Some(history) = history {
+            match fs::write(&path, history.report(&info.to_string())) {
+                Ok(_) => eprintln!("crash report is written to {}", path.display()),
+                Err(e) => eprintln!("failed to write crash report {}: {}", path.display(), e),
+            }
+        }
+        previous(info);
+    }));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::MockBus;
+
+    #[test]
+    fn test_history() {
+        let mut mem = MockBus::new();
+        // LDA #$01; STA $2007; INX
+        let program = CPU::transform("a9 01 8d 07 20 e8");
+        mem.space[0x600..0x600 + program.len()].copy_from_slice(&program);
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.program_counter = 0x600;
+
+        let mut history = ExecutionHistory::new(2);
+        cpu.interpret_fn(0x605, |cpu| history.record(cpu));
+
+        let lines: Vec<&String> = history.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("0600  A9 01"));
+        assert!(lines[1].starts_with("0602  8D 07 20  STA $2007"));
+
+        let report = history.report("boom");
+        assert!(report.starts_with("crash: boom\n"));
+        assert!(report.contains("last instruction:\n  0602  8D 07 20  STA $2007"));
+        assert!(report.contains("bus access: Write $2007\n"));
+        assert!(report.contains("last 2 instructions:\n  0600"));
+    }
+}
@@ -0,0 +1,144 @@
+// Comparison of generated trace lines against a reference log (e.g. nestest.log
+// http://www.qmtpro.com/~nes/misc/nestest.log), line by line while the rom is running.
+use std::collections::VecDeque;
+use std::fmt;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+// number of matched lines shown before a mismatch
+const CONTEXT_SIZE: usize = 5;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    // 1-based line number in the reference log
+    pub line: usize,
+    pub expected: Option<String>,
+    pub actual: String,
+    pub context: Vec<String>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "mismatch at line {}:", self.line)?;
+        for line in self.context.iter() {
+            writeln!(f, "           {}", line)?;
+        }
+        match &self.expected {
+            Some(expected) => writeln!(f, "expected:  {}", expected)?,
+            None => writeln!(f, "expected:  <end of log>")?,
+        }
+        write!(f, "actual:    {}", self.actual)
+    }
+}
+
+pub struct GoldenLog {
+    lines: Vec<String>,
+    position: usize,
+    context: VecDeque<String>,
+    // compare only instruction and registers, PPU/CYC columns are ignored
+    ignore_timing: bool,
+}
+
+fn strip_timing(line: &str) -> &str {
+    match line.find(" PPU:") {
+        Some(idx) => &line[..idx],
+        None => line,
+    }
+}
+
+impl GoldenLog {
+    pub fn new(reference: &str) -> Self {
+        GoldenLog {
+            lines: reference.lines().map(|l| l.trim_end().to_string()).collect(),
+            position: 0,
+            context: VecDeque::with_capacity(CONTEXT_SIZE),
+            ignore_timing: false,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load(path: &Path) -> Result<GoldenLog, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Ok(GoldenLog::new(&content))
+    }
+
+    pub fn ignore_timing(mut self, ignore: bool) -> Self {
+        self.ignore_timing = ignore;
+        self
+    }
+
+    /// Compares the next reference line with `actual`
+    pub fn check(&mut self, actual: &str) -> Result<(), Mismatch> {
+        let actual = actual.trim_end();
+        let expected = self.lines.get(self.position);
+        let matches = match expected {
+            Some(expected) if self.ignore_timing => {
+                strip_timing(expected) == strip_timing(actual)
+            }
+            Some(expected) => expected == actual,
+            None => false,
+        };
+        if !matches {
+            return Err(Mismatch {
+                line: self.position + 1,
+                expected: expected.cloned(),
+                actual: actual.to_string(),
+                context: self.context.iter().cloned().collect(),
+            });
+        }
+
+        if self.context.len() == CONTEXT_SIZE {
+            self.context.pop_front();
+        }
+        self.context.push_back(actual.to_string());
+        self.position += 1;
+        Ok(())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.lines.len()
+    }
+
+    pub fn matched_lines(&self) -> usize {
+        self.position
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let mut log = GoldenLog::new("line 1\nline 2  \nline 3\n");
+        assert_eq!(log.check("line 1"), Ok(()));
+        assert_eq!(log.check("line 2"), Ok(()));
+        assert_eq!(
+            log.check("line 4"),
+            Err(Mismatch {
+                line: 3,
+                expected: Some("line 3".to_string()),
+                actual: "line 4".to_string(),
+                context: vec!["line 1".to_string(), "line 2".to_string()],
+            })
+        );
+        assert_eq!(log.matched_lines(), 2);
+        assert_eq!(log.check("line 3"), Ok(()));
+        assert!(log.is_finished());
+        assert_eq!(log.check("line 4").unwrap_err().expected, None);
+    }
+
+    #[test]
+    fn test_ignore_timing() {
+        let reference = "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7";
+        let actual = "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0,  0 CYC:0";
+        assert!(GoldenLog::new(reference).check(actual).is_err());
+        assert!(GoldenLog::new(reference)
+            .ignore_timing(true)
+            .check(actual)
+            .is_ok());
+    }
+}
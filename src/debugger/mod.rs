@@ -0,0 +1,415 @@
+pub mod breakpoint;
+pub mod call_stack;
+pub mod coverage;
+#[cfg(feature = "std")]
+pub mod crash_report;
+pub mod golden_log;
+pub mod monitor;
+pub mod profiler;
+#[cfg(feature = "std")]
+pub mod remote;
+pub mod watch;
+pub mod window;
+
+use crate::cpu::cpu::CPU;
+use crate::cpu::{next_mem_access, MemAccessKind};
+use breakpoint::{ppu_register, BreakReason, Breakpoint};
+use call_stack::{CallFrame, CallStack};
+use watch::Watch;
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StepMode {
+    Into,
+    // JSR is executed as a single instruction
+    Over,
+    // runs till the current subroutine returns
+    Out,
+}
+
+// stepping in progress, works as a temporary breakpoint
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Stepping {
+    Next,
+    Return { pc: u16, stack_pointer: u8 },
+    Rts { stack_pointer: u8 },
+}
+
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watches: Vec<Watch>,
+    call_stack: CallStack,
+    stepping: Option<Stepping>,
+    last_scanline: Option<usize>,
+    // pc of the last hit: the next check at the same pc means "continue"
+    resume_pc: Option<u16>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: vec![],
+            watches: vec![],
+            call_stack: CallStack::new(),
+            stepping: None,
+            last_scanline: None,
+            resume_pc: None,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) -> bool {
+        let before = self.breakpoints.len();
+        self.breakpoints.retain(|b| b != breakpoint);
+        before != self.breakpoints.len()
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_watch(&mut self, watch: Watch) {
+        self.watches.push(watch);
+    }
+
+    pub fn remove_watch(&mut self, source: &str) -> bool {
+        let before = self.watches.len();
+        self.watches.retain(|w| w.source() != source.trim());
+        before != self.watches.len()
+    }
+
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    /// Shadow call stack, innermost frame first
+    pub fn backtrace(&self) -> Vec<CallFrame> {
+        self.call_stack.backtrace()
+    }
+
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    /// Current values of all watches, e.g. to be printed on every step or frame
+    pub fn format_watches(&self, cpu: &mut CPU) -> Vec<String> {
+        self.watches.iter().map(|w| w.format(cpu)).collect()
+    }
+
+    /// Has to be called before every instruction (e.g. from `CPU::interpret_fn` callback).
+    /// Returns the reason if the instruction at PC hits a breakpoint and the run loop should pause.
+    pub fn check(&mut self, cpu: &mut CPU) -> Option<BreakReason> {
+        let pc = cpu.program_counter;
+        let scanline = cpu.bus.trace().ppu_scanline;
+        let scanline_changed = self.last_scanline.is_some_and(|last| last != scanline);
+        self.last_scanline = Some(scanline);
+        let stack_mismatch = self.call_stack.observe(cpu);
+
+        if self.resume_pc.take() == Some(pc) {
+            return None;
+        }
+
+        if let Some(reason) = self.check_stepping(cpu) {
+            self.resume_pc = Some(pc);
+            return Some(reason);
+        }
+
+        if self.breakpoints.is_empty() {
+            return None;
+        }
+
+        let access = next_mem_access(cpu);
+
+        let reason = self.breakpoints.iter().find_map(|bp| match bp {
+            Breakpoint::Pc(addr) if *addr == pc => Some(BreakReason::Pc(pc)),
+            Breakpoint::Scanline(line) if scanline_changed && *line == scanline => {
+                Some(BreakReason::Scanline { pc, scanline })
+            }
+            Breakpoint::Read(range) => match access {
+                Some(a) if a.kind != MemAccessKind::Write && range.contains(&a.addr) => {
+                    Some(BreakReason::Read { pc, addr: a.addr })
+                }
+                _ => None,
+            },
+            Breakpoint::Write(range) => match access {
+                Some(a) if a.kind != MemAccessKind::Read && range.contains(&a.addr) => {
+                    Some(BreakReason::Write { pc, addr: a.addr })
+                }
+                _ => None,
+            },
+            Breakpoint::PpuRegister(register) => match access.and_then(|a| ppu_register(a.addr)) {
+                Some(reg) if reg == *register => Some(BreakReason::PpuRegister { pc, register: reg }),
+                _ => None,
+            },
+            Breakpoint::StackMismatch => stack_mismatch.map(BreakReason::StackMismatch),
+            _ => None,
+        });
+
+        if reason.is_some() {
+            self.stepping = None;
+            self.resume_pc = Some(pc);
+        }
+        reason
+    }
+
+    fn check_stepping(&mut self, cpu: &mut CPU) -> Option<BreakReason> {
+        let pc = cpu.program_counter;
+        let stop = match self.stepping? {
+            Stepping::Next => true,
+            Stepping::Return { pc: ret, stack_pointer } => {
+                pc == ret && cpu.stack_pointer() == stack_pointer
+            }
+            Stepping::Rts { stack_pointer } => {
+                // RTS of a nested subroutine is executed with lower stack pointer
+                if cpu.bus.read(pc) == RTS && cpu.stack_pointer() >= stack_pointer {
+                    self.stepping = Some(Stepping::Next);
+                }
+                false
+            }
+        };
+        if stop {
+            self.stepping = None;
+            Some(BreakReason::Step(pc))
+        } else {
+            None
+        }
+    }
+
+    /// Stops before the next instruction.
+    pub fn pause(&mut self) {
+        self.stepping = Some(Stepping::Next);
+    }
+
+    /// Sets up a step from the current (paused) pc. Completion is reported by `check` as `BreakReason::Step`,
+    /// unless a breakpoint is hit first.
+    pub fn schedule_step(&mut self, cpu: &mut CPU, mode: StepMode) {
+        let pc = cpu.program_counter;
+        let stack_pointer = cpu.stack_pointer();
+        let opcode = cpu.bus.read(pc);
+        self.stepping = Some(match mode {
+            StepMode::Over if opcode == JSR => Stepping::Return {
+                pc: pc.wrapping_add(3),
+                stack_pointer,
+            },
+            StepMode::Out if opcode != RTS => Stepping::Rts { stack_pointer },
+            _ => Stepping::Next,
+        });
+        self.resume_pc = Some(pc);
+    }
+
+    pub fn step(&mut self, cpu: &mut CPU, mode: StepMode) -> BreakReason {
+        self.schedule_step(cpu, mode);
+        self.run(cpu)
+    }
+
+    /// Runs the cpu until a breakpoint is hit. Calling it again continues from the hit.
+    pub fn run(&mut self, cpu: &mut CPU) -> BreakReason {
+        loop {
+            if let Some(reason) = self.check(cpu) {
+                return reason;
+            }
+            cpu.step();
+        }
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::bus::MockBus;
+    use crate::ppu::ppu::NesPPU;
+    use crate::rom::test_ines_rom;
+
+    fn cpu_with_program(program: &str) -> CPU {
+        let mut mem = MockBus::new();
+        let program = CPU::transform(program);
+        mem.space[0x600..0x600 + program.len()].copy_from_slice(&program);
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.program_counter = 0x600;
+        cpu
+    }
+
+    #[test]
+    fn test_pc_breakpoint() {
+        // NOP; loop: INC $10; JMP loop
+        let mut cpu = cpu_with_program("ea e6 10 4c 01 06");
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(Breakpoint::Pc(0x603));
+
+        assert_eq!(debugger.run(&mut cpu), BreakReason::Pc(0x603));
+        assert_eq!(cpu.program_counter, 0x603);
+        assert_eq!(cpu.bus.read(0x10), 1);
+
+        assert_eq!(debugger.run(&mut cpu), BreakReason::Pc(0x603));
+        assert_eq!(cpu.bus.read(0x10), 2);
+    }
+
+    #[test]
+    fn test_memory_breakpoints() {
+        // LDA #$05; STA $0210; INC $0210; LDA $0300; loop: JMP loop
+        let mut cpu = cpu_with_program("a9 05 8d 10 02 ee 10 02 ad 00 03 4c 0b 06");
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(Breakpoint::Write(0x0200..=0x02ff));
+        debugger.add_breakpoint(Breakpoint::Read(0x0300..=0x0300));
+
+        assert_eq!(
+            debugger.run(&mut cpu),
+            BreakReason::Write {
+                pc: 0x602,
+                addr: 0x0210
+            }
+        );
+        assert_eq!(
+            debugger.run(&mut cpu),
+            BreakReason::Write {
+                pc: 0x605,
+                addr: 0x0210
+            }
+        );
+        assert_eq!(
+            debugger.run(&mut cpu),
+            BreakReason::Read {
+                pc: 0x608,
+                addr: 0x0300
+            }
+        );
+    }
+
+    #[test]
+    fn test_ppu_register_breakpoint() {
+        // LDA #$80; STA $2008 (mirror of $2000); LDA $2002
+        let mut cpu = cpu_with_program("a9 80 8d 08 20 ad 02 20 4c 08 06");
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(Breakpoint::PpuRegister(0x2002));
+        debugger.add_breakpoint(Breakpoint::PpuRegister(0x2000));
+
+        assert_eq!(
+            debugger.run(&mut cpu),
+            BreakReason::PpuRegister {
+                pc: 0x602,
+                register: 0x2000
+            }
+        );
+        assert_eq!(
+            debugger.run(&mut cpu),
+            BreakReason::PpuRegister {
+                pc: 0x605,
+                register: 0x2002
+            }
+        );
+    }
+
+    #[test]
+    fn test_scanline_breakpoint() {
+        let bus = Bus::<NesPPU>::new(test_ines_rom::test_rom());
+        let mut cpu = CPU::new(Box::from(bus));
+        cpu.program_counter = 0x8000;
+
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(Breakpoint::Scanline(10));
+        match debugger.run(&mut cpu) {
+            BreakReason::Scanline { scanline, .. } => assert_eq!(scanline, 10),
+            other => panic!("unexpected break {:?}", other),
+        }
+        assert_eq!(cpu.bus.trace().ppu_scanline, 10);
+    }
+
+    // JSR sub; INC $11; loop: JMP loop
+    // sub: INC $10; JSR nested; RTS
+    // nested: INC $12; RTS
+    const SUBROUTINES: &str = "20 08 06 e6 11 4c 05 06 e6 10 20 0e 06 60 e6 12 60";
+
+    #[test]
+    fn test_step_into() {
+        let mut cpu = cpu_with_program(SUBROUTINES);
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.step(&mut cpu, StepMode::Into), BreakReason::Step(0x608));
+        assert_eq!(debugger.step(&mut cpu, StepMode::Into), BreakReason::Step(0x60a));
+        assert_eq!(cpu.bus.read(0x10), 1);
+    }
+
+    #[test]
+    fn test_step_over() {
+        let mut cpu = cpu_with_program(SUBROUTINES);
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.step(&mut cpu, StepMode::Over), BreakReason::Step(0x603));
+        assert_eq!(cpu.bus.read(0x10), 1);
+        assert_eq!(cpu.bus.read(0x12), 1);
+
+        // not a subroutine call - same as step into
+        assert_eq!(debugger.step(&mut cpu, StepMode::Over), BreakReason::Step(0x605));
+        assert_eq!(cpu.bus.read(0x11), 1);
+    }
+
+    #[test]
+    fn test_step_out() {
+        let mut cpu = cpu_with_program(SUBROUTINES);
+        let mut debugger = Debugger::new();
+        debugger.step(&mut cpu, StepMode::Into);
+        assert_eq!(debugger.step(&mut cpu, StepMode::Out), BreakReason::Step(0x603));
+        assert_eq!(cpu.bus.read(0x12), 1);
+        assert_eq!(cpu.bus.read(0x11), 0);
+    }
+
+    #[test]
+    fn test_step_over_stops_at_breakpoint() {
+        let mut cpu = cpu_with_program(SUBROUTINES);
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(Breakpoint::Pc(0x60e));
+        assert_eq!(debugger.step(&mut cpu, StepMode::Over), BreakReason::Pc(0x60e));
+        assert_eq!(debugger.step(&mut cpu, StepMode::Out), BreakReason::Step(0x60d));
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let mut cpu = cpu_with_program(SUBROUTINES);
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(Breakpoint::Pc(0x60e));
+        debugger.run(&mut cpu);
+        let entries: Vec<u16> = debugger.backtrace().iter().map(|f| f.entry).collect();
+        assert_eq!(entries, vec![0x60e, 0x608]);
+
+        debugger.step(&mut cpu, StepMode::Out);
+        assert_eq!(debugger.backtrace().len(), 1);
+    }
+
+    #[test]
+    fn test_stack_mismatch_breakpoint() {
+        // JSR sub; loop: JMP loop; sub: PLA; PLA; RTS
+        let mut cpu = cpu_with_program("20 06 06 4c 03 06 68 68 60");
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(Breakpoint::StackMismatch);
+        assert_eq!(
+            debugger.run(&mut cpu),
+            BreakReason::StackMismatch(call_stack::StackMismatch {
+                pc: 0x608,
+                expected: 0x603,
+                actual: 0x0001,
+            })
+        );
+    }
+
+    #[test]
+    fn test_remove_breakpoint() {
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(Breakpoint::Pc(0x8000));
+        debugger.add_breakpoint(Breakpoint::Pc(0x8000));
+        assert_eq!(debugger.breakpoints().len(), 1);
+        assert!(debugger.remove_breakpoint(&Breakpoint::Pc(0x8000)));
+        assert!(!debugger.remove_breakpoint(&Breakpoint::Pc(0x8000)));
+    }
+}
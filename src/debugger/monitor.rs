@@ -0,0 +1,285 @@
+// Text command interface to the debugger (machine language monitor), used by the native frontend console.
+use crate::cheats::search::{CheatSearch, Filter};
+use crate::cpu::cpu::CPU;
+use crate::cpu::{opscode, trace_with_symbols};
+use crate::debugger::breakpoint::{parse_addr, Breakpoint};
+use crate::debugger::watch::Watch;
+use crate::debugger::window::{decode, readable, DisasmWindow};
+use crate::debugger::{Debugger, StepMode};
+use crate::disasm;
+use crate::symbols::Symbols;
+use std::collections::HashMap;
+
+const HELP: &str = "\
+r                    registers
+bp [spec]            add a breakpoint (8057, write:0200-02ff, ppu:2002, scanline:241, stack) or list them
+bd <spec>            delete a breakpoint
+w [expr]             add a watch ($00FE, word($0010), A + X) or show watches
+wd <expr>            delete a watch
+bt                   backtrace
+mem <addr> [len]     memory dump, len is hex (default 40)
+poke <addr> <bytes>  write bytes to memory
+d [addr] [count]     disassemble (around pc by default)
+asm <bytes>          decode instruction bytes, e.g. asm a9 01
+search [filter]      cheat search: start a new one, or keep RAM addresses matching
+                     =, !=, >, < (vs the last step), = 3, > $10 (vs a value), +1, -1 (changed by)
+s, step              step into
+n, next              step over
+o, out               step out
+g, go                continue";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    // stay in the monitor, show the output
+    Output(String),
+    Go,
+    Step(StepMode),
+}
+
+pub struct Monitor<'a> {
+    symbols: &'a Symbols,
+    window: DisasmWindow,
+    search: Option<CheatSearch>,
+}
+
+fn parse_bytes(args: &[&str]) -> Result<Vec<u8>, String> {
+    args.iter()
+        .map(|b| {
+            u8::from_str_radix(b.trim_start_matches('$'), 16)
+                .map_err(|_| format!("bad byte '{}'", b))
+        })
+        .collect()
+}
+
+fn parse_count(s: Option<&&str>, default: u16) -> Result<u16, String> {
+    match s {
+        Some(s) => u16::from_str_radix(s.trim_start_matches('$'), 16)
+            .map_err(|_| format!("bad number '{}'", s)),
+        None => Ok(default),
+    }
+}
+
+impl<'a> Monitor<'a> {
+    pub fn new(symbols: &'a Symbols) -> Self {
+        Monitor {
+            symbols,
+            window: DisasmWindow::new(),
+            search: None,
+        }
+    }
+
+    pub fn help() -> &'static str {
+        HELP
+    }
+
+    pub fn execute(
+        &mut self,
+        line: &str,
+        cpu: &mut CPU,
+        debugger: &mut Debugger,
+    ) -> Result<Action, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(Action::Output(String::new())),
+        };
+        let rest = line.trim_start()[command.len()..].trim();
+
+        let output = match command {
+            "help" | "?" => HELP.to_string(),
+            "r" | "regs" => trace_with_symbols(cpu, self.symbols),
+            "bp" if args.is_empty() => debugger
+                .breakpoints()
+                .iter()
+                .map(|bp| bp.to_string())
+                .collect::<Vec<String>>()
+                .join("\n"),
+            "bp" => {
+                debugger.add_breakpoint(Breakpoint::parse(rest, self.symbols)?);
+                String::new()
+            }
+            "bd" => {
+                if !debugger.remove_breakpoint(&Breakpoint::parse(rest, self.symbols)?) {
+                    return Err(format!("no breakpoint '{}'", rest));
+                }
+                String::new()
+            }
+            "w" if args.is_empty() => debugger.format_watches(cpu).join("\n"),
+            "w" => {
+                let watch = Watch::parse(rest, self.symbols)?;
+                let value = watch.format(cpu);
+                debugger.add_watch(watch);
+                value
+            }
+            "wd" => {
+                if !debugger.remove_watch(rest) {
+                    return Err(format!("no watch '{}'", rest));
+                }
+                String::new()
+            }
+            "bt" => debugger
+                .call_stack()
+                .format_backtrace(self.symbols)
+                .trim_end()
+                .to_string(),
+            "mem" => {
+                let addr = parse_addr(args.first().ok_or("mem <addr> [len]")?, self.symbols)?;
+                let len = parse_count(args.get(1), 0x40)?;
+                self.dump(cpu, addr, len)
+            }
+            "poke" => {
+                let addr = parse_addr(args.first().ok_or("poke <addr> <bytes>")?, self.symbols)?;
+                for (i, data) in parse_bytes(&args[1..])?.into_iter().enumerate() {
+                    cpu.bus.write(addr.wrapping_add(i as u16), data);
+                }
+                // the code could be changed
+                self.window.invalidate();
+                String::new()
+            }
+            "d" | "dis" if args.is_empty() => {
+                let (lines, index) = self.window.around(cpu, 11);
+                lines
+                    .iter()
+                    .enumerate()
+                    .map(|(i, line)| format!("{}{}", if i == index { "> " } else { "  " }, line))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            }
+            "d" | "dis" => {
+                let mut addr = parse_addr(args[0], self.symbols)?;
+                let count = parse_count(args.get(1), 0x10)?;
+                let mut lines = vec![];
+                for _ in 0..count {
+                    match decode(cpu, addr) {
+                        Some(line) => {
+                            addr = addr.wrapping_add(line.bytes.len() as u16);
+                            lines.push(format!("  {}", line));
+                        }
+                        None => break,
+                    }
+                }
+                lines.join("\n")
+            }
+            "asm" => {
+                let bytes = parse_bytes(args)?;
+                let ops = opscode::lookup(*bytes.first().ok_or("asm <bytes>")?)
+                    .ok_or("unknown opcode")?;
+                if bytes.len() != ops.len as usize {
+                    return Err(format!("{} takes {} byte(s)", ops.mnemonic, ops.len));
+                }
+                let operand =
+                    disasm::operand(&bytes, cpu.program_counter as usize, ops, &HashMap::new());
+                format!("{} {}", ops.mnemonic, operand).trim().to_string()
+            }
+            "search" if args.is_empty() => {
+                let search = CheatSearch::new(&mut *cpu.bus);
+                let output = format!("{} addresses", search.len());
+                self.search = Some(search);
+                output
+            }
+            "search" => {
+                let filter = Filter::parse(rest)?;
+                let search = self.search.as_mut().ok_or("no search, start one with 'search'")?;
+                search.filter(&mut *cpu.bus, filter);
+                // a long list is useless, keep narrowing it down
+                if search.len() > 16 {
+                    format!("{} addresses", search.len())
+                } else {
+                    search
+                        .results()
+                        .iter()
+                        .map(|(addr, value)| format!("${:04X} = ${:02X}", addr, value))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                }
+            }
+            "s" | "step" => return Ok(Action::Step(StepMode::Into)),
+            "n" | "next" => return Ok(Action::Step(StepMode::Over)),
+            "o" | "out" => return Ok(Action::Step(StepMode::Out)),
+            "g" | "go" => return Ok(Action::Go),
+            _ => return Err(format!("unknown command '{}', try 'help'", command)),
+        };
+        Ok(Action::Output(output))
+    }
+
+    fn dump(&mut self, cpu: &mut CPU, addr: u16, len: u16) -> String {
+        let mut lines = vec![];
+        let mut offset = 0u16;
+        while offset < len {
+            let start = addr.wrapping_add(offset);
+            let bytes: Vec<String> = (0..16.min(len - offset))
+                .map(|i| {
+                    let pos = start.wrapping_add(i);
+                    // reading io registers has side effects
+                    if readable(pos) {
+                        format!("{:02x}", cpu.bus.read(pos))
+                    } else {
+                        "--".to_string()
+                    }
+                })
+                .collect();
+            lines.push(format!("{:04x}: {}", start, bytes.join(" ")));
+            offset = offset.saturating_add(16);
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::MockBus;
+
+    fn output(action: Result<Action, String>) -> String {
+        match action {
+            Ok(Action::Output(output)) => output,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut mem = MockBus::new();
+        // LDA #$01; STA $0200; loop: JMP loop
+        let program = CPU::transform("a9 01 8d 00 02 4c 05 06");
+        mem.space[0x600..0x600 + program.len()].copy_from_slice(&program);
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.program_counter = 0x600;
+        let mut debugger = Debugger::new();
+        let symbols = Symbols::new();
+        let mut monitor = Monitor::new(&symbols);
+        let mut run = |line: &str, cpu: &mut CPU| monitor.execute(line, cpu, &mut debugger);
+
+        assert_eq!(output(run("asm a9 01", &mut cpu)), "LDA #$01");
+        assert!(run("asm a9", &mut cpu).is_err());
+        assert!(run("jump 0600", &mut cpu).is_err());
+
+        assert_eq!(output(run("bp 0605", &mut cpu)), "");
+        assert_eq!(output(run("bp", &mut cpu)), "pc:$0605");
+        assert_eq!(run("s", &mut cpu), Ok(Action::Step(StepMode::Into)));
+        assert_eq!(run("go", &mut cpu), Ok(Action::Go));
+
+        assert_eq!(output(run("poke 0201 ab cd", &mut cpu)), "");
+        assert_eq!(
+            output(run("mem 01fe 14", &mut cpu)),
+            "01fe: 00 00 00 ab cd 00 00 00 00 00 00 00 00 00 00 00\n020e: 00 00 00 00"
+        );
+        assert_eq!(output(run("mem 2000 2", &mut cpu)), "2000: -- --");
+
+        assert_eq!(
+            output(run("d 0600 2", &mut cpu)),
+            "  0600: LDA #$01\n  0602: STA $0200"
+        );
+        assert!(output(run("d", &mut cpu)).contains("\n> 0600: LDA #$01\n"));
+        assert!(output(run("r", &mut cpu)).starts_with("0600  A9 01"));
+
+        assert_eq!(output(run("w $0201", &mut cpu)), "$0201 = $AB");
+        assert!(run("wd $0300", &mut cpu).is_err());
+
+        assert!(run("search =", &mut cpu).is_err());
+        assert_eq!(output(run("search", &mut cpu)), "10240 addresses");
+        assert_eq!(output(run("poke 0201 ac", &mut cpu)), "");
+        assert_eq!(output(run("search +1", &mut cpu)), "$0201 = $AC");
+        assert!(run("search ~", &mut cpu).is_err());
+    }
+}
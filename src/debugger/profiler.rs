@@ -0,0 +1,180 @@
+// Subroutine level profiler: cpu cycles spent in every subroutine, tracked by JSR/RTS.
+//
+// inclusive - cycles from JSR till the matching RTS (both included), nested calls included
+// exclusive - inclusive minus cycles of nested calls
+//
+// Frames are matched by stack pointer, so returns that skip frames (e.g. PLA PLA RTS)
+// and interrupts (RTI) don't break the bookkeeping.
+use crate::cpu::cpu::CPU;
+use crate::symbols::Symbols;
+use std::collections::HashMap;
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct SubroutineStats {
+    pub entry: u16,
+    pub calls: usize,
+    pub inclusive_cycles: usize,
+    pub exclusive_cycles: usize,
+}
+
+struct Frame {
+    entry: u16,
+    // stack pointer before JSR
+    stack_pointer: u8,
+    start_cycles: usize,
+    nested_cycles: usize,
+}
+
+pub struct Profiler {
+    frames: Vec<Frame>,
+    stats: HashMap<u16, SubroutineStats>,
+    // stack pointer and cycles at JSR, the subroutine entry is known after it's executed
+    pending_call: Option<(u8, usize)>,
+    pending_return: bool,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            frames: vec![],
+            stats: HashMap::new(),
+            pending_call: None,
+            pending_return: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Profiler::new();
+    }
+
+    /// Has to be called before every instruction (e.g. from `CPU::interpret_fn` callback)
+    pub fn observe(&mut self, cpu: &mut CPU) {
+        let cycles = cpu.bus.trace().cpu_cycles;
+        let stack_pointer = cpu.stack_pointer();
+
+        if let Some((call_stack_pointer, start_cycles)) = self.pending_call.take() {
+            self.frames.push(Frame {
+                entry: cpu.program_counter,
+                stack_pointer: call_stack_pointer,
+                start_cycles,
+                nested_cycles: 0,
+            });
+        }
+
+        if self.pending_return {
+            self.pending_return = false;
+            while let Some(frame) = self.frames.last() {
+                if frame.stack_pointer > stack_pointer {
+                    break;
+                }
+                let frame = self.frames.pop().unwrap();
+                let inclusive = cycles - frame.start_cycles;
+                let stats = self.stats.entry(frame.entry).or_insert(SubroutineStats {
+                    entry: frame.entry,
+                    calls: 0,
+                    inclusive_cycles: 0,
+                    exclusive_cycles: 0,
+                });
+                stats.calls += 1;
+                stats.inclusive_cycles += inclusive;
+                stats.exclusive_cycles += inclusive - frame.nested_cycles;
+                if let Some(parent) = self.frames.last_mut() {
+                    parent.nested_cycles += inclusive;
+                }
+            }
+        }
+
+        match cpu.bus.read(cpu.program_counter) {
+            JSR => self.pending_call = Some((stack_pointer, cycles)),
+            RTS | RTI => self.pending_return = true,
+            _ => {}
+        }
+    }
+
+    /// Finished calls only, the most expensive (exclusive cycles) first
+    pub fn report(&self) -> Vec<SubroutineStats> {
+        let mut report: Vec<SubroutineStats> = self.stats.values().cloned().collect();
+        report.sort_by(|a, b| {
+            b.exclusive_cycles
+                .cmp(&a.exclusive_cycles)
+                .then(a.entry.cmp(&b.entry))
+        });
+        report
+    }
+
+    pub fn format_report(&self, symbols: &Symbols) -> String {
+        let mut out = format!(
+            "{:<24} {:>8} {:>12} {:>12}\n",
+            "subroutine", "calls", "inclusive", "exclusive"
+        );
+        for stats in self.report() {
+            let name = match symbols.name(stats.entry) {
+                Some(name) => name.to_string(),
+                None => format!("${:04X}", stats.entry),
+            };
+            out.push_str(&format!(
+                "{:<24} {:>8} {:>12} {:>12}\n",
+                name, stats.calls, stats.inclusive_cycles, stats.exclusive_cycles
+            ));
+        }
+        out
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::MockBus;
+
+    #[test]
+    fn test_profile() {
+        let mut mem = MockBus::new();
+        // 0600: JSR outer; JSR inner; BRK
+        // 0607: outer: JSR inner; NOP; RTS
+        // 060c: inner: INX; RTS
+        let program = CPU::transform("20 07 06 20 0c 06 00 20 0c 06 ea 60 e8 60");
+        mem.space[0x600..0x600 + program.len()].copy_from_slice(&program);
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.program_counter = 0x600;
+
+        let mut profiler = Profiler::new();
+        // BRK stops the program
+        cpu.interpret_fn(0xffff, |cpu| profiler.observe(cpu));
+
+        // inner: JSR(6) + INX(2) + RTS(6)
+        // outer: JSR(6) + inner(14) + NOP(2) + RTS(6)
+        assert_eq!(
+            profiler.report(),
+            vec![
+                SubroutineStats {
+                    entry: 0x60c,
+                    calls: 2,
+                    inclusive_cycles: 28,
+                    exclusive_cycles: 28,
+                },
+                SubroutineStats {
+                    entry: 0x607,
+                    calls: 1,
+                    inclusive_cycles: 28,
+                    exclusive_cycles: 14,
+                },
+            ]
+        );
+
+        let mut symbols = Symbols::new();
+        symbols.insert(0x60c, "inner");
+        let report = profiler.format_report(&symbols);
+        assert!(report.lines().nth(1).unwrap().starts_with("inner "));
+        assert!(report.lines().nth(2).unwrap().starts_with("$0607 "));
+    }
+}
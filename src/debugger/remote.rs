@@ -0,0 +1,582 @@
+// Debugger over TCP for external GUIs and editors. One client at a time, one JSON object per line
+// both ways. Requests can carry an "id", it's copied to the response:
+//
+//   {"id":1,"cmd":"break","spec":"write:0200-02ff"}  add a breakpoint (specs as for --break)
+//   {"cmd":"delete","spec":"8057"}                    delete a breakpoint
+//   {"cmd":"breakpoints"}                             -> {"ok":true,"breakpoints":["pc:$8057"]}
+//   {"cmd":"registers"}                               -> {"ok":true,"pc":32768,"a":0,...}
+//   {"cmd":"read","addr":"0200","len":16}             -> {"ok":true,"addr":512,"bytes":[...]}
+//                                                        io registers read as null (side effects)
+//   {"cmd":"pause"}                                   stop before the next instruction
+//   {"cmd":"step","mode":"into|over|out"}             when stopped
+//   {"cmd":"continue"}                                when stopped
+//
+// Errors are {"ok":false,"error":"..."}. Every stop is reported without a request:
+//   {"event":"stopped","reason":"breakpoint at $8057","pc":32855}
+// Addresses can be numbers, hex strings or symbols. All the numbers are decimal integers, the
+// requests are flat objects (no arrays or nested objects).
+use crate::cpu::cpu::CPU;
+use crate::debugger::breakpoint::{parse_addr, BreakReason, Breakpoint};
+use crate::debugger::monitor::Action;
+use crate::debugger::window::readable;
+use crate::debugger::{Debugger, StepMode};
+use crate::dump::string;
+use crate::symbols::Symbols;
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::iter::Peekable;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::str::Chars;
+
+const MAX_READ: u16 = 0x1000;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Value {
+    String(String),
+    Number(i64),
+    Bool(bool),
+    Null,
+}
+
+// a flat object is all the protocol needs: arrays, nested objects and numbers other than
+// integers are rejected
+fn parse_object(line: &str) -> Result<HashMap<String, Value>, String> {
+    fn skip_spaces(chars: &mut Peekable<Chars>) {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+    }
+    fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<(), String> {
+        skip_spaces(chars);
+        match chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected '{}', got '{}'", expected, c)),
+            None => Err(format!("expected '{}'", expected)),
+        }
+    }
+    // the 4 hex digits after "\u"
+    fn parse_code_unit(chars: &mut Peekable<Chars>) -> Result<u32, String> {
+        let hex: String = chars.by_ref().take(4).collect();
+        if hex.len() != 4 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("bad escape '\\u{}'", hex));
+        }
+        Ok(u32::from_str_radix(&hex, 16).unwrap())
+    }
+    fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+        expect(chars, '"')?;
+        let mut s = String::new();
+        loop {
+            match chars.next().ok_or("unterminated string")? {
+                '"' => return Ok(s),
+                '\\' => match chars.next().ok_or("unterminated string")? {
+                    c @ '"' | c @ '\\' | c @ '/' => s.push(c),
+                    'b' => s.push('\u{8}'),
+                    'f' => s.push('\u{c}'),
+                    'n' => s.push('\n'),
+                    'r' => s.push('\r'),
+                    't' => s.push('\t'),
+                    'u' => {
+                        let mut code = parse_code_unit(chars)?;
+                        // outside of the BMP: a surrogate pair, "\uD83D\uDE00"
+                        if (0xd800..0xdc00).contains(&code) {
+                            if chars.next() != Some('\\') || chars.next() != Some('u') {
+                                return Err(String::from("unpaired surrogate in a string"));
+                            }
+                            let low = parse_code_unit(chars)?;
+                            if !(0xdc00..0xe000).contains(&low) {
+                                return Err(String::from("unpaired surrogate in a string"));
+                            }
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                        }
+                        s.push(std::char::from_u32(code).ok_or("unpaired surrogate in a string")?);
+                    }
+                    c => return Err(format!("bad escape '\\{}'", c)),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+    fn parse_value(chars: &mut Peekable<Chars>) -> Result<Value, String> {
+        skip_spaces(chars);
+        match chars.peek() {
+            Some('"') => Ok(Value::String(parse_string(chars)?)),
+            Some(c) if *c == '-' || c.is_ascii_digit() => {
+                let mut number = String::new();
+                if chars.peek() == Some(&'-') {
+                    number.push(chars.next().unwrap());
+                }
+                while chars.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    number.push(chars.next().unwrap());
+                }
+                if chars.peek().is_some_and(|c| matches!(c, '.' | 'e' | 'E')) {
+                    return Err(format!(
+                        "only integer numbers are supported, got '{}...'",
+                        number
+                    ));
+                }
+                number
+                    .parse()
+                    .map(Value::Number)
+                    .map_err(|_| format!("bad number '{}'", number))
+            }
+            Some('[') | Some('{') => {
+                Err(String::from("arrays and nested objects are not supported"))
+            }
+            _ => {
+                let mut word = String::new();
+                while chars.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+                    word.push(chars.next().unwrap());
+                }
+                match word.as_str() {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    "null" => Ok(Value::Null),
+                    _ => Err(format!("unexpected '{}'", word)),
+                }
+            }
+        }
+    }
+
+    let mut chars = line.chars().peekable();
+    let mut object = HashMap::new();
+    expect(&mut chars, '{')?;
+    skip_spaces(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            skip_spaces(&mut chars);
+            let key = parse_string(&mut chars)?;
+            expect(&mut chars, ':')?;
+            object.insert(key, parse_value(&mut chars)?);
+            skip_spaces(&mut chars);
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => return Err(String::from("expected ',' or '}'")),
+            }
+        }
+    }
+    skip_spaces(&mut chars);
+    if chars.next().is_some() {
+        return Err(String::from("trailing characters after the object"));
+    }
+    Ok(object)
+}
+
+struct Request {
+    fields: HashMap<String, Value>,
+}
+
+impl Request {
+    fn str(&self, key: &str) -> Result<&str, String> {
+        match self.fields.get(key) {
+            Some(Value::String(s)) => Ok(s),
+            _ => Err(format!("\"{}\" is missing", key)),
+        }
+    }
+
+    fn addr(&self, key: &str, symbols: &Symbols) -> Result<u16, String> {
+        match self.fields.get(key) {
+            Some(Value::Number(n)) if (0..=0xffff).contains(n) => Ok(*n as u16),
+            Some(Value::String(s)) => parse_addr(s, symbols),
+            _ => Err(format!("\"{}\" is missing or out of range", key)),
+        }
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+pub struct RemoteServer<'a> {
+    symbols: &'a Symbols,
+    listener: TcpListener,
+    client: Option<Client>,
+}
+
+impl<'a> RemoteServer<'a> {
+    /// Listens on `addr`, e.g. "127.0.0.1:6502"
+    pub fn bind(addr: &str, symbols: &'a Symbols) -> Result<Self, String> {
+        let listener =
+            TcpListener::bind(addr).map_err(|e| format!("failed to listen on {}: {}", addr, e))?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|e| format!("failed to listen on {}: {}", addr, e))?;
+        Ok(RemoteServer {
+            symbols,
+            listener,
+            client: None,
+        })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.local_addr().ok()
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Accepts a client and serves the requests received so far without blocking, to be called
+    /// regularly (e.g. every frame) while the emulator runs.
+    pub fn poll(&mut self, cpu: &mut CPU, debugger: &mut Debugger) {
+        if self.client.is_none() {
+            if let Ok((stream, _)) = self.listener.accept() {
+                if stream.set_nonblocking(true).is_ok() {
+                    self.client = Some(Client {
+                        stream,
+                        buffer: vec![],
+                    });
+                }
+            }
+        }
+        while let Some(line) = self.read_line() {
+            let response = match self.handle(&line, cpu, debugger) {
+                (_, Some(_)) => error_response(&line, "not stopped"),
+                (response, None) => response,
+            };
+            self.send(&response);
+        }
+    }
+
+    /// Reports the stop and serves requests until the client continues or steps. Returns the step
+    /// to schedule, `None` to continue. A client disconnect continues the run.
+    pub fn stopped(
+        &mut self,
+        reason: &BreakReason,
+        cpu: &mut CPU,
+        debugger: &mut Debugger,
+    ) -> Option<StepMode> {
+        self.send(&format!(
+            "{{\"event\":\"stopped\",\"reason\":{},\"pc\":{}}}",
+            string(&reason.to_string()),
+            cpu.program_counter
+        ));
+        self.set_blocking(true);
+        let mut step = None;
+        while let Some(line) = self.read_line() {
+            let (response, action) = self.handle(&line, cpu, debugger);
+            self.send(&response);
+            match action {
+                Some(Action::Step(mode)) => {
+                    step = Some(mode);
+                    break;
+                }
+                Some(_) => break,
+                None => {}
+            }
+        }
+        self.set_blocking(false);
+        step
+    }
+
+    fn set_blocking(&mut self, blocking: bool) {
+        if let Some(client) = &self.client {
+            if client.stream.set_nonblocking(!blocking).is_err() {
+                self.client = None;
+            }
+        }
+    }
+
+    fn send(&mut self, line: &str) {
+        if let Some(client) = &mut self.client {
+            let sent = client
+                .stream
+                .write_all(line.as_bytes())
+                .and_then(|_| client.stream.write_all(b"\n"));
+            if sent.is_err() {
+                self.client = None;
+            }
+        }
+    }
+
+    // the next complete line; `None` when nothing more is available (or blocking: the client left)
+    fn read_line(&mut self) -> Option<String> {
+        let client = self.client.as_mut()?;
+        loop {
+            if let Some(end) = client.buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = client.buffer.drain(..=end).collect();
+                return Some(String::from_utf8_lossy(&line).trim().to_string());
+            }
+            let mut chunk = [0u8; 1024];
+            match client.stream.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(len) => client.buffer.extend_from_slice(&chunk[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return None,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+        self.client = None;
+        None
+    }
+
+    // response line and the run control action, if the request asked for one
+    fn handle(
+        &self,
+        line: &str,
+        cpu: &mut CPU,
+        debugger: &mut Debugger,
+    ) -> (String, Option<Action>) {
+        let request = match parse_object(line) {
+            Ok(fields) => Request { fields },
+            Err(e) => return (error_response(line, &e), None),
+        };
+        match self.execute(&request, cpu, debugger) {
+            Ok((fields, action)) => {
+                let mut response = String::from("{");
+                if let Some(id) = id(&request) {
+                    response.push_str(&format!("\"id\":{},", id));
+                }
+                response.push_str("\"ok\":true");
+                for (key, value) in fields {
+                    response.push_str(&format!(",\"{}\":{}", key, value));
+                }
+                response.push('}');
+                (response, action)
+            }
+            Err(e) => (error_response(line, &e), None),
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn execute(
+        &self,
+        request: &Request,
+        cpu: &mut CPU,
+        debugger: &mut Debugger,
+    ) -> Result<(Vec<(&'static str, String)>, Option<Action>), String> {
+        let mut fields = vec![];
+        let mut action = None;
+        match request.str("cmd")? {
+            "break" => {
+                debugger.add_breakpoint(Breakpoint::parse(request.str("spec")?, self.symbols)?)
+            }
+            "delete" => {
+                let spec = request.str("spec")?;
+                if !debugger.remove_breakpoint(&Breakpoint::parse(spec, self.symbols)?) {
+                    return Err(format!("no breakpoint '{}'", spec));
+                }
+            }
+            "breakpoints" => {
+                let specs: Vec<String> = debugger
+                    .breakpoints()
+                    .iter()
+                    .map(|bp| string(&bp.to_string()))
+                    .collect();
+                fields.push(("breakpoints", format!("[{}]", specs.join(","))));
+            }
+            "registers" => {
+                fields.push(("pc", cpu.program_counter.to_string()));
+                fields.push(("a", cpu.register_a().to_string()));
+                fields.push(("x", cpu.register_x().to_string()));
+                fields.push(("y", cpu.register_y().to_string()));
+                fields.push(("sp", cpu.stack_pointer().to_string()));
+                fields.push(("p", cpu.status().to_string()));
+                let timing = cpu.bus.trace();
+                fields.push(("cycles", timing.cpu_cycles.to_string()));
+                fields.push(("scanline", timing.ppu_scanline.to_string()));
+            }
+            "read" => {
+                let addr = request.addr("addr", self.symbols)?;
+                let len = match request.fields.get("len") {
+                    Some(Value::Number(len)) if (1..=MAX_READ as i64).contains(len) => *len as u16,
+                    None => 1,
+                    _ => return Err(format!("\"len\" has to be 1-{}", MAX_READ)),
+                };
+                let bytes: Vec<String> = (0..len)
+                    .map(|i| {
+                        let pos = addr.wrapping_add(i);
+                        // reading io registers has side effects
+                        if readable(pos) {
+                            cpu.bus.read(pos).to_string()
+                        } else {
+                            String::from("null")
+                        }
+                    })
+                    .collect();
+                fields.push(("addr", addr.to_string()));
+                fields.push(("bytes", format!("[{}]", bytes.join(","))));
+            }
+            "pause" => debugger.pause(),
+            "step" => {
+                let mode = match request.fields.get("mode") {
+                    None => StepMode::Into,
+                    Some(Value::String(mode)) if mode == "into" => StepMode::Into,
+                    Some(Value::String(mode)) if mode == "over" => StepMode::Over,
+                    Some(Value::String(mode)) if mode == "out" => StepMode::Out,
+                    Some(_) => return Err(String::from("\"mode\" has to be into, over or out")),
+                };
+                action = Some(Action::Step(mode));
+            }
+            "continue" => action = Some(Action::Go),
+            cmd => return Err(format!("unknown command '{}'", cmd)),
+        }
+        Ok((fields, action))
+    }
+}
+
+fn id(request: &Request) -> Option<String> {
+    match request.fields.get("id")? {
+        Value::Number(n) => Some(n.to_string()),
+        Value::String(s) => Some(string(s)),
+        _ => None,
+    }
+}
+
+fn error_response(line: &str, error: &str) -> String {
+    match parse_object(line)
+        .ok()
+        .and_then(|fields| id(&Request { fields }))
+    {
+        Some(id) => format!("{{\"id\":{},\"ok\":false,\"error\":{}}}", id, string(error)),
+        None => format!("{{\"ok\":false,\"error\":{}}}", string(error)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::MockBus;
+    use std::io::{BufRead, BufReader};
+    use std::thread;
+
+    fn cpu() -> CPU {
+        let mut mem = MockBus::new();
+        // LDA #$01; INC $10
+        mem.space[0x600..0x604].copy_from_slice(&[0xa9, 0x01, 0xe6, 0x10]);
+        mem.space[0x200] = 0x42;
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.program_counter = 0x600;
+        cpu
+    }
+
+    #[test]
+    fn test_parse_object() {
+        let object = parse_object(r#" {"cmd": "read", "addr":-12, "s":"a\"A", "b":true} "#);
+        let object = object.unwrap();
+        assert_eq!(object["cmd"], Value::String(String::from("read")));
+        assert_eq!(object["addr"], Value::Number(-12));
+        assert_eq!(object["s"], Value::String(String::from("a\"A")));
+        assert_eq!(object["b"], Value::Bool(true));
+        assert_eq!(parse_object("{}").unwrap().len(), 0);
+        assert!(parse_object(r#"{"cmd":"read""#).is_err());
+        assert!(parse_object(r#"{"cmd":"read"} x"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_escapes() {
+        let string = |line: &str| parse_object(line).map(|object| object["s"].clone());
+        assert_eq!(
+            string(r#"{"s":"\/\\\u0041\ud83d\ude00\r"}"#),
+            Ok(Value::String(String::from("/\\A\u{1f600}\r")))
+        );
+        // truncated or malformed
+        assert!(string(r#"{"s":"\u00"#).is_err());
+        assert!(string(r#"{"s":"\u12"}"#).is_err());
+        assert!(string(r#"{"s":"\u+123"}"#).is_err());
+        assert!(string(r#"{"s":"\q"}"#).is_err());
+        assert!(string(r#"{"s":"\"#).is_err());
+        // lone surrogates
+        assert!(string(r#"{"s":"\ud83d"}"#).is_err());
+        assert!(string(r#"{"s":"\ud83d\u0041"}"#).is_err());
+        assert!(string(r#"{"s":"\ude00"}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_unsupported() {
+        for line in [
+            r#"{"n":1.5}"#,
+            r#"{"n":1e3}"#,
+            r#"{"n":-}"#,
+            r#"{"n":1-2}"#,
+            r#"{"a":[1]}"#,
+            r#"{"a":{}}"#,
+            r#"{"a":"#,
+            r#"{"a""#,
+            r#"{"a":1,}"#,
+            r#"{"#,
+            "",
+        ]
+        .iter()
+        {
+            assert!(parse_object(line).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_commands() {
+        let symbols = Symbols::new();
+        let server = RemoteServer::bind("127.0.0.1:0", &symbols).unwrap();
+        let mut cpu = cpu();
+        let mut debugger = Debugger::new();
+        let mut request = |line: &str| server.handle(line, &mut cpu, &mut debugger);
+
+        assert_eq!(
+            request(r#"{"id":7,"cmd":"break","spec":"0602"}"#),
+            (String::from(r#"{"id":7,"ok":true}"#), None)
+        );
+        assert_eq!(
+            request(r#"{"cmd":"breakpoints"}"#).0,
+            r#"{"ok":true,"breakpoints":["pc:$0602"]}"#
+        );
+        assert_eq!(
+            request(r#"{"cmd":"read","addr":"$01ff","len":2}"#).0,
+            r#"{"ok":true,"addr":511,"bytes":[0,66]}"#
+        );
+        assert!(request(r#"{"cmd":"registers"}"#)
+            .0
+            .starts_with(r#"{"ok":true,"pc":1536,"a":0,"#));
+        assert_eq!(
+            request(r#"{"cmd":"step","mode":"over"}"#).1,
+            Some(Action::Step(StepMode::Over))
+        );
+        assert_eq!(
+            request(r#"{"id":"x","cmd":"delete","spec":"8000"}"#).0,
+            r#"{"id":"x","ok":false,"error":"no breakpoint '8000'"}"#
+        );
+        assert_eq!(
+            request(r#"{"cmd":"run"}"#).0,
+            r#"{"ok":false,"error":"unknown command 'run'"}"#
+        );
+    }
+
+    #[test]
+    fn test_session() {
+        let symbols = Symbols::new();
+        let mut server = RemoteServer::bind("127.0.0.1:0", &symbols).unwrap();
+        let addr = server.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut response = || {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                line
+            };
+            stream
+                .write_all(b"{\"cmd\":\"break\",\"spec\":\"0602\"}\n")
+                .unwrap();
+            assert_eq!(response(), "{\"ok\":true}\n");
+            let stop = response();
+            stream.write_all(b"{\"cmd\":\"continue\"}\n").unwrap();
+            assert_eq!(response(), "{\"ok\":true}\n");
+            stop
+        });
+
+        let mut cpu = cpu();
+        let mut debugger = Debugger::new();
+        while debugger.breakpoints().is_empty() {
+            server.poll(&mut cpu, &mut debugger);
+            thread::yield_now();
+        }
+        let reason = debugger.run(&mut cpu);
+        assert_eq!(server.stopped(&reason, &mut cpu, &mut debugger), None);
+        assert_eq!(
+            client.join().unwrap(),
+            "{\"event\":\"stopped\",\"reason\":\"breakpoint at $0602\",\"pc\":1538}\n"
+        );
+    }
+}
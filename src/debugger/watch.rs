@@ -0,0 +1,333 @@
+// Watch expressions, evaluated against the current cpu state:
+//   $00FE          - byte in memory
+//   word($0010)    - little endian word in memory, e.g. a pointer
+//   byte(word($10) + Y) - (indirect),Y
+//   A + X          - registers A, X, Y, SP, P, PC
+//   #$10, #16, 16  - constants
+//   player_x       - a name from the symbol file, same as its $address
+// operators: + - & (left to right, no precedence)
+// Inside byte()/word() addresses are not dereferenced: word($10 + X) reads $10+X and $11+X.
+use crate::cpu::cpu::CPU;
+use crate::symbols::Symbols;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Register {
+    A,
+    X,
+    Y,
+    SP,
+    P,
+    PC,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Op {
+    Add,
+    Sub,
+    And,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Expr {
+    Const(u16),
+    Mem(u16),
+    Reg(Register),
+    Byte(Box<Expr>),
+    Word(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Token {
+    Num(u16),
+    Addr(u16),
+    Ident(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    let take = |i: &mut usize, f: &dyn Fn(char) -> bool| {
+        let begin = *i;
+        while *i < chars.len() && f(chars[*i]) {
+            *i += 1;
+        }
+        chars[begin..*i].iter().collect::<String>()
+    };
+    let hex = |digits: &str| {
+        u16::from_str_radix(digits, 16).map_err(|_| format!("bad hex number '${}'", digits))
+    };
+    let dec = |digits: &str| {
+        digits
+            .parse::<u16>()
+            .map_err(|_| format!("bad number '{}'", digits))
+    };
+
+    while i < chars.len() {
+        match chars[i] {
+            c if c.is_whitespace() => i += 1,
+            '+' | '-' | '&' | '(' | ')' => {
+                tokens.push(match chars[i] {
+                    '+' => Token::Op(Op::Add),
+                    '-' => Token::Op(Op::Sub),
+                    '&' => Token::Op(Op::And),
+                    '(' => Token::Open,
+                    _ => Token::Close,
+                });
+                i += 1;
+            }
+            '$' => {
+                i += 1;
+                tokens.push(Token::Addr(hex(&take(&mut i, &|c| c.is_ascii_hexdigit()))?));
+            }
+            '#' => {
+                i += 1;
+                if i < chars.len() && chars[i] == '$' {
+                    i += 1;
+                    tokens.push(Token::Num(hex(&take(&mut i, &|c| c.is_ascii_hexdigit()))?));
+                } else {
+                    tokens.push(Token::Num(dec(&take(&mut i, &|c| c.is_ascii_digit()))?));
+                }
+            }
+            c if c.is_ascii_digit() => {
+                tokens.push(Token::Num(dec(&take(&mut i, &|c| c.is_ascii_digit()))?))
+            }
+            c if c.is_alphabetic() || c == '_' || c == '@' => tokens.push(Token::Ident(take(
+                &mut i,
+                &|c| c.is_alphanumeric() || c == '_' || c == '@',
+            ))),
+            c => return Err(format!("unexpected '{}'", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    symbols: &'a Symbols,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut left = self.operand()?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            self.position += 1;
+            let right = self.operand()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn operand(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Num(n)) => Ok(Expr::Const(n)),
+            Some(Token::Addr(addr)) => Ok(Expr::Mem(addr)),
+            Some(Token::Open) => {
+                let expr = self.expr()?;
+                self.close()?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => {
+                let register = match name.to_ascii_uppercase().as_str() {
+                    "A" => Some(Register::A),
+                    "X" => Some(Register::X),
+                    "Y" => Some(Register::Y),
+                    "SP" => Some(Register::SP),
+                    "P" => Some(Register::P),
+                    "PC" => Some(Register::PC),
+                    _ => None,
+                };
+                if let Some(register) = register {
+                    return Ok(Expr::Reg(register));
+                }
+                if self.peek() == Some(&Token::Open) {
+                    let function = name.to_ascii_lowercase();
+                    if function == "byte" || function == "word" {
+                        self.position += 1;
+                        let arg = Box::new(self.expr()?);
+                        self.close()?;
+                        return Ok(if function == "byte" {
+                            Expr::Byte(arg)
+                        } else {
+                            Expr::Word(arg)
+                        });
+                    }
+                    return Err(format!("unknown function '{}'", name));
+                }
+                match self.symbols.addr(&name) {
+                    Some(addr) => Ok(Expr::Mem(addr)),
+                    None => Err(format!("unknown name '{}'", name)),
+                }
+            }
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Close) => Ok(()),
+            _ => Err("missing ')'".to_string()),
+        }
+    }
+}
+
+fn apply(op: Op, left: u16, right: u16) -> u16 {
+    match op {
+        Op::Add => left.wrapping_add(right),
+        Op::Sub => left.wrapping_sub(right),
+        Op::And => left & right,
+    }
+}
+
+fn eval(expr: &Expr, cpu: &mut CPU) -> u16 {
+    match expr {
+        Expr::Const(n) => *n,
+        Expr::Mem(addr) => cpu.bus.read(*addr) as u16,
+        Expr::Reg(register) => match register {
+            Register::A => cpu.register_a() as u16,
+            Register::X => cpu.register_x() as u16,
+            Register::Y => cpu.register_y() as u16,
+            Register::SP => cpu.stack_pointer() as u16,
+            Register::P => cpu.status() as u16,
+            Register::PC => cpu.program_counter,
+        },
+        Expr::Byte(arg) => {
+            let addr = address(arg, cpu);
+            cpu.bus.read(addr) as u16
+        }
+        Expr::Word(arg) => {
+            let addr = address(arg, cpu);
+            let lo = cpu.bus.read(addr) as u16;
+            let hi = cpu.bus.read(addr.wrapping_add(1)) as u16;
+            hi << 8 | lo
+        }
+        Expr::Binary(op, left, right) => apply(*op, eval(left, cpu), eval(right, cpu)),
+    }
+}
+
+// byte()/word() argument: $addr is an address, not a memory value
+fn address(expr: &Expr, cpu: &mut CPU) -> u16 {
+    match expr {
+        Expr::Mem(addr) => *addr,
+        Expr::Binary(op, left, right) => apply(*op, address(left, cpu), address(right, cpu)),
+        _ => eval(expr, cpu),
+    }
+}
+
+fn is_byte(expr: &Expr) -> bool {
+    match expr {
+        Expr::Const(n) => *n <= 0xff,
+        Expr::Mem(_) | Expr::Byte(_) => true,
+        Expr::Reg(register) => *register != Register::PC,
+        Expr::Word(_) => false,
+        Expr::Binary(_, left, right) => is_byte(left) && is_byte(right),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Watch {
+    source: String,
+    expr: Expr,
+}
+
+impl Watch {
+    pub fn parse(source: &str, symbols: &Symbols) -> Result<Watch, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            symbols,
+        };
+        let expr = parser
+            .expr()
+            .map_err(|e| format!("bad watch '{}': {}", source, e))?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("bad watch '{}': unexpected {:?}", source, token));
+        }
+        Ok(Watch {
+            source: source.trim().to_string(),
+            expr,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn eval(&self, cpu: &mut CPU) -> u16 {
+        eval(&self.expr, cpu)
+    }
+
+    /// "A + X = $12"
+    pub fn format(&self, cpu: &mut CPU) -> String {
+        let value = self.eval(cpu);
+        if is_byte(&self.expr) && value <= 0xff {
+            format!("{} = ${:02X}", self.source, value)
+        } else {
+            format!("{} = ${:04X}", self.source, value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::MockBus;
+
+    #[test]
+    fn test_watch() {
+        let mut mem = MockBus::new();
+        mem.space[0xfe] = 0x12;
+        mem.space[0x10] = 0x00;
+        mem.space[0x11] = 0x03;
+        mem.space[0x12] = 0x04;
+        mem.space[0x305] = 0x77;
+        // LDX #$02; LDY #$05; LDA #$f0
+        let program = CPU::transform("a2 02 a0 05 a9 f0");
+        mem.space[0x600..0x600 + program.len()].copy_from_slice(&program);
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.program_counter = 0x600;
+        cpu.interpret_fn(0x606, |_| {});
+
+        let mut symbols = Symbols::new();
+        symbols.insert(0xfe, "lives");
+        let mut watch = |source: &str| Watch::parse(source, &symbols).unwrap().format(&mut cpu);
+
+        assert_eq!(watch("$00FE"), "$00FE = $12");
+        assert_eq!(watch("lives"), "lives = $12");
+        assert_eq!(watch("word($0010)"), "word($0010) = $0300");
+        assert_eq!(watch("word($10 + X)"), "word($10 + X) = $0004");
+        assert_eq!(watch("byte(word($10) + Y)"), "byte(word($10) + Y) = $77");
+        assert_eq!(watch("A + X"), "A + X = $F2");
+        assert_eq!(watch("a + #$20"), "a + #$20 = $0110");
+        assert_eq!(watch("P & #$80"), "P & #$80 = $80");
+        assert_eq!(watch("pc - 6"), "pc - 6 = $0600");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let symbols = Symbols::new();
+        assert!(Watch::parse("word($10", &symbols).is_err());
+        assert!(Watch::parse("A +", &symbols).is_err());
+        assert!(Watch::parse("A X", &symbols).is_err());
+        assert!(Watch::parse("lives", &symbols).is_err());
+        assert!(Watch::parse("peek($10)", &symbols).is_err());
+        assert!(Watch::parse("$zz", &symbols).is_err());
+    }
+}
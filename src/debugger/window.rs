@@ -0,0 +1,283 @@
+// Disassembly window around the current PC for debugger frontends.
+//
+// Memory is read through the cpu bus, so it's always the code of the currently mapped bank.
+// Instruction boundaries before PC can't be known for sure on 6502: PCs seen before are remembered,
+// otherwise decoding is re-synchronized by disassembling from a few bytes earlier.
+// Remembered instructions are checked against memory on every request and re-disassembled
+// if the code was changed (self-modifying code in RAM, bank switching).
+use crate::bus::CpuBus;
+use crate::cpu::cpu::CPU;
+use crate::cpu::opscode;
+use crate::disasm;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+// how far back decoding starts when there is no remembered instruction before an address
+const SYNC_DISTANCE: u16 = 16;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DisasmLine {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub asm: String,
+}
+
+impl fmt::Display for DisasmLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x}: {}", self.addr, self.asm)
+    }
+}
+
+// PPU/APU/IO registers reads have side effects
+pub(super) fn readable(addr: u16) -> bool {
+    !(0x2000..0x4020).contains(&addr)
+}
+
+pub(super) fn decode<B: CpuBus + ?Sized>(cpu: &mut CPU<B>, addr: u16) -> Option<DisasmLine> {
+    if !readable(addr) {
+        return None;
+    }
+    let ops = opscode::lookup(cpu.bus.read(addr))?;
+    let mut bytes = Vec::with_capacity(ops.len as usize);
+    for i in 0..ops.len as u16 {
+        let pos = addr.wrapping_add(i);
+        if !readable(pos) {
+            return None;
+        }
+        bytes.push(cpu.bus.read(pos));
+    }
+    let asm = format!(
+        "{} {}",
+        ops.mnemonic,
+        disasm::operand(&bytes, addr as usize, ops, &HashMap::new())
+    );
+    Some(DisasmLine {
+        addr,
+        bytes,
+        asm: asm.trim().to_string(),
+    })
+}
+
+fn is_fresh<B: CpuBus + ?Sized>(cpu: &mut CPU<B>, line: &DisasmLine) -> bool {
+    line.bytes
+        .iter()
+        .enumerate()
+        .all(|(i, b)| cpu.bus.read(line.addr.wrapping_add(i as u16)) == *b)
+}
+
+pub struct DisasmWindow {
+    // instructions that were executed (seen at pc)
+    known: BTreeMap<u16, DisasmLine>,
+}
+
+impl DisasmWindow {
+    pub fn new() -> Self {
+        DisasmWindow {
+            known: BTreeMap::new(),
+        }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.known.clear();
+    }
+
+    /// Up to `size` instructions around the current pc and the index of the pc line
+    pub fn around<B: CpuBus + ?Sized>(
+        &mut self,
+        cpu: &mut CPU<B>,
+        size: usize,
+    ) -> (Vec<DisasmLine>, usize) {
+        let pc = cpu.program_counter;
+        let current = match decode(cpu, pc) {
+            Some(line) => line,
+            None => return (vec![], 0),
+        };
+        if size == 0 {
+            return (vec![], 0);
+        }
+        self.remember(&current);
+
+        let mut before = vec![];
+        let mut addr = pc;
+        while before.len() < (size - 1) / 2 {
+            match self.previous(cpu, addr) {
+                Some(line) => {
+                    addr = line.addr;
+                    before.push(line);
+                }
+                None => break,
+            }
+        }
+        before.reverse();
+
+        let index = before.len();
+        let mut lines = before;
+        let mut addr = pc.wrapping_add(current.bytes.len() as u16);
+        lines.push(current);
+        while lines.len() < size {
+            let line = match decode(cpu, addr) {
+                Some(line) => line,
+                None if readable(addr) => {
+                    let data = cpu.bus.read(addr);
+                    DisasmLine {
+                        addr,
+                        bytes: vec![data],
+                        asm: format!(".byte ${:02x}", data),
+                    }
+                }
+                None => break,
+            };
+            addr = addr.wrapping_add(line.bytes.len() as u16);
+            lines.push(line);
+        }
+        (lines, index)
+    }
+
+    // instructions overlapping with the executed one were decoded wrong or the code was changed
+    fn remember(&mut self, line: &DisasmLine) {
+        let end = line.addr as u32 + line.bytes.len() as u32;
+        let overlapping: Vec<u16> = self
+            .known
+            .range(line.addr.saturating_sub(2)..)
+            .take_while(|(addr, _)| (**addr as u32) < end)
+            .filter(|(addr, known)| {
+                **addr != line.addr && **addr as u32 + known.bytes.len() as u32 > line.addr as u32
+            })
+            .map(|(addr, _)| *addr)
+            .collect();
+        for addr in overlapping {
+            self.known.remove(&addr);
+        }
+        self.known.insert(line.addr, line.clone());
+    }
+
+    fn previous<B: CpuBus + ?Sized>(&mut self, cpu: &mut CPU<B>, addr: u16) -> Option<DisasmLine> {
+        for len in 1..=3u16 {
+            let start = addr.wrapping_sub(len);
+            if let Some(line) = self.known.get(&start) {
+                if line.bytes.len() == len as usize {
+                    if is_fresh(cpu, line) {
+                        return Some(line.clone());
+                    }
+                    // dirty: the code was modified, re-disassemble
+                    self.known.remove(&start);
+                    break;
+                }
+            }
+        }
+
+        // the earliest start from which linear decoding lands exactly on `addr`
+        for distance in (1..=SYNC_DISTANCE).rev() {
+            let mut pos = addr.wrapping_sub(distance);
+            let mut left = distance;
+            let mut last = None;
+            while left > 0 {
+                match decode(cpu, pos) {
+                    Some(line) if line.bytes.len() as u16 <= left => {
+                        left -= line.bytes.len() as u16;
+                        pos = pos.wrapping_add(line.bytes.len() as u16);
+                        last = Some(line);
+                    }
+                    _ => break,
+                }
+            }
+            if left == 0 {
+                return last;
+            }
+        }
+        None
+    }
+}
+
+impl Default for DisasmWindow {
+    fn default() -> Self {
+        DisasmWindow::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::MockBus;
+
+    fn cpu_with_program(program: &str) -> CPU {
+        let mut mem = MockBus::new();
+        let program = CPU::transform(program);
+        mem.space[0x600..0x600 + program.len()].copy_from_slice(&program);
+        let mut cpu = CPU::new(Box::from(mem));
+        cpu.program_counter = 0x600;
+        cpu
+    }
+
+    fn asm(lines: &[DisasmLine]) -> Vec<String> {
+        lines.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn test_around() {
+        // LDA #$05; STA $0200; loop: INC $10; JMP loop
+        let mut cpu = cpu_with_program("a9 05 8d 00 02 e6 10 4c 05 06");
+        let mut window = DisasmWindow::new();
+        cpu.program_counter = 0x605;
+
+        let (lines, index) = window.around(&mut cpu, 5);
+        assert_eq!(
+            asm(&lines),
+            vec![
+                "0600: LDA #$05",
+                "0602: STA $0200",
+                "0605: INC $10",
+                "0607: JMP $0605",
+                "060a: BRK"
+            ]
+        );
+        assert_eq!(index, 2);
+    }
+
+    #[test]
+    fn test_known_boundaries() {
+        // loop: INX; INX; JMP loop
+        // preceded by bytes that make backward decoding ambiguous: "a9 e8" is LDA #$e8
+        let mut cpu = cpu_with_program("e8 e8 4c 00 06");
+        for addr in 0x5f0..0x5ff {
+            cpu.bus.write(addr, 0xea);
+        }
+        cpu.bus.write(0x5ff, 0xa9);
+        cpu.program_counter = 0x602;
+        let (lines, _) = DisasmWindow::new().around(&mut cpu, 3);
+        assert_eq!(asm(&lines), vec!["0601: INX", "0602: JMP $0600", "0605: BRK"]);
+        let (lines, _) = DisasmWindow::new().around(&mut cpu, 5);
+        assert_eq!(lines[0].to_string(), "05ff: LDA #$e8");
+
+        // already executed instructions are used as anchors
+        let mut window = DisasmWindow::new();
+        for pc in 0x600..=0x602 {
+            cpu.program_counter = pc;
+            window.around(&mut cpu, 5);
+        }
+        let (lines, index) = window.around(&mut cpu, 5);
+        assert_eq!(
+            asm(&lines),
+            vec!["0600: INX", "0601: INX", "0602: JMP $0600", "0605: BRK", "0606: BRK"]
+        );
+        assert_eq!(index, 2);
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        let mut cpu = cpu_with_program("a9 05 8d 00 02 e6 10 4c 05 06");
+        let mut window = DisasmWindow::new();
+        cpu.program_counter = 0x605;
+        window.around(&mut cpu, 5);
+
+        // INC $10 -> INX; INX
+        cpu.bus.write(0x605, 0xe8);
+        cpu.bus.write(0x606, 0xe8);
+        let (lines, index) = window.around(&mut cpu, 4);
+        assert_eq!(
+            asm(&lines),
+            vec!["0602: STA $0200", "0605: INX", "0606: INX", "0607: JMP $0605"]
+        );
+        assert_eq!(index, 1);
+    }
+}
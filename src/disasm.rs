@@ -1,71 +1,160 @@
 use crate::cpu::mem::AddressingMode;
 use crate::cpu::opscode;
+use crate::rom::Rom;
+use crate::symbols::Symbols;
 use byteorder::{ByteOrder, LittleEndian};
 use std::cmp::min;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 pub struct Disasm {
     pub program: Vec<String>,
     pub hex_dump: Vec<Vec<u8>>,
     pub ops_index_map: HashMap<u16, usize>,
+    pub labels: HashMap<u16, String>,
+    /// Jump, call and branch targets and the instructions that go there, sorted
+    pub xrefs: HashMap<u16, Vec<u16>>,
+}
+
+const JSR: u8 = 0x20;
+const JMP: u8 = 0x4c;
+const JMP_INDIRECT: u8 = 0x6c;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+const BRK: u8 = 0x00;
+
+const NMI_VECTOR: usize = 0xfffa;
+const RESET_VECTOR: usize = 0xfffc;
+const IRQ_VECTOR: usize = 0xfffe;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+// max number of bytes in a single .byte line
+const DATA_LINE_LEN: usize = 8;
+
+// FCEUX code/data log: a byte per PRG ROM byte (CHR ROM bytes follow), the bits this reads
+// http://fceux.com/web/help/CodeDataLogger.html
+const CDL_CODE: u8 = 0b01;
+const CDL_DATA: u8 = 0b10;
+
+// PRG ROM (or data laid out like it) at $8000-$FFFF after power on, see `Disasm::from_rom`
+fn power_on_layout(prg: &[u8]) -> Vec<u8> {
+    let mut memory = vec![0u8; 0x10000];
+    memory[0x8000..0xc000].copy_from_slice(&prg[..PRG_BANK_SIZE]);
+    memory[0xc000..].copy_from_slice(&prg[prg.len() - PRG_BANK_SIZE..]);
+    memory
+}
+
+fn vectors(memory: &[u8]) -> Vec<u16> {
+    assert_eq!(memory.len(), 0x10000, "expected full cpu address space");
+    [NMI_VECTOR, RESET_VECTOR, IRQ_VECTOR]
+        .iter()
+        .map(|vector| LittleEndian::read_u16(&memory[*vector..]))
+        .collect()
+}
+
+fn is_branch(ops: &opscode::OpsCode) -> bool {
+    ops.len == 2 && matches!(ops.mode, AddressingMode::NoneAddressing)
+}
+
+fn branch_target(begin: usize, offset: u8) -> usize {
+    (begin + 2).wrapping_add((offset as i8) as usize)
+}
+
+fn label_name(addr: u16) -> String {
+    format!("L_{:04X}", addr)
+}
+
+fn data_line(begin: usize, bytes: &[u8]) -> String {
+    let data: Vec<String> = bytes.iter().map(|b| format!("${:02x}", b)).collect();
+    format!("{:04x}: .byte {}", begin, data.join(","))
+}
+
+// `bytes` holds the whole instruction located at `begin`
+pub(crate) fn operand(
+    bytes: &[u8],
+    begin: usize,
+    ops: &opscode::OpsCode,
+    labels: &HashMap<u16, String>,
+) -> String {
+    match ops.len {
+        1 => String::from(""),
+        2 => {
+            let address: u8 = bytes[1];
+            let zero_page = match labels.get(&(address as u16)) {
+                Some(label) => label.clone(),
+                None => format!("${:02x}", address),
+            };
+            match ops.mode {
+                AddressingMode::Immediate => format!("#${:02x}", address),
+                AddressingMode::ZeroPage => zero_page,
+                AddressingMode::ZeroPage_X => format!("{},X", zero_page),
+                AddressingMode::ZeroPage_Y => format!("{},Y", zero_page),
+                AddressingMode::Indirect_X => format!("({},X)", zero_page),
+                AddressingMode::Indirect_Y | AddressingMode::Indirect_Y_PageCross => {
+                    format!("({}),Y", zero_page)
+                }
+                AddressingMode::NoneAddressing => {
+                    // assuming local jumps: BNE, BVS, etc.... todo: check ?
+                    let target = branch_target(begin, address);
+                    match labels.get(&(target as u16)) {
+                        Some(label) => label.clone(),
+                        None => format!("${:04x}", target),
+                    }
+                }
+
+                _ => panic!(
+                    "unexpected addressing mode {:?} has ops-len 2. code {:02x}",
+                    ops.mode, ops.code
+                ),
+            }
+        }
+        3 => {
+            let address = LittleEndian::read_u16(&bytes[1..]);
+            let absolute = match labels.get(&address) {
+                Some(label) => label.clone(),
+                _ => format!("${:04x}", address),
+            };
+            match ops.mode {
+                AddressingMode::Absolute_X | AddressingMode::Absolute_X_PageCross => {
+                    format!("{},X", absolute)
+                }
+                AddressingMode::Absolute_Y | AddressingMode::Absolute_Y_PageCross => {
+                    format!("{},Y", absolute)
+                }
+                _ if ops.code == JMP_INDIRECT => format!("({})", absolute),
+                _ => absolute,
+            }
+        }
+        _ => String::from(""),
+    }
 }
 
 impl Disasm {
     pub fn new(program: &[u8], start: usize) -> Self {
-        let ref opscodes: HashMap<u8, &'static opscode::OpsCode> = *opscode::OPSCODES_MAP;
-
         let mut begin = start;
         let mut asm = Vec::new();
         let mut mapping: HashMap<u16, usize> = HashMap::new();
         let mut hex_dump: Vec<Vec<u8>> = Vec::new();
         while begin < program.len() {
-            //todo: should be another condition as well
-            let code = &program[begin];
-            if !opscodes.contains_key(code) {
-                panic!("unknown ops code {:02x}", code);
-            }
-
-            let ops = opscodes.get(code).unwrap();
-
-            let tmp = match ops.len {
-                1 => {
-                    hex_dump.push(vec![*code]);
-                    String::from("")
-                }
-                2 => {
-                    let address: u8 = program[begin + 1];
-                    hex_dump.push(vec![*code, address]);
-                    match ops.mode {
-                        AddressingMode::Immediate => format!("#${:02x}", address),
-                        AddressingMode::ZeroPage => format!("${:02x}", address),
-                        AddressingMode::ZeroPage_X => format!("${:02x},X", address),
-                        AddressingMode::ZeroPage_Y => format!("${:02x},Y", address),
-                        AddressingMode::Indirect_X => format!("(${:02x},X)", address),
-                        AddressingMode::Indirect_Y => format!("(${:02x}),Y", address),
-                        AddressingMode::NoneAddressing => {
-                            // assuming local jumps: BNE, BVS, etc.... todo: check ?
-                            let address: usize = (begin + 2).wrapping_add((address as i8) as usize);
-                            format!("${:04x}", address)
-                        }
-
-                        _ => panic!(
-                            "unexpected addressing mode {:?} has ops-len 2. code {:02x}",
-                            ops.mode, ops.code
-                        ),
-                    }
+            let ops = opscode::lookup(program[begin])
+                .filter(|ops| begin + ops.len as usize <= program.len());
+            // no such opcode or the operand is cut off by the end of the program
+            let ops = match ops {
+                Some(ops) => ops,
+                None => {
+                    let end = match opscode::lookup(program[begin]) {
+                        Some(_) => program.len(),
+                        None => begin + 1,
+                    };
+                    hex_dump.push(program[begin..end].to_vec());
+                    asm.push(data_line(begin, &program[begin..end]));
+                    mapping.insert(begin as u16, asm.len() - 1);
+                    begin = end;
+                    continue;
                 }
-                3 => {
-                    if begin + 1 >= program.len() || begin + 2 >= program.len() {
-                        panic!("unexpected end of program. code {:02x} requires 2 parameters, but only {} byte(s) left ", ops.code, program.len() - begin);
-                    }
-                    hex_dump.push(vec![*code, program[begin + 1], program[begin + 2]]);
-                    format!(
-                        "${:04x}",
-                        LittleEndian::read_u16(&program[begin + 1 as usize..])
-                    )
-                }
-                _ => String::from(""),
             };
+            hex_dump.push(program[begin..begin + ops.len as usize].to_vec());
+            let tmp = operand(&program[begin..], begin, ops, &HashMap::new());
 
             let asm_str = format!("{:04x}: {} {}", begin, ops.mnemonic, tmp)
                 .trim()
@@ -79,9 +168,309 @@ impl Disasm {
             program: asm,
             ops_index_map: mapping,
             hex_dump: hex_dump,
+            labels: HashMap::new(),
+            xrefs: HashMap::new(),
         }
     }
 
+    /// Recursive traversal disassembly: follows JMP/JSR/branches starting from the entry points.
+    /// Bytes that are never reached are treated as data and dumped as `.byte` lines.
+    /// Jump/branch targets get `L_XXXX` labels (or names from `symbols`), which are used in operands
+    /// instead of raw addresses.
+    /// As with `Disasm::new`, an address is an index in `program`.
+    pub fn traverse(program: &[u8], entry_points: &[u16], symbols: &Symbols) -> Self {
+        Disasm::traverse_from(program, 0, entry_points, symbols, &[])
+    }
+
+    // only `program[start..]` is disassembled, jumps below `start` are not followed.
+    // `cdl` holds code/data log flags for `program` positions (empty if there is no log): bytes
+    // logged as data only are never decoded, runs of bytes logged as code are extra entry points
+    // (code reached through jump tables and RTS tricks)
+    fn traverse_from(
+        program: &[u8],
+        start: usize,
+        entry_points: &[u16],
+        symbols: &Symbols,
+        cdl: &[u8],
+    ) -> Self {
+        let flags = |pos: usize| cdl.get(pos).copied().unwrap_or(0);
+        // instructions start positions
+        let mut code = vec![false; program.len()];
+        // positions belonging to any instruction (opcode + operands)
+        let mut covered = vec![false; program.len()];
+
+        // the logged code goes last: the entry points are popped first
+        let mut pending: Vec<usize> = (start..program.len())
+            .filter(|pos| {
+                flags(*pos) & CDL_CODE != 0 && (*pos == 0 || flags(pos - 1) & CDL_CODE == 0)
+            })
+            .collect();
+        pending.extend(entry_points.iter().map(|addr| *addr as usize));
+        let mut targets: Vec<usize> = entry_points.iter().map(|addr| *addr as usize).collect();
+        let mut xrefs: HashMap<u16, Vec<u16>> = HashMap::new();
+        while let Some(mut begin) = pending.pop() {
+            while begin >= start && begin < program.len() && !covered[begin] {
+                if flags(begin) & (CDL_CODE | CDL_DATA) == CDL_DATA {
+                    break;
+                }
+                let ops = match opscode::lookup(program[begin]) {
+                    Some(ops) => ops,
+                    None => break,
+                };
+                let end = begin + ops.len as usize;
+                if end > program.len() || covered[begin..end].iter().any(|c| *c) {
+                    break;
+                }
+                code[begin] = true;
+                covered[begin..end].iter_mut().for_each(|c| *c = true);
+
+                let (target, stop) = match ops.code {
+                    JMP => (Some(LittleEndian::read_u16(&program[begin + 1..]) as usize), true),
+                    JSR => (Some(LittleEndian::read_u16(&program[begin + 1..]) as usize), false),
+                    // indirect jump target is not known statically
+                    JMP_INDIRECT | RTS | RTI | BRK => (None, true),
+                    _ if is_branch(ops) => (Some(branch_target(begin, program[begin + 1])), false),
+                    _ => (None, false),
+                };
+                if let Some(target) = target {
+                    pending.push(target);
+                    targets.push(target);
+                    xrefs.entry(target as u16).or_default().push(begin as u16);
+                }
+                if stop {
+                    break;
+                }
+                begin = end;
+            }
+        }
+
+        let mut labels: HashMap<u16, String> = targets
+            .into_iter()
+            .filter(|addr| *addr >= start && *addr < program.len() && code[*addr])
+            .map(|addr| (addr as u16, label_name(addr as u16)))
+            .collect();
+        for (addr, name) in symbols.iter() {
+            labels.insert(addr, name.to_string());
+        }
+        xrefs.retain(|addr, _| code.get(*addr as usize) == Some(&true));
+        for sources in xrefs.values_mut() {
+            sources.sort_unstable();
+            sources.dedup();
+        }
+
+        let mut asm = Vec::new();
+        let mut mapping: HashMap<u16, usize> = HashMap::new();
+        let mut hex_dump: Vec<Vec<u8>> = Vec::new();
+        let mut begin = start;
+        while begin < program.len() {
+            if let Some(label) = labels.get(&(begin as u16)) {
+                hex_dump.push(vec![]);
+                match xrefs.get(&(begin as u16)) {
+                    Some(sources) => {
+                        let sources: Vec<String> = sources
+                            .iter()
+                            .map(|addr| format!("${:04x}", addr))
+                            .collect();
+                        asm.push(format!("{}: ; xref {}", label, sources.join(",")));
+                    }
+                    None => asm.push(format!("{}:", label)),
+                }
+            }
+            if code[begin] {
+                let ops = opscode::lookup(program[begin]).unwrap();
+                let end = begin + ops.len as usize;
+                hex_dump.push(program[begin..end].to_vec());
+                asm.push(
+                    format!(
+                        "{:04x}: {} {}",
+                        begin,
+                        ops.mnemonic,
+                        operand(&program[begin..end], begin, ops, &labels)
+                    )
+                    .trim()
+                    .to_string(),
+                );
+                mapping.insert(begin as u16, asm.len() - 1);
+                begin = end;
+            } else {
+                let mut end = begin + 1;
+                while end < program.len()
+                    && !covered[end]
+                    && !labels.contains_key(&(end as u16))
+                    && end - begin < DATA_LINE_LEN
+                {
+                    end += 1;
+                }
+                let bytes = &program[begin..end];
+                hex_dump.push(bytes.to_vec());
+                asm.push(data_line(begin, bytes));
+                mapping.insert(begin as u16, asm.len() - 1);
+                begin = end;
+            }
+        }
+
+        Disasm {
+            program: asm,
+            ops_index_map: mapping,
+            hex_dump,
+            labels,
+            xrefs,
+        }
+    }
+
+    /// Traversal disassembly of the whole cpu address space (64KB), starting from NMI/RESET/IRQ vectors
+    pub fn from_vectors(memory: &[u8], symbols: &Symbols) -> Self {
+        Disasm::traverse(memory, &vectors(memory), symbols)
+    }
+
+    /// Traversal disassembly of PRG ROM as it is seen by cpu at $8000-$FFFF after power on:
+    /// 16KB PRG is mirrored, for bigger roms the first bank is at $8000 and the last one at $C000
+    /// (that's the way most of the mappers start).
+    pub fn from_rom(rom: &Rom, symbols: &Symbols) -> Self {
+        let memory = power_on_layout(&rom.prg_rom);
+        Disasm::traverse_from(&memory, 0x8000, &vectors(&memory), symbols, &[])
+    }
+
+    /// `from_rom` guided by an FCEUX code/data log (.cdl) of the same rom: bytes the game read
+    /// as data stay `.byte`, code only reached through jump tables gets disassembled
+    pub fn from_rom_cdl(rom: &Rom, cdl: &[u8], symbols: &Symbols) -> Result<Self, String> {
+        let prg = &rom.prg_rom;
+        if cdl.len() < prg.len() {
+            return Err(format!(
+                "code/data log is {} bytes, shorter than the {} bytes of PRG ROM",
+                cdl.len(),
+                prg.len()
+            ));
+        }
+        let memory = power_on_layout(prg);
+        let cdl = power_on_layout(&cdl[..prg.len()]);
+        let entry_points = vectors(&memory);
+        Ok(Disasm::traverse_from(
+            &memory,
+            0x8000,
+            &entry_points,
+            symbols,
+            &cdl,
+        ))
+    }
+
+    /// Traversal disassembly of a single 16KB PRG bank. The last bank is at $C000 with the
+    /// vectors as the entry points; the others are switchable, they are put at $8000 and
+    /// traversed from the bank start and the `symbols` inside the bank (the mapper isn't known).
+    pub fn from_bank(rom: &Rom, bank: usize, symbols: &Symbols) -> Result<Self, String> {
+        let banks = rom.prg_rom.len() / PRG_BANK_SIZE;
+        if bank >= banks {
+            return Err(format!("no PRG bank {}, the rom has {}", bank, banks));
+        }
+        let prg = &rom.prg_rom[bank * PRG_BANK_SIZE..(bank + 1) * PRG_BANK_SIZE];
+        let mut memory = vec![0u8; 0x10000];
+        if bank == banks - 1 {
+            memory[0xc000..].copy_from_slice(prg);
+            let entry_points = vectors(&memory);
+            return Ok(Disasm::traverse_from(
+                &memory,
+                0xc000,
+                &entry_points,
+                symbols,
+                &[],
+            ));
+        }
+        memory[0x8000..0xc000].copy_from_slice(prg);
+        let mut entry_points = vec![0x8000];
+        entry_points.extend(
+            symbols
+                .iter()
+                .map(|(addr, _)| addr)
+                .filter(|addr| (0x8000..0xc000).contains(addr)),
+        );
+        Ok(Disasm::traverse_from(
+            &memory[..0xc000],
+            0x8000,
+            &entry_points,
+            symbols,
+            &[],
+        ))
+    }
+
+    /// Source that can be assembled back with ca65: `.org`, labels, `.byte` for data regions
+    /// and no address prefixes. Labels pointing outside of the disassembled code become equates.
+    /// Unofficial opcodes are kept as `.byte` (ca65 doesn't know them in 6502 mode).
+    pub fn to_ca65(&self) -> String {
+        let defined: HashSet<&str> = self
+            .program
+            .iter()
+            .zip(self.hex_dump.iter())
+            .filter(|(_, bytes)| bytes.is_empty())
+            .map(|(line, _)| &line[..line.find(':').unwrap_or(line.len())])
+            .collect();
+        let mut equates: Vec<(&u16, &String)> = self
+            .labels
+            .iter()
+            .filter(|(_, name)| !defined.contains(name.as_str()))
+            .collect();
+        equates.sort();
+
+        let mut out = String::new();
+        for (addr, name) in equates {
+            out.push_str(&format!("{} = ${:04x}\n", name, addr));
+        }
+
+        if let Some(origin) = self.ops_index_map.keys().min() {
+            out.push_str(&format!(".org ${:04x}\n", origin));
+        }
+        for (line, bytes) in self.program.iter().zip(self.hex_dump.iter()) {
+            if bytes.is_empty() {
+                out.push_str(line);
+                out.push('\n');
+                continue;
+            }
+            // "xxxx: <asm>"
+            let asm = &line[line.find(": ").unwrap() + 2..];
+
+            let ops = opscode::lookup(bytes[0]).filter(|_| !asm.starts_with(".byte"));
+            let asm = match ops {
+                Some(ops) if ops.mnemonic.starts_with('*') => {
+                    let data: Vec<String> = bytes.iter().map(|b| format!("${:02x}", b)).collect();
+                    format!(".byte {} ; {}", data.join(","), asm)
+                }
+                // ca65 would pick zero page encoding for an absolute operand below $100
+                Some(ops)
+                    if ops.len == 3
+                        && bytes[2] == 0
+                        && !matches!(ops.mode, AddressingMode::NoneAddressing) =>
+                {
+                    format!("{} a:{}", ops.mnemonic, &asm[ops.mnemonic.len() + 1..])
+                }
+                _ => asm.to_string(),
+            };
+            out.push_str(&format!("    {}\n", asm));
+        }
+        out
+    }
+
+    /// Listing with the bytes of every line in an aligned column:
+    ///   8000  a9 00                    LDA #$00
+    pub fn to_listing(&self) -> String {
+        let mut out = String::new();
+        for (line, bytes) in self.program.iter().zip(self.hex_dump.iter()) {
+            if bytes.is_empty() {
+                out.push_str(line);
+                out.push('\n');
+                continue;
+            }
+            let (addr, asm) = line.split_at(line.find(": ").unwrap());
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            out.push_str(&format!(
+                "{}  {:width$} {}\n",
+                addr,
+                hex.join(" "),
+                &asm[2..],
+                width = DATA_LINE_LEN * 3 - 1
+            ));
+        }
+        out
+    }
+
     pub fn slice(&self, pos: u16) -> (&[String], usize) {
         let index = *self.ops_index_map.get(&pos).unwrap();
         let slice_size = min(10 as usize, self.program.len());
@@ -97,13 +486,11 @@ impl Disasm {
 }
 
 pub fn disasm(program: &[u8], start: usize) -> Vec<String> {
-    let ref opscodes: HashMap<u8, &'static opscode::OpsCode> = *opscode::OPSCODES_MAP;
-
     let mut begin = start;
     let mut result = Vec::new();
     while begin < program.len() {
         let code = &program[begin];
-        let ops = opscodes.get(code).unwrap();
+        let ops = opscode::lookup(*code).unwrap();
 
         let tmp = match ops.len {
             2 => format!("#${:02x}", program[begin + 1]),
@@ -136,6 +523,209 @@ mod test {
         assert_eq!(asm.ops_index_map.get(&2), Some(&1));
     }
 
+    #[test]
+    fn test_traverse() {
+        // 0000: JSR $000b
+        // 0003: BNE $000a
+        // 0005: JMP $000a
+        // 0008: .byte $ff,$02 - unreachable data
+        // 000a: RTS
+        // 000b: LDX #$01
+        // 000d: JMP $000a
+        // 0010: .byte $ff
+        let program = CPU::transform("20 0b 00 d0 05 4c 0a 00 ff 02 60 a2 01 4c 0a 00 ff");
+        let asm = Disasm::traverse(&program, &[0], &Symbols::new());
+        let result = vec![
+            "L_0000:",
+            "0000: JSR L_000B",
+            "0003: BNE L_000A",
+            "0005: JMP L_000A",
+            "0008: .byte $ff,$02",
+            "L_000A: ; xref $0003,$0005,$000d",
+            "000a: RTS",
+            "L_000B: ; xref $0000",
+            "000b: LDX #$01",
+            "000d: JMP L_000A",
+            "0010: .byte $ff",
+        ];
+        assert_eq!(asm.program, result);
+        assert_eq!(asm.hex_dump[4], vec!(0xff, 0x02));
+        assert_eq!(asm.hex_dump[5], Vec::<u8>::new());
+        assert_eq!(asm.ops_index_map.get(&0x000b), Some(&8));
+        assert_eq!(asm.labels.get(&0x000a), Some(&"L_000A".to_string()));
+        assert_eq!(asm.xrefs.get(&0x000a), Some(&vec![0x0003, 0x0005, 0x000d]));
+        assert_eq!(asm.xrefs.get(&0x0000), None);
+    }
+
+    #[test]
+    fn test_from_vectors() {
+        let mut memory = vec![0xffu8; 0x10000];
+        // reset: LDA #$00; loop: JMP loop
+        memory[0x8000..0x8005].copy_from_slice(&CPU::transform("a9 00 4c 02 80"));
+        // nmi/irq: RTI
+        memory[0x8010] = 0x40;
+        memory[0xfffa..].copy_from_slice(&CPU::transform("10 80 00 80 10 80"));
+
+        let asm = Disasm::from_vectors(&memory, &Symbols::new());
+        let reset = *asm.ops_index_map.get(&0x8000).unwrap();
+        assert_eq!(asm.program[reset], "8000: LDA #$00");
+        assert_eq!(asm.program[reset - 1], "L_8000:");
+        assert_eq!(asm.program[reset + 1], "L_8002: ; xref $8002");
+        assert_eq!(asm.program[reset + 2], "8002: JMP L_8002");
+        assert_eq!(asm.program[reset + 3], "8005: .byte $ff,$ff,$ff,$ff,$ff,$ff,$ff,$ff");
+        let nmi = *asm.ops_index_map.get(&0x8010).unwrap();
+        assert_eq!(asm.program[nmi], "8010: RTI");
+    }
+
+    #[test]
+    fn test_traverse_with_symbols() {
+        // reset: LDA $10; STA $2000; loop: JMP loop; .byte $ff
+        let program = CPU::transform("a5 10 8d 00 20 4c 05 00 ff");
+        let mut symbols = Symbols::new();
+        symbols.insert(0x0000, "reset");
+        symbols.insert(0x0010, "frame_counter");
+        symbols.insert(0x2000, "PPU_CTRL");
+        symbols.insert(0x0008, "table");
+
+        let asm = Disasm::traverse(&program, &[0], &symbols);
+        let result = vec![
+            "reset:",
+            "0000: LDA frame_counter",
+            "0002: STA PPU_CTRL",
+            "L_0005: ; xref $0005",
+            "0005: JMP L_0005",
+            "table:",
+            "0008: .byte $ff",
+        ];
+        assert_eq!(asm.program, result);
+    }
+
+    #[test]
+    fn test_from_rom() {
+        let mut rom = crate::rom::test_ines_rom::test_rom();
+        // 16KB PRG: visible both at $8000 and $C000
+        let mut prg = vec![0xffu8; PRG_BANK_SIZE];
+        // reset: SEI; loop: JMP loop
+        prg[0..4].copy_from_slice(&CPU::transform("78 4c 01 c0"));
+        // nmi/irq: RTI
+        prg[4] = 0x40;
+        prg[0x3ffa..].copy_from_slice(&CPU::transform("04 80 00 c0 04 80"));
+        rom.prg_rom = prg;
+
+        let asm = Disasm::from_rom(&rom, &Symbols::new());
+        assert_eq!(asm.program[0], "8000: .byte $78,$4c,$01,$c0");
+        assert_eq!(asm.program[1], "L_8004:");
+        assert_eq!(asm.program[2], "8004: RTI");
+        let reset = *asm.ops_index_map.get(&0xc000).unwrap();
+        assert_eq!(asm.program[reset], "c000: SEI");
+        assert_eq!(asm.program[reset + 2], "c001: JMP L_C001");
+        assert_eq!(asm.ops_index_map.get(&0x0000), None);
+    }
+
+    #[test]
+    fn test_from_bank() {
+        let mut rom = crate::rom::test_ines_rom::test_rom();
+        let mut prg = vec![0xffu8; 2 * PRG_BANK_SIZE];
+        // bank 0: RTS at the start, a named routine at $8010
+        prg[0] = 0x60;
+        prg[0x10..0x12].copy_from_slice(&CPU::transform("e8 60"));
+        // bank 1: reset vector -> $C000, CLI; RTI
+        prg[PRG_BANK_SIZE..PRG_BANK_SIZE + 2].copy_from_slice(&CPU::transform("58 40"));
+        prg[2 * PRG_BANK_SIZE - 6..].copy_from_slice(&CPU::transform("00 c0 00 c0 00 c0"));
+        rom.prg_rom = prg;
+        let mut symbols = Symbols::new();
+        symbols.insert(0x8010, "inc_x");
+
+        let asm = Disasm::from_bank(&rom, 0, &symbols).unwrap();
+        assert_eq!(asm.program[0], "L_8000:");
+        assert_eq!(asm.program[1], "8000: RTS");
+        let routine = *asm.ops_index_map.get(&0x8010).unwrap();
+        assert_eq!(asm.program[routine - 1], "inc_x:");
+        assert_eq!(asm.program[routine], "8010: INX");
+        assert_eq!(asm.ops_index_map.get(&0xc000), None);
+
+        let asm = Disasm::from_bank(&rom, 1, &symbols).unwrap();
+        assert_eq!(asm.program[1], "c000: CLI");
+        assert_eq!(asm.ops_index_map.get(&0x8000), None);
+
+        assert!(Disasm::from_bank(&rom, 2, &symbols).is_err());
+    }
+
+    #[test]
+    fn test_from_rom_cdl() {
+        let mut rom = crate::rom::test_ines_rom::test_rom();
+        let mut prg = vec![0xffu8; PRG_BANK_SIZE];
+        // reset: JSR print; .byte $a9,$01 (read by print, looks like LDA #$01); JMP ($0010)
+        prg[0..8].copy_from_slice(&CPU::transform("20 10 c0 a9 01 6c 10 00"));
+        // print: RTS
+        prg[0x10] = 0x60;
+        // handler, only reached through the indirect jump: INX; RTI
+        prg[0x20..0x22].copy_from_slice(&CPU::transform("e8 40"));
+        prg[0x3ffa..].copy_from_slice(&CPU::transform("00 c0 00 c0 00 c0"));
+        rom.prg_rom = prg;
+        let mut cdl = vec![0u8; PRG_BANK_SIZE + rom.chr_rom.len()];
+        cdl[0..3].copy_from_slice(&[CDL_CODE; 3]);
+        cdl[3..5].copy_from_slice(&[CDL_DATA; 2]);
+        cdl[5..8].copy_from_slice(&[CDL_CODE; 3]);
+        cdl[0x10] = CDL_CODE;
+        cdl[0x20..0x22].copy_from_slice(&[CDL_CODE; 2]);
+
+        let asm = Disasm::from_rom(&rom, &Symbols::new());
+        let inline = *asm.ops_index_map.get(&0xc003).unwrap();
+        assert_eq!(asm.program[inline], "c003: LDA #$01");
+        assert_eq!(asm.ops_index_map.get(&0xc020), None);
+
+        let asm = Disasm::from_rom_cdl(&rom, &cdl, &Symbols::new()).unwrap();
+        let inline = *asm.ops_index_map.get(&0xc003).unwrap();
+        assert_eq!(asm.program[inline], "c003: .byte $a9,$01");
+        assert_eq!(asm.program[inline + 1], "c005: JMP ($0010)");
+        let handler = *asm.ops_index_map.get(&0xc020).unwrap();
+        assert_eq!(asm.program[handler], "c020: INX");
+        assert!(Disasm::from_rom_cdl(&rom, &cdl[..100], &Symbols::new()).is_err());
+    }
+
+    #[test]
+    fn test_data_and_listing() {
+        // LDX #$08; JMP $0000 cut off by the end of the program
+        let asm = Disasm::new(&CPU::transform("a2 08 4c 00"), 0);
+        assert_eq!(asm.program, vec!["0000: LDX #$08", "0002: .byte $4c,$00"]);
+
+        let asm = Disasm::traverse(&CPU::transform("d0 fe ff"), &[0], &Symbols::new());
+        let expected = "L_0000: ; xref $0000\n\
+                        0000  d0 fe                   BNE L_0000\n\
+                        0002  ff                      .byte $ff\n";
+        assert_eq!(asm.to_listing(), expected);
+    }
+
+    #[test]
+    fn test_absolute_indexed() {
+        let asm = Disasm::new(&CPU::transform("bd 00 02 b9 10 02 6c 00 03"), 0);
+        let result = vec!["0000: LDA $0200,X", "0003: LDA $0210,Y", "0006: JMP ($0300)"];
+        assert_eq!(asm.program, result);
+    }
+
+    #[test]
+    fn test_to_ca65() {
+        // reset: LDA $0010; STA $2000,X; *NOP; loop: BNE loop; JMP reset; .byte $ff,$02
+        let program = CPU::transform("ad 10 00 9d 00 20 1a d0 fe 4c 00 00 ff 02");
+        let mut symbols = Symbols::new();
+        symbols.insert(0x0000, "reset");
+        symbols.insert(0x2000, "PPU_CTRL");
+
+        let asm = Disasm::traverse(&program, &[0], &symbols);
+        let expected = "PPU_CTRL = $2000\n\
+                        .org $0000\n\
+                        reset: ; xref $0009\n\
+                        \x20   LDA a:$0010\n\
+                        \x20   STA PPU_CTRL,X\n\
+                        \x20   .byte $1a ; *NOP\n\
+                        L_0007: ; xref $0007\n\
+                        \x20   BNE L_0007\n\
+                        \x20   JMP reset\n\
+                        \x20   .byte $ff,$02\n";
+        assert_eq!(asm.to_ca65(), expected);
+    }
+
     #[test]
     fn test_slice() {
         let asm = Disasm::new(
@@ -0,0 +1,209 @@
+// Machine state as JSON, for external analysis tools and visualization notebooks:
+//
+//   {
+//     "frame": 120,
+//     "mirroring": "vertical",
+//     "nametables": [{"addr": 8192, "tiles": [960 tile indices], "attributes": [64 bytes]}, ...],
+//     "oam": [{"index": 0, "x": 88, "y": 127, "tile": 58, "palette": 0,
+//              "behind_background": false, "flip_horizontal": false, "flip_vertical": false}, ...],
+//     "palettes": {"indices": [32 bytes], "rgb": [["#7c7c7c", ...4 colors], ...8 palettes]},
+//     "ram": [{"start": 0, "end": 255, "bytes": [...]}]
+//   }
+//
+// The 4 logical nametables ($2000, $2400, $2800, $2C00) are listed as the CPU sees them, through
+// the mirroring. All the numbers are decimal. Written by hand: the library has no JSON dependency.
+use crate::bus::{self, Bus};
+use crate::ppu::ppu::NesPPU;
+use crate::rom::Mirroring;
+
+const NAMETABLES: [u16; 4] = [0x2000, 0x2400, 0x2800, 0x2c00];
+const TILES: u16 = 960;
+const ATTRIBUTES: u16 = 64;
+
+pub(crate) fn bytes<I: IntoIterator<Item = u8>>(bytes: I) -> String {
+    let values: Vec<String> = bytes.into_iter().map(|b| b.to_string()).collect();
+    format!("[{}]", values.join(","))
+}
+
+/// Quoted and escaped JSON string
+pub(crate) fn string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// RAM and PRG RAM without going through the bus: reads can have side effects
+fn ram_byte(bus: &Bus<NesPPU>, addr: u16) -> u8 {
+    if addr < 0x2000 {
+        bus.ram[(addr & 0x7ff) as usize]
+    } else {
+        bus.prg_ram[(addr - 0x6000) as usize]
+    }
+}
+
+fn nametables(ppu: &NesPPU) -> String {
+    let nametables: Vec<String> = NAMETABLES
+        .iter()
+        .map(|&base| {
+            let byte = |addr: u16| ppu.read_nametable(addr);
+            format!(
+                "{{\"addr\":{},\"tiles\":{},\"attributes\":{}}}",
+                base,
+                bytes((base..base + TILES).map(byte)),
+                bytes((base + TILES..base + TILES + ATTRIBUTES).map(byte))
+            )
+        })
+        .collect();
+    format!("[{}]", nametables.join(","))
+}
+
+fn oam(ppu: &NesPPU) -> String {
+    let sprites: Vec<String> = ppu
+        .oam_data
+        .chunks(4)
+        .enumerate()
+        .map(|(idx, sprite)| {
+            format!(
+                "{{\"index\":{},\"x\":{},\"y\":{},\"tile\":{},\"palette\":{},\
+                 \"behind_background\":{},\"flip_horizontal\":{},\"flip_vertical\":{}}}",
+                idx,
+                sprite[3],
+                sprite[0],
+                sprite[1],
+                sprite[2] & 0b11,
+                sprite[2] & 0b0010_0000 != 0,
+                sprite[2] & 0b0100_0000 != 0,
+                sprite[2] & 0b1000_0000 != 0
+            )
+        })
+        .collect();
+    format!("[{}]", sprites.join(","))
+}
+
+fn palettes(ppu: &NesPPU) -> String {
+    let rgb: Vec<String> = ppu
+        .rgb_palettes
+        .iter()
+        .map(|palette| {
+            let colors: Vec<String> = palette
+                .iter()
+                .map(|(r, g, b)| format!("\"#{:02x}{:02x}{:02x}\"", r, g, b))
+                .collect();
+            format!("[{}]", colors.join(","))
+        })
+        .collect();
+    format!(
+        "{{\"indices\":{},\"rgb\":[{}]}}",
+        bytes(ppu.palette_table.iter().copied()),
+        rgb.join(",")
+    )
+}
+
+/// `ram_ranges`: inclusive (start, end) address ranges of RAM ($0000-$1FFF) or PRG RAM
+/// ($6000-$7FFF)
+pub fn state_json(
+    bus: &Bus<NesPPU>,
+    frame: usize,
+    ram_ranges: &[(u16, u16)],
+) -> Result<String, String> {
+    let mut ram = vec![];
+    for &(start, end) in ram_ranges {
+        if start > end {
+            return Err(format!("bad range ${:04X}-${:04X}", start, end));
+        }
+        if let Some(addr) = (start..=end).find(|&addr| !bus::is_ram(addr)) {
+            return Err(format!("${:04X} is not in RAM", addr));
+        }
+        ram.push(format!(
+            "{{\"start\":{},\"end\":{},\"bytes\":{}}}",
+            start,
+            end,
+            bytes((start..=end).map(|addr| ram_byte(bus, addr)))
+        ));
+    }
+
+    let ppu = bus.ppu();
+    let mirroring = match ppu.mirroring {
+        Mirroring::VERTICAL => "vertical",
+        Mirroring::HORIZONTAL => "horizontal",
+        Mirroring::SINGLE_SCREEN_LOWER => "single screen lower",
+        Mirroring::SINGLE_SCREEN_UPPER => "single screen upper",
+        Mirroring::CUSTOM(_) => "custom",
+    };
+    Ok(format!(
+        "{{\"frame\":{},\"mirroring\":\"{}\",\"nametables\":{},\"oam\":{},\"palettes\":{},\"ram\":[{}]}}",
+        frame,
+        mirroring,
+        nametables(ppu),
+        oam(ppu),
+        palettes(ppu),
+        ram.join(",")
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test_ines_rom;
+
+    #[test]
+    fn test_state_json() {
+        let mut bus = Bus::<NesPPU>::new(test_ines_rom::test_rom());
+        bus.ram[0x10] = 0xaa;
+        bus.ram[0x11] = 7;
+        bus.prg_ram[0] = 0x42;
+        {
+            let ppu = bus.ppu_mut();
+            ppu.mirroring = Mirroring::HORIZONTAL;
+            // $2400 mirrors $2000
+            ppu.vram[0] = 0x24;
+            ppu.vram[0x3c0] = 0xff;
+            ppu.oam_data[4..8].copy_from_slice(&[0x7f, 0x3a, 0b0110_0001, 0x58]);
+            ppu.palette_table[0] = 0x0f;
+        }
+
+        let json = state_json(&bus, 3, &[(0x10, 0x11), (0x6000, 0x6000)]).unwrap();
+        assert!(json.starts_with("{\"frame\":3,\"mirroring\":\"horizontal\","));
+        assert!(json.contains("{\"addr\":8192,\"tiles\":[36,0,"));
+        assert!(json.contains("{\"addr\":9216,\"tiles\":[36,0,"));
+        assert!(json.contains("{\"addr\":10240,\"tiles\":[0,0,"));
+        assert!(json.contains("\"attributes\":[255,0,"));
+        assert!(json.contains(
+            "{\"index\":1,\"x\":88,\"y\":127,\"tile\":58,\"palette\":1,\
+             \"behind_background\":true,\"flip_horizontal\":true,\"flip_vertical\":false}"
+        ));
+        assert!(json.contains("\"indices\":[15,0,"));
+        assert!(json.ends_with(
+            "\"ram\":[{\"start\":16,\"end\":17,\"bytes\":[170,7]},\
+             {\"start\":24576,\"end\":24576,\"bytes\":[66]}]}"
+        ));
+        assert_eq!(json.matches("\"index\":").count(), 64);
+        assert_eq!(json.matches("\"tiles\":").count(), 4);
+        assert_eq!(json.matches('{').count(), json.matches('}').count());
+        assert_eq!(json.matches('[').count(), json.matches(']').count());
+    }
+
+    #[test]
+    fn test_string() {
+        assert_eq!(string("a \"b\" \\ c\n\t"), "\"a \\\"b\\\" \\\\ c\\n\\u0009\"");
+    }
+
+    #[test]
+    fn test_bad_ranges() {
+        let bus = Bus::<NesPPU>::new(test_ines_rom::test_rom());
+        assert_eq!(
+            state_json(&bus, 0, &[(0x1ff0, 0x2001)]).err(),
+            Some(String::from("$2000 is not in RAM"))
+        );
+        assert!(state_json(&bus, 0, &[(0x20, 0x10)]).is_err());
+    }
+}
@@ -0,0 +1,1206 @@
+// High level api for embedding the emulator: rom in, frames out.
+//
+//   let rom = Rom::load(&data)?;
+//   let mut emulator = Emulator::builder(rom).ram_init(RamInit::Fill(0xff)).build();
+//   loop {
+//       let frame = emulator.run_frame(&Inputs::new(JoypadButton::START))?;
+//       // frame.data is 256x240 RGB24
+//   }
+use crate::bus::{Bus, CpuBus};
+use crate::cheats::Cheats;
+use crate::config_file::ConfigFile;
+#[cfg(feature = "std")]
+use crate::cpu::TraceFormat;
+use crate::cpu::cpu::CPU;
+use crate::cpu::mem::Mem;
+#[cfg(feature = "std")]
+use crate::cpu::trace_filter::TraceFilter;
+#[cfg(feature = "std")]
+use crate::cpu::trace_writer::TraceWriter;
+use crate::dump;
+use crate::error::RustnessError;
+use crate::events::EmulatorEvents;
+use crate::input::JoypadButton;
+use crate::ppu::ppu::NesPPU;
+use crate::region::Region;
+use crate::rom::db::GameDb;
+use crate::rom::Rom;
+#[cfg(feature = "save-state")]
+use crate::save_state::Snapshot;
+use crate::screen::frame::{Frame, PixelSink};
+use crate::screen::indexed::IndexedFrame;
+use crate::screen::palette;
+#[cfg(feature = "std")]
+use crate::debugger::crash_report::{self, ExecutionHistory};
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::io::Write;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+/// RAM content at power on: it's random on the real hardware, a few games depend on it
+/// https://wiki.nesdev.com/w/index.php/CPU_power_up_state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RamInit {
+    Fill(u8),
+    /// 4 bytes of $00, 4 bytes of $FF, ... (same as FCEUX)
+    Alternating,
+}
+
+impl RamInit {
+    fn byte(&self, addr: usize) -> u8 {
+        match self {
+            RamInit::Fill(value) => *value,
+            RamInit::Alternating if addr & 0b100 == 0 => 0x00,
+            RamInit::Alternating => 0xff,
+        }
+    }
+
+    /// For frontends that drive the `Bus` themselves, at power on and on a power cycle
+    pub fn fill(&self, ram: &mut [u8]) {
+        for (addr, byte) in ram.iter_mut().enumerate() {
+            *byte = self.byte(addr);
+        }
+    }
+}
+
+// todo: audio sample rate, once there is an APU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    /// start address instead of the reset vector, e.g. $C000 for nestest.nes in automation mode
+    pub start_pc: Option<u16>,
+    pub ram_init: RamInit,
+    /// NES color index -> RGB, see `palette::from_pal`
+    pub palette: [(u8, u8, u8); 64],
+    /// Pixels are rendered for 1 of every `frame_skip` frames, for fast-forward and batch runs
+    pub frame_skip: usize,
+    /// Pixels are drawn on a worker thread, the emulation thread only runs the PPU timing
+    pub render_thread: bool,
+    /// TV system timing, taken from the rom header when not set
+    pub region: Option<Region>,
+    /// 8 sprites per scanline (flicker) as on the hardware, off for cleaner visuals
+    pub sprite_limit: bool,
+    /// Extra scanlines of CPU time per frame against slowdown, 0 - off. See `NesPPU::set_overclock`
+    pub overclock: usize,
+    /// VRAM writes during rendering and the $3000-$3EFF mirror are errors, see
+    /// `NesPPU::set_strict_vram`
+    pub strict_vram: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            start_pc: None,
+            ram_init: RamInit::Fill(0),
+            palette: palette::SYSTEM_PALETTE,
+            frame_skip: 1,
+            render_thread: false,
+            region: None,
+            sprite_limit: true,
+            overclock: 0,
+            strict_vram: false,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+struct Trace {
+    filter: TraceFilter,
+    format: TraceFormat,
+    output: TraceWriter<Box<dyn Write + Send>>,
+}
+
+#[cfg(feature = "std")]
+struct CrashDump {
+    path: PathBuf,
+    history: ExecutionHistory,
+}
+
+pub struct EmulatorBuilder {
+    rom: Rom,
+    config: Config,
+    game_title: Option<String>,
+    #[cfg(feature = "std")]
+    trace: Option<Trace>,
+    #[cfg(feature = "std")]
+    crash_dump: Option<CrashDump>,
+}
+
+impl EmulatorBuilder {
+    pub fn new(rom: Rom) -> Self {
+        EmulatorBuilder {
+            rom,
+            config: Config::default(),
+            game_title: None,
+            #[cfg(feature = "std")]
+            trace: None,
+            #[cfg(feature = "std")]
+            crash_dump: None,
+        }
+    }
+
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Emulation options of the configuration file (region, palette, sprite limit, ...), fails
+    /// when its palette file can't be read
+    pub fn config_file(mut self, file: &ConfigFile) -> Result<Self, String> {
+        file.apply(&mut self.config)?;
+        Ok(self)
+    }
+
+    pub fn start_pc(mut self, pc: u16) -> Self {
+        self.config.start_pc = Some(pc);
+        self
+    }
+
+    pub fn ram_init(mut self, ram_init: RamInit) -> Self {
+        self.config.ram_init = ram_init;
+        self
+    }
+
+    pub fn palette(mut self, palette: [(u8, u8, u8); 64]) -> Self {
+        self.config.palette = palette;
+        self
+    }
+
+    pub fn frame_skip(mut self, n: usize) -> Self {
+        self.config.frame_skip = n;
+        self
+    }
+
+    pub fn render_thread(mut self, enabled: bool) -> Self {
+        self.config.render_thread = enabled;
+        self
+    }
+
+    pub fn region(mut self, region: Region) -> Self {
+        self.config.region = Some(region);
+        self
+    }
+
+    pub fn sprite_limit(mut self, enabled: bool) -> Self {
+        self.config.sprite_limit = enabled;
+        self
+    }
+
+    pub fn overclock(mut self, lines: usize) -> Self {
+        self.config.overclock = lines;
+        self
+    }
+
+    pub fn strict_vram(mut self, enabled: bool) -> Self {
+        self.config.strict_vram = enabled;
+        self
+    }
+
+    /// `Emulator::game_title`: the game database entry of the rom (by the CRC32 of PRG+CHR),
+    /// the rom file name if the game is unknown
+    pub fn game_db(mut self, db: &GameDb, rom_path: &Path) -> Self {
+        self.game_title = Some(db.title(&self.rom, rom_path));
+        self
+    }
+
+    /// nestest-like log of executed instructions, buffered and flushed at the end of each frame
+    #[cfg(feature = "std")]
+    pub fn trace<W: Write + Send + 'static>(mut self, output: W, filter: TraceFilter) -> Self {
+        self.trace = Some(Trace {
+            filter,
+            format: TraceFormat::Text,
+            output: TraceWriter::new(Box::new(output)),
+        });
+        self
+    }
+
+    /// `trace` with a JSON object per instruction instead, see `cpu::trace_json`
+    #[cfg(feature = "std")]
+    pub fn trace_json<W: Write + Send + 'static>(self, output: W, filter: TraceFilter) -> Self {
+        let mut builder = self.trace(output, filter);
+        if let Some(trace) = builder.trace.as_mut() {
+            trace.format = TraceFormat::Json;
+        }
+        builder
+    }
+
+    /// On a bus/PPU fault the machine state and the last `history` instructions are written to
+    /// `path` as JSON (see `crash_report::crash_dump`), for bug reports. The file is overwritten
+    /// by every fault. Recording the history slows the emulation down.
+    #[cfg(feature = "std")]
+    pub fn crash_dump<P: Into<PathBuf>>(mut self, path: P, history: usize) -> Self {
+        self.crash_dump = Some(CrashDump {
+            path: path.into(),
+            history: ExecutionHistory::new(history),
+        });
+        self
+    }
+
+    pub fn build(self) -> Emulator {
+        let mut emulator = Emulator::new(self.rom, self.config);
+        emulator.game_title = self.game_title;
+        #[cfg(feature = "std")]
+        {
+            emulator.trace = self.trace;
+            emulator.crash_dump = self.crash_dump;
+        }
+        emulator
+    }
+}
+
+/// Buttons held during the frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Inputs {
+    pub joypad1: JoypadButton,
+    pub joypad2: JoypadButton,
+}
+
+impl Inputs {
+    pub fn new(joypad1: JoypadButton) -> Self {
+        Inputs {
+            joypad1,
+            joypad2: JoypadButton::empty(),
+        }
+    }
+
+    pub fn two_players(joypad1: JoypadButton, joypad2: JoypadButton) -> Self {
+        Inputs { joypad1, joypad2 }
+    }
+}
+
+impl Default for Inputs {
+    fn default() -> Self {
+        Inputs::new(JoypadButton::empty())
+    }
+}
+
+/// Limit of `Emulator::run_until`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Budget {
+    Frames(usize),
+    /// CPU cycles
+    Cycles(usize),
+}
+
+impl Budget {
+    /// Emulated time, NTSC frames
+    pub fn seconds(seconds: usize) -> Self {
+        Budget::Frames(seconds * 60)
+    }
+}
+
+/// Owns the whole machine, can be moved to another thread
+pub struct Emulator {
+    cpu: CPU<Bus<NesPPU>>,
+    // for the power cycle
+    config: Config,
+    frame_count: usize,
+    cheats: Cheats,
+    game_title: Option<String>,
+    #[cfg(feature = "std")]
+    trace: Option<Trace>,
+    #[cfg(feature = "std")]
+    crash_dump: Option<CrashDump>,
+}
+
+impl Emulator {
+    pub fn builder(rom: Rom) -> EmulatorBuilder {
+        EmulatorBuilder::new(rom)
+    }
+
+    pub fn new(rom: Rom, config: Config) -> Self {
+        let mut bus = Bus::<NesPPU>::new(rom);
+        config.ram_init.fill(&mut bus.ram);
+        bus.ppu_mut().system_palette = config.palette;
+        bus.ppu_mut().resolve_palettes();
+        bus.ppu_mut().set_frame_skip(config.frame_skip);
+        bus.ppu_mut().set_render_thread(config.render_thread);
+        bus.ppu_mut().set_sprite_limit(config.sprite_limit);
+        bus.ppu_mut().set_overclock(config.overclock);
+        bus.ppu_mut().set_strict_vram(config.strict_vram);
+        if let Some(region) = config.region {
+            bus.set_region(region);
+        }
+
+        let mut cpu = CPU::with_bus(Box::new(bus));
+        cpu.program_counter = match config.start_pc {
+            Some(pc) => pc,
+            None => cpu.bus.read_u16(0xfffc),
+        };
+        Emulator {
+            cpu,
+            config,
+            frame_count: 0,
+            cheats: Cheats::new(),
+            game_title: None,
+            #[cfg(feature = "std")]
+            trace: None,
+            #[cfg(feature = "std")]
+            crash_dump: None,
+        }
+    }
+
+    /// Runs till the start of the next vblank: that's when the picture is complete
+    /// and games read the controllers.
+    /// `inputs` are latched right away, for the NMI handler of the frame just completed:
+    /// sample them after the frame pacing wait, not before, or they are a frame late.
+    /// A bus/PPU fault interrupts the frame, the machine stays consistent and can be run further
+    /// The frame is the PPU buffer itself, no copies are made.
+    pub fn run_frame(&mut self, inputs: &Inputs) -> Result<&Frame, RustnessError> {
+        self.run_to_vblank(inputs)?;
+        Ok(self.cpu.bus.ppu().frame())
+    }
+
+    /// Same as `run_frame`, the picture goes straight to `sink`
+    pub fn run_frame_to(
+        &mut self,
+        inputs: &Inputs,
+        sink: &mut dyn PixelSink,
+    ) -> Result<(), RustnessError> {
+        self.run_to_vblank(inputs)?;
+        self.cpu.bus.ppu().blit(sink);
+        Ok(())
+    }
+
+    /// Same as `run_frame`, the picture is written as palette indices, see `IndexedFrame`
+    pub fn run_frame_indexed(
+        &mut self,
+        inputs: &Inputs,
+        out: &mut IndexedFrame,
+    ) -> Result<(), RustnessError> {
+        self.run_to_vblank(inputs)?;
+        self.cpu.bus.ppu().blit_indexed(out);
+        Ok(())
+    }
+
+    /// Runs till `condition` holds (checked before every instruction) or the budget is spent,
+    /// for tests and scripts:
+    ///
+    ///   // RAM $00F0 == 3 or 10 seconds elapse
+    ///   let done = emulator.run_until(&inputs, Budget::seconds(10), |cpu| cpu.bus.ram[0xf0] == 3)?;
+    ///
+    /// Returns false if the budget ran out first. Stops mid frame: `frame()` is the last
+    /// complete one.
+    pub fn run_until<F>(
+        &mut self,
+        inputs: &Inputs,
+        budget: Budget,
+        mut condition: F,
+    ) -> Result<bool, RustnessError>
+    where
+        F: FnMut(&CPU<Bus<NesPPU>>) -> bool,
+    {
+        self.set_inputs(inputs);
+        let start_cycles = self.cpu.bus.trace().cpu_cycles;
+        let mut frames = 0;
+        loop {
+            if condition(&self.cpu) {
+                self.flush_trace();
+                return Ok(true);
+            }
+            let spent = match budget {
+                Budget::Frames(limit) => frames >= limit,
+                Budget::Cycles(limit) => self.cpu.bus.trace().cpu_cycles - start_cycles >= limit,
+            };
+            if spent {
+                self.flush_trace();
+                return Ok(false);
+            }
+            if self.step()? {
+                self.flush_trace();
+                self.cheats.apply(&mut *self.cpu.bus);
+                self.frame_count += 1;
+                frames += 1;
+            }
+        }
+    }
+
+    fn set_inputs(&mut self, inputs: &Inputs) {
+        let joypad = self.cpu.bus.joypad1_mut();
+        joypad.set_button_pressed_status(JoypadButton::all(), false);
+        joypad.set_button_pressed_status(inputs.joypad1, true);
+        let joypad = self.cpu.bus.joypad2_mut();
+        joypad.set_button_pressed_status(JoypadButton::all(), false);
+        joypad.set_button_pressed_status(inputs.joypad2, true);
+    }
+
+    fn run_to_vblank(&mut self, inputs: &Inputs) -> Result<(), RustnessError> {
+        self.set_inputs(inputs);
+        while !self.step()? {}
+        self.flush_trace();
+        self.cheats.apply(&mut *self.cpu.bus);
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    /// One instruction, true if the frame is complete
+    fn step(&mut self) -> Result<bool, RustnessError> {
+        #[cfg(feature = "std")]
+        if let Some(trace) = self.trace.as_mut() {
+            if trace.filter.matches(&mut self.cpu) {
+                // tracing is best effort, a failing output doesn't stop the emulation
+                let _ = trace.output.write_line(&trace.format.line(&mut self.cpu));
+            }
+        }
+        #[cfg(feature = "std")]
+        if let Some(crash_dump) = self.crash_dump.as_mut() {
+            crash_dump.history.record(&mut self.cpu);
+        }
+        self.cpu.step();
+        if let Some(error) = self.cpu.bus.take_error() {
+            self.flush_trace();
+            self.write_crash_dump(&error);
+            return Err(error);
+        }
+        Ok(self.cpu.bus.poll_frame_complete())
+    }
+
+    // best effort, like the trace: the fault is reported to the caller anyway
+    #[allow(unused_variables)]
+    fn write_crash_dump(&self, error: &RustnessError) {
+        #[cfg(feature = "std")]
+        if let Some(crash_dump) = self.crash_dump.as_ref() {
+            let dump = crash_report::crash_dump(
+                &self.cpu,
+                &self.cpu.bus,
+                &crash_dump.history,
+                &error.to_string(),
+            );
+            let _ = fs::write(&crash_dump.path, dump);
+        }
+    }
+
+    fn flush_trace(&mut self) {
+        #[cfg(feature = "std")]
+        if let Some(trace) = self.trace.as_mut() {
+            let _ = trace.output.flush();
+        }
+    }
+
+    /// Reset button: the RESET interrupt sequence (PC from $FFFC, SP -= 3, I set) and the PPU
+    /// registers cleared, RAM is kept
+    pub fn soft_reset(&mut self) {
+        self.cpu.bus.reset();
+        self.cpu.reset();
+    }
+
+    /// Power cycle: RAM filled with the `RamInit` pattern again, CPU and PPU in the power on
+    /// state, PRG RAM kept. Cheats and subscribers stay, `frame_count` keeps counting.
+    pub fn hard_reset(&mut self) {
+        self.cpu.bus.power_on();
+        self.config.ram_init.fill(&mut self.cpu.bus.ram);
+        self.cpu.power_on();
+        if let Some(pc) = self.config.start_pc {
+            self.cpu.program_counter = pc;
+        }
+    }
+
+    /// The PPU frame buffer: the last complete frame right after `run_frame`
+    pub fn frame(&self) -> &Frame {
+        self.cpu.bus.ppu().frame()
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// Name of the game for the window title, see `EmulatorBuilder::game_db`.
+    /// None when the emulator was built without a game database
+    pub fn game_title(&self) -> Option<&str> {
+        self.game_title.as_deref()
+    }
+
+    /// The machine state in the save state file format (`save_state`), e.g. for a save slot
+    /// of the embedder's own. `frame_count`, cheats and subscribers are not part of it
+    #[cfg(feature = "save-state")]
+    pub fn save_state(&self) -> Vec<u8> {
+        self.snapshot().to_bytes()
+    }
+
+    /// Restores a `save_state` of the same rom, fails on another rom or a corrupted state
+    #[cfg(feature = "save-state")]
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        self.cpu.load_state(data)
+    }
+
+    /// In-memory copy of the machine state, see `save_state::Snapshot`
+    #[cfg(feature = "save-state")]
+    pub fn snapshot(&self) -> Snapshot {
+        self.cpu.snapshot()
+    }
+
+    /// `frame_count` is not rewound, it counts every emulated frame
+    #[cfg(feature = "save-state")]
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), String> {
+        self.cpu.restore(snapshot)
+    }
+
+    /// RAM freeze cheats, applied at the start of every vblank
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    pub fn cpu(&self) -> &CPU<Bus<NesPPU>> {
+        &self.cpu
+    }
+
+    /// e.g. to poke memory or load a save state
+    pub fn cpu_mut(&mut self) -> &mut CPU<Bus<NesPPU>> {
+        &mut self.cpu
+    }
+
+    pub fn ppu(&self) -> &NesPPU {
+        self.cpu.bus.ppu()
+    }
+
+    /// Nametables, attribute tables, decoded OAM, palettes and the given RAM ranges (inclusive)
+    /// as JSON, see `dump`
+    pub fn dump_state_json(&self, ram_ranges: &[(u16, u16)]) -> Result<String, String> {
+        dump::state_json(&self.cpu.bus, self.frame_count, ram_ranges)
+    }
+
+    /// Switches the palette at runtime, e.g. to `ColorVision::Deuteranopia.palette()`
+    pub fn set_palette(&mut self, palette: [(u8, u8, u8); 64]) {
+        self.cpu.bus.ppu_mut().set_palette(palette);
+    }
+
+    /// "No sprite flicker" toggle, takes effect from the next frame
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.cpu.bus.ppu_mut().set_sprite_limit(enabled);
+    }
+
+    /// Extra scanlines of CPU time per frame, 0 - off. Takes effect from the next frame
+    pub fn set_overclock(&mut self, lines: usize) {
+        self.cpu.bus.ppu_mut().set_overclock(lines);
+    }
+
+    /// Can be switched at any time, e.g. while fast-forward is held.
+    /// `run_frame` returns the last rendered picture for the skipped frames
+    pub fn set_frame_skip(&mut self, n: usize) {
+        self.cpu.bus.ppu_mut().set_frame_skip(n);
+    }
+
+    /// Runs frames with the given buttons held, endless: bound it with `take`.
+    /// Frames are copied out of the emulator, for tools and tests; frontends should use
+    /// `run_frame`.
+    ///
+    ///   for frame in emulator.frames(Inputs::default()).take(600) {
+    ///       let frame = frame?;
+    ///   }
+    pub fn frames(&mut self, inputs: Inputs) -> Frames<'_> {
+        Frames {
+            emulator: self,
+            inputs,
+        }
+    }
+
+    /// Subscribers are called while the frame runs, before `run_frame` returns
+    pub fn subscribe(&mut self, subscriber: Box<dyn EmulatorEvents + Send>) {
+        self.cpu.bus.subscribe(subscriber);
+    }
+}
+
+/// See `Emulator::frames`
+pub struct Frames<'a> {
+    emulator: &'a mut Emulator,
+    pub inputs: Inputs,
+}
+
+impl<'a> Iterator for Frames<'a> {
+    // a fault is reported as an error, the next frame continues from there
+    type Item = Result<Frame, RustnessError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.emulator.run_frame(&self.inputs).cloned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cheats::Cheat;
+    use crate::error::BusError;
+    use crate::events::Event;
+    use crate::rom::test_ines_rom;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    const VBLANK_SCANLINE: usize = 241;
+
+    #[test]
+    fn test_run_frame() {
+        let mut emulator = Emulator::new(
+            test_ines_rom::test_rom(),
+            Config {
+                start_pc: Some(0x8000),
+                ..Config::default()
+            },
+        );
+        assert_eq!(emulator.cpu().program_counter, 0x8000);
+
+        emulator
+            .run_frame(&Inputs::new(JoypadButton::BUTTON_A | JoypadButton::START))
+            .unwrap();
+        assert_eq!(emulator.frame_count(), 1);
+        assert_eq!(emulator.ppu().line, VBLANK_SCANLINE);
+        let cycles = emulator.cpu().bus.trace().cpu_cycles;
+
+        emulator.run_frame(&Inputs::new(JoypadButton::BUTTON_A)).unwrap();
+        assert_eq!(emulator.frame_count(), 2);
+        assert_eq!(emulator.ppu().line, VBLANK_SCANLINE);
+        // 262 scanlines of 341 ppu cycles, 3 ppu cycles per cpu cycle
+        let frame_cycles = emulator.cpu().bus.trace().cpu_cycles - cycles;
+        assert!((29_775..29_790).contains(&frame_cycles), "{}", frame_cycles);
+
+        let cpu = emulator.cpu_mut();
+        cpu.bus.write(0x4016, 1);
+        cpu.bus.write(0x4016, 0);
+        let buttons: Vec<u8> = (0..8).map(|_| cpu.bus.read(0x4016)).collect();
+        assert_eq!(buttons, vec![1, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_overclock() {
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x0600)
+            .overclock(20)
+            .build();
+        // JMP $0600
+        for (idx, byte) in [0x4c, 0x00, 0x06].iter().enumerate() {
+            emulator.cpu_mut().bus.write(0x0600 + idx as u16, *byte);
+        }
+        emulator.run_frame(&Inputs::default()).unwrap();
+        let cycles = emulator.cpu().bus.trace().cpu_cycles;
+        emulator.run_frame(&Inputs::default()).unwrap();
+        let spent = emulator.cpu().bus.trace().cpu_cycles - cycles;
+        let frame_cycles = 341 * (262 + 20) / 3;
+        assert!((frame_cycles - 5..frame_cycles + 10).contains(&spent), "{}", spent);
+
+        emulator.set_overclock(0);
+        emulator.run_frame(&Inputs::default()).unwrap();
+        let cycles = emulator.cpu().bus.trace().cpu_cycles;
+        emulator.run_frame(&Inputs::default()).unwrap();
+        let spent = emulator.cpu().bus.trace().cpu_cycles - cycles;
+        assert!((29_775..29_790).contains(&spent), "{}", spent);
+    }
+
+    #[test]
+    fn test_regions() {
+        // (region, vblank line, cpu cycles per frame)
+        let expected = [
+            (Region::Ntsc, 241, 341 * 262 / 3),
+            (Region::Pal, 241, 341 * 312 * 5 / 16),
+            (Region::Dendy, 291, 341 * 312 / 3),
+        ];
+        for &(region, vblank_line, frame_cycles) in expected.iter() {
+            let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+                .start_pc(0x8000)
+                .region(region)
+                .build();
+            assert_eq!(emulator.cpu().bus.region(), region);
+            emulator.run_frame(&Inputs::default()).unwrap();
+            assert_eq!(emulator.ppu().line, vblank_line);
+            let cycles = emulator.cpu().bus.trace().cpu_cycles;
+            emulator.run_frame(&Inputs::default()).unwrap();
+            let spent = emulator.cpu().bus.trace().cpu_cycles - cycles;
+            assert!(
+                (frame_cycles - 5..frame_cycles + 10).contains(&spent),
+                "{:?}: {}",
+                region,
+                spent
+            );
+        }
+    }
+
+    // shared with the emulator, so the test can look at the output
+    #[cfg(feature = "std")]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    #[cfg(feature = "std")]
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_builder() {
+        let output = Arc::new(Mutex::new(vec![]));
+        let mut palette = palette::SYSTEM_PALETTE;
+        palette[0] = (1, 2, 3);
+        let mut filter = TraceFilter::new();
+        filter.pc_range = Some(0x8000..=0x8001);
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x8000)
+            .ram_init(RamInit::Alternating)
+            .palette(palette)
+            .frame_skip(2)
+            .trace(Output(output.clone()), filter)
+            .build();
+
+        let cpu = emulator.cpu_mut();
+        assert_eq!(cpu.program_counter, 0x8000);
+        let ram: Vec<u8> = (0..10).map(|addr| cpu.bus.read(0x0700 + addr)).collect();
+        assert_eq!(ram, vec![0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0, 0]);
+        assert_eq!(emulator.ppu().system_palette[0], (1, 2, 3));
+        assert_eq!(emulator.ppu().frame_skip(), 2);
+
+        emulator.run_frame(&Inputs::default()).unwrap();
+        // ORA ($01,X) at $8000, then $8002 and so on
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        assert!(output.starts_with("8000  01 01     ORA ($01,X)"), "{}", output);
+    }
+
+    #[test]
+    fn test_game_title() {
+        let mut db = GameDb::new();
+        db.insert(test_ines_rom::test_rom().crc32(), "Test Game");
+        let emulator = Emulator::builder(test_ines_rom::test_rom())
+            .game_db(&db, Path::new("roms/test.nes"))
+            .build();
+        assert_eq!(emulator.game_title(), Some("Test Game"));
+
+        // unknown game, the file name
+        let emulator = Emulator::builder(test_ines_rom::test_rom())
+            .game_db(&GameDb::new(), Path::new("roms/test.nes"))
+            .build();
+        assert_eq!(emulator.game_title(), Some("test"));
+
+        let emulator = Emulator::builder(test_ines_rom::test_rom()).build();
+        assert_eq!(emulator.game_title(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_trace_json() {
+        let output = Arc::new(Mutex::new(vec![]));
+        let mut filter = TraceFilter::new();
+        filter.pc_range = Some(0x8000..=0x8001);
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x8000)
+            .trace_json(Output(output.clone()), filter)
+            .build();
+
+        emulator.run_frame(&Inputs::default()).unwrap();
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        assert!(output.starts_with("{\"pc\":32768,\"opcode\":1,\"mnemonic\":\"ORA\""));
+    }
+
+    #[test]
+    fn test_run_until() {
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x0600)
+            .build();
+        // loop: INC $F0; JMP loop
+        for (idx, byte) in CPU::transform("e6 f0 4c 00 06").iter().enumerate() {
+            emulator.cpu_mut().bus.write(0x0600 + idx as u16, *byte);
+        }
+        let inputs = Inputs::default();
+
+        let met = emulator
+            .run_until(&inputs, Budget::Frames(1), |cpu| cpu.bus.ram[0xf0] == 3)
+            .unwrap();
+        assert!(met);
+        assert_eq!(emulator.cpu().bus.ram[0xf0], 3);
+        assert_eq!(emulator.frame_count(), 0);
+
+        let met = emulator
+            .run_until(&inputs, Budget::Frames(2), |cpu| cpu.bus.ram[0x10] == 1)
+            .unwrap();
+        assert!(!met);
+        assert_eq!(emulator.frame_count(), 2);
+        assert_eq!(emulator.ppu().line, VBLANK_SCANLINE);
+
+        let cycles = emulator.cpu().bus.trace().cpu_cycles;
+        let met = emulator
+            .run_until(&inputs, Budget::Cycles(1000), |_| false)
+            .unwrap();
+        assert!(!met);
+        let spent = emulator.cpu().bus.trace().cpu_cycles - cycles;
+        assert!((1000..1010).contains(&spent), "{}", spent);
+    }
+
+    #[test]
+    fn test_cheats() {
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x0600)
+            .build();
+        // loop: INC $F0; JMP loop
+        for (idx, byte) in CPU::transform("e6 f0 4c 00 06").iter().enumerate() {
+            emulator.cpu_mut().bus.write(0x0600 + idx as u16, *byte);
+        }
+        emulator
+            .cheats_mut()
+            .add(Cheat::parse("00F0:80").unwrap().description("frozen"));
+
+        let inputs = Inputs::default();
+        emulator.run_frame(&inputs).unwrap();
+        assert_eq!(emulator.cpu().bus.ram[0xf0], 0x80);
+
+        // the game changes the value during the frame, the cheat restores it at vblank
+        emulator
+            .run_until(&inputs, Budget::Cycles(100), |_| false)
+            .unwrap();
+        assert_ne!(emulator.cpu().bus.ram[0xf0], 0x80);
+        emulator.run_frame(&inputs).unwrap();
+        assert_eq!(emulator.cpu().bus.ram[0xf0], 0x80);
+
+        emulator.cheats_mut().set_enabled(0, false).unwrap();
+        emulator.run_frame(&inputs).unwrap();
+        assert_ne!(emulator.cpu().bus.ram[0xf0], 0x80);
+    }
+
+    #[test]
+    fn test_fault() {
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x0600)
+            .build();
+        // LDA #$07; STA $2002; LDX #$01; loop: JMP loop
+        for (idx, byte) in CPU::transform("a9 07 8d 02 20 a2 01 4c 07 06").iter().enumerate() {
+            emulator.cpu_mut().bus.write(0x0600 + idx as u16, *byte);
+        }
+
+        assert_eq!(
+            emulator.run_frame(&Inputs::default()).err(),
+            Some(RustnessError::Bus(BusError::PpuStatusWrite(0x07)))
+        );
+        assert_eq!(emulator.cpu().program_counter, 0x0605);
+        assert_eq!(emulator.frame_count(), 0);
+
+        assert!(emulator.run_frame(&Inputs::default()).is_ok());
+        assert_eq!(emulator.cpu().register_x(), 1);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_crash_dump() {
+        let path = std::env::temp_dir().join("rustness_test_crash_dump.json");
+        let _ = fs::remove_file(&path);
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x0600)
+            .crash_dump(&path, 2)
+            .build();
+        // LDX #$05; LDA #$07; STA $2002
+        for (idx, byte) in CPU::transform("a2 05 a9 07 8d 02 20").iter().enumerate() {
+            emulator.cpu_mut().bus.write(0x0600 + idx as u16, *byte);
+        }
+        emulator.cpu_mut().bus.ram[0x10] = 0x42;
+
+        assert!(emulator.run_frame(&Inputs::default()).is_err());
+        let dump = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(dump.starts_with(
+            "{\"reason\":\"bus: write to PPU status register ($2002): $07\",\
+             \"cpu\":{\"pc\":1543,\"a\":7,\"x\":5,"
+        ));
+        assert!(dump.contains("\"mapper\":{\"number\":3,\"mirroring\":\"vertical\","));
+        assert!(dump.contains("\"bus_access\":\"Write $2002\""));
+        assert!(dump.contains("\"ram\":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,66,"));
+        assert!(dump.contains("\"trace\":[\"0602  A9 07"));
+        assert!(dump.ends_with("\"]}"));
+    }
+
+    #[derive(Default)]
+    struct Events {
+        vblanks: usize,
+        scanlines: Vec<usize>,
+        events: Vec<Event>,
+    }
+
+    struct Recorder(Arc<Mutex<Events>>);
+
+    impl EmulatorEvents for Recorder {
+        fn on_event(&mut self, event: &Event) {
+            self.0.lock().unwrap().events.push(*event);
+        }
+
+        fn on_vblank(&mut self, _frame: &Frame) {
+            self.0.lock().unwrap().vblanks += 1;
+        }
+
+        fn on_scanline(&mut self, line: usize) {
+            self.0.lock().unwrap().scanlines.push(line);
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        let events = Arc::new(Mutex::new(Events::default()));
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x8000)
+            .build();
+        emulator.subscribe(Box::new(Recorder(events.clone())));
+
+        emulator.run_frame(&Inputs::default()).unwrap();
+        assert_eq!(events.lock().unwrap().vblanks, 1);
+        assert_eq!(
+            events.lock().unwrap().scanlines,
+            (1..=VBLANK_SCANLINE).collect::<Vec<_>>()
+        );
+
+        emulator.run_frame(&Inputs::default()).unwrap();
+        let events = events.lock().unwrap();
+        assert_eq!(events.vblanks, 2);
+        assert_eq!(events.scanlines.len(), VBLANK_SCANLINE + 262);
+        assert_eq!(events.scanlines[VBLANK_SCANLINE + 262 - 1], VBLANK_SCANLINE);
+    }
+
+    #[test]
+    fn test_typed_events() {
+        let events = Arc::new(Mutex::new(Events::default()));
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x8000)
+            .build();
+        emulator.subscribe(Box::new(Recorder(events.clone())));
+        // NMI on vblank
+        emulator.cpu_mut().bus.write(0x2000, 0b1000_0000);
+
+        emulator.run_frame(&Inputs::default()).unwrap();
+        emulator.run_frame(&Inputs::default()).unwrap();
+        let events = &events.lock().unwrap().events;
+        let filtered: Vec<Event> = events
+            .iter()
+            .filter(|event| !matches!(event, Event::ScanlineStarted { .. }))
+            .cloned()
+            .collect();
+        assert_eq!(
+            filtered,
+            vec![
+                Event::FrameCompleted { n: 1 },
+                Event::NmiFired,
+                Event::FrameCompleted { n: 2 }
+            ]
+        );
+        assert_eq!(events[0], Event::ScanlineStarted { y: 1 });
+        assert_eq!(events[VBLANK_SCANLINE - 1], Event::ScanlineStarted { y: VBLANK_SCANLINE });
+        assert_eq!(events[VBLANK_SCANLINE], Event::FrameCompleted { n: 1 });
+    }
+
+    #[test]
+    fn test_irq_events() {
+        let events = Arc::new(Mutex::new(Events::default()));
+        // VRC6, the IRQ vector in the fixed last bank points to the handler at $0700
+        let mut rom = test_ines_rom::test_rom();
+        rom.mapper = 24;
+        let len = rom.prg_rom.len();
+        rom.prg_rom[len - 2..].copy_from_slice(&[0x00, 0x07]);
+        let mut emulator = Emulator::builder(rom).start_pc(0x0600).build();
+        emulator.subscribe(Box::new(Recorder(events.clone())));
+        // IRQ every 5 cpu cycles: latch $FB, IRQ on in cpu cycle mode, CLI, loop: JMP loop
+        let program = CPU::transform("a9 fb 8d 00 f0 a9 07 8d 01 f0 58 4c 0b 06");
+        // INC $10, acknowledge, RTI
+        let handler = CPU::transform("e6 10 8d 02 f0 40");
+        let bus = &mut emulator.cpu_mut().bus;
+        for (i, byte) in program.iter().enumerate() {
+            bus.write(0x0600 + i as u16, *byte);
+        }
+        for (i, byte) in handler.iter().enumerate() {
+            bus.write(0x0700 + i as u16, *byte);
+        }
+
+        emulator.run_frame(&Inputs::default()).unwrap();
+        let irqs = events
+            .lock()
+            .unwrap()
+            .events
+            .iter()
+            .filter(|event| **event == Event::IrqFired)
+            .count();
+        assert!(irqs > 1);
+        // one event per handler run, the handler counts them in a byte
+        assert_eq!(irqs as u8, emulator.cpu_mut().bus.read(0x0010));
+    }
+
+    #[test]
+    fn test_bank_switch_events() {
+        let events = Arc::new(Mutex::new(Events::default()));
+        let mut rom = test_ines_rom::test_rom();
+        rom.mapper = 24;
+        let mut emulator = Emulator::builder(rom).start_pc(0x8000).build();
+        emulator.subscribe(Box::new(Recorder(events.clone())));
+        let bus = &mut emulator.cpu_mut().bus;
+        // the 16KB PRG bank, a CHR bank, then the IRQ latch: not a bank register
+        bus.write(0x8000, 0x12);
+        bus.write(0xd001, 5);
+        bus.write(0xf000, 0xfb);
+        assert_eq!(
+            events.lock().unwrap().events,
+            vec![
+                Event::MapperBankSwitched {
+                    addr: 0x8000,
+                    bank: 2
+                },
+                Event::MapperBankSwitched {
+                    addr: 0xd001,
+                    bank: 5
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_deterministic_frames() {
+        let run = || {
+            let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+                .start_pc(0x8000)
+                .ram_init(RamInit::Alternating)
+                .build();
+            let inputs = [JoypadButton::START, JoypadButton::empty(), JoypadButton::BUTTON_A];
+            inputs
+                .iter()
+                .map(|buttons| emulator.run_frame(&Inputs::new(*buttons)).unwrap().hash())
+                .collect::<Vec<u64>>()
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    #[cfg(feature = "save-state")]
+    fn test_save_state() {
+        let mut rom = test_ines_rom::test_rom();
+        // INC $10, JMP $8000
+        rom.prg_rom[..5].copy_from_slice(&[0xe6, 0x10, 0x4c, 0x00, 0x80]);
+        let mut emulator = Emulator::builder(rom).start_pc(0x8000).build();
+        let inputs = Inputs::new(JoypadButton::START);
+        emulator.run_frame(&inputs).unwrap();
+        let state = emulator.save_state();
+        let counter = emulator.cpu_mut().bus.read(0x0010);
+
+        let run = |emulator: &mut Emulator| {
+            for _ in 0..3 {
+                emulator.run_frame(&inputs).unwrap();
+            }
+            (emulator.cpu_mut().bus.read(0x0010), emulator.cpu().program_counter)
+        };
+        let after = run(&mut emulator);
+        assert_ne!(after.0, counter);
+
+        emulator.load_state(&state).unwrap();
+        assert_eq!(emulator.cpu_mut().bus.read(0x0010), counter);
+        assert_eq!(run(&mut emulator), after);
+        assert!(emulator.load_state(&state[..state.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn test_frames() {
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x8000)
+            .build();
+        let frames: Vec<Frame> = emulator
+            .frames(Inputs::default())
+            .take(3)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(emulator.frame_count(), 3);
+        assert!(frames[2] == *emulator.frame());
+    }
+
+    #[test]
+    fn test_frame_is_not_copied() {
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x8000)
+            .build();
+        let buffer = emulator.run_frame(&Inputs::default()).unwrap().data.as_ptr();
+        let next = emulator.run_frame(&Inputs::default()).unwrap().data.as_ptr();
+        assert_eq!(buffer, next);
+        assert_eq!(emulator.ppu().frame_buffer().as_ptr(), buffer);
+    }
+
+    struct Counter {
+        blits: usize,
+    }
+
+    impl PixelSink for Counter {
+        fn blit(&mut self, data: &[u8], pitch: usize) {
+            assert_eq!(data.len(), pitch * Frame::HIGHT);
+            self.blits += 1;
+        }
+    }
+
+    #[test]
+    fn test_run_frame_to() {
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x8000)
+            .build();
+        let mut sink = Counter { blits: 0 };
+        emulator.run_frame_to(&Inputs::default(), &mut sink).unwrap();
+        emulator.run_frame_to(&Inputs::default(), &mut sink).unwrap();
+        assert_eq!(sink.blits, 2);
+        assert_eq!(emulator.frame_count(), 2);
+    }
+
+    #[test]
+    fn test_run_frame_indexed() {
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x8000)
+            .build();
+        let mut indexed = IndexedFrame::new();
+        emulator
+            .run_frame_indexed(&Inputs::default(), &mut indexed)
+            .unwrap();
+        assert!(indexed.to_frame() == *emulator.frame());
+        assert_eq!(indexed.palette, emulator.ppu().system_palette);
+    }
+
+    #[test]
+    fn test_run_in_thread() {
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x8000)
+            .build();
+        let frames = thread::spawn(move || {
+            emulator.run_frame(&Inputs::default()).unwrap();
+            emulator.frame_count()
+        });
+        assert_eq!(frames.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut emulator = Emulator::new(test_ines_rom::test_rom(), Config::default());
+        // the test rom is filled with 01
+        assert_eq!(emulator.cpu().program_counter, 0x0101);
+        emulator.cpu_mut().program_counter = 0x8000;
+        emulator.cpu_mut().bus.write(0x10, 0x42);
+        emulator.cpu_mut().bus.write(0x2000, 0x80);
+        emulator.soft_reset();
+        assert_eq!(emulator.cpu().program_counter, 0x0101);
+        assert_eq!(emulator.cpu().stack_pointer(), 0xfd - 3);
+        assert_eq!(emulator.cpu().bus.ram[0x10], 0x42);
+        assert!(!emulator.ppu().ctrl.generate_vblank_nmi());
+    }
+
+    #[test]
+    fn test_hard_reset() {
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .ram_init(RamInit::Fill(0xff))
+            .build();
+        emulator.cpu_mut().program_counter = 0x8000;
+        emulator.cpu_mut().bus.write(0x10, 0x42);
+        emulator.cpu_mut().bus.write(0x6000, 0x24);
+        emulator.cpu_mut().bus.ppu_mut().vram[0] = 0x24;
+        emulator.cpu_mut().bus.ppu_mut().oam_data[0] = 0x10;
+        emulator.soft_reset();
+        emulator.hard_reset();
+
+        assert_eq!(emulator.cpu().program_counter, 0x0101);
+        assert_eq!(emulator.cpu().stack_pointer(), 0xfd);
+        assert_eq!(emulator.cpu().bus.ram[0x10], 0xff);
+        // battery backed
+        assert_eq!(emulator.cpu().bus.prg_ram[0], 0x24);
+        assert_eq!(emulator.ppu().vram[0], 0);
+        assert_eq!(emulator.ppu().oam_data[0], 0);
+    }
+}
@@ -0,0 +1,176 @@
+// Gym-like environment for reinforcement learning agents playing NES games.
+//
+// Every step holds the agent's buttons for a fixed number of frames (action repeat) and returns
+// the last picture, the 2KB of work RAM and whether the episode is over. `reset` brings the
+// machine back to the state it was in when the environment was created: episodes are
+// deterministic, the same actions give the same observations (see `save_state` on determinism).
+//
+//   let emulator = Emulator::builder(rom).ram_init(RamInit::Fill(0)).build();
+//   let mut env = Env::new(emulator, 4).done_when(|ram| ram[0x075a] == 0xff);
+//   loop {
+//       env.reset()?;
+//       loop {
+//           let (frame, ram, done) = env.step(agent.act())?;
+//           if done { break; }
+//       }
+//   }
+//
+// Headless: no window, no audio, no pacing, episodes run as fast as the CPU allows.
+use crate::emulator::{Emulator, Inputs};
+use crate::input::JoypadButton;
+use crate::save_state::Snapshot;
+use crate::screen::frame::Frame;
+
+// end of episode condition on the work RAM
+type DoneFn = Box<dyn Fn(&[u8]) -> bool + Send>;
+
+pub struct Env {
+    emulator: Emulator,
+    frames_per_step: usize,
+    // the episode start
+    start: Snapshot,
+    start_frame: Frame,
+    done: Option<DoneFn>,
+    max_steps: Option<usize>,
+    steps: usize,
+    finished: bool,
+}
+
+impl Env {
+    /// Episodes start from the current state of `emulator`: freshly built or right after
+    /// `run_frame` (the picture is not part of the saved state, it has to be redrawn in full)
+    pub fn new(emulator: Emulator, frames_per_step: usize) -> Self {
+        assert!(frames_per_step > 0, "a step runs at least one frame");
+        Env {
+            start: emulator.snapshot(),
+            start_frame: emulator.frame().clone(),
+            emulator,
+            frames_per_step,
+            done: None,
+            max_steps: None,
+            steps: 0,
+            finished: false,
+        }
+    }
+
+    /// End of the episode, checked on the work RAM after every step (game over, level cleared)
+    pub fn done_when<F: Fn(&[u8]) -> bool + Send + 'static>(mut self, done: F) -> Self {
+        self.done = Some(Box::new(done));
+        self
+    }
+
+    /// Time limit: the episode is over after `n` steps
+    pub fn max_steps(mut self, n: usize) -> Self {
+        self.max_steps = Some(n);
+        self
+    }
+
+    /// Starts a new episode, returns the first observation
+    pub fn reset(&mut self) -> Result<(&Frame, &[u8]), String> {
+        self.emulator.restore(&self.start)?;
+        self.steps = 0;
+        self.finished = false;
+        Ok((&self.start_frame, &self.emulator.cpu().bus.ram[..]))
+    }
+
+    /// Holds `buttons` for `frames_per_step` frames. Returns the picture, the work RAM
+    /// ($0000-$07FF) and whether the episode is over: `reset` has to be called then.
+    pub fn step(&mut self, buttons: JoypadButton) -> Result<(&Frame, &[u8], bool), String> {
+        if self.finished {
+            return Err("the episode is over, call reset".to_string());
+        }
+        let inputs = Inputs::new(buttons);
+        for _ in 0..self.frames_per_step {
+            self.emulator
+                .run_frame(&inputs)
+                .map_err(|e| e.to_string())?;
+        }
+        self.steps += 1;
+
+        let ram = &self.emulator.cpu().bus.ram[..];
+        self.finished = self.done.as_ref().is_some_and(|done| done(ram))
+            || self.max_steps.is_some_and(|max| self.steps >= max);
+        Ok((self.emulator.frame(), ram, self.finished))
+    }
+
+    /// Steps taken in the current episode
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    /// Changes made here (e.g. RAM pokes) last until the next `reset`
+    pub fn emulator_mut(&mut self) -> &mut Emulator {
+        &mut self.emulator
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::cpu::CPU;
+    use crate::rom::test_ines_rom;
+
+    // counts the frames with the A button held at $10
+    fn env() -> Env {
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x0600)
+            .build();
+        // loop: strobe, LDA $4016; AND #1; CLC; ADC $10; STA $10; wait for vblank
+        let program = CPU::transform(
+            "a9 01 8d 16 40 a9 00 8d 16 40 \
+             ad 16 40 29 01 18 65 10 85 10 \
+             2c 02 20 10 fb 4c 00 06",
+        );
+        for (idx, byte) in program.iter().enumerate() {
+            emulator.cpu_mut().bus.write(0x0600 + idx as u16, *byte);
+        }
+        Env::new(emulator, 4)
+    }
+
+    fn episode(env: &mut Env, actions: &[JoypadButton]) -> Vec<(u64, Vec<u8>, bool)> {
+        env.reset().unwrap();
+        actions
+            .iter()
+            .map(|&buttons| {
+                let (frame, ram, done) = env.step(buttons).unwrap();
+                (frame.hash(), ram.to_vec(), done)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_deterministic_episodes() {
+        let actions = [
+            JoypadButton::BUTTON_A,
+            JoypadButton::empty(),
+            JoypadButton::BUTTON_A | JoypadButton::LEFT,
+        ];
+        let mut env = env();
+        let first = episode(&mut env, &actions);
+        assert_eq!(first[0].1[0x10], 4);
+        assert_eq!(first[1].1[0x10], 4);
+        assert_eq!(first[2].1[0x10], 8);
+        assert_eq!(env.steps(), 3);
+        assert_eq!(episode(&mut env, &actions), first);
+    }
+
+    #[test]
+    fn test_done() {
+        let mut env = env().done_when(|ram| ram[0x10] >= 8).max_steps(5);
+        env.reset().unwrap();
+        assert!(!env.step(JoypadButton::BUTTON_A).unwrap().2);
+        assert!(env.step(JoypadButton::BUTTON_A).unwrap().2);
+        assert!(env.step(JoypadButton::BUTTON_A).is_err());
+
+        let (_, ram) = env.reset().unwrap();
+        assert_eq!(ram[0x10], 0);
+        let dones: Vec<bool> = (0..5)
+            .map(|_| env.step(JoypadButton::empty()).unwrap().2)
+            .collect();
+        assert_eq!(dones, vec![false, false, false, false, true]);
+    }
+}
@@ -0,0 +1,133 @@
+// Errors surfaced to the embedder.
+//
+// Bus and PPU faults don't stop the emulation: the access is handled the way the hardware
+// would (the write is ignored, the address is mirrored), the fault is recorded and can be
+// picked up with `CpuBus::take_error` (`Emulator::run_frame` returns it). They usually mean an
+// unsupported mapper or an emulation bug.
+//
+// `Display` is written out rather than derived (thiserror needs std), `std::error::Error` comes
+// with the `std` feature.
+use core::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RustnessError {
+    Rom(RomError),
+    Bus(BusError),
+    Ppu(PpuError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RomError {
+    NotINes,
+    Nes2NotSupported,
+    UnexpectedEof,
+    NoPrgRom,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BusError {
+    PpuStatusWrite(u8),
+    PrgRomWrite { addr: u16, data: u8 },
+    UnmappedWrite { addr: u16, data: u8 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PpuError {
+    ChrRomWrite { addr: u16, data: u8 },
+    // $3000-$3EFF mirrors $2000-$2EFF, games aren't expected to use it (strict mode only),
+    // or an address past $3FFF
+    UnusedMirror(u16),
+    // strict mode only, see `NesPPU::set_strict_vram`
+    VramWriteDuringRendering { addr: u16, data: u8, line: usize },
+}
+
+impl fmt::Display for RustnessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RustnessError::Rom(e) => write!(f, "rom: {}", e),
+            RustnessError::Bus(e) => write!(f, "bus: {}", e),
+            RustnessError::Ppu(e) => write!(f, "ppu: {}", e),
+        }
+    }
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::NotINes => write!(f, "not an iNES file"),
+            RomError::Nes2NotSupported => write!(f, "NES2.0 format is not supported"),
+            RomError::UnexpectedEof => write!(f, "unexpected end of file"),
+            RomError::NoPrgRom => write!(f, "no PRG ROM"),
+        }
+    }
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BusError::PpuStatusWrite(data) => {
+                write!(f, "write to PPU status register ($2002): ${:02X}", data)
+            }
+            BusError::PrgRomWrite { addr, data } => {
+                write!(f, "write to PRG ROM ${:04X}: ${:02X}", addr, data)
+            }
+            BusError::UnmappedWrite { addr, data } => {
+                write!(f, "write to unmapped address ${:04X}: ${:02X}", addr, data)
+            }
+        }
+    }
+}
+
+impl fmt::Display for PpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PpuError::ChrRomWrite { addr, data } => {
+                write!(f, "write to CHR ROM ${:04X}: ${:02X}", addr, data)
+            }
+            PpuError::UnusedMirror(addr) => write!(f, "access to unused VRAM mirror ${:04X}", addr),
+            PpuError::VramWriteDuringRendering { addr, data, line } => write!(
+                f,
+                "VRAM write ${:04X}: ${:02X} during rendering, scanline {}",
+                addr, data, line
+            ),
+        }
+    }
+}
+
+impl From<RomError> for RustnessError {
+    fn from(e: RomError) -> Self {
+        RustnessError::Rom(e)
+    }
+}
+
+impl From<BusError> for RustnessError {
+    fn from(e: BusError) -> Self {
+        RustnessError::Bus(e)
+    }
+}
+
+impl From<PpuError> for RustnessError {
+    fn from(e: PpuError) -> Self {
+        RustnessError::Ppu(e)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RustnessError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RustnessError::Rom(e) => Some(e),
+            RustnessError::Bus(e) => Some(e),
+            RustnessError::Ppu(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RomError {}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BusError {}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PpuError {}
@@ -0,0 +1,31 @@
+// Notifications from the running machine, for consumers that don't drive the frame loop
+// themselves (renderer, recorder, debugger, ...). Subscribers are registered with
+// `Bus::subscribe`/`Emulator::subscribe` and called in the order of subscription. They have to
+// be `Send`, as the emulator can be moved to another thread.
+use crate::screen::frame::Frame;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The cpu is entering the NMI handler
+    NmiFired,
+    /// The cpu is entering the IRQ handler (mapper IRQ). todo: the APU frame counter and DMC
+    IrqFired,
+    /// Start of vblank, `n` counts frames since power on (starting with 1)
+    FrameCompleted { n: usize },
+    /// 0..`Region::scanlines`, `Region::vblank_line` is the first vblank line
+    ScanlineStarted { y: usize },
+    /// A write to the mapper bank register at `addr`, `bank` - the bank it selects (PRG or CHR,
+    /// in the register's bank size)
+    MapperBankSwitched { addr: u16, bank: usize },
+}
+
+pub trait EmulatorEvents {
+    /// Every event, in the order they happen
+    fn on_event(&mut self, _event: &Event) {}
+    /// Start of vblank: the picture is complete
+    fn on_vblank(&mut self, _frame: &Frame) {}
+    /// The PPU moved to the scanline `line`, same as `Event::ScanlineStarted`
+    fn on_scanline(&mut self, _line: usize) {}
+    /// Audio output, see `audio::AudioSink`. todo: not called yet, there is no APU
+    fn on_apu_samples(&mut self, _samples: &[f32]) {}
+}
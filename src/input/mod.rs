@@ -1,5 +1,9 @@
+#[cfg(feature = "save-state")]
+use serde::{Deserialize, Serialize};
+
 bitflags! {
         // https://wiki.nesdev.com/w/index.php/Controller_reading_code
+        #[cfg_attr(feature = "save-state", derive(Serialize, Deserialize))]
         pub struct JoypadButton: u8 {
             const RIGHT             = 0b10000000;
             const LEFT              = 0b01000000;
@@ -12,6 +16,8 @@ bitflags! {
         }
 }
 
+#[cfg_attr(feature = "save-state", derive(Serialize, Deserialize))]
+#[derive(Clone)]
 pub struct Joypad {
     strobe: bool,
     button_index: u8,
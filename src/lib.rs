@@ -1,12 +1,65 @@
+// Features:
+// - `std` (default): file IO (rom db, symbol files, save state slots), crash reports and the
+//   instruction trace output, the `Emulator` front and the tools around the machine (debugger,
+//   movies, cheats, ...). Without it the crate is `no_std + alloc`: the cpu, the PPU, the bus
+//   with the mappers and the renderer, no file system access and no printing.
+// - `save-state` (default, needs `std`): save states and snapshots, pulls in serde and bincode.
+//
+// SDL2 frontend lives in native/, the terminal one (crossterm) in snake/: library consumers
+// don't depend on them.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod achievements;
+pub mod audio;
 pub mod bus;
+#[cfg(feature = "std")]
+pub mod cheats;
+#[cfg(feature = "std")]
+pub mod clock;
+#[cfg(feature = "std")]
+pub mod config_file;
 pub mod cpu;
+#[cfg(feature = "std")]
+pub mod debugger;
+#[cfg(feature = "std")]
 pub mod disasm;
+#[cfg(feature = "std")]
+pub mod dump;
+#[cfg(feature = "save-state")]
+pub mod env;
+#[cfg(feature = "std")]
+pub mod emulator;
+pub mod error;
+pub mod events;
 pub mod input;
+#[cfg(feature = "std")]
+pub mod movie;
 pub mod ppu;
+mod prelude;
+pub mod region;
 pub mod rom;
+#[cfg(feature = "save-state")]
+pub mod rewind;
+#[cfg(feature = "save-state")]
+pub mod rollback;
+#[cfg(feature = "save-state")]
+pub mod run_ahead;
+#[cfg(feature = "save-state")]
+pub mod save_state;
 pub mod screen;
+pub mod simple_machine;
+#[cfg(feature = "std")]
+pub mod symbols;
+
+#[cfg(feature = "std")]
+pub use emulator::{Budget, Config, Emulator, EmulatorBuilder, Inputs, RamInit};
+pub use error::RustnessError;
+pub use region::Region;
+pub use events::{EmulatorEvents, Event};
 
 #[macro_use]
 extern crate bitflags;
-#[macro_use]
-extern crate lazy_static;
+
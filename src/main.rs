@@ -1,49 +1,299 @@
 use rustness::bus::Bus;
 use rustness::cpu::cpu::CPU;
 use rustness::cpu::mem::Mem;
-use rustness::input;
+use rustness::cpu::trace_writer::TraceWriter;
+use rustness::debugger::coverage::Coverage;
+use rustness::debugger::golden_log::GoldenLog;
+use rustness::disasm::Disasm;
 use rustness::ppu::ppu::NesPPU;
-use rustness::rom::Rom;
+use rustness::input::JoypadButton;
+use rustness::rom::header::{self, Header};
+use rustness::rom::mapper;
+use rustness::rom::{crc32, crc32_update, Mirroring, Rom};
+use rustness::screen::debug_images;
+use rustness::symbols::Symbols;
+use rustness::{Emulator, Inputs};
 use std::io::Read;
 
 use rustness::bus::DynamicBusWrapper;
 use std::cell::RefCell;
-use std::fs::File;
+use std::env;
+use std::fs::{self, File};
 use std::fs::OpenOptions;
-use std::io::prelude::*;
+use std::path::Path;
 use std::rc::Rc;
+
+// usage: rustness [rom] [--pc=C000] [--golden=nestest.log [--ignore-timing]] [--coverage]
+//   --golden runs the rom comparing each trace line against the reference log,
+//   stops at the first mismatch; otherwise the trace is written to nestest.log
+//   --coverage prints executed opcodes summary when the run is over
+//
+// subcommands:
+//   rustness dump --rom game.nes [--what pattern,nametable,palette] [--frame N] [--out dir]
+//     runs N frames headless (no buttons pressed) and writes PNGs of the PPU memory
+//   rustness info game.nes
+//     header fields (iNES or NES 2.0), mapper name and checksums, for roms that don't run too
+//   rustness disasm game.nes [--bank N] [--symbols file] [--cdl file] [--ca65 | --listing] [--out file]
+//     control-flow aware disassembly of $8000-$FFFF as at power on, or of one 16KB PRG bank;
+//     --cdl takes an FCEUX code/data log of the rom to tell code from data (not with --bank),
+//     --ca65 gives source ca65 assembles back, --listing adds the bytes of every line.
+//     Printed to stdout without --out
 fn main() {
-    // let mut file = File::open("test_rom/ice_climber.nes").unwrap();
-    let mut file = File::open("test_rom/nestest.nes").unwrap();
+    let args = env::args().collect::<Vec<String>>();
+    let result = match args.get(1).map(|arg| arg.as_str()) {
+        Some("dump") => Some(dump(&args[2..])),
+        Some("info") => Some(info(&args[2..])),
+        Some("disasm") => Some(disasm(&args[2..])),
+        _ => None,
+    };
+    if let Some(result) = result {
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let rom_path = args
+        .iter()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .map(|arg| arg.as_str())
+        // .unwrap_or("test_rom/ice_climber.nes");
+        .unwrap_or("test_rom/nestest.nes");
+    let mut file = File::open(rom_path).unwrap();
     let mut data = Vec::new();
     file.read_to_end(&mut data).unwrap();
 
     let rom = Rom::load(&data).unwrap();
 
-    let func = |_: &NesPPU, _: &mut input::Joypad| {
-        // do nothing
-    };
-
-    let mut bus = Bus::<NesPPU>::new(rom, func);
+    let mut bus = Bus::<NesPPU>::new(rom);
 
-    let start_pc = Mem::read_u16(&mut bus, 0xfffc);
+    let start_pc = match args.iter().find(|arg| arg.starts_with("--pc=")) {
+        // nestest.log is produced in "automation" mode, starting at $C000
+        Some(arg) => u16::from_str_radix(&arg["--pc=".len()..], 16).unwrap(),
+        None => Mem::read_u16(&mut bus, 0xfffc),
+    };
 
     let memory = Rc::from(RefCell::from(bus));
     let mem_wraper = DynamicBusWrapper::new(memory.clone());
     let mut cpu = CPU::new(Box::from(mem_wraper));
     cpu.program_counter = start_pc; //0x8000 as u16 + pc as u16;
 
-    let mut file = OpenOptions::new()
+    let mut coverage = if args.iter().any(|arg| arg == "--coverage") {
+        Some(Coverage::new())
+    } else {
+        None
+    };
+
+    if let Some(arg) = args.iter().find(|arg| arg.starts_with("--golden=")) {
+        let mut golden = GoldenLog::load(Path::new(&arg["--golden=".len()..]))
+            .unwrap()
+            .ignore_timing(args.iter().any(|arg| arg == "--ignore-timing"));
+        cpu.interpret_fn(0xffff, |cpu| {
+            if golden.is_finished() {
+                println!("all {} lines match", golden.matched_lines());
+                print_coverage(&coverage);
+                std::process::exit(0);
+            }
+            if let Err(mismatch) = golden.check(&rustness::cpu::trace(cpu)) {
+                println!("{}", mismatch);
+                print_coverage(&coverage);
+                std::process::exit(1);
+            }
+            if let Some(coverage) = coverage.as_mut() {
+                coverage.record(cpu);
+            }
+        });
+        return;
+    }
+
+    let file = OpenOptions::new()
         .write(true)
         .create(true)
         // .append(true)
         .open("nestest.log")
         .unwrap();
+    let mut log = TraceWriter::new(file);
 
     cpu.interpret_fn(0xffff, |cpu| {
-        file.write_all(&(rustness::cpu::trace(cpu) + "\n").as_bytes())
-            .unwrap();
-        file.flush().unwrap();
-        println!("{}", rustness::cpu::trace(cpu));
+        let line = rustness::cpu::trace(cpu);
+        log.write_line(&line).unwrap();
+        println!("{}", line);
+        if let Some(coverage) = coverage.as_mut() {
+            coverage.record(cpu);
+        }
     });
+    log.flush().unwrap();
+    print_coverage(&coverage);
+}
+
+fn print_coverage(coverage: &Option<Coverage>) {
+    if let Some(coverage) = coverage {
+        println!("{}", coverage.summary());
+    }
+}
+
+// `--name value` or `--name=value`
+fn option<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let flag = format!("--{}", name);
+    let prefix = format!("--{}=", name);
+    args.iter().enumerate().find_map(|(idx, arg)| {
+        if arg == &flag {
+            args.get(idx + 1).map(|value| value.as_str())
+        } else {
+            arg.strip_prefix(&prefix)
+        }
+    })
+}
+
+fn load_rom(path: &str) -> Result<Rom, String> {
+    let data = fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    Rom::load(&data).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn dump(args: &[String]) -> Result<(), String> {
+    let rom = load_rom(option(args, "rom").ok_or("--rom is missing")?)?;
+    let what = option(args, "what").unwrap_or("pattern,nametable,palette");
+    let frames = match option(args, "frame") {
+        Some(frame) => frame
+            .parse::<usize>()
+            .map_err(|_| format!("bad frame number '{}'", frame))?,
+        None => 0,
+    };
+    let out = Path::new(option(args, "out").unwrap_or("."));
+
+    let mut emulator = Emulator::builder(rom).build();
+    let inputs = Inputs::new(JoypadButton::empty());
+    for _ in 0..frames {
+        emulator.run_frame(&inputs).map_err(|e| e.to_string())?;
+    }
+
+    fs::create_dir_all(out).map_err(|e| format!("failed to create {}: {}", out.display(), e))?;
+    let ppu = emulator.ppu();
+    for kind in what.split(',') {
+        let image = match kind {
+            "pattern" => debug_images::pattern_tables(ppu, 0),
+            "nametable" => debug_images::nametables(ppu),
+            "palette" => debug_images::palettes(ppu),
+            _ => {
+                return Err(format!(
+                    "unknown image '{}', expected pattern, nametable or palette",
+                    kind
+                ))
+            }
+        };
+        let path = out.join(format!("{}_{}.png", kind, frames));
+        fs::write(&path, image.to_png())
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+// the rom path comes right after the subcommand
+fn rom_arg(args: &[String]) -> Result<&str, String> {
+    match args.first() {
+        Some(path) if !path.starts_with("--") => Ok(path),
+        _ => Err(String::from("the rom path is missing")),
+    }
+}
+
+fn disasm(args: &[String]) -> Result<(), String> {
+    let rom = load_rom(rom_arg(args)?)?;
+    let symbols = match option(args, "symbols") {
+        Some(path) => Symbols::load(Path::new(path))?,
+        None => Symbols::new(),
+    };
+    let cdl = match option(args, "cdl") {
+        Some(path) => Some(fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))?),
+        None => None,
+    };
+    let asm = match option(args, "bank") {
+        Some(_) if cdl.is_some() => {
+            return Err(String::from("--cdl covers the whole rom, not a bank"))
+        }
+        Some(bank) => {
+            let bank = bank
+                .parse::<usize>()
+                .map_err(|_| format!("bad bank number '{}'", bank))?;
+            Disasm::from_bank(&rom, bank, &symbols)?
+        }
+        None => match cdl {
+            Some(cdl) => Disasm::from_rom_cdl(&rom, &cdl, &symbols)?,
+            None => Disasm::from_rom(&rom, &symbols),
+        },
+    };
+    let text = if args.iter().any(|arg| arg == "--ca65") {
+        asm.to_ca65()
+    } else if args.iter().any(|arg| arg == "--listing") {
+        asm.to_listing()
+    } else {
+        asm.program.join("\n") + "\n"
+    };
+    match option(args, "out") {
+        Some(path) => fs::write(path, text).map_err(|e| format!("failed to write {}: {}", path, e)),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+fn kb(size: usize) -> String {
+    if size % 1024 == 0 {
+        format!("{}KB", size / 1024)
+    } else {
+        format!("{} bytes", size)
+    }
+}
+
+fn info(args: &[String]) -> Result<(), String> {
+    let path = rom_arg(args)?;
+    let data = fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    let header = Header::parse(&data).map_err(|e| format!("{}: {}", path, e))?;
+
+    let format = if header.nes2.is_some() { "NES 2.0" } else { "iNES" };
+    println!("format:     {}", format);
+    let name = header::mapper_name(header.mapper).unwrap_or("unknown");
+    match header.nes2 {
+        Some(nes2) => println!("mapper:     {}.{} {}", header.mapper, nes2.submapper, name),
+        None => println!("mapper:     {} {}", header.mapper, name),
+    }
+    let supported = header.mapper <= 0xff && mapper::is_supported(header.mapper as u8);
+    println!("supported:  {}", if supported { "yes" } else { "no, runs as NROM" });
+    println!("PRG ROM:    {}", kb(header.prg_rom));
+    println!("CHR ROM:    {}", kb(header.chr_rom));
+    let mirroring = match (header.four_screen, header.mirroring) {
+        (true, _) => "four screen",
+        (false, Mirroring::VERTICAL) => "vertical",
+        (false, Mirroring::HORIZONTAL) => "horizontal",
+        (false, _) => "mapper controlled",
+    };
+    println!("mirroring:  {}", mirroring);
+    println!("battery:    {}", if header.battery { "yes" } else { "no" });
+    println!("trainer:    {}", if header.trainer { "yes" } else { "no" });
+    println!("console:    {:?}", header.console);
+    println!("region:     {:?}", header.timing);
+    match header.nes2 {
+        Some(nes2) => {
+            println!("PRG RAM:    {}", kb(nes2.prg_ram));
+            println!("PRG NVRAM:  {}", kb(nes2.prg_nvram));
+            println!("CHR RAM:    {}", kb(nes2.chr_ram));
+            println!("CHR NVRAM:  {}", kb(nes2.chr_nvram));
+            println!("misc ROMs:  {}", nes2.misc_roms);
+            println!("expansion:  {}", nes2.expansion_device);
+        }
+        None => println!("PRG RAM:    {}", kb(header.prg_ram)),
+    }
+
+    let (prg, chr) = header
+        .split(&data)
+        .ok_or_else(|| format!("{}: the file is shorter than the header says", path))?;
+    // PRG+CHR is the game database key
+    println!("CRC32:      {:08X}", crc32_update(crc32(prg), chr));
+    println!("PRG CRC32:  {:08X}", crc32(prg));
+    println!("CHR CRC32:  {:08X}", crc32(chr));
+    println!("file CRC32: {:08X}", crc32(&data));
+    Ok(())
 }
@@ -0,0 +1,159 @@
+// Input movies in FCEUX's FM2 format (the one TASVideos and speedrunners use):
+// key-value header lines, then one line per frame
+//   |0|RLDUTSBA|........||
+// The first field is the command (soft/hard reset), then one field per controller port:
+// a letter for a pressed button, '.' or ' ' for a released one.
+// http://fceux.com/web/help/fm2.html
+//
+// Only the inputs of the two standard controllers are read, commands are ignored.
+use crate::emulator::Inputs;
+use crate::input::JoypadButton;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+// in the FM2 order, the highest bit first
+const BUTTONS: &str = "RLDUTSBA";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub frames: Vec<Inputs>,
+}
+
+fn parse_port(field: &str) -> Result<JoypadButton, String> {
+    if field.is_empty() {
+        return Ok(JoypadButton::empty());
+    }
+    if field.chars().count() != BUTTONS.len() {
+        return Err(format!("bad controller input '{}'", field));
+    }
+    let mut bits = 0u8;
+    for (idx, c) in field.chars().enumerate() {
+        if c != '.' && c != ' ' {
+            bits |= 0x80 >> idx;
+        }
+    }
+    Ok(JoypadButton::from_bits_truncate(bits))
+}
+
+fn format_port(buttons: JoypadButton) -> String {
+    BUTTONS
+        .chars()
+        .enumerate()
+        .map(|(idx, c)| {
+            if buttons.bits() & (0x80 >> idx) != 0 {
+                c
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+impl Movie {
+    pub fn new() -> Self {
+        Movie { frames: vec![] }
+    }
+
+    pub fn parse_fm2(content: &str) -> Result<Movie, String> {
+        let mut movie = Movie::new();
+        for (line_num, line) in content.lines().enumerate() {
+            // header: "version 3", "romFilename smb", ...
+            if !line.starts_with('|') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('|').collect();
+            if fields.len() < 4 {
+                return Err(format!("line {}: bad input line", line_num + 1));
+            }
+            let port = |idx: usize| {
+                parse_port(fields[idx]).map_err(|e| format!("line {}: {}", line_num + 1, e))
+            };
+            movie.frames.push(Inputs::two_players(port(2)?, port(3)?));
+        }
+        Ok(movie)
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut content = String::from("version 3\nport0 1\nport1 1\nport2 0\n");
+        for inputs in self.frames.iter() {
+            content.push_str(&format!(
+                "|0|{}|{}||\n",
+                format_port(inputs.joypad1),
+                format_port(inputs.joypad2)
+            ));
+        }
+        content
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load(path: &Path) -> Result<Movie, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Movie::parse_fm2(&content)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_fm2())
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+
+    /// Inputs of the frame, no buttons once the movie is over
+    pub fn inputs(&self, frame: usize) -> Inputs {
+        self.frames.get(frame).copied().unwrap_or_default()
+    }
+}
+
+impl Default for Movie {
+    fn default() -> Self {
+        Movie::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_fm2() {
+        let movie = Movie::parse_fm2(
+            "version 3\nromFilename smb\nport0 1\n\
+             |0|........|........||\n\
+             |0|R......A|..D.....||\n\
+             |1|   UT   |||\n",
+        )
+        .unwrap();
+        assert_eq!(movie.frames.len(), 3);
+        assert_eq!(movie.inputs(0), Inputs::default());
+        assert_eq!(
+            movie.inputs(1),
+            Inputs::two_players(
+                JoypadButton::RIGHT | JoypadButton::BUTTON_A,
+                JoypadButton::DOWN
+            )
+        );
+        assert_eq!(
+            movie.inputs(2).joypad1,
+            JoypadButton::UP | JoypadButton::START
+        );
+        assert_eq!(movie.inputs(3), Inputs::default());
+
+        assert!(Movie::parse_fm2("|0|RL|||").is_err());
+        assert!(Movie::parse_fm2("|0").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut movie = Movie::new();
+        movie.frames.push(Inputs::new(JoypadButton::SELECT));
+        movie.frames.push(Inputs::two_players(
+            JoypadButton::LEFT,
+            JoypadButton::BUTTON_B,
+        ));
+        let content = movie.to_fm2();
+        assert!(content.ends_with("|0|.....S..|........||\n|0|.L......|......B.||\n"));
+        assert_eq!(Movie::parse_fm2(&content).unwrap(), movie);
+    }
+}
@@ -0,0 +1,83 @@
+// Background memory and features on the cartridge side of the PPU bus. Only MMC5 has them,
+// https://wiki.nesdev.com/w/index.php/MMC5:
+// - two more nametable pages for `Mirroring::CUSTOM`: 2 - ExRAM, 3 - the fill page (one tile
+//   and one palette all over);
+// - extended attributes: an ExRAM byte per background tile, bits 0-5 - its 4KB CHR bank,
+//   6-7 - its palette;
+// - the vertical split: the tiles left or right of a screen column come from ExRAM instead,
+//   with their own vertical scroll and 4KB CHR bank.
+//
+// The board keeps the registers and ExRAM, the PPU draws from a copy it gets after every
+// change (`PPU::set_cartridge_video`). The 4KB banks are fetched from the whole CHR ROM
+// (`PPU::set_chr_banks`), not from the 8KB pattern table window.
+use crate::prelude::*;
+
+pub const PAGE_SIZE: usize = 0x400;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeVideo {
+    /// Nametable pages 2 and 3, 1KB each
+    pub pages: Vec<u8>,
+    /// Page 2 holds the extended attributes of the background tiles
+    pub extended_attributes: bool,
+    pub split: Option<Split>,
+    /// Bits 6-7 of the extended attribute banks
+    pub chr_upper: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Split {
+    /// The split is right of `tile` (a screen tile column), left of it otherwise
+    pub right: bool,
+    pub tile: u8,
+    /// 0-239, the split wraps around at the bottom of its nametable
+    pub scroll_y: u8,
+    /// 4KB CHR bank of the split tiles
+    pub bank: u8,
+}
+
+impl Split {
+    /// The tile fetched for screen tile column `column` (0-32) comes from the split
+    pub fn covers(&self, column: usize) -> bool {
+        if self.right {
+            column >= self.tile as usize
+        } else {
+            column < self.tile as usize
+        }
+    }
+}
+
+impl CartridgeVideo {
+    pub fn page(&self, page: usize) -> &[u8] {
+        &self.pages[(page - 2) * PAGE_SIZE..(page - 1) * PAGE_SIZE]
+    }
+
+    /// Extended attributes and the split change tiles the dirty tracking doesn't see
+    pub fn redraws_all(&self) -> bool {
+        self.extended_attributes || self.split.is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_side() {
+        let left = Split {
+            right: false,
+            tile: 4,
+            scroll_y: 0,
+            bank: 0,
+        };
+        assert!(left.covers(3));
+        assert!(!left.covers(4));
+        let right = Split {
+            right: true,
+            ..left
+        };
+        assert!(!right.covers(3));
+        assert!(right.covers(4));
+        assert!(right.covers(32));
+    }
+}
@@ -0,0 +1,195 @@
+// Background tiles that changed since they were drawn into the persistent frame buffer:
+// on mostly static screens only what the game touched gets re-rendered.
+//
+// Nametable tiles are tracked in vram order (after mirroring), sprites in screen tiles:
+// the background under them is redrawn the next frame. A scanline drawn with a different
+// scroll/nametable/pattern table than in the previous frame is redrawn whole.
+
+use crate::prelude::*;
+
+const NAMETABLE_TILES: usize = 32 * 30;
+// the 2 vram pages, then the 2 pages on the board (`Mirroring::CUSTOM`)
+const PAGES: usize = 4;
+// a scrolled background tile straddles two screen tiles, hence the extra column and row
+const SCREEN_COLUMNS: usize = 33;
+const SCREEN_ROWS: usize = 31;
+const SCREEN_LINES: usize = 241;
+
+/// What a background scanline is drawn with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineSetup {
+    pub scroll_x: u8,
+    pub scroll_y: u8,
+    pub nametable: u16,
+    pub bank: u16,
+}
+
+pub struct DirtyTiles {
+    // changes for the next frame, and the ones to draw in the current frame
+    next: Vec<bool>,
+    current: Vec<bool>,
+    next_screen: Vec<bool>,
+    current_screen: Vec<bool>,
+    next_all: bool,
+    current_all: bool,
+    lines: Vec<Option<LineSetup>>,
+    line_changed: bool,
+}
+
+impl DirtyTiles {
+    pub fn new() -> Self {
+        DirtyTiles {
+            next: vec![false; PAGES * NAMETABLE_TILES],
+            current: vec![false; PAGES * NAMETABLE_TILES],
+            next_screen: vec![false; SCREEN_COLUMNS * SCREEN_ROWS],
+            current_screen: vec![false; SCREEN_COLUMNS * SCREEN_ROWS],
+            next_all: true,
+            current_all: true,
+            lines: vec![None; SCREEN_LINES],
+            line_changed: true,
+        }
+    }
+
+    /// Palette, pattern table or a whole vram change
+    pub fn mark_all(&mut self) {
+        self.next_all = true;
+        self.current_all = true;
+    }
+
+    /// Write to the nametable/attribute byte at `idx` of the (mirrored) vram, the board pages
+    /// follow it
+    pub fn mark_vram(&mut self, idx: usize) {
+        let (nametable, offset) = (idx / 0x400, idx % 0x400);
+        if offset < NAMETABLE_TILES {
+            self.mark(nametable * NAMETABLE_TILES + offset);
+            return;
+        }
+        // an attribute byte covers 4x4 tiles
+        let (row, column) = ((offset - 0x3c0) / 8 * 4, (offset - 0x3c0) % 8 * 4);
+        for row in row..(row + 4).min(30) {
+            for column in column..column + 4 {
+                self.mark(nametable * NAMETABLE_TILES + row * 32 + column);
+            }
+        }
+    }
+
+    fn mark(&mut self, tile: usize) {
+        // tiles written mid-frame are drawn in the remaining lines too
+        self.next[tile] = true;
+        self.current[tile] = true;
+    }
+
+    /// Sprites were drawn over the background at `oam` positions
+    pub fn mark_sprites(&mut self, oam: &[u8]) {
+        for sprite in oam.chunks(4) {
+            let (x, y) = (sprite[3] as usize, sprite[0] as usize);
+            // 8x8, the last pixel is 7 away
+            let (right, bottom) = (x + 7, y + 7);
+            for row in y / 8..=(bottom / 8).min(SCREEN_ROWS - 1) {
+                for column in x / 8..=right / 8 {
+                    self.next_screen[row * SCREEN_COLUMNS + column] = true;
+                }
+            }
+        }
+    }
+
+    pub fn start_frame(&mut self) {
+        core::mem::swap(&mut self.current, &mut self.next);
+        self.next.iter_mut().for_each(|dirty| *dirty = false);
+        core::mem::swap(&mut self.current_screen, &mut self.next_screen);
+        self.next_screen.iter_mut().for_each(|dirty| *dirty = false);
+        self.current_all = self.next_all;
+        self.next_all = false;
+    }
+
+    pub fn start_line(&mut self, line: usize, setup: LineSetup) {
+        self.line_changed = self.lines[line] != Some(setup);
+        self.lines[line] = Some(setup);
+    }
+
+    /// `tile` - index in all the nametable pages, `screen_x`/`screen_y` - where its pixels go
+    pub fn needs_redraw(&self, tile: usize, screen_x: isize, screen_y: isize) -> bool {
+        if self.current_all || self.line_changed || self.current[tile] {
+            return true;
+        }
+        let row = (screen_y.max(0) as usize / 8).min(SCREEN_ROWS - 1);
+        let first = (screen_x.max(0) as usize / 8).min(SCREEN_COLUMNS - 1);
+        let last = ((screen_x + 7).max(0) as usize / 8).min(SCREEN_COLUMNS - 1);
+        self.current_screen[row * SCREEN_COLUMNS + first]
+            || self.current_screen[row * SCREEN_COLUMNS + last]
+    }
+}
+
+impl Default for DirtyTiles {
+    fn default() -> Self {
+        DirtyTiles::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SETUP: LineSetup = LineSetup {
+        scroll_x: 0,
+        scroll_y: 0,
+        nametable: 0x2000,
+        bank: 0,
+    };
+
+    fn settled() -> DirtyTiles {
+        let mut dirty = DirtyTiles::new();
+        dirty.start_frame();
+        dirty.start_frame();
+        dirty.start_line(1, SETUP);
+        dirty.start_line(1, SETUP);
+        dirty
+    }
+
+    #[test]
+    fn test_nothing_changed() {
+        let mut dirty = DirtyTiles::new();
+        assert!(dirty.needs_redraw(0, 0, 0));
+        dirty = settled();
+        assert!(!dirty.needs_redraw(0, 0, 0));
+        assert!(!dirty.needs_redraw(2 * NAMETABLE_TILES - 1, 248, 232));
+    }
+
+    #[test]
+    fn test_attribute_write() {
+        let mut dirty = settled();
+        // second nametable, attribute byte of the 4x4 tiles block at row 4, column 8
+        dirty.mark_vram(0x400 + 0x3c0 + 8 + 2);
+        assert!(dirty.needs_redraw(NAMETABLE_TILES + 4 * 32 + 8, 0, 0));
+        assert!(dirty.needs_redraw(NAMETABLE_TILES + 7 * 32 + 11, 0, 0));
+        assert!(!dirty.needs_redraw(NAMETABLE_TILES + 8 * 32 + 8, 0, 0));
+        assert!(!dirty.needs_redraw(4 * 32 + 8, 0, 0));
+
+        dirty.start_frame();
+        assert!(dirty.needs_redraw(NAMETABLE_TILES + 4 * 32 + 8, 0, 0));
+        dirty.start_frame();
+        assert!(!dirty.needs_redraw(NAMETABLE_TILES + 4 * 32 + 8, 0, 0));
+    }
+
+    #[test]
+    fn test_sprites_and_scroll() {
+        let mut dirty = settled();
+        let mut oam = vec![0xff; 256];
+        oam[0..4].copy_from_slice(&[20, 0x01, 0, 100]);
+        dirty.mark_sprites(&oam);
+        assert!(!dirty.needs_redraw(0, 100, 20));
+        dirty.start_frame();
+        assert!(dirty.needs_redraw(0, 100, 20));
+        assert!(dirty.needs_redraw(0, 96, 24));
+        assert!(!dirty.needs_redraw(0, 120, 20));
+
+        dirty.start_line(
+            1,
+            LineSetup {
+                scroll_x: 1,
+                ..SETUP
+            },
+        );
+        assert!(dirty.needs_redraw(0, 120, 20));
+    }
+}
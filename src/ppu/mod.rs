@@ -1,2 +1,6 @@
+pub mod cartridge;
+pub mod dirty_tiles;
 pub mod ppu;
 pub mod registers;
+#[cfg(feature = "std")]
+pub mod render_thread;
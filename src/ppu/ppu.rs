@@ -1,12 +1,24 @@
 // http://www.dustmop.io/blog/2015/04/28/nes-graphics-part-1/
 
+use crate::error::PpuError;
+use crate::ppu::cartridge::{self, CartridgeVideo};
+use crate::ppu::dirty_tiles::{DirtyTiles, LineSetup};
+#[cfg(feature = "std")]
+use crate::ppu::render_thread::RenderThread;
 use crate::ppu::registers::control::ControlRegister;
 use crate::ppu::registers::mask::MaskRegister;
 use crate::ppu::registers::status::StatusRegister;
+use crate::region::Region;
 use crate::rom::Mirroring;
-use crate::screen::frame::Frame;
+use crate::screen::frame::{Frame, PixelSink};
+#[cfg(feature = "std")]
+use crate::screen::indexed::IndexedFrame;
+use crate::screen::palette;
 use crate::screen::render;
-use std::cell::RefCell;
+#[cfg(feature = "save-state")]
+use serde::{Deserialize, Serialize};
+use crate::prelude::*;
+use core::mem;
 
 pub struct NesPPU {
     pub chr_rom: Vec<u8>,
@@ -21,15 +33,49 @@ pub struct NesPPU {
     pub oam_data: [u8; 256],
     pub line: usize,
     pub cycles: usize,
+    // scanlines per frame and the vblank line
+    pub region: Region,
     nmi_interrupt: Option<u8>,
     pub palette_table: [u8; 32],
     read_data_buf: u8,
 
-    pub frame: RefCell<Frame>,
+    // persistent buffer the picture is rendered into, handed out by reference
+    frame: Frame,
+    // background tiles to re-render into `frame`
+    pub dirty_tiles: DirtyTiles,
+    // pixels are rendered for 1 of every `frame_skip` frames, timing and NMI run for all of them
+    frame_skip: usize,
+    frames: usize,
+    // 8 sprites per scanline are drawn, the sprite overflow flag is set either way
+    sprite_limit: bool,
+    // $2007 writes while the picture is drawn are faults
+    strict_vram: bool,
+    // overclock: scanlines of extra CPU time after vblank, the PPU stands still meanwhile
+    extra_lines: usize,
+    // dots of the extra lines left in this frame
+    idle_dots: usize,
+    // pixels are drawn here instead of the emulation thread when set
+    #[cfg(feature = "std")]
+    render_thread: Option<RenderThread>,
+    // NES color index -> RGB, SYSTEM_PALETTE by default
+    pub system_palette: [(u8, u8, u8); 64],
+    // palette_table resolved to RGB: 4 background then 4 sprite palettes.
+    // Refreshed on palette writes and at the start of every frame
+    pub rgb_palettes: [[(u8, u8, u8); 4]; 8],
+    // the first fault since the last `take_error`
+    error: Option<PpuError>,
+    // $2007 pattern table (or board nametable) write for the cartridge, see `take_chr_write`
+    chr_write: Option<(u16, u8)>,
+    // nametable pages and background features of the board (MMC5), see `ppu::cartridge`
+    cartridge: Option<CartridgeVideo>,
+    // the whole CHR ROM, for the 4KB banks of `cartridge`
+    chr_banks: Vec<u8>,
 
     pub sprite_zero_pixels: Vec<(u8, u8)>
 }
 
+#[cfg_attr(feature = "save-state", derive(Serialize, Deserialize))]
+#[derive(Clone)]
 pub struct Addr {
     value: (u8, u8),
     hi_ptr: bool,
@@ -74,6 +120,8 @@ impl Addr {
     }
 }
 
+#[cfg_attr(feature = "save-state", derive(Serialize, Deserialize))]
+#[derive(Clone)]
 pub struct Scroll {
     pub scroll_x: u8,
     pub scroll_y: u8,
@@ -103,6 +151,27 @@ impl Scroll {
     }
 }
 
+// Everything except rom data (chr rom, mirroring) and the rendered frame
+#[cfg(feature = "save-state")]
+#[derive(Serialize, Deserialize)]
+pub struct PpuState {
+    ctrl: ControlRegister,
+    mask: MaskRegister,
+    status: StatusRegister,
+    oam_addr: u8,
+    scroll: Scroll,
+    addr: Addr,
+    vram: Vec<u8>,
+    oam_data: Vec<u8>,
+    line: usize,
+    cycles: usize,
+    nmi_interrupt: Option<u8>,
+    palette_table: Vec<u8>,
+    read_data_buf: u8,
+    sprite_zero_pixels: Vec<(u8, u8)>,
+    idle_dots: usize,
+}
+
 pub trait PPU {
     fn write_to_ctrl(&mut self, value: u8);
     fn write_to_mask(&mut self, value: u8);
@@ -115,17 +184,187 @@ pub trait PPU {
     fn write_to_data(&mut self, value: u8);
     fn read_data(&mut self) -> u8;
     fn write_oam_dma(&mut self, value: &[u8; 256]);
+    /// true when the picture is complete (start of vblank)
     fn tick(&mut self, cycles: u16) -> bool;
     fn poll_nmi_interrupt(&mut self) -> Option<u8>;
+    fn set_region(&mut self, _region: Region) {}
+    /// Reset button: the registers are cleared, memory is kept
+    fn reset(&mut self) {}
+    /// Power on state, memory included
+    fn power_on(&mut self) {}
+    fn take_error(&mut self) -> Option<PpuError> {
+        None
+    }
+    /// The pattern tables after a bank switch, 8KB
+    fn set_chr(&mut self, _chr: Vec<u8>) {}
+    fn set_mirroring(&mut self, _mirroring: Mirroring) {}
+    /// The board's nametable pages and background features (MMC5), after a change
+    fn set_cartridge_video(&mut self, _video: Option<CartridgeVideo>) {}
+    /// The whole CHR ROM, for the board's 4KB background banks (see `ppu::cartridge`)
+    fn set_chr_banks(&mut self, _chr: Vec<u8>) {}
+    /// $2007 write to the pattern tables or a nametable page on the board: it's up to the
+    /// cartridge, see `write_chr` and `set_cartridge_video`
+    fn take_chr_write(&mut self) -> Option<(u16, u8)> {
+        None
+    }
+    /// A pattern table write the cartridge took (CHR RAM)
+    fn write_chr(&mut self, _addr: u16, _data: u8) {}
+    fn scanline(&self) -> usize {
+        0
+    }
+    /// The background or the sprites are on
+    fn rendering_enabled(&self) -> bool {
+        false
+    }
 }
 
 impl NesPPU {
+    /// Hands the current picture to `sink`
+    pub fn blit(&self, sink: &mut dyn PixelSink) {
+        sink.blit(self.frame_buffer(), Frame::WIDTH * 3);
+    }
+
+    /// Hands the current picture to `out` as NES color indices into the current system palette
+    #[cfg(feature = "std")]
+    pub fn blit_indexed(&self, out: &mut IndexedFrame) {
+        out.set_palette(self.system_palette);
+        self.blit(out);
+    }
+
+    /// The picture, complete at the start of vblank (when `tick` returns true)
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    /// RGB24, `Frame::WIDTH * 3` bytes per row
+    pub fn frame_buffer(&self) -> &[u8] {
+        &self.frame.data
+    }
+
+    /// Fast-forward: render 1 of every `n` frames (1 - all of them).
+    /// Skipped frames leave the previous picture in the buffer.
+    pub fn set_frame_skip(&mut self, n: usize) {
+        self.frame_skip = n.max(1);
+    }
+
+    pub fn frame_skip(&self) -> usize {
+        self.frame_skip
+    }
+
+    /// Off - no sprite flicker: all the sprites of a scanline are drawn. Games that hide
+    /// sprites on purpose with the limit (e.g. behind doors) show them then.
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.sprite_limit = enabled;
+    }
+
+    pub fn sprite_limit(&self) -> bool {
+        self.sprite_limit
+    }
+
+    /// Strict mode for homebrew development: $2007 writes with rendering on outside of vblank
+    /// are reported as `PpuError::VramWriteDuringRendering`. The hardware is busy fetching tiles
+    /// then, the write lands at a garbage address and scrolling glitches; games update VRAM
+    /// in vblank or with the rendering off (forced blank). The write itself still goes through.
+    /// $2007 accesses to the $3000-$3EFF mirror are reported as `PpuError::UnusedMirror` too.
+    pub fn set_strict_vram(&mut self, enabled: bool) {
+        self.strict_vram = enabled;
+    }
+
+    pub fn strict_vram(&self) -> bool {
+        self.strict_vram
+    }
+
+    /// Background or sprites are on and the PPU is on a visible or the pre-render scanline
+    pub fn is_render_period(&self) -> bool {
+        (self.mask.show_background() || self.mask.show_sprites())
+            && (self.line < 240 || self.line == self.region.scanlines() - 1)
+    }
+
+    /// Overclock: `lines` scanlines of CPU time are added to every frame right after vblank, the
+    /// PPU waits for them. Games that run out of frame time (slowdown) get it, NMI timing and the
+    /// frame rate stay the same. 0 turns it off, from the next frame on.
+    /// todo: the APU has to skip the extra time too (music pitch), once there is one
+    pub fn set_overclock(&mut self, lines: usize) {
+        self.extra_lines = lines;
+    }
+
+    pub fn overclock(&self) -> usize {
+        self.extra_lines
+    }
+
+    /// false while a skipped frame runs
+    pub fn is_rendering(&self) -> bool {
+        self.frames.is_multiple_of(self.frame_skip)
+    }
+
+    /// Draws the picture on a worker thread, `frame()` is still complete at the start of vblank
+    #[cfg(feature = "std")]
+    pub fn set_render_thread(&mut self, enabled: bool) {
+        match (enabled, self.render_thread.is_some()) {
+            (true, false) => {
+                let frame = mem::replace(&mut self.frame, Frame::new());
+                self.render_thread = Some(RenderThread::spawn(frame));
+            }
+            (false, true) => {
+                self.render_thread = None;
+                // the buffer of the emulation thread wasn't kept up to date
+                self.dirty_tiles.mark_all();
+            }
+            _ => {}
+        }
+    }
+
+    // renderers read the ppu state while writing to the buffer: it's moved out
+    // for the duration, an empty Vec doesn't allocate
+    fn render_with<F: FnOnce(&NesPPU, &mut Frame)>(&mut self, render: F) {
+        let mut frame = mem::replace(&mut self.frame, Frame { data: Vec::new() });
+        render(self, &mut frame);
+        self.frame = frame;
+    }
+
+    // the render thread takes the scanline, false - there is none: it's drawn here
+    #[cfg(feature = "std")]
+    fn thread_scanline(&self, line: usize) -> bool {
+        match self.render_thread.as_ref() {
+            Some(thread) => {
+                thread.scanline(render::fetch_scanline(self, line));
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn thread_scanline(&self, _line: usize) -> bool {
+        false
+    }
+
+    // the render thread draws the sprites and gives the complete picture back
+    #[cfg(feature = "std")]
+    fn thread_end_frame(&mut self) -> bool {
+        match self.render_thread.as_ref() {
+            Some(thread) => {
+                let recycled = mem::replace(&mut self.frame, Frame { data: Vec::new() });
+                let sprites = render::fetch_sprites(self);
+                self.frame =
+                    thread.end_frame(sprites, self.rgb_palettes, self.sprite_limit, recycled);
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn thread_end_frame(&mut self) -> bool {
+        false
+    }
+
     pub fn new_empty_rom() -> Self {
         NesPPU::new(vec![0; 2048], Mirroring::HORIZONTAL)
     }
 
     pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
-        NesPPU {
+        let mut ppu = NesPPU {
             chr_rom: chr_rom,
             mirroring: mirroring,
             ctrl: ControlRegister::new(),
@@ -138,12 +377,102 @@ impl NesPPU {
             oam_data: [0; 64 * 4],
             line: 0,
             cycles: 0,
+            region: Region::Ntsc,
             nmi_interrupt: None,
             palette_table: [0; 32],
             read_data_buf: 0,
-            frame: RefCell::from(Frame::new()),
+            frame: Frame::new(),
+            dirty_tiles: DirtyTiles::new(),
+            frame_skip: 1,
+            frames: 0,
+            sprite_limit: true,
+            strict_vram: false,
+            extra_lines: 0,
+            idle_dots: 0,
+            #[cfg(feature = "std")]
+            render_thread: None,
+            system_palette: palette::SYSTEM_PALETTE,
+            rgb_palettes: [[(0, 0, 0); 4]; 8],
+            error: None,
+            chr_write: None,
+            cartridge: None,
+            chr_banks: Vec::new(),
             sprite_zero_pixels: vec!(),
+        };
+        ppu.resolve_palettes();
+        ppu
+    }
+
+    /// Switches the NES color -> RGB table, the next frame is drawn with it
+    pub fn set_palette(&mut self, palette: [(u8, u8, u8); 64]) {
+        self.system_palette = palette;
+        self.resolve_palettes();
+    }
+
+    pub fn resolve_palettes(&mut self) {
+        let before = self.rgb_palettes;
+        for (idx, palette) in self.rgb_palettes.iter_mut().enumerate() {
+            for (color, rgb) in palette.iter_mut().enumerate() {
+                // color 0 of background palettes is the universal backdrop at $3F00
+                let entry = match (idx, color) {
+                    (0..=3, 0) => 0,
+                    _ => idx * 4 + color,
+                };
+                *rgb = self.system_palette[self.palette_table[entry] as usize];
+            }
         }
+        if self.rgb_palettes[..4] != before[..4] {
+            self.dirty_tiles.mark_all();
+        }
+    }
+
+    #[cfg(feature = "save-state")]
+    pub fn save_state(&self) -> PpuState {
+        PpuState {
+            ctrl: self.ctrl,
+            mask: self.mask,
+            status: self.status,
+            oam_addr: self.oam_addr,
+            scroll: self.scroll.clone(),
+            addr: self.addr.clone(),
+            vram: self.vram.to_vec(),
+            oam_data: self.oam_data.to_vec(),
+            line: self.line,
+            cycles: self.cycles,
+            nmi_interrupt: self.nmi_interrupt,
+            palette_table: self.palette_table.to_vec(),
+            read_data_buf: self.read_data_buf,
+            sprite_zero_pixels: self.sprite_zero_pixels.clone(),
+            idle_dots: self.idle_dots,
+        }
+    }
+
+    #[cfg(feature = "save-state")]
+    pub fn load_state(&mut self, state: PpuState) -> Result<(), String> {
+        if state.vram.len() != self.vram.len()
+            || state.oam_data.len() != self.oam_data.len()
+            || state.palette_table.len() != self.palette_table.len()
+        {
+            return Err("corrupted PPU state".to_string());
+        }
+        self.ctrl = state.ctrl;
+        self.mask = state.mask;
+        self.status = state.status;
+        self.oam_addr = state.oam_addr;
+        self.scroll = state.scroll;
+        self.addr = state.addr;
+        self.vram.copy_from_slice(&state.vram);
+        self.oam_data.copy_from_slice(&state.oam_data);
+        self.line = state.line;
+        self.cycles = state.cycles;
+        self.nmi_interrupt = state.nmi_interrupt;
+        self.palette_table.copy_from_slice(&state.palette_table);
+        self.read_data_buf = state.read_data_buf;
+        self.sprite_zero_pixels = state.sprite_zero_pixels;
+        self.idle_dots = state.idle_dots;
+        self.resolve_palettes();
+        self.dirty_tiles.mark_all();
+        Ok(())
     }
 
     // Horizontal:
@@ -153,6 +482,8 @@ impl NesPPU {
     // Vertical:
     //   [ A ] [ B ]
     //   [ a ] [ b ]
+    //
+    // Custom (MMC5): any page in any of the 4. The board pages follow vram: $0800-$0FFF
     pub fn mirror_vram_addr(&self, addr: u16) -> u16 {
         let mirrored_vram = addr & 0b10111111111111; // mirror down 0x3000-0x3eff to 0x2000 - 0x2eff
         let vram_index = mirrored_vram - 0x2000; // to vram vector
@@ -162,10 +493,59 @@ impl NesPPU {
             (Mirroring::HORIZONTAL, 2) => vram_index - 0x400,
             (Mirroring::HORIZONTAL, 1) => vram_index - 0x400,
             (Mirroring::HORIZONTAL, 3) => vram_index - 0x800,
+            (Mirroring::SINGLE_SCREEN_LOWER, _) => vram_index % 0x400,
+            (Mirroring::SINGLE_SCREEN_UPPER, _) => vram_index % 0x400 + 0x400,
+            (Mirroring::CUSTOM(pages), slot) => pages[slot as usize] as u16 * 0x400 + vram_index % 0x400,
             _ => vram_index,
         }
     }
 
+    /// A 1KB nametable page: 0 and 1 - vram, 2 and 3 - the board's (`Mirroring::CUSTOM`)
+    pub fn nametable(&self, page: usize) -> &[u8] {
+        const NO_PAGE: [u8; cartridge::PAGE_SIZE] = [0; cartridge::PAGE_SIZE];
+        match &self.cartridge {
+            _ if page < 2 => &self.vram[page * 0x400..(page + 1) * 0x400],
+            Some(cartridge) => cartridge.page(page),
+            None => &NO_PAGE,
+        }
+    }
+
+    /// The nametable byte at $2000-$3EFF, without the side effects of $2007
+    pub fn read_nametable(&self, addr: u16) -> u8 {
+        let idx = self.mirror_vram_addr(addr) as usize;
+        self.nametable(idx / 0x400)[idx % 0x400]
+    }
+
+    pub fn cartridge_video(&self) -> Option<&CartridgeVideo> {
+        self.cartridge.as_ref()
+    }
+
+    /// The 4KB `bank` of the whole CHR ROM, the pattern tables window without one (CHR RAM)
+    pub fn chr_bank(&self, bank: usize) -> &[u8] {
+        if self.chr_banks.is_empty() {
+            return &self.chr_rom[bank % 2 * 0x1000..(bank % 2 + 1) * 0x1000];
+        }
+        let start = bank * 0x1000 % self.chr_banks.len();
+        &self.chr_banks[start..start + 0x1000]
+    }
+
+    // vram, or a page on the board: the cartridge takes the write and hands the page back
+    fn write_nametable(&mut self, addr: u16, value: u8) {
+        let idx = self.mirror_vram_addr(addr) as usize;
+        if idx < self.vram.len() {
+            self.vram[idx] = value;
+            self.dirty_tiles.mark_vram(idx);
+        } else {
+            self.chr_write = Some((addr & 0x2fff, value));
+        }
+    }
+
+    fn fault(&mut self, error: PpuError) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
     fn increment_vram_addr(&mut self) {
         self.addr.increment(self.ctrl.vram_addr_increment());
 
@@ -182,6 +562,17 @@ impl NesPPU {
         (y+5 == self.line) && x <= cycle && self.mask.show_sprites()
     }
 
+    // more than 8 sprites on the line, the hardware's buggy evaluation after the 8th isn't emulated
+    fn has_sprite_overflow(&self, line: usize) -> bool {
+        let height = self.ctrl.sprite_size() as usize;
+        let sprites = self
+            .oam_data
+            .chunks(4)
+            .filter(|oam| line >= oam[0] as usize && line < oam[0] as usize + height)
+            .count();
+        sprites > render::SPRITES_PER_LINE
+    }
+
 
 }
 
@@ -232,23 +623,37 @@ impl PPU for NesPPU {
 
     fn write_to_data(&mut self, value: u8) {
         let addr = self.addr.read();
+        if self.strict_vram && self.is_render_period() {
+            let line = self.line;
+            self.fault(PpuError::VramWriteDuringRendering {
+                addr,
+                data: value,
+                line,
+            });
+        }
         match addr {
-            0..=0x1fff => println!("attempt to write to chr rom space {}", addr), //panic!("attempt to write to chr rom space {}", addr),
-            0x2000..=0x2fff => {
-                self.vram[self.mirror_vram_addr(addr) as usize] = value;
+            0..=0x1fff => self.chr_write = Some((addr, value)),
+            0x2000..=0x2fff => self.write_nametable(addr, value),
+            0x3000..=0x3eff => {
+                if self.strict_vram {
+                    self.fault(PpuError::UnusedMirror(addr));
+                }
+                self.write_nametable(addr, value);
             }
-            0x3000..=0x3eff => unimplemented!("addr {} shouldn't be used in reallity", addr),
 
             //Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
             0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
                 let add_mirror = addr - 0x10;
                 self.palette_table[(add_mirror - 0x3f00) as usize] = value;
+                self.resolve_palettes();
             }
             0x3f00..=0x3fff =>
             {
                 self.palette_table[(addr - 0x3f00) as usize] = value;
+                self.resolve_palettes();
             }
-            _ => panic!("unexpected access to mirrored space {}", addr),
+            // the address register is kept under $4000, the write is dropped
+            _ => self.fault(PpuError::UnusedMirror(addr)),
         }
         self.increment_vram_addr();
     }
@@ -266,10 +671,17 @@ impl PPU for NesPPU {
             }
             0x2000..=0x2fff => {
                 let result = self.read_data_buf;
-                self.read_data_buf = self.vram[self.mirror_vram_addr(addr) as usize];
+                self.read_data_buf = self.read_nametable(addr);
+                result
+            }
+            0x3000..=0x3eff => {
+                if self.strict_vram {
+                    self.fault(PpuError::UnusedMirror(addr));
+                }
+                let result = self.read_data_buf;
+                self.read_data_buf = self.read_nametable(addr);
                 result
             }
-            0x3000..=0x3eff => unimplemented!("addr {} shouldn't be used in reallity", addr),
 
             //Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
             0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
@@ -281,7 +693,10 @@ impl PPU for NesPPU {
             {
                 self.palette_table[(addr - 0x3f00) as usize]
             }
-            _ => panic!("unexpected access to mirrored space {}", addr),
+            _ => {
+                self.fault(PpuError::UnusedMirror(addr));
+                self.read_data_buf
+            }
         }
     }
 
@@ -293,7 +708,9 @@ impl PPU for NesPPU {
     }
 
     fn tick(&mut self, cycles: u16) -> bool {
-        self.cycles += cycles as usize;
+        let idle = self.idle_dots.min(cycles as usize);
+        self.idle_dots -= idle;
+        self.cycles += cycles as usize - idle;
         if self.cycles >= 341 {
             if self.has_sprite_hit(self.cycles) {
                 self.status.set_sprite_zero_hit(true);
@@ -305,26 +722,55 @@ impl PPU for NesPPU {
             self.cycles = self.cycles - 341;
             self.line += 1;
 
-            if(self.line < 241) {
-                render::render_bg_scanline(&self, self.line, &mut self.frame.borrow_mut());
+            if self.line < 240
+                && (self.mask.show_background() || self.mask.show_sprites())
+                && self.has_sprite_overflow(self.line)
+            {
+                self.status.set_sprite_overflow(true);
             }
 
-            if self.line == 241 {
-                render::render_sprites(self, &mut self.frame.borrow_mut());
+            if self.line < 241 && self.is_rendering() {
+                let line = self.line;
+                self.dirty_tiles.start_line(line, LineSetup {
+                    scroll_x: self.scroll.scroll_x,
+                    scroll_y: self.scroll.scroll_y,
+                    nametable: self.ctrl.nametable_addr(),
+                    bank: self.ctrl.bknd_pattern_addr(),
+                });
+                if !self.thread_scanline(line) {
+                    self.render_with(|ppu, frame| render::render_bg_scanline(ppu, line, frame));
+                }
+            }
+
+            if self.line == self.region.vblank_line() {
+                if self.is_rendering() && !self.thread_end_frame() {
+                    self.render_with(render::render_sprites);
+                    self.dirty_tiles.mark_sprites(&self.oam_data);
+                }
                 self.status.set_vblank_status(true);
                 self.status.set_sprite_zero_hit(false);
                 if self.ctrl.generate_vblank_nmi() {
                     self.nmi_interrupt = Some(1);
                 }
+                return true;
             }
 
-            if self.line >= 262 {
-                // self.frame.borrow_mut().clear();
+            if self.line >= self.region.scanlines() {
+                // self.frame.clear();
+                // system_palette could have been swapped in between frames
+                self.resolve_palettes();
+                if !self.is_rendering() {
+                    // changes of the skipped frame weren't drawn
+                    self.dirty_tiles.mark_all();
+                }
+                self.dirty_tiles.start_frame();
+                self.frames = self.frames.wrapping_add(1);
                 self.line = 0;
                 self.nmi_interrupt = None;
                 self.status.set_sprite_zero_hit(false);
+                self.status.set_sprite_overflow(false);
                 self.status.reset_vblank_status();
-                return true;
+                self.idle_dots = self.extra_lines * 341;
             }
         }
         return false;
@@ -335,6 +781,95 @@ impl PPU for NesPPU {
     fn poll_nmi_interrupt(&mut self) -> Option<u8> {
         self.nmi_interrupt.take()
     }
+
+    fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    // https://wiki.nesdev.com/w/index.php/PPU_power_up_state
+    fn reset(&mut self) {
+        self.ctrl = ControlRegister::new();
+        self.mask = MaskRegister::new();
+        self.scroll = Scroll::new();
+        self.addr.reset_latch();
+        self.read_data_buf = 0;
+        self.nmi_interrupt = None;
+    }
+
+    // vram, oam and palette contents are unspecified at power on, zeroes here
+    fn power_on(&mut self) {
+        self.reset();
+        self.status = StatusRegister::new();
+        self.oam_addr = 0;
+        self.addr = Addr::new();
+        self.vram = [0; 2048];
+        self.oam_data = [0; 64 * 4];
+        self.palette_table = [0; 32];
+        self.line = 0;
+        self.cycles = 0;
+        self.idle_dots = 0;
+        self.sprite_zero_pixels.clear();
+        self.error = None;
+        self.resolve_palettes();
+        self.dirty_tiles.mark_all();
+    }
+
+    fn take_error(&mut self) -> Option<PpuError> {
+        self.error.take()
+    }
+
+    fn set_chr(&mut self, chr: Vec<u8>) {
+        self.chr_rom = chr;
+        self.dirty_tiles.mark_all();
+    }
+
+    fn set_mirroring(&mut self, mirroring: Mirroring) {
+        if self.mirroring != mirroring {
+            self.mirroring = mirroring;
+            self.dirty_tiles.mark_all();
+        }
+    }
+
+    fn set_cartridge_video(&mut self, video: Option<CartridgeVideo>) {
+        match (&self.cartridge, &video) {
+            (Some(before), Some(after)) if before.pages.len() == after.pages.len() => {
+                for (idx, _) in before
+                    .pages
+                    .iter()
+                    .zip(after.pages.iter())
+                    .enumerate()
+                    .filter(|(_, (a, b))| a != b)
+                {
+                    self.dirty_tiles.mark_vram(self.vram.len() + idx);
+                }
+            }
+            _ => self.dirty_tiles.mark_all(),
+        }
+        self.cartridge = video;
+    }
+
+    fn set_chr_banks(&mut self, chr: Vec<u8>) {
+        self.chr_banks = chr;
+        self.dirty_tiles.mark_all();
+    }
+
+    fn take_chr_write(&mut self) -> Option<(u16, u8)> {
+        self.chr_write.take()
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr_rom[addr as usize] = data;
+        // the nametable tiles using the pattern aren't tracked
+        self.dirty_tiles.mark_all();
+    }
+
+    fn scanline(&self) -> usize {
+        self.line
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.mask.show_background() || self.mask.show_sprites()
+    }
 }
 
 #[cfg(test)]
@@ -413,6 +948,74 @@ pub mod test {
         }
     }
 
+    #[test]
+    fn test_unused_mirror_fault() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ppu_addr(0x30);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.write_to_data(0x66);
+        assert_eq!(ppu.take_error(), None);
+        assert_eq!(ppu.vram[0x0005], 0x66);
+
+        ppu.set_strict_vram(true);
+        ppu.write_to_ppu_addr(0x30);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.read_data();
+        assert_eq!(ppu.take_error(), Some(PpuError::UnusedMirror(0x3005)));
+        assert_eq!(ppu.take_error(), None);
+        assert_eq!(ppu.read_data(), 0x66);
+    }
+
+    #[test]
+    fn test_out_of_range_address_fault() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.addr.set(0x4000);
+        ppu.write_to_data(0x66);
+        assert_eq!(ppu.take_error(), Some(PpuError::UnusedMirror(0x4000)));
+        ppu.addr.set(0x4000);
+        ppu.read_data();
+        assert_eq!(ppu.take_error(), Some(PpuError::UnusedMirror(0x4000)));
+    }
+
+    #[test]
+    fn test_strict_vram() {
+        let mut ppu = NesPPU::new_empty_rom();
+        let write = |ppu: &mut NesPPU| {
+            ppu.write_to_ppu_addr(0x23);
+            ppu.write_to_ppu_addr(0x05);
+            ppu.write_to_data(0x66);
+        };
+        ppu.write_to_mask(0b0001_1000);
+        ppu.line = 100;
+        write(&mut ppu);
+        assert_eq!(ppu.take_error(), None);
+
+        ppu.set_strict_vram(true);
+        write(&mut ppu);
+        assert_eq!(
+            ppu.take_error(),
+            Some(PpuError::VramWriteDuringRendering {
+                addr: 0x2305,
+                data: 0x66,
+                line: 100
+            })
+        );
+        assert_eq!(ppu.vram[0x305], 0x66);
+
+        // vblank, forced blank
+        ppu.line = 245;
+        write(&mut ppu);
+        ppu.line = 100;
+        ppu.write_to_mask(0);
+        write(&mut ppu);
+        assert_eq!(ppu.take_error(), None);
+        // pre-render line
+        ppu.write_to_mask(0b0000_1000);
+        ppu.line = 261;
+        write(&mut ppu);
+        assert!(ppu.take_error().is_some());
+    }
+
     #[test]
     fn test_ppu_vram_writes() {
         let mut ppu = NesPPU::new_empty_rom();
@@ -423,6 +1026,221 @@ pub mod test {
         assert_eq!(ppu.vram[0x0305], 0x66);
     }
 
+    fn write_vram(ppu: &mut NesPPU, addr: u16, data: &[u8]) {
+        ppu.write_to_ppu_addr((addr >> 8) as u8);
+        ppu.write_to_ppu_addr((addr & 0xff) as u8);
+        for byte in data {
+            ppu.write_to_data(*byte);
+        }
+    }
+
+    fn render_frame(ppu: &mut NesPPU) {
+        while !ppu.tick(341) {}
+    }
+
+    #[test]
+    fn test_dirty_tiles_render_same_picture() {
+        let mut chr = vec![0; 0x2000];
+        // tile 1: solid color 1, tile 2: solid color 3
+        chr[16..24].copy_from_slice(&[0xff; 8]);
+        chr[32..48].copy_from_slice(&[0xff; 16]);
+        let setup = |ppu: &mut NesPPU| {
+            write_vram(ppu, 0x3f00, &[0x0f, 0x01, 0x21, 0x31]);
+            write_vram(ppu, 0x3f11, &[0x16]);
+            write_vram(ppu, 0x2000, &[1; 64]);
+            ppu.oam_data[0..4].copy_from_slice(&[100, 1, 0, 200]);
+        };
+        let change = |ppu: &mut NesPPU| {
+            write_vram(ppu, 0x2045, &[2, 2]);
+            write_vram(ppu, 0x23c0, &[0xff]);
+            ppu.oam_data[0..4].copy_from_slice(&[40, 1, 0, 80]);
+        };
+
+        let mut ppu = NesPPU::new(chr.clone(), Mirroring::HORIZONTAL);
+        setup(&mut ppu);
+        // the first frames after palette writes are drawn whole
+        render_frame(&mut ppu);
+        render_frame(&mut ppu);
+        change(&mut ppu);
+        render_frame(&mut ppu);
+
+        let mut fresh = NesPPU::new(chr, Mirroring::HORIZONTAL);
+        setup(&mut fresh);
+        change(&mut fresh);
+        render_frame(&mut fresh);
+
+        assert!(ppu.frame() == fresh.frame());
+    }
+
+    #[test]
+    fn test_render_thread_draws_same_picture() {
+        let mut chr = vec![0; 0x2000];
+        chr[16..24].copy_from_slice(&[0xff; 8]);
+        chr[32..48].copy_from_slice(&[0x0f; 16]);
+        let mut inline = NesPPU::new(chr.clone(), Mirroring::VERTICAL);
+        let mut threaded = NesPPU::new(chr, Mirroring::VERTICAL);
+        threaded.set_render_thread(true);
+
+        for ppu in [&mut inline, &mut threaded].iter_mut() {
+            write_vram(ppu, 0x3f00, &[0x0f, 0x01, 0x21, 0x31, 0x0f, 0x16]);
+            write_vram(ppu, 0x3f11, &[0x27, 0x28, 0x29]);
+            write_vram(ppu, 0x2000, &[1; 200]);
+            write_vram(ppu, 0x2400, &[2; 300]);
+            write_vram(ppu, 0x23c0, &[0x55; 8]);
+            ppu.oam_data[0..8].copy_from_slice(&[100, 2, 0b0100_0001, 200, 30, 1, 0, 20]);
+        }
+        for (scroll_x, scroll_y) in [(0, 0), (13, 0), (0, 21)].iter() {
+            for ppu in [&mut inline, &mut threaded].iter_mut() {
+                ppu.scroll.scroll_x = *scroll_x;
+                ppu.scroll.scroll_y = *scroll_y;
+                render_frame(ppu);
+            }
+            assert!(inline.frame() == threaded.frame());
+        }
+
+        threaded.set_render_thread(false);
+        render_frame(&mut threaded);
+        render_frame(&mut inline);
+        assert!(inline.frame() == threaded.frame());
+    }
+
+    #[test]
+    fn test_frame_skip() {
+        let mut chr = vec![0; 0x2000];
+        chr[16..24].copy_from_slice(&[0xff; 8]);
+        let mut ppu = NesPPU::new(chr, Mirroring::HORIZONTAL);
+        write_vram(&mut ppu, 0x3f00, &[0x0f, 0x01]);
+        ppu.set_frame_skip(2);
+        render_frame(&mut ppu);
+        let before = ppu.frame().clone();
+
+        write_vram(&mut ppu, 0x2000, &[1]);
+        ppu.write_to_ctrl(0b1000_0000);
+        render_frame(&mut ppu);
+        assert!(!ppu.is_rendering());
+        assert!(*ppu.frame() == before);
+        // timing and NMI work as usual
+        assert_eq!(ppu.poll_nmi_interrupt(), Some(1));
+
+        render_frame(&mut ppu);
+        assert!(ppu.is_rendering());
+        assert!(*ppu.frame() != before);
+    }
+
+    #[test]
+    fn test_overclock() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.set_overclock(10);
+        // to the end of the frame: 262 lines
+        let mut dots = 0;
+        while ppu.frames == 0 {
+            ppu.tick(1);
+            dots += 1;
+        }
+        assert_eq!(dots, 262 * 341);
+
+        // 10 lines the PPU stands still, then runs as usual
+        for _ in 0..10 * 341 / 2 {
+            ppu.tick(2);
+        }
+        assert_eq!((ppu.line, ppu.cycles), (0, 0));
+        ppu.tick(2);
+        assert_eq!(ppu.cycles, 2);
+
+        ppu.set_overclock(0);
+        while ppu.frames == 1 {
+            ppu.tick(1);
+        }
+        assert_eq!(ppu.idle_dots, 0);
+    }
+
+    #[test]
+    fn test_sprite_limit() {
+        let mut chr = vec![0; 0x2000];
+        chr[16..24].copy_from_slice(&[0xff; 8]);
+        let mut ppu = NesPPU::new(chr, Mirroring::HORIZONTAL);
+        write_vram(&mut ppu, 0x3f00, &[0x0f]);
+        write_vram(&mut ppu, 0x3f11, &[0x16]);
+        ppu.write_to_mask(0b0001_1000);
+        ppu.oam_data = [0xff; 256];
+        for idx in 0..9 {
+            ppu.oam_data[idx * 4..idx * 4 + 4].copy_from_slice(&[100, 1, 0, idx as u8 * 16]);
+        }
+        // the 10th sprite shares the lower half of its rows with the first 8
+        ppu.oam_data[36..40].copy_from_slice(&[104, 1, 0, 200]);
+        render_frame(&mut ppu);
+
+        let pixel = |ppu: &NesPPU, x: usize, y: usize| {
+            let base = (y * Frame::WIDTH + x) * 3;
+            (ppu.frame().data[base], ppu.frame().data[base + 1], ppu.frame().data[base + 2])
+        };
+        let sprite = palette::SYSTEM_PALETTE[0x16];
+        assert_eq!(pixel(&ppu, 7 * 16, 100), sprite);
+        assert_ne!(pixel(&ppu, 8 * 16, 100), sprite);
+        assert_ne!(pixel(&ppu, 200, 107), sprite);
+        assert_eq!(pixel(&ppu, 200, 108), sprite);
+        assert!(ppu.status.snapshot() & 0b0010_0000 != 0);
+
+        // cleared at the pre-render line
+        for _ in 0..21 {
+            ppu.tick(341);
+        }
+        assert_eq!(ppu.line, 0);
+        assert!(ppu.status.snapshot() & 0b0010_0000 == 0);
+
+        ppu.set_sprite_limit(false);
+        render_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 8 * 16, 100), sprite);
+        assert_eq!(pixel(&ppu, 200, 107), sprite);
+        // the game still sees the overflow
+        assert!(ppu.status.snapshot() & 0b0010_0000 != 0);
+    }
+
+    #[test]
+    fn test_sprite_limit_8x16() {
+        let mut chr = vec![0; 0x2000];
+        chr[16..24].copy_from_slice(&[0xff; 8]);
+        let mut ppu = NesPPU::new(chr, Mirroring::HORIZONTAL);
+        write_vram(&mut ppu, 0x3f00, &[0x0f]);
+        write_vram(&mut ppu, 0x3f11, &[0x16]);
+        ppu.write_to_ctrl(0b0010_0000);
+        ppu.write_to_mask(0b0001_1000);
+        ppu.oam_data = [0xff; 256];
+        for idx in 0..8 {
+            ppu.oam_data[idx * 4..idx * 4 + 4].copy_from_slice(&[100, 1, 0, idx as u8 * 16]);
+        }
+        // below the first 8 as 8x8 sprites, on their lower halves as 8x16
+        ppu.oam_data[32..36].copy_from_slice(&[110, 1, 0, 200]);
+        render_frame(&mut ppu);
+
+        let pixel = |ppu: &NesPPU, x: usize, y: usize| {
+            let base = (y * Frame::WIDTH + x) * 3;
+            (ppu.frame().data[base], ppu.frame().data[base + 1], ppu.frame().data[base + 2])
+        };
+        let sprite = palette::SYSTEM_PALETTE[0x16];
+        assert_eq!(pixel(&ppu, 7 * 16, 100), sprite);
+        assert_ne!(pixel(&ppu, 200, 115), sprite);
+        assert_eq!(pixel(&ppu, 200, 116), sprite);
+    }
+
+    #[test]
+    fn test_palette_writes_are_resolved() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x00);
+        ppu.write_to_data(0x0f); // backdrop
+        ppu.write_to_data(0x01);
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x15);
+        ppu.write_to_data(0x21); // sprite palette 1, color 1
+
+        let rgb = |color: u8| palette::SYSTEM_PALETTE[color as usize];
+        assert_eq!(ppu.rgb_palettes[0][0], rgb(0x0f));
+        assert_eq!(ppu.rgb_palettes[0][1], rgb(0x01));
+        assert_eq!(ppu.rgb_palettes[2][0], rgb(0x0f));
+        assert_eq!(ppu.rgb_palettes[5][1], rgb(0x21));
+    }
+
     // todo:figure out why it's writing to rom
     // #[test]
     // #[should_panic]
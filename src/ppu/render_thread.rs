@@ -0,0 +1,102 @@
+// Pixel generation on a worker thread. PPU timing (vblank, NMI, sprite 0 hit) stays on the
+// emulation thread: it only does the vram/chr fetches for every scanline and sends them over,
+// decoding, palette mapping and drawing happen on the worker.
+use crate::screen::frame::Frame;
+use crate::screen::render::{self, ScanlineFetch, SpriteFetch};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+// sent by value through bounded channels: their slots are allocated once, so there are
+// no allocations per scanline or frame
+#[allow(clippy::large_enum_variant)]
+enum Command {
+    Scanline(ScanlineFetch),
+    EndFrame {
+        sprites: [SpriteFetch; 64],
+        palettes: [[(u8, u8, u8); 4]; 8],
+        sprite_limit: bool,
+    },
+}
+
+const SCANLINES: usize = 240;
+
+pub struct RenderThread {
+    commands: Option<SyncSender<Command>>,
+    // finished frames, and buffers to draw the next ones into
+    frames: Receiver<Frame>,
+    free: SyncSender<Frame>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl RenderThread {
+    /// `frame` is the buffer the first frame is drawn into
+    pub fn spawn(frame: Frame) -> Self {
+        // a whole frame of scanlines can be queued before the emulation thread waits
+        let (commands, commands_rx) = sync_channel(SCANLINES + 1);
+        let (frames_tx, frames) = sync_channel(1);
+        let (free, free_rx) = sync_channel::<Frame>(1);
+        let worker = thread::spawn(move || {
+            let mut frame = frame;
+            for command in commands_rx {
+                match command {
+                    Command::Scanline(fetch) => render::draw_scanline(&mut frame, &fetch),
+                    Command::EndFrame {
+                        sprites,
+                        palettes,
+                        sprite_limit,
+                    } => {
+                        render::draw_sprites(&mut frame, &palettes, &sprites, sprite_limit);
+                        let next = free_rx.recv().unwrap_or_else(|_| Frame::new());
+                        if frames_tx.send(std::mem::replace(&mut frame, next)).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+        RenderThread {
+            commands: Some(commands),
+            frames,
+            free,
+            worker: Some(worker),
+        }
+    }
+
+    pub fn scanline(&self, fetch: ScanlineFetch) {
+        self.send(Command::Scanline(fetch));
+    }
+
+    /// Waits for the worker to finish the frame. `recycled` is reused for the next one
+    pub fn end_frame(
+        &self,
+        sprites: [SpriteFetch; 64],
+        palettes: [[(u8, u8, u8); 4]; 8],
+        sprite_limit: bool,
+        recycled: Frame,
+    ) -> Frame {
+        // the worker is gone only if it panicked, that's a bug in the renderer
+        self.free.send(recycled).expect("render thread is gone");
+        self.send(Command::EndFrame {
+            sprites,
+            palettes,
+            sprite_limit,
+        });
+        self.frames.recv().expect("render thread is gone")
+    }
+
+    fn send(&self, command: Command) {
+        if let Some(commands) = self.commands.as_ref() {
+            commands.send(command).expect("render thread is gone");
+        }
+    }
+}
+
+impl Drop for RenderThread {
+    fn drop(&mut self) {
+        // closing the channel stops the worker
+        self.commands.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,6 @@
+// What the std prelude gives every module, for the no_std build: the modules of the core take
+// it with `use crate::prelude::*`, with std it's the std prelude.
+pub use alloc::boxed::Box;
+pub use alloc::string::{String, ToString};
+pub use alloc::vec::Vec;
+pub use alloc::{format, vec};
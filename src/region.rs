@@ -0,0 +1,150 @@
+// TV system timing. The CPU and the PPU are driven by one master clock, the regions differ in
+// the dividers and in the number of scanlines per frame:
+//
+//            master clock   CPU divider  PPU dots/CPU cycle  scanlines  vblank NMI line  fps
+//   NTSC     21.477272 MHz  12           3                   262        241              60.10
+//   PAL      26.601712 MHz  16           3.2                 312        241              50.01
+//   Dendy    26.601712 MHz  15           3                   312        291              50.01
+//
+// Dendy is the famiclone timing (ex-USSR): PAL frame, NTSC-like CPU/PPU ratio, with the extra
+// lines after the picture instead of in vblank, so NTSC games run at the right speed.
+// https://wiki.nesdev.com/w/index.php/Cycle_reference_chart
+use crate::prelude::*;
+use crate::rom::{Rom, TVFormat};
+use core::str::FromStr;
+use core::time::Duration;
+
+const DOTS_PER_LINE: u64 = 341;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    /// iNES 1.0 headers only tell NTSC from PAL (and often don't set the flag at all)
+    pub fn from_rom(rom: &Rom) -> Region {
+        match rom.tv_format {
+            TVFormat::PAL => Region::Pal,
+            TVFormat::NTSC => Region::Ntsc,
+        }
+    }
+
+    pub fn master_clock_hz(&self) -> u64 {
+        match self {
+            Region::Ntsc => 21_477_272,
+            Region::Pal | Region::Dendy => 26_601_712,
+        }
+    }
+
+    pub fn cpu_divider(&self) -> u64 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    fn ppu_divider(&self) -> u64 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal | Region::Dendy => 5,
+        }
+    }
+
+    /// CPU (and APU) clock
+    pub fn cpu_clock_hz(&self) -> u64 {
+        self.master_clock_hz() / self.cpu_divider()
+    }
+
+    /// Scanlines per frame, pre-render line included
+    pub fn scanlines(&self) -> usize {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// The line vblank (and NMI) starts at
+    pub fn vblank_line(&self) -> usize {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    /// PPU dots for `cycles` CPU cycles. PAL runs 3.2 dots per cycle:
+    /// `remainder` carries the fraction of a dot (in master clock ticks) from one call to the next
+    pub fn ppu_dots(&self, cycles: u16, remainder: &mut u8) -> u16 {
+        let ticks = cycles as u64 * self.cpu_divider() + *remainder as u64;
+        *remainder = (ticks % self.ppu_divider()) as u8;
+        (ticks / self.ppu_divider()) as u16
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        let dots = DOTS_PER_LINE * self.scanlines() as u64;
+        self.master_clock_hz() as f64 / (self.ppu_divider() * dots) as f64
+    }
+
+    /// Emulated time of one frame, for frame pacing
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.frame_rate())
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Region, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            "dendy" => Ok(Region::Dendy),
+            _ => Err(format!(
+                "unknown region '{}', expected ntsc, pal or dendy",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_timing() {
+        assert_eq!(Region::Ntsc.cpu_clock_hz(), 1_789_772);
+        assert_eq!(Region::Pal.cpu_clock_hz(), 1_662_607);
+        assert_eq!(Region::Dendy.cpu_clock_hz(), 1_773_447);
+        assert!((Region::Ntsc.frame_rate() - 60.0988).abs() < 0.001);
+        assert!((Region::Pal.frame_rate() - 50.0070).abs() < 0.001);
+        assert_eq!(Region::Dendy.frame_rate(), Region::Pal.frame_rate());
+    }
+
+    #[test]
+    fn test_ppu_dots() {
+        let mut remainder = 0;
+        assert_eq!(Region::Ntsc.ppu_dots(7, &mut remainder), 21);
+        assert_eq!(Region::Dendy.ppu_dots(7, &mut remainder), 21);
+        assert_eq!(remainder, 0);
+
+        // 3.2 dots per cycle
+        let dots: Vec<u16> = (0..5)
+            .map(|_| Region::Pal.ppu_dots(1, &mut remainder))
+            .collect();
+        assert_eq!(dots, vec![3, 3, 3, 3, 4]);
+        assert_eq!(remainder, 0);
+        assert_eq!(Region::Pal.ppu_dots(2, &mut remainder), 6);
+        assert_eq!(remainder, 2);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("PAL".parse(), Ok(Region::Pal));
+        assert_eq!("dendy".parse(), Ok(Region::Dendy));
+        assert!("secam".parse::<Region>().is_err());
+    }
+}
@@ -0,0 +1,159 @@
+// Rewind: the last states of the machine within a memory budget, taken once per frame (or every
+// few frames) and restored backwards while the rewind button is held.
+//
+//   let mut rewind = Rewind::new(64 << 20);
+//   loop {
+//       if rewind_held {
+//           if let Some(snapshot) = rewind.pop() {
+//               emulator.restore(&snapshot)?;
+//           }
+//       } else {
+//           rewind.push(&emulator.snapshot());
+//       }
+//       emulator.run_frame(&inputs)?;
+//   }
+//
+// The newest state is kept as is, every older one as the delta from the state after it (see
+// `save_state::delta_encode`): a frame of difference is a few hundred bytes, 64MB hold minutes.
+// The oldest states are dropped to stay within the budget.
+use crate::save_state::{delta_decode, delta_encode, Snapshot};
+use std::collections::VecDeque;
+
+pub struct Rewind {
+    budget: usize,
+    rom_crc32: u32,
+    newest: Option<Vec<u8>>,
+    // oldest first, each one from the state after it
+    deltas: VecDeque<Vec<u8>>,
+    deltas_size: usize,
+}
+
+impl Rewind {
+    /// `budget`: memory for the states, in bytes
+    pub fn new(budget: usize) -> Self {
+        Rewind {
+            budget,
+            rom_crc32: 0,
+            newest: None,
+            deltas: VecDeque::new(),
+            deltas_size: 0,
+        }
+    }
+
+    pub fn push(&mut self, snapshot: &Snapshot) {
+        if let Some(newest) = self.newest.take() {
+            let delta = delta_encode(&snapshot.state, &newest);
+            self.deltas_size += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.rom_crc32 = snapshot.rom_crc32;
+        self.newest = Some(snapshot.state.clone());
+        while self.memory_used() > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.deltas_size -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// The newest state, it's removed: the next call gives the one before it
+    pub fn pop(&mut self) -> Option<Snapshot> {
+        let state = self.newest.take()?;
+        if let Some(delta) = self.deltas.pop_back() {
+            self.deltas_size -= delta.len();
+            let previous = delta_decode(&state, &delta).expect("rewind deltas are made here");
+            self.newest = Some(previous);
+        }
+        Some(Snapshot {
+            rom_crc32: self.rom_crc32,
+            state,
+        })
+    }
+
+    /// Number of states kept
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Bytes the states take
+    pub fn memory_used(&self) -> usize {
+        self.deltas_size + self.newest.as_ref().map_or(0, |state| state.len())
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.deltas_size = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::cpu::CPU;
+    use crate::emulator::{Emulator, Inputs};
+    use crate::rom::test_ines_rom;
+
+    // INC $10; JMP loop
+    fn emulator() -> Emulator {
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x0600)
+            .build();
+        let program = CPU::transform("e6 10 4c 00 06");
+        for (idx, byte) in program.iter().enumerate() {
+            emulator.cpu_mut().bus.write(0x0600 + idx as u16, *byte);
+        }
+        emulator
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut emulator = emulator();
+        let mut rewind = Rewind::new(1 << 20);
+        let mut snapshots = vec![];
+        for _ in 0..50 {
+            let snapshot = emulator.snapshot();
+            rewind.push(&snapshot);
+            snapshots.push(snapshot);
+            emulator.run_frame(&Inputs::default()).unwrap();
+        }
+        assert_eq!(rewind.len(), 50);
+        let full_size: usize = snapshots.iter().map(|s| s.state.len()).sum();
+        assert!(
+            rewind.memory_used() * 10 < full_size,
+            "{}",
+            rewind.memory_used()
+        );
+
+        for snapshot in snapshots.iter().rev() {
+            assert_eq!(rewind.pop().as_ref(), Some(snapshot));
+        }
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut emulator = emulator();
+        let state_size = emulator.snapshot().state.len();
+        let mut rewind = Rewind::new(state_size + 1000);
+        for _ in 0..100 {
+            rewind.push(&emulator.snapshot());
+            emulator.run_frame(&Inputs::default()).unwrap();
+        }
+        assert!(rewind.memory_used() <= state_size + 1000);
+        assert!((2..100).contains(&rewind.len()), "{}", rewind.len());
+
+        // a budget below one state keeps the newest one
+        let mut rewind = Rewind::new(0);
+        rewind.push(&emulator.snapshot());
+        rewind.push(&emulator.snapshot());
+        assert_eq!(rewind.len(), 1);
+        rewind.clear();
+        assert_eq!(rewind.memory_used(), 0);
+    }
+}
@@ -0,0 +1,285 @@
+// Rollback netcode (GGPO style) for two players over the network.
+//
+// Every frame runs right away with the local input and a prediction of the remote one: the last
+// input received from the peer, buttons are usually held for many frames. When the real remote
+// input of a frame arrives and differs from the prediction, the machine goes back to the state
+// at the start of that frame and runs again up to the present with the corrected inputs.
+//
+//   let mut session = Rollback::new(Player::One, 8);
+//   loop {
+//       for (frame, buttons) in network.received() {
+//           session.add_remote_input(&mut emulator, frame, buttons)?;
+//       }
+//       if session.can_advance() {
+//           let buttons = keyboard.buttons();
+//           network.send(session.frame(), buttons);
+//           let picture = session.advance(&mut emulator, buttons)?;
+//       }
+//   }
+//
+// The transport is up to the frontend: remote inputs have to be delivered in order (resend the
+// unacknowledged ones), duplicates are ignored. Both peers start from the same state: same rom,
+// same `RamInit`, or a snapshot sent over.
+// Event subscribers see the re-simulated frames too.
+use crate::emulator::{Emulator, Inputs};
+use crate::input::JoypadButton;
+use crate::save_state::Snapshot;
+use crate::screen::frame::Frame;
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Player {
+    One,
+    Two,
+}
+
+impl Player {
+    fn inputs(&self, local: JoypadButton, remote: JoypadButton) -> Inputs {
+        match self {
+            Player::One => Inputs::two_players(local, remote),
+            Player::Two => Inputs::two_players(remote, local),
+        }
+    }
+}
+
+// a frame that ran with a predicted remote input
+struct Predicted {
+    // the machine state at the start of the frame
+    snapshot: Snapshot,
+    local: JoypadButton,
+    remote: JoypadButton,
+}
+
+pub struct Rollback {
+    local: Player,
+    max_prediction: usize,
+    // next frame to run
+    frame: usize,
+    // next expected remote input
+    remote_frame: usize,
+    // frames remote_frame..frame
+    predicted: VecDeque<Predicted>,
+    // remote inputs ahead of the local side, frames frame..remote_frame
+    received: VecDeque<JoypadButton>,
+    last_remote: JoypadButton,
+    rollbacks: usize,
+}
+
+impl Rollback {
+    /// `max_prediction`: how many frames the local side can run ahead of the remote input,
+    /// each rollback re-runs up to that many frames
+    pub fn new(local: Player, max_prediction: usize) -> Self {
+        Rollback {
+            local,
+            max_prediction,
+            frame: 0,
+            remote_frame: 0,
+            predicted: VecDeque::new(),
+            received: VecDeque::new(),
+            last_remote: JoypadButton::empty(),
+            rollbacks: 0,
+        }
+    }
+
+    /// The frame `advance` runs next, inputs are sent to the peer with this number
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// Frames before this one have the inputs of both players
+    pub fn confirmed_frame(&self) -> usize {
+        self.remote_frame.min(self.frame)
+    }
+
+    /// How many times the machine went back to correct a misprediction
+    pub fn rollbacks(&self) -> usize {
+        self.rollbacks
+    }
+
+    /// False when the remote side is too far behind: wait for its input (or drop the frame)
+    pub fn can_advance(&self) -> bool {
+        self.predicted.len() < self.max_prediction
+    }
+
+    /// Runs the next frame with the local input
+    pub fn advance<'a>(
+        &mut self,
+        emulator: &'a mut Emulator,
+        local: JoypadButton,
+    ) -> Result<&'a Frame, String> {
+        assert!(self.can_advance(), "prediction window is full");
+        let remote = match self.received.pop_front() {
+            Some(remote) => remote,
+            None => {
+                self.predicted.push_back(Predicted {
+                    snapshot: emulator.snapshot(),
+                    local,
+                    remote: self.last_remote,
+                });
+                self.last_remote
+            }
+        };
+        self.frame += 1;
+        emulator
+            .run_frame(&self.local.inputs(local, remote))
+            .map_err(|e| e.to_string())
+    }
+
+    /// Input of the remote player for `frame`. A misprediction rolls the machine back
+    /// and re-runs the frames since then.
+    pub fn add_remote_input(
+        &mut self,
+        emulator: &mut Emulator,
+        frame: usize,
+        remote: JoypadButton,
+    ) -> Result<(), String> {
+        if frame < self.remote_frame {
+            return Ok(());
+        }
+        if frame > self.remote_frame {
+            return Err(format!(
+                "remote input for frame {} is missing, got frame {}",
+                self.remote_frame, frame
+            ));
+        }
+        self.remote_frame += 1;
+        self.last_remote = remote;
+
+        let played = match self.predicted.pop_front() {
+            Some(played) => played,
+            None => {
+                self.received.push_back(remote);
+                return Ok(());
+            }
+        };
+        if played.remote == remote {
+            return Ok(());
+        }
+
+        self.rollbacks += 1;
+        emulator.restore(&played.snapshot)?;
+        self.resimulate(emulator, played.local, remote)?;
+        for i in 0..self.predicted.len() {
+            let snapshot = emulator.snapshot();
+            let played = &mut self.predicted[i];
+            played.snapshot = snapshot;
+            played.remote = remote;
+            let local = played.local;
+            self.resimulate(emulator, local, remote)?;
+        }
+        Ok(())
+    }
+
+    fn resimulate(
+        &self,
+        emulator: &mut Emulator,
+        local: JoypadButton,
+        remote: JoypadButton,
+    ) -> Result<(), String> {
+        emulator
+            .run_frame(&self.local.inputs(local, remote))
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::cpu::CPU;
+    use crate::rom::test_ines_rom;
+
+    // sums up the A button of both controllers, read all the frame long
+    fn emulator() -> Emulator {
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x0600)
+            .build();
+        let program = CPU::transform(
+            "a9 01 8d 16 40 a9 00 8d 16 40 \
+             ad 16 40 18 65 10 85 10 \
+             ad 17 40 18 65 11 85 11 \
+             4c 00 06",
+        );
+        for (idx, byte) in program.iter().enumerate() {
+            emulator.cpu_mut().bus.write(0x0600 + idx as u16, *byte);
+        }
+        emulator
+    }
+
+    fn buttons(pressed: bool) -> JoypadButton {
+        if pressed {
+            JoypadButton::BUTTON_A
+        } else {
+            JoypadButton::empty()
+        }
+    }
+
+    #[test]
+    fn test_rollback_matches_the_offline_run() {
+        let local: Vec<JoypadButton> = (0..20).map(|f| buttons(f % 7 < 2)).collect();
+        let remote: Vec<JoypadButton> = (0..20).map(|f| buttons((5..9).contains(&f))).collect();
+
+        let mut offline = emulator();
+        for frame in 0..20 {
+            offline
+                .run_frame(&Inputs::two_players(local[frame], remote[frame]))
+                .unwrap();
+        }
+
+        // the remote input comes 3 frames late
+        let mut online = emulator();
+        let mut session = Rollback::new(Player::One, 4);
+        for frame in 0..20 {
+            if frame >= 3 {
+                session
+                    .add_remote_input(&mut online, frame - 3, remote[frame - 3])
+                    .unwrap();
+            }
+            assert_eq!(session.frame(), frame);
+            session.advance(&mut online, local[frame]).unwrap();
+        }
+        assert_eq!(session.confirmed_frame(), 17);
+
+        for frame in 17..20 {
+            session
+                .add_remote_input(&mut online, frame, remote[frame])
+                .unwrap();
+        }
+        assert_eq!(session.confirmed_frame(), 20);
+        // mispredicted at the press and at the release
+        assert_eq!(session.rollbacks(), 2);
+        assert_eq!(online.snapshot(), offline.snapshot());
+        assert_eq!(online.cpu().bus.ram[0x11], offline.cpu().bus.ram[0x11]);
+        assert_ne!(online.cpu().bus.ram[0x11], 0);
+    }
+
+    #[test]
+    fn test_remote_ahead() {
+        let mut online = emulator();
+        let mut offline = emulator();
+        let mut session = Rollback::new(Player::Two, 2);
+        for frame in 0..3 {
+            session
+                .add_remote_input(&mut online, frame, buttons(true))
+                .unwrap();
+        }
+        // duplicate
+        session
+            .add_remote_input(&mut online, 1, buttons(false))
+            .unwrap();
+        assert!(session
+            .add_remote_input(&mut online, 5, buttons(true))
+            .is_err());
+
+        for _ in 0..5 {
+            assert!(session.can_advance());
+            session.advance(&mut online, buttons(false)).unwrap();
+            offline
+                .run_frame(&Inputs::two_players(buttons(true), buttons(false)))
+                .unwrap();
+        }
+        assert!(!session.can_advance());
+        assert_eq!(session.rollbacks(), 0);
+        assert_eq!(online.snapshot(), offline.snapshot());
+    }
+}
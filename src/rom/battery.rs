@@ -0,0 +1,75 @@
+// Battery backed PRG RAM ($6000-$7FFF, `RomFlags::BATTERY_RAM`): the game saves of Zelda,
+// Final Fantasy, Dragon Warrior outlive the power off. The RAM goes into `game.sav` next to the
+// rom, the plain 8KB other emulators read and write too (the whole 64KB on MMC5 boards).
+//
+//   let path = battery::path(&rom_path);
+//   battery::load(&path, bus.battery_ram())?;
+//   ... on exit
+//   battery::save(&path, bus.battery_ram())?;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+/// Save file of the rom: `game.nes` -> `game.sav`
+#[cfg(feature = "std")]
+pub fn path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("sav")
+}
+
+/// Fills `ram` from the save file. false - there is no file yet, `ram` is left as it is
+#[cfg(feature = "std")]
+pub fn load(path: &Path, ram: &mut [u8]) -> Result<bool, String> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    let data = fs::read(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    if data.len() != ram.len() {
+        return Err(format!(
+            "{}: {} bytes of PRG RAM expected, got {}",
+            path.display(),
+            ram.len(),
+            data.len()
+        ));
+    }
+    ram.copy_from_slice(&data);
+    Ok(true)
+}
+
+#[cfg(feature = "std")]
+pub fn save(path: &Path, ram: &[u8]) -> Result<(), String> {
+    fs::write(path, ram).map_err(|e| format!("failed to write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+#[cfg(feature = "std")]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let path = std::env::temp_dir().join("rustness_test_battery.sav");
+        let _ = fs::remove_file(&path);
+        let mut ram = [0xaa; 0x2000];
+        assert_eq!(load(&path, &mut ram), Ok(false));
+        assert_eq!(ram[0], 0xaa);
+
+        ram[0x1fff] = 0x42;
+        save(&path, &ram).unwrap();
+        let mut loaded = [0; 0x2000];
+        assert_eq!(load(&path, &mut loaded), Ok(true));
+        assert_eq!(&loaded[..], &ram[..]);
+
+        let mut small = [0; 16];
+        assert!(load(&path, &mut small).unwrap_err().contains("16 bytes"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_path() {
+        assert_eq!(
+            path(Path::new("roms/zelda.nes")),
+            PathBuf::from("roms/zelda.sav")
+        );
+    }
+}
@@ -0,0 +1,109 @@
+// Game database keyed by CRC32 of PRG+CHR data (iNES header excluded),
+// the same way No-Intro/NesCartDB identify dumps.
+//
+// File format, one game per line:
+//   3337EC46 Super Mario Bros.
+//   # comment
+use crate::rom::Rom;
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+pub struct GameDb {
+    games: HashMap<u32, String>,
+}
+
+impl GameDb {
+    pub fn new() -> Self {
+        GameDb {
+            games: HashMap::new(),
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<GameDb, String> {
+        let mut db = GameDb::new();
+        for (line_num, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let crc = parts.next().unwrap_or("");
+            let title = parts.next().unwrap_or("").trim();
+            let crc = u32::from_str_radix(crc, 16)
+                .map_err(|_| format!("line {}: bad crc32 '{}'", line_num + 1, crc))?;
+            if title.is_empty() {
+                return Err(format!("line {}: missing title", line_num + 1));
+            }
+            db.insert(crc, title);
+        }
+        Ok(db)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load(path: &Path) -> Result<GameDb, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        GameDb::parse(&content)
+    }
+
+    pub fn insert(&mut self, crc32: u32, title: &str) {
+        self.games.insert(crc32, title.to_string());
+    }
+
+    pub fn lookup(&self, rom: &Rom) -> Option<&str> {
+        self.games.get(&rom.crc32()).map(|t| t.as_str())
+    }
+
+    /// Recognized game name, or the rom file name (without extension) if the game is unknown
+    #[cfg(feature = "std")]
+    pub fn title(&self, rom: &Rom, rom_path: &Path) -> String {
+        match self.lookup(rom) {
+            Some(title) => title.to_string(),
+            None => rom_path
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| String::from("unknown")),
+        }
+    }
+}
+
+impl Default for GameDb {
+    fn default() -> Self {
+        GameDb::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "std")]
+    use crate::rom::test_ines_rom;
+
+    #[test]
+    fn test_parse() {
+        let db = GameDb::parse("# comment\n\n3337ec46 Super Mario Bros.\nDEADBEEF  Some Game \n")
+            .unwrap();
+        assert_eq!(db.games.get(&0x3337ec46).unwrap(), "Super Mario Bros.");
+        assert_eq!(db.games.get(&0xdeadbeef).unwrap(), "Some Game");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(GameDb::parse("zzz Game").is_err());
+        assert!(GameDb::parse("3337ec46").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_title_falls_back_to_file_name() {
+        let rom = test_ines_rom::test_rom();
+        let mut db = GameDb::new();
+        assert_eq!(db.title(&rom, Path::new("roms/pacman.nes")), "pacman");
+
+        db.insert(rom.crc32(), "Test Game");
+        assert_eq!(db.title(&rom, Path::new("roms/pacman.nes")), "Test Game");
+    }
+}
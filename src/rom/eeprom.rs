@@ -0,0 +1,351 @@
+// Serial (I2C) EEPROM of the Bandai FCG/LZ93D50 boards (mapper 16/159): the save memory of
+// the Dragon Ball Z and SD Gundam games, non-volatile without a battery.
+//   24C02 (mapper 16, 256 bytes): START, device address 1010xxxR, word address, data bytes
+//   X24C01 (mapper 159, 128 bytes): START, 7 bit word address + R bit (LSB first), data bytes
+// The mapper drives SCL/SDA with writes to $800D (bit 5 - SCL, bit 6 - SDA) and reads SDA back
+// at bit 4 of $6000-$7FFF.
+// https://wiki.nesdev.com/w/index.php/Bandai_FCG_board
+//
+// None of the Bandai boards has a real-time clock: Famicom Jump II (mapper 153) has 8KB of
+// battery backed WRAM instead of the EEPROM. The board is `mapper::Bandai`.
+use crate::prelude::*;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    X24C01,
+    C24C02,
+}
+
+impl Chip {
+    pub fn size(&self) -> usize {
+        match self {
+            Chip::X24C01 => 128,
+            Chip::C24C02 => 256,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Idle,
+    // 24C02 only
+    Device,
+    Address,
+    Read,
+    Write,
+    // the chip pulls SDA low for a clock
+    SendAck,
+    // the host acknowledges a read byte to get the next one
+    WaitAck,
+}
+
+pub struct Eeprom {
+    chip: Chip,
+    data: Vec<u8>,
+    mode: Mode,
+    // after the ack
+    next_mode: Mode,
+    shift: u8,
+    bits: u8,
+    addr: u8,
+    scl: bool,
+    sda: bool,
+    // SDA as driven by the chip, high when released
+    output: bool,
+}
+
+impl Eeprom {
+    pub fn new(chip: Chip) -> Self {
+        Eeprom {
+            chip,
+            data: vec![0xff; chip.size()],
+            mode: Mode::Idle,
+            next_mode: Mode::Idle,
+            shift: 0,
+            bits: 0,
+            addr: 0,
+            scl: false,
+            sda: false,
+            output: true,
+        }
+    }
+
+    pub fn chip(&self) -> Chip {
+        self.chip
+    }
+
+    /// The contents, for the save file
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn set_data(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() != self.data.len() {
+            return Err(format!(
+                "{:?} EEPROM holds {} bytes, got {}",
+                self.chip,
+                self.data.len(),
+                data.len()
+            ));
+        }
+        self.data.copy_from_slice(data);
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        let data =
+            fs::read(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        self.set_data(&data)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, &self.data)
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+
+    /// SDA as seen by the host: low when the chip or the host pulls it low
+    pub fn sda(&self) -> bool {
+        self.output && self.sda
+    }
+
+    /// New levels of the clock and data lines
+    pub fn write(&mut self, scl: bool, sda: bool) {
+        let (prev_scl, prev_sda) = (self.scl, self.sda);
+        self.scl = scl;
+        self.sda = sda;
+
+        if prev_scl && scl && prev_sda && !sda {
+            // START: SDA falls while SCL is high
+            self.mode = match self.chip {
+                Chip::X24C01 => Mode::Address,
+                Chip::C24C02 => Mode::Device,
+            };
+            self.bits = 0;
+            self.output = true;
+        } else if prev_scl && scl && !prev_sda && sda {
+            // STOP
+            self.mode = Mode::Idle;
+            self.output = true;
+        } else if scl && !prev_scl {
+            self.clock_rise(sda);
+        } else if !scl && prev_scl {
+            self.clock_fall();
+        }
+    }
+
+    fn shift_in(&mut self, bit: bool) {
+        if self.bits == 8 {
+            return;
+        }
+        self.shift = match self.chip {
+            // X24C01 sends the address LSB first, the data too
+            Chip::X24C01 => self.shift | (bit as u8) << self.bits,
+            Chip::C24C02 => self.shift | (bit as u8) << (7 - self.bits),
+        };
+        self.bits += 1;
+    }
+
+    fn clock_rise(&mut self, sda: bool) {
+        match self.mode {
+            Mode::Device | Mode::Address | Mode::Write => {
+                if self.bits == 0 {
+                    self.shift = 0;
+                }
+                self.shift_in(sda);
+            }
+            Mode::Read if self.bits < 8 => {
+                let byte = self.data[self.addr as usize];
+                let bit = match self.chip {
+                    Chip::X24C01 => byte >> self.bits,
+                    Chip::C24C02 => byte >> (7 - self.bits),
+                };
+                self.output = bit & 1 != 0;
+                self.bits += 1;
+            }
+            Mode::SendAck => self.output = false,
+            Mode::WaitAck => {
+                // the host doesn't acknowledge the last byte it wants
+                self.next_mode = if sda { Mode::Idle } else { Mode::Read };
+            }
+            _ => {}
+        }
+    }
+
+    fn clock_fall(&mut self) {
+        match self.mode {
+            Mode::Device if self.bits == 8 => {
+                if self.shift & 0xf0 == 0xa0 {
+                    self.next_mode = if self.shift & 1 != 0 {
+                        Mode::Read
+                    } else {
+                        Mode::Address
+                    };
+                    self.ack();
+                } else {
+                    // addressed to another chip
+                    self.mode = Mode::Idle;
+                }
+            }
+            Mode::Address if self.bits == 8 => {
+                match self.chip {
+                    Chip::X24C01 => {
+                        self.addr = self.shift & 0x7f;
+                        self.next_mode = if self.shift & 0x80 != 0 {
+                            Mode::Read
+                        } else {
+                            Mode::Write
+                        };
+                    }
+                    Chip::C24C02 => {
+                        self.addr = self.shift;
+                        self.next_mode = Mode::Write;
+                    }
+                }
+                self.ack();
+            }
+            Mode::Write if self.bits == 8 => {
+                self.data[self.addr as usize] = self.shift;
+                self.advance_addr();
+                self.next_mode = Mode::Write;
+                self.ack();
+            }
+            Mode::Read if self.bits == 8 => {
+                self.advance_addr();
+                self.mode = Mode::WaitAck;
+                self.output = true;
+            }
+            Mode::SendAck | Mode::WaitAck => {
+                self.mode = self.next_mode;
+                self.bits = 0;
+                self.output = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn ack(&mut self) {
+        self.mode = Mode::SendAck;
+        self.bits = 0;
+        self.output = true;
+    }
+
+    fn advance_addr(&mut self) {
+        self.addr = ((self.addr as usize + 1) % self.chip.size()) as u8;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // bit-banging host, the way the games do it through $800D
+    struct Host<'a>(&'a mut Eeprom);
+
+    impl<'a> Host<'a> {
+        fn start(&mut self) {
+            self.0.write(false, true);
+            self.0.write(true, true);
+            self.0.write(true, false);
+            self.0.write(false, false);
+        }
+
+        fn stop(&mut self) {
+            self.0.write(false, false);
+            self.0.write(true, false);
+            self.0.write(true, true);
+        }
+
+        fn bit(&mut self, bit: bool) {
+            self.0.write(false, bit);
+            self.0.write(true, bit);
+            self.0.write(false, bit);
+        }
+
+        // MSB first for the 24C02, LSB first for the X24C01
+        fn send(&mut self, byte: u8, lsb_first: bool) -> bool {
+            for idx in 0..8 {
+                let shift = if lsb_first { idx } else { 7 - idx };
+                self.bit(byte >> shift & 1 != 0);
+            }
+            // ack clock, SDA released
+            self.0.write(false, true);
+            self.0.write(true, true);
+            let ack = !self.0.sda();
+            self.0.write(false, true);
+            ack
+        }
+
+        fn receive(&mut self, lsb_first: bool, last: bool) -> u8 {
+            let mut byte = 0;
+            for idx in 0..8 {
+                self.0.write(false, true);
+                self.0.write(true, true);
+                let shift = if lsb_first { idx } else { 7 - idx };
+                byte |= (self.0.sda() as u8) << shift;
+            }
+            self.0.write(false, true);
+            self.bit(last);
+            byte
+        }
+    }
+
+    #[test]
+    fn test_24c02() {
+        let mut eeprom = Eeprom::new(Chip::C24C02);
+        let mut host = Host(&mut eeprom);
+        host.start();
+        assert!(host.send(0xa0, false));
+        assert!(host.send(0x10, false));
+        assert!(host.send(0x12, false));
+        assert!(host.send(0x34, false));
+        host.stop();
+
+        // random read: dummy write of the address, then a read
+        host.start();
+        assert!(host.send(0xa0, false));
+        assert!(host.send(0x10, false));
+        host.start();
+        assert!(host.send(0xa1, false));
+        assert_eq!(host.receive(false, false), 0x12);
+        assert_eq!(host.receive(false, true), 0x34);
+        host.stop();
+
+        // another device
+        host.start();
+        assert!(!host.send(0x50, false));
+        host.stop();
+
+        assert_eq!(&eeprom.data()[0x10..0x13], &[0x12, 0x34, 0xff]);
+    }
+
+    #[test]
+    fn test_x24c01() {
+        let mut eeprom = Eeprom::new(Chip::X24C01);
+        let mut host = Host(&mut eeprom);
+        host.start();
+        // address 5, write
+        assert!(host.send(0x05, true));
+        assert!(host.send(0xa5, true));
+        host.stop();
+
+        host.start();
+        assert!(host.send(0x85, true));
+        assert_eq!(host.receive(true, true), 0xa5);
+        host.stop();
+        assert_eq!(eeprom.data()[5], 0xa5);
+    }
+
+    #[test]
+    fn test_set_data() {
+        let mut eeprom = Eeprom::new(Chip::X24C01);
+        assert!(eeprom.set_data(&[0; 256]).is_err());
+        eeprom.set_data(&[0x42; 128]).unwrap();
+        assert_eq!(eeprom.data()[127], 0x42);
+    }
+}
@@ -0,0 +1,245 @@
+// The 16 byte header as written in the file, iNES or NES 2.0, for `rustness info`. Rom::load
+// only reads what the emulator uses and refuses NES 2.0; this keeps every field, running the
+// game or not.
+// https://www.nesdev.org/wiki/INES https://www.nesdev.org/wiki/NES_2.0
+use crate::prelude::*;
+use crate::rom::Mirroring;
+
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Timing {
+    NTSC,
+    PAL,
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Console {
+    NES,
+    VsSystem,
+    Playchoice10,
+    /// NES 2.0 byte 13 holds the type
+    Extended(u8),
+}
+
+/// NES 2.0 only fields
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Nes2 {
+    pub submapper: u8,
+    pub prg_ram: usize,
+    pub prg_nvram: usize,
+    pub chr_ram: usize,
+    pub chr_nvram: usize,
+    pub misc_roms: u8,
+    pub expansion_device: u8,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Header {
+    pub mapper: u16,
+    pub prg_rom: usize,
+    pub chr_rom: usize,
+    pub mirroring: Mirroring,
+    pub four_screen: bool,
+    pub battery: bool,
+    pub trainer: bool,
+    pub console: Console,
+    pub timing: Timing,
+    /// iNES byte 8, NES 2.0 has separate PRG-RAM and PRG-NVRAM sizes
+    pub prg_ram: usize,
+    pub nes2: Option<Nes2>,
+}
+
+// NES 2.0 RAM sizes: 64 << shift, 0 - none
+fn ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+// NES 2.0 ROM sizes: MSB nibble 0xF switches to 2^E * (MM * 2 + 1) bytes
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0xf {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        1usize.checked_shl(exponent).unwrap_or(0) * multiplier
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * unit
+    }
+}
+
+impl Header {
+    pub fn parse(data: &[u8]) -> Result<Header, String> {
+        if data.len() < HEADER_SIZE || &data[..4] != b"NES\x1A" {
+            return Err(String::from("not an iNES file"));
+        }
+        let is_nes2 = data[7] & 0x0c == 0x08;
+        let mirroring = if data[6] & 1 == 1 {
+            Mirroring::VERTICAL
+        } else {
+            Mirroring::HORIZONTAL
+        };
+        let console = match data[7] & 0b11 {
+            0 => Console::NES,
+            1 => Console::VsSystem,
+            2 => Console::Playchoice10,
+            _ if is_nes2 => Console::Extended(data[13] & 0x0f),
+            // iNES has only the two flags
+            _ => Console::VsSystem,
+        };
+        let mut mapper = (data[7] & 0xf0 | data[6] >> 4) as u16;
+        let mut header = Header {
+            mapper,
+            prg_rom: data[4] as usize * 16384,
+            chr_rom: data[5] as usize * 8192,
+            mirroring,
+            four_screen: data[6] & 0b1000 != 0,
+            battery: data[6] & 0b10 != 0,
+            trainer: data[6] & 0b100 != 0,
+            console,
+            timing: if data[9] & 1 == 1 {
+                Timing::PAL
+            } else {
+                Timing::NTSC
+            },
+            // 0 means 8KB for compatibility
+            prg_ram: data[8].max(1) as usize * 8192,
+            nes2: None,
+        };
+        if is_nes2 {
+            mapper |= ((data[8] & 0x0f) as u16) << 8;
+            let nes2 = Nes2 {
+                submapper: data[8] >> 4,
+                prg_ram: ram_size(data[10] & 0x0f),
+                prg_nvram: ram_size(data[10] >> 4),
+                chr_ram: ram_size(data[11] & 0x0f),
+                chr_nvram: ram_size(data[11] >> 4),
+                misc_roms: data[14] & 0b11,
+                expansion_device: data[15] & 0x3f,
+            };
+            header.mapper = mapper;
+            header.prg_rom = rom_size(data[4], data[9] & 0x0f, 16384);
+            header.chr_rom = rom_size(data[5], data[9] >> 4, 8192);
+            header.timing = match data[12] & 0b11 {
+                0 => Timing::NTSC,
+                1 => Timing::PAL,
+                2 => Timing::MultiRegion,
+                _ => Timing::Dendy,
+            };
+            header.prg_ram = nes2.prg_ram + nes2.prg_nvram;
+            header.nes2 = Some(nes2);
+        }
+        Ok(header)
+    }
+
+    /// File offset of the PRG ROM
+    pub fn prg_offset(&self) -> usize {
+        HEADER_SIZE + if self.trainer { TRAINER_SIZE } else { 0 }
+    }
+
+    /// PRG and CHR ROM as stored in the file, `None` if the file is shorter than the header says
+    pub fn split<'a>(&self, data: &'a [u8]) -> Option<(&'a [u8], &'a [u8])> {
+        let prg_end = self.prg_offset().checked_add(self.prg_rom)?;
+        let chr_end = prg_end.checked_add(self.chr_rom)?;
+        if data.len() < chr_end {
+            return None;
+        }
+        Some((&data[self.prg_offset()..prg_end], &data[prg_end..chr_end]))
+    }
+}
+
+/// Board names of the common mappers
+pub fn mapper_name(mapper: u16) -> Option<&'static str> {
+    let name = match mapper {
+        0 => "NROM",
+        1 => "MMC1 (SxROM)",
+        2 => "UxROM",
+        3 => "CNROM",
+        4 => "MMC3 (TxROM)",
+        5 => "MMC5 (ExROM)",
+        7 => "AxROM",
+        9 => "MMC2 (PxROM)",
+        10 => "MMC4 (FxROM)",
+        11 => "Color Dreams",
+        16 => "Bandai FCG",
+        19 => "Namco 163",
+        21 | 23 | 25 => "Konami VRC2/VRC4",
+        22 => "Konami VRC2a",
+        24 => "Konami VRC6a",
+        26 => "Konami VRC6b",
+        34 => "BNROM / NINA-001",
+        66 => "GxROM",
+        69 => "Sunsoft FME-7",
+        71 => "Camerica",
+        85 => "Konami VRC7",
+        153 => "Bandai LZ93D50 + SRAM",
+        159 => "Bandai LZ93D50 + 24C01",
+        206 => "Namco 118",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header(bytes: [u8; 12]) -> Vec<u8> {
+        let mut data = b"NES\x1A".to_vec();
+        data.extend_from_slice(&bytes);
+        data
+    }
+
+    #[test]
+    fn test_ines() {
+        let header = Header::parse(&header([2, 1, 0x33, 0x10, 0, 1, 0, 0, 0, 0, 0, 0])).unwrap();
+        assert_eq!(header.mapper, 0x13);
+        assert_eq!((header.prg_rom, header.chr_rom), (32768, 8192));
+        assert_eq!(header.mirroring, Mirroring::VERTICAL);
+        assert!(header.battery && !header.trainer && !header.four_screen);
+        assert_eq!(header.timing, Timing::PAL);
+        assert_eq!(header.prg_ram, 8192);
+        assert_eq!(header.nes2, None);
+    }
+
+    #[test]
+    fn test_nes2() {
+        let data = header([
+            0x05, // 1 << 1 * 3 = 6 bytes of PRG with the exponent notation
+            0x02, 0x40, 0x18, // NES 2.0, mapper 0x314
+            0x23, // submapper 2
+            0x0f, 0x70, // 8KB PRG-NVRAM
+            0x07, // 8KB CHR-RAM
+            0x03, 0, 0x01, 0x2a,
+        ]);
+        let header = Header::parse(&data).unwrap();
+        assert_eq!(header.mapper, 0x314);
+        assert_eq!((header.prg_rom, header.chr_rom), (6, 2 * 8192));
+        assert_eq!(header.timing, Timing::Dendy);
+        assert_eq!(header.prg_ram, 8192);
+        let nes2 = header.nes2.unwrap();
+        assert_eq!(nes2.submapper, 2);
+        assert_eq!((nes2.prg_ram, nes2.prg_nvram), (0, 8192));
+        assert_eq!((nes2.chr_ram, nes2.chr_nvram), (8192, 0));
+        assert_eq!((nes2.misc_roms, nes2.expansion_device), (1, 0x2a));
+    }
+
+    #[test]
+    fn test_split() {
+        let mut data = header([1, 1, 0b100, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend(vec![0; TRAINER_SIZE]);
+        data.extend(vec![1; 16384]);
+        data.extend(vec![2; 8192]);
+        let header = Header::parse(&data).unwrap();
+        let (prg, chr) = header.split(&data).unwrap();
+        assert!(prg.iter().all(|b| *b == 1) && prg.len() == 16384);
+        assert!(chr.iter().all(|b| *b == 2) && chr.len() == 8192);
+        assert_eq!(header.split(&data[..data.len() - 1]), None);
+        assert!(Header::parse(b"NES").is_err());
+    }
+}
@@ -0,0 +1,156 @@
+// 32KB PRG ROM banks and single-screen mirroring, 8KB of CHR RAM (Battletoads, Wizards &
+// Warriors, Marble Madness). https://wiki.nesdev.com/w/index.php/AxROM
+//
+// One register at $8000-$FFFF: bits 0-2 - the PRG bank, bit 4 - the nametable all 4 screens
+// show. Bus conflicts of the AMROM/AOROM boards aren't emulated, games avoid them anyway.
+use super::chr::Chr;
+use super::Mapper;
+use crate::rom::{Mirroring, Rom};
+
+const PRG_BANK: usize = 0x8000;
+
+pub struct Axrom {
+    bank: usize,
+    banks: usize,
+    mirroring: Mirroring,
+    // the boards have CHR RAM, a rom with CHR ROM uses that instead
+    chr: Chr,
+}
+
+impl Axrom {
+    pub fn new(rom: &Rom) -> Self {
+        Axrom {
+            bank: 0,
+            banks: (rom.prg_rom.len() / PRG_BANK).max(1),
+            mirroring: Mirroring::SINGLE_SCREEN_LOWER,
+            chr: Chr::new(rom),
+        }
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_read(&mut self, rom: &Rom, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xffff => {
+                let offset = self.bank * PRG_BANK + (addr - 0x8000) as usize;
+                Some(rom.prg_rom[offset % rom.prg_rom.len()])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, _rom: &Rom, addr: u16, data: u8) -> bool {
+        if addr < 0x8000 {
+            return false;
+        }
+        self.bank = (data & 0b111) as usize % self.banks;
+        self.mirroring = if data & 0b1_0000 == 0 {
+            Mirroring::SINGLE_SCREEN_LOWER
+        } else {
+            Mirroring::SINGLE_SCREEN_UPPER
+        };
+        true
+    }
+
+    fn ppu_read(&mut self, rom: &Rom, addr: u16) -> u8 {
+        self.chr.read(rom, addr as usize)
+    }
+
+    fn ppu_write(&mut self, _rom: &Rom, addr: u16, data: u8) -> bool {
+        self.chr.write(addr as usize, data)
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn bank_register(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then_some(self.bank)
+    }
+
+    fn power_on(&mut self) {
+        self.bank = 0;
+        self.mirroring = Mirroring::SINGLE_SCREEN_LOWER;
+    }
+
+    #[cfg(feature = "save-state")]
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![
+            self.bank as u8,
+            (self.mirroring == Mirroring::SINGLE_SCREEN_UPPER) as u8,
+        ];
+        state.extend_from_slice(self.chr.ram());
+        state
+    }
+
+    #[cfg(feature = "save-state")]
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        match data {
+            [bank, upper, chr_ram @ ..]
+                if (*bank as usize) < self.banks && self.chr.load_ram(chr_ram) =>
+            {
+                self.bank = *bank as usize;
+                self.mirroring = if *upper == 1 {
+                    Mirroring::SINGLE_SCREEN_UPPER
+                } else {
+                    Mirroring::SINGLE_SCREEN_LOWER
+                };
+                Ok(())
+            }
+            _ => Err("corrupted AxROM state".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test_ines_rom;
+
+    #[test]
+    fn test_prg_banks_and_mirroring() {
+        let rom = test_ines_rom::banked_rom(7, 4, PRG_BANK, 0, 0);
+        let mut mapper = Axrom::new(&rom);
+        assert_eq!(mapper.cpu_read(&rom, 0xffff), Some(0));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SINGLE_SCREEN_LOWER));
+
+        assert!(mapper.cpu_write(&rom, 0x8000, 0b1_0010));
+        assert_eq!(mapper.cpu_read(&rom, 0x8000), Some(2));
+        assert_eq!(mapper.cpu_read(&rom, 0xffff), Some(2));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SINGLE_SCREEN_UPPER));
+        // 4 banks only
+        mapper.cpu_write(&rom, 0xc000, 0b0_0111);
+        assert_eq!(mapper.cpu_read(&rom, 0x8000), Some(3));
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SINGLE_SCREEN_LOWER));
+        assert!(!mapper.cpu_write(&rom, 0x5000, 1));
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let rom = test_ines_rom::banked_rom(7, 4, PRG_BANK, 0, 0);
+        let mut mapper = Axrom::new(&rom);
+        assert!(mapper.ppu_write(&rom, 0x1234, 0x42));
+        assert_eq!(mapper.ppu_read(&rom, 0x1234), 0x42);
+    }
+
+    #[test]
+    fn test_single_screen_vram() {
+        use crate::bus::Bus;
+        use crate::ppu::ppu::NesPPU;
+
+        let mut rom = test_ines_rom::banked_rom(7, 4, PRG_BANK, 0, 0);
+        rom.mapper = 7;
+        let mut bus = Bus::<NesPPU>::new(rom);
+        assert_eq!(bus.ppu().mirroring, Mirroring::SINGLE_SCREEN_LOWER);
+        bus.write(0x8000, 0b1_0000);
+        assert_eq!(bus.ppu().mirroring, Mirroring::SINGLE_SCREEN_UPPER);
+        // all 4 nametables are the second KB of vram
+        for (n, nametable) in [0x20u8, 0x24, 0x28, 0x2c].iter().enumerate() {
+            bus.write(0x2006, *nametable);
+            bus.write(0x2006, 0x05);
+            bus.write(0x2007, n as u8 + 1);
+            assert_eq!(bus.ppu().vram[0x405], n as u8 + 1);
+        }
+        assert_eq!(bus.ppu().vram[0x005], 0);
+    }
+}
@@ -0,0 +1,321 @@
+// Bandai FCG-1/2 and LZ93D50 (mapper 16, 159): a 16KB PRG bank, 1KB CHR banks, a CPU cycle
+// IRQ counter and the serial EEPROM for the game saves (Dragon Ball Z, SD Gundam, Rokudenashi
+// Blues). https://wiki.nesdev.com/w/index.php/Bandai_FCG_board
+//
+// Registers repeat every 16 bytes: $x0-$x7 - CHR banks, $x8 - PRG bank at $8000 ($C000 is the
+// last bank), $x9 - mirroring, $xA - IRQ enable and acknowledge, $xB/$xC - IRQ counter,
+// $xD - EEPROM clock and data lines. The FCG chips have them at $6000-$7FFF, the LZ93D50 at
+// $8000-$FFFF; mapper 16 doesn't say which one, both ranges are taken. The FCG chips write the
+// counter directly, the LZ93D50 writes a latch that $xA copies into the counter.
+//
+// The EEPROM (24C02 on mapper 16, X24C01 on mapper 159) is read back at bit 4 of $6000-$7FFF.
+// todo: mapper 153 (Famicom Jump II, battery backed PRG RAM and a 512KB PRG outer bank)
+use super::chr::Chr;
+use super::Mapper;
+use crate::rom::eeprom::{Chip, Eeprom};
+use crate::rom::{Mirroring, Rom};
+
+const PRG_BANK: usize = 0x4000;
+const CHR_BANK: usize = 0x400;
+// register bytes in a save state, followed by the EEPROM and the CHR RAM
+#[cfg(feature = "save-state")]
+const REGISTERS_LEN: usize = 16;
+
+pub struct Bandai {
+    chr: [u8; 8],
+    prg: u8,
+    // $x9: 0 - vertical, 1 - horizontal, 2/3 - single screen
+    control: u8,
+    irq_enabled: bool,
+    irq_counter: u16,
+    irq_latch: u16,
+    irq_pending: bool,
+    switched: bool,
+    chr_memory: Chr,
+    eeprom: Eeprom,
+}
+
+impl Bandai {
+    pub fn new(rom: &Rom) -> Self {
+        let chip = match rom.mapper {
+            159 => Chip::X24C01,
+            _ => Chip::C24C02,
+        };
+        Bandai {
+            chr: [0; 8],
+            prg: 0,
+            control: 0,
+            irq_enabled: false,
+            irq_counter: 0,
+            irq_latch: 0,
+            irq_pending: false,
+            switched: false,
+            chr_memory: Chr::new(rom),
+            eeprom: Eeprom::new(chip),
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8, latched: bool) {
+        match addr & 0x0f {
+            reg @ 0x0..=0x7 => {
+                self.chr[reg as usize] = data;
+                self.switched = true;
+            }
+            0x8 => self.prg = data,
+            0x9 => self.control = data & 0b11,
+            0xa => {
+                self.irq_enabled = data & 0x01 != 0;
+                if latched {
+                    self.irq_counter = self.irq_latch;
+                }
+                self.irq_pending = false;
+            }
+            0xb if latched => self.irq_latch = self.irq_latch & 0xff00 | data as u16,
+            0xc if latched => self.irq_latch = self.irq_latch & 0x00ff | (data as u16) << 8,
+            0xb => self.irq_counter = self.irq_counter & 0xff00 | data as u16,
+            0xc => self.irq_counter = self.irq_counter & 0x00ff | (data as u16) << 8,
+            0xd => self.eeprom.write(data & 0x20 != 0, data & 0x40 != 0),
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Bandai {
+    fn cpu_read(&mut self, rom: &Rom, addr: u16) -> Option<u8> {
+        let offset = match addr {
+            0x8000..=0xbfff => (self.prg & 0x0f) as usize * PRG_BANK + (addr - 0x8000) as usize,
+            0xc000..=0xffff => rom.prg_rom.len() - PRG_BANK + (addr - 0xc000) as usize,
+            _ => return None,
+        };
+        Some(rom.prg_rom[offset % rom.prg_rom.len()])
+    }
+
+    fn cpu_write(&mut self, _rom: &Rom, addr: u16, data: u8) -> bool {
+        if addr < 0x8000 {
+            return false;
+        }
+        self.write_register(addr, data, true);
+        true
+    }
+
+    // the other bits are open bus
+    fn prg_ram_read(&mut self, _addr: u16) -> Option<u8> {
+        Some((self.eeprom.sda() as u8) << 4)
+    }
+
+    fn prg_ram_write(&mut self, addr: u16, data: u8) -> bool {
+        if self.eeprom.chip() == Chip::X24C01 {
+            return false;
+        }
+        self.write_register(addr, data, false);
+        true
+    }
+
+    fn ppu_read(&mut self, rom: &Rom, addr: u16) -> u8 {
+        let bank = self.chr[addr as usize / CHR_BANK] as usize;
+        self.chr_memory
+            .read(rom, bank * CHR_BANK + addr as usize % CHR_BANK)
+    }
+
+    fn ppu_write(&mut self, _rom: &Rom, addr: u16, data: u8) -> bool {
+        let bank = self.chr[addr as usize / CHR_BANK] as usize;
+        self.chr_memory
+            .write(bank * CHR_BANK + addr as usize % CHR_BANK, data)
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match self.control {
+            0 => Mirroring::VERTICAL,
+            1 => Mirroring::HORIZONTAL,
+            2 => Mirroring::SINGLE_SCREEN_LOWER,
+            _ => Mirroring::SINGLE_SCREEN_UPPER,
+        })
+    }
+
+    fn take_chr_switched(&mut self) -> bool {
+        core::mem::replace(&mut self.switched, false)
+    }
+
+    fn bank_register(&self, addr: u16) -> Option<usize> {
+        match addr & 0x0f {
+            reg @ 0x0..=0x7 => Some(self.chr[reg as usize] as usize),
+            0x8 => Some((self.prg & 0x0f) as usize),
+            _ => None,
+        }
+    }
+
+    // the counter is checked, then decremented: the IRQ comes on the cycle it's 0
+    fn tick(&mut self, cycles: u16) {
+        if !self.irq_enabled {
+            return;
+        }
+        if self.irq_counter < cycles {
+            self.irq_pending = true;
+        }
+        self.irq_counter = self.irq_counter.wrapping_sub(cycles);
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn eeprom(&mut self) -> Option<&mut Eeprom> {
+        Some(&mut self.eeprom)
+    }
+
+    fn power_on(&mut self) {
+        self.irq_enabled = false;
+        self.irq_pending = false;
+    }
+
+    // the EEPROM contents, not the transfer in progress: the games don't save mid frame
+    #[cfg(feature = "save-state")]
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = self.chr.to_vec();
+        state.extend_from_slice(&[
+            self.prg,
+            self.control,
+            self.irq_enabled as u8,
+            self.irq_pending as u8,
+        ]);
+        state.extend_from_slice(&self.irq_counter.to_le_bytes());
+        state.extend_from_slice(&self.irq_latch.to_le_bytes());
+        state.extend_from_slice(self.eeprom.data());
+        state.extend_from_slice(self.chr_memory.ram());
+        state
+    }
+
+    #[cfg(feature = "save-state")]
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let eeprom_len = self.eeprom.chip().size();
+        if data.len() < REGISTERS_LEN + eeprom_len {
+            return Err("corrupted Bandai state".to_string());
+        }
+        let (registers, memory) = data.split_at(REGISTERS_LEN);
+        let (eeprom, chr_ram) = memory.split_at(eeprom_len);
+        match registers {
+            [chr @ .., prg, control, enabled, pending, c0, c1, l0, l1]
+                if self.chr_memory.load_ram(chr_ram) =>
+            {
+                self.chr.copy_from_slice(chr);
+                self.prg = *prg;
+                self.control = *control;
+                self.irq_enabled = *enabled != 0;
+                self.irq_pending = *pending != 0;
+                self.irq_counter = u16::from_le_bytes([*c0, *c1]);
+                self.irq_latch = u16::from_le_bytes([*l0, *l1]);
+                self.eeprom.set_data(eeprom)
+            }
+            _ => Err("corrupted Bandai state".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test_ines_rom;
+
+    #[test]
+    fn test_banks() {
+        let rom = test_ines_rom::banked_rom(159, 8, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Bandai::new(&rom);
+        mapper.cpu_write(&rom, 0x8008, 3);
+        assert_eq!(mapper.cpu_read(&rom, 0x8000), Some(3));
+        assert_eq!(mapper.cpu_read(&rom, 0xc000), Some(7));
+        // registers repeat every 16 bytes
+        mapper.cpu_write(&rom, 0xfff7, 33);
+        assert!(mapper.take_chr_switched());
+        assert_eq!(mapper.ppu_read(&rom, 0x1c00), 33);
+        mapper.cpu_write(&rom, 0x8009, 1);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::HORIZONTAL));
+        // no FCG registers on the LZ93D50 board
+        assert!(!mapper.prg_ram_write(0x6008, 5));
+    }
+
+    #[test]
+    fn test_fcg_registers() {
+        let rom = test_ines_rom::banked_rom(16, 8, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Bandai::new(&rom);
+        assert!(mapper.prg_ram_write(0x6008, 5));
+        assert_eq!(mapper.cpu_read(&rom, 0x8000), Some(5));
+        // the counter directly, no latch
+        mapper.prg_ram_write(0x600b, 3);
+        mapper.prg_ram_write(0x600c, 0);
+        mapper.prg_ram_write(0x600a, 1);
+        mapper.tick(3);
+        assert!(!mapper.irq());
+        mapper.tick(1);
+        assert!(mapper.irq());
+    }
+
+    #[test]
+    fn test_irq() {
+        let rom = test_ines_rom::banked_rom(16, 8, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Bandai::new(&rom);
+        mapper.cpu_write(&rom, 0x800b, 0x00);
+        mapper.cpu_write(&rom, 0x800c, 0x01);
+        mapper.cpu_write(&rom, 0x800a, 1);
+        mapper.tick(256);
+        assert!(!mapper.irq());
+        mapper.tick(1);
+        assert!(mapper.irq());
+
+        // acknowledged, the latch is copied again
+        mapper.cpu_write(&rom, 0x800a, 1);
+        assert!(!mapper.irq());
+        mapper.tick(257);
+        assert!(mapper.irq());
+
+        mapper.cpu_write(&rom, 0x800a, 0);
+        mapper.tick(1000);
+        assert!(!mapper.irq());
+    }
+
+    #[test]
+    fn test_eeprom() {
+        let rom = test_ines_rom::banked_rom(159, 8, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Bandai::new(&rom);
+        let lines = |mapper: &mut Bandai, scl: bool, sda: bool| {
+            mapper.cpu_write(&rom, 0x800d, (scl as u8) << 5 | (sda as u8) << 6);
+        };
+        // START, then address 3 for a write, LSB first
+        lines(&mut mapper, true, true);
+        lines(&mut mapper, true, false);
+        lines(&mut mapper, false, false);
+        for bit in 0..8 {
+            let sda = 0x03 >> bit & 1 != 0;
+            lines(&mut mapper, false, sda);
+            lines(&mut mapper, true, sda);
+            lines(&mut mapper, false, sda);
+        }
+        // the chip acknowledges: SDA is pulled low on the ack clock
+        lines(&mut mapper, false, true);
+        lines(&mut mapper, true, true);
+        assert_eq!(mapper.prg_ram_read(0x6000), Some(0x00));
+        lines(&mut mapper, false, true);
+        assert_eq!(mapper.prg_ram_read(0x7fff), Some(0x10));
+        assert_eq!(mapper.eeprom().unwrap().chip(), Chip::X24C01);
+    }
+
+    #[cfg(feature = "save-state")]
+    #[test]
+    fn test_state() {
+        let rom = test_ines_rom::banked_rom(16, 8, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Bandai::new(&rom);
+        mapper.cpu_write(&rom, 0x8008, 2);
+        mapper.cpu_write(&rom, 0x8003, 9);
+        mapper.cpu_write(&rom, 0x800b, 0x40);
+        mapper.cpu_write(&rom, 0x800a, 1);
+        mapper.tick(10);
+        mapper.eeprom().unwrap().set_data(&[0x42; 256]).unwrap();
+        let state = mapper.save_state();
+
+        let mut loaded = Bandai::new(&rom);
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.save_state(), state);
+        assert_eq!(loaded.ppu_read(&rom, 0x0c00), 9);
+        assert_eq!(loaded.eeprom().unwrap().data()[255], 0x42);
+        assert!(loaded.load_state(&state[1..]).is_err());
+    }
+}
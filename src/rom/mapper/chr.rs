@@ -0,0 +1,80 @@
+// Pattern table memory of a board: the rom's CHR ROM, or CHR RAM on the carts without it
+// (`Rom::chr_ram`). The board keeps the RAM, it goes into the mapper save state.
+use crate::prelude::*;
+use crate::rom::Rom;
+
+pub(super) struct Chr {
+    ram: Vec<u8>,
+}
+
+impl Chr {
+    pub(super) fn new(rom: &Rom) -> Self {
+        Chr {
+            ram: vec![0; rom.chr_ram],
+        }
+    }
+
+    /// `offset` wraps around the memory size, no memory at all reads as 0
+    pub(super) fn read(&self, rom: &Rom, offset: usize) -> u8 {
+        let memory = if self.ram.is_empty() {
+            &rom.chr_rom
+        } else {
+            &self.ram
+        };
+        match memory.len() {
+            0 => 0,
+            len => memory[offset % len],
+        }
+    }
+
+    /// false - CHR ROM
+    pub(super) fn write(&mut self, offset: usize, data: u8) -> bool {
+        match self.ram.len() {
+            0 => false,
+            len => {
+                self.ram[offset % len] = data;
+                true
+            }
+        }
+    }
+
+    /// Empty with CHR ROM
+    #[cfg(feature = "save-state")]
+    pub(super) fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// false - the size doesn't match
+    #[cfg(feature = "save-state")]
+    pub(super) fn load_ram(&mut self, data: &[u8]) -> bool {
+        if data.len() != self.ram.len() {
+            return false;
+        }
+        self.ram.copy_from_slice(data);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test_ines_rom;
+
+    #[test]
+    fn test_chr_rom() {
+        let rom = test_ines_rom::test_rom();
+        let mut chr = Chr::new(&rom);
+        assert!(!chr.write(0, 1));
+        assert_eq!(chr.read(&rom, 0x2000 + 5), 2);
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let mut rom = test_ines_rom::test_rom();
+        rom.chr_rom = vec![];
+        rom.chr_ram = 0x2000;
+        let mut chr = Chr::new(&rom);
+        assert!(chr.write(0x2005, 0x42));
+        assert_eq!(chr.read(&rom, 5), 0x42);
+    }
+}
@@ -0,0 +1,142 @@
+// NROM with switchable CHR: a latch at $8000-$FFFF selects the 8KB bank of CHR ROM, up to
+// 2MB of it (Arkanoid, Gradius, Solomon's Key). https://wiki.nesdev.com/w/index.php/CNROM
+//
+// The ROM drives the data bus too when the latch is written: the value the latch gets is
+// the written one ANDed with the ROM byte at that address (bus conflict). Games write a value
+// into a ROM location holding the same value.
+use super::chr::Chr;
+use super::nrom::fixed_prg;
+use super::Mapper;
+use crate::rom::Rom;
+
+const CHR_BANK: usize = 0x2000;
+
+pub struct Cnrom {
+    bank: usize,
+    banks: usize,
+    switched: bool,
+    chr: Chr,
+}
+
+impl Cnrom {
+    pub fn new(rom: &Rom) -> Self {
+        Cnrom {
+            bank: 0,
+            banks: (rom.chr_rom.len() / CHR_BANK).max(1),
+            switched: false,
+            chr: Chr::new(rom),
+        }
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_read(&mut self, rom: &Rom, addr: u16) -> Option<u8> {
+        fixed_prg(rom, addr)
+    }
+
+    fn cpu_write(&mut self, rom: &Rom, addr: u16, data: u8) -> bool {
+        let rom_byte = match fixed_prg(rom, addr) {
+            Some(byte) => byte,
+            None => return false,
+        };
+        let bank = (data & rom_byte) as usize % self.banks;
+        if bank != self.bank {
+            self.bank = bank;
+            self.switched = true;
+        }
+        true
+    }
+
+    fn ppu_read(&mut self, rom: &Rom, addr: u16) -> u8 {
+        self.chr.read(rom, self.bank * CHR_BANK + addr as usize)
+    }
+
+    fn ppu_write(&mut self, _rom: &Rom, addr: u16, data: u8) -> bool {
+        self.chr.write(self.bank * CHR_BANK + addr as usize, data)
+    }
+
+    fn take_chr_switched(&mut self) -> bool {
+        core::mem::replace(&mut self.switched, false)
+    }
+
+    fn bank_register(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then_some(self.bank)
+    }
+
+    fn power_on(&mut self) {
+        self.bank = 0;
+    }
+
+    #[cfg(feature = "save-state")]
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.bank as u8];
+        state.extend_from_slice(self.chr.ram());
+        state
+    }
+
+    #[cfg(feature = "save-state")]
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        match data {
+            [bank, chr_ram @ ..] if (*bank as usize) < self.banks && self.chr.load_ram(chr_ram) => {
+                self.bank = *bank as usize;
+                Ok(())
+            }
+            _ => Err("corrupted CNROM state".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::mapper::chr_window;
+    use crate::rom::test_ines_rom;
+
+    fn rom() -> Rom {
+        let mut rom = test_ines_rom::banked_rom(3, 1, 0x8000, 4, CHR_BANK);
+        // no bus conflicts, the latch gets the written value
+        rom.prg_rom = vec![0xff; 0x8000];
+        rom
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let rom = rom();
+        let mut mapper = Cnrom::new(&rom);
+        assert_eq!(chr_window(&mut mapper, &rom), vec![0; CHR_BANK]);
+
+        assert!(mapper.cpu_write(&rom, 0x8000, 2));
+        assert!(mapper.take_chr_switched());
+        assert!(!mapper.take_chr_switched());
+        assert_eq!(chr_window(&mut mapper, &rom), vec![2; CHR_BANK]);
+        assert_eq!(mapper.cpu_read(&rom, 0xfffc), Some(0xff));
+
+        // only 4 banks
+        mapper.cpu_write(&rom, 0xc000, 7);
+        assert_eq!(mapper.ppu_read(&rom, 0x1fff), 3);
+        assert!(!mapper.ppu_write(&rom, 0, 1));
+    }
+
+    #[test]
+    fn test_bus_conflict() {
+        let mut rom = rom();
+        rom.prg_rom[0x10] = 0x01;
+        let mut mapper = Cnrom::new(&rom);
+        mapper.cpu_write(&rom, 0x8010, 3);
+        assert_eq!(mapper.ppu_read(&rom, 0), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "save-state")]
+    fn test_state() {
+        let rom = rom();
+        let mut mapper = Cnrom::new(&rom);
+        mapper.cpu_write(&rom, 0x8000, 3);
+        let state = mapper.save_state();
+        mapper.power_on();
+        assert_eq!(mapper.ppu_read(&rom, 0), 0);
+        mapper.load_state(&state).unwrap();
+        assert_eq!(mapper.ppu_read(&rom, 0), 3);
+        assert!(mapper.load_state(&[9]).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+// Two discrete latch boards: one register at $8000-$FFFF selects a 32KB PRG bank and an 8KB
+// CHR bank, the bit layout differs.
+// - GxROM, mapper 66 (Super Mario Bros. + Duck Hunt, Dragon Power): 4-5 - PRG, 0-1 - CHR.
+//   https://wiki.nesdev.com/w/index.php/GxROM
+// - Color Dreams, mapper 11 (unlicensed Color Dreams and Wisdom Tree games): 0-1 - PRG,
+//   4-7 - CHR. https://wiki.nesdev.com/w/index.php/Color_Dreams
+//
+// Both have bus conflicts, the same as CNROM: the latch gets the written value ANDed with the
+// ROM byte at that address.
+use super::chr::Chr;
+use super::Mapper;
+use crate::rom::Rom;
+
+const PRG_BANK: usize = 0x8000;
+const CHR_BANK: usize = 0x2000;
+
+pub struct Gxrom {
+    color_dreams: bool,
+    prg: usize,
+    chr: usize,
+    switched: bool,
+    chr_memory: Chr,
+}
+
+impl Gxrom {
+    pub fn new(rom: &Rom) -> Self {
+        Gxrom {
+            color_dreams: rom.mapper == 11,
+            prg: 0,
+            chr: 0,
+            switched: false,
+            chr_memory: Chr::new(rom),
+        }
+    }
+
+    fn prg_offset(&self, rom: &Rom, addr: u16) -> usize {
+        (self.prg * PRG_BANK + (addr - 0x8000) as usize) % rom.prg_rom.len()
+    }
+}
+
+impl Mapper for Gxrom {
+    fn cpu_read(&mut self, rom: &Rom, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xffff => Some(rom.prg_rom[self.prg_offset(rom, addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, rom: &Rom, addr: u16, data: u8) -> bool {
+        if addr < 0x8000 {
+            return false;
+        }
+        let data = data & rom.prg_rom[self.prg_offset(rom, addr)];
+        let (prg, chr) = if self.color_dreams {
+            (data & 0b11, data >> 4)
+        } else {
+            ((data >> 4) & 0b11, data & 0b11)
+        };
+        self.prg = prg as usize;
+        if chr as usize != self.chr {
+            self.chr = chr as usize;
+            self.switched = true;
+        }
+        true
+    }
+
+    fn ppu_read(&mut self, rom: &Rom, addr: u16) -> u8 {
+        self.chr_memory
+            .read(rom, self.chr * CHR_BANK + addr as usize)
+    }
+
+    fn ppu_write(&mut self, _rom: &Rom, addr: u16, data: u8) -> bool {
+        self.chr_memory
+            .write(self.chr * CHR_BANK + addr as usize, data)
+    }
+
+    fn take_chr_switched(&mut self) -> bool {
+        core::mem::replace(&mut self.switched, false)
+    }
+
+    // the PRG bank, the CHR one is switched by the same write
+    fn bank_register(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then_some(self.prg)
+    }
+
+    fn power_on(&mut self) {
+        self.prg = 0;
+        self.chr = 0;
+    }
+
+    #[cfg(feature = "save-state")]
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.prg as u8, self.chr as u8];
+        state.extend_from_slice(self.chr_memory.ram());
+        state
+    }
+
+    #[cfg(feature = "save-state")]
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        match data {
+            [prg, chr, chr_ram @ ..]
+                if *prg < 4 && *chr < 16 && self.chr_memory.load_ram(chr_ram) =>
+            {
+                self.prg = *prg as usize;
+                self.chr = *chr as usize;
+                Ok(())
+            }
+            _ => Err("corrupted GxROM state".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test_ines_rom;
+
+    fn rom(mapper: u8) -> Rom {
+        let mut rom = test_ines_rom::banked_rom(mapper, 4, PRG_BANK, 16, CHR_BANK);
+        // the latch writes at $8001 of bank 0 don't conflict
+        rom.prg_rom[1] = 0xff;
+        rom
+    }
+
+    #[test]
+    fn test_gxrom() {
+        let rom = rom(66);
+        let mut mapper = Gxrom::new(&rom);
+        assert!(mapper.cpu_write(&rom, 0x8001, 0b0010_0011));
+        assert!(mapper.take_chr_switched());
+        assert_eq!(mapper.cpu_read(&rom, 0x8000), Some(2));
+        assert_eq!(mapper.ppu_read(&rom, 0x1000), 3);
+        assert!(!mapper.cpu_write(&rom, 0x6000, 1));
+    }
+
+    #[test]
+    fn test_color_dreams() {
+        let rom = rom(11);
+        let mut mapper = Gxrom::new(&rom);
+        mapper.cpu_write(&rom, 0x8001, 0b1011_0001);
+        assert_eq!(mapper.cpu_read(&rom, 0x8000), Some(1));
+        assert_eq!(mapper.ppu_read(&rom, 0x0000), 11);
+    }
+
+    #[test]
+    fn test_bus_conflict() {
+        let rom = rom(66);
+        let mut mapper = Gxrom::new(&rom);
+        // the ROM byte at $8000 of bank 0 is 0
+        mapper.cpu_write(&rom, 0x8000, 0b0011_0011);
+        assert_eq!(mapper.cpu_read(&rom, 0x8000), Some(0));
+        assert!(!mapper.take_chr_switched());
+    }
+
+    #[cfg(feature = "save-state")]
+    #[test]
+    fn test_state() {
+        let rom = rom(11);
+        let mut mapper = Gxrom::new(&rom);
+        mapper.cpu_write(&rom, 0x8001, 0b1111_0011);
+        let state = mapper.save_state();
+        let mut loaded = Gxrom::new(&rom);
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.ppu_read(&rom, 0), 15);
+        assert_eq!(loaded.cpu_read(&rom, 0x8000), Some(3));
+        assert!(loaded.load_state(&[4, 0]).is_err());
+    }
+}
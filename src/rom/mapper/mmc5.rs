@@ -0,0 +1,708 @@
+// MMC5 (ExROM): PRG banks down to 8KB with RAM mappable into them, CHR banks down to 1KB,
+// 1KB of extra RAM (ExRAM), a nametable per slot, a scanline IRQ and a multiplier
+// (Castlevania III, Just Breed, Uncharted Waters). https://wiki.nesdev.com/w/index.php/MMC5
+//
+// The board has 64KB of PRG RAM in 8KB banks: $6000-$7FFF shows the one in $5113, $8000-$DFFF
+// the ones with bit 7 clear. It is the battery RAM of the cart (`Mapper::battery_ram`).
+//
+// $5105 picks the page of every nametable: one of the vram halves, ExRAM or the fill page
+// ($5106, $5107), `Mirroring::CUSTOM` for the PPU. ExRAM mode 0 and 1 - a nametable, 1 - the
+// extended attributes as well, 2 - CPU RAM, 3 - CPU ROM. The PPU draws ExRAM, the fill page,
+// the extended attributes and the vertical split ($5200-$5202) from a copy,
+// see `ppu::cartridge`. Not emulated:
+// - separate sprite (set A) and background (set B) patterns in 8x16 sprite mode: the PPU gets
+//   the set written last, the same as in 8x8 mode;
+// - the CPU writes to ExRAM in modes 0 and 1 go through outside of rendering as well;
+// - the expansion audio at $5000-$5015 (no APU).
+use super::chr::Chr;
+use super::Mapper;
+use crate::ppu::cartridge::{CartridgeVideo, Split, PAGE_SIZE};
+use crate::prelude::*;
+use crate::rom::{Mirroring, Rom};
+
+const PRG_BANK: usize = 0x2000;
+const CHR_BANK: usize = 0x400;
+const PRG_RAM_SIZE: usize = 0x10000;
+const EXRAM_SIZE: usize = 0x400;
+// register bytes in a save state, followed by ExRAM, the PRG RAM and the CHR RAM
+#[cfg(feature = "save-state")]
+const STATE_LEN: usize = 49;
+
+pub struct Mmc5 {
+    // $5100, 0 - 32KB .. 3 - 8KB banks
+    prg_mode: u8,
+    // $5101, 0 - 8KB .. 3 - 1KB banks
+    chr_mode: u8,
+    // $5102, $5103: the PRG RAM takes writes with 2 and 1 in them
+    ram_protect: [u8; 2],
+    // $5104
+    exram_mode: u8,
+    // $5105, 2 bits per nametable: CIRAM page 0 or 1, ExRAM, fill
+    nametables: u8,
+    // $5106, $5107
+    fill_tile: u8,
+    fill_attribute: u8,
+    // $5113-$5117, bit 7 - ROM (RAM otherwise), $5117 is always ROM
+    prg_banks: [u8; 5],
+    // $5120-$5127 - set A, $5128-$512B - set B, with the $5130 upper bits
+    chr_banks: [u16; 12],
+    chr_upper: u8,
+    chr_set_b: bool,
+    // $5200-$5202
+    split: [u8; 3],
+    // $5203, $5204
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline: u8,
+    // $5205, $5206
+    multiplicand: u8,
+    multiplier: u8,
+    switched: bool,
+    // ExRAM, the fill page or the split changed since the PPU got its copy
+    video_changed: bool,
+    exram: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr_memory: Chr,
+}
+
+impl Mmc5 {
+    pub fn new(rom: &Rom) -> Self {
+        let mut mapper = Mmc5 {
+            prg_mode: 3,
+            chr_mode: 0,
+            ram_protect: [0; 2],
+            exram_mode: 0,
+            nametables: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0; 5],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            chr_set_b: false,
+            split: [0; 3],
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline: 0,
+            multiplicand: 0xff,
+            multiplier: 0xff,
+            switched: false,
+            video_changed: true,
+            exram: vec![0; EXRAM_SIZE],
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            chr_memory: Chr::new(rom),
+        };
+        mapper.power_on();
+        mapper
+    }
+
+    // the bank register of a $8000-$FFFF address, the size of its bank
+    fn prg_slot(&self, addr: u16) -> (u8, usize) {
+        match (self.prg_mode, addr) {
+            (0, _) => (self.prg_banks[4], 0x8000),
+            (1, 0x8000..=0xbfff) | (2, 0x8000..=0xbfff) => (self.prg_banks[2], 0x4000),
+            (1, _) => (self.prg_banks[4], 0x4000),
+            (2, 0xc000..=0xdfff) => (self.prg_banks[3], PRG_BANK),
+            (2, _) => (self.prg_banks[4], PRG_BANK),
+            _ => (
+                self.prg_banks[1 + (addr as usize - 0x8000) / PRG_BANK],
+                PRG_BANK,
+            ),
+        }
+    }
+
+    // Ok - an offset into the PRG ROM, Err - into the PRG RAM (a RAM bank)
+    fn prg_rom_offset(&self, addr: u16) -> Result<usize, usize> {
+        let (bank, size) = self.prg_slot(addr);
+        let base = (bank & 0x7f) as usize * PRG_BANK;
+        let offset = (base & !(size - 1)) + (addr as usize & (size - 1));
+        if bank & 0x80 != 0 || addr >= 0xe000 {
+            Ok(offset)
+        } else {
+            Err(offset % PRG_RAM_SIZE)
+        }
+    }
+
+    fn ram_writable(&self) -> bool {
+        self.ram_protect == [0b10, 0b01]
+    }
+
+    // $6000-$7FFF, the $5113 bank
+    fn prg_ram_offset(&self, addr: u16) -> usize {
+        (self.prg_banks[0] & 0x07) as usize * PRG_BANK + (addr as usize - 0x6000)
+    }
+
+    // ExRAM is a nametable in modes 0 and 1 only, the PPU sees zeros otherwise
+    fn exram_nametable(&self) -> bool {
+        self.exram_mode < 2
+    }
+
+    fn fill_page(&self) -> Vec<u8> {
+        let mut page = vec![self.fill_tile; PAGE_SIZE];
+        page[0x3c0..].fill(self.fill_attribute * 0b0101_0101);
+        page
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) -> bool {
+        match addr {
+            // todo: expansion audio, two pulse channels and PCM
+            0x5000..=0x5015 => {}
+            0x5100 => self.prg_mode = data & 0b11,
+            0x5101 => {
+                self.chr_mode = data & 0b11;
+                self.switched = true;
+            }
+            0x5102 | 0x5103 => self.ram_protect[(addr - 0x5102) as usize] = data & 0b11,
+            0x5104 => {
+                self.exram_mode = data & 0b11;
+                self.video_changed = true;
+            }
+            0x5105 => self.nametables = data,
+            0x5106 => {
+                self.fill_tile = data;
+                self.video_changed = true;
+            }
+            0x5107 => {
+                self.fill_attribute = data & 0b11;
+                self.video_changed = true;
+            }
+            0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = data,
+            0x5120..=0x512b => {
+                let idx = (addr - 0x5120) as usize;
+                self.chr_banks[idx] = data as u16 | (self.chr_upper as u16) << 8;
+                self.chr_set_b = idx >= 8;
+                self.switched = true;
+            }
+            0x5130 => {
+                self.chr_upper = data & 0b11;
+                self.video_changed = true;
+            }
+            0x5200..=0x5202 => {
+                self.split[(addr - 0x5200) as usize] = data;
+                self.video_changed = true;
+            }
+            0x5203 => self.irq_compare = data,
+            0x5204 => self.irq_enabled = data & 0x80 != 0,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            0x5c00..=0x5fff => {
+                if self.exram_mode != 3 {
+                    self.exram[(addr - 0x5c00) as usize] = data;
+                    self.video_changed |= self.exram_nametable();
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let slot = addr as usize / CHR_BANK;
+        // 1KB banks in 1KB mode .. 8KB in 8KB mode, the register of the last one in a bank
+        let size = 8 >> self.chr_mode;
+        let register = if self.chr_set_b {
+            // set B has 4KB worth of registers, repeated in both pattern tables
+            let size = size.min(4);
+            8 + (slot % 4) / size * size + size - 1
+        } else {
+            slot / size * size + size - 1
+        };
+        let bank_len = size * CHR_BANK;
+        self.chr_banks[register] as usize * bank_len + addr as usize % bank_len
+    }
+
+    // $5204: bit 7 - IRQ pending (cleared by the read), bit 6 - the PPU is rendering
+    fn read_status(&mut self) -> u8 {
+        let status = (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6;
+        self.irq_pending = false;
+        status
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_read(&mut self, rom: &Rom, addr: u16) -> Option<u8> {
+        match addr {
+            0x5204 => Some(self.read_status()),
+            0x5205 => Some((self.multiplicand as u16 * self.multiplier as u16) as u8),
+            0x5206 => Some(((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8),
+            0x5c00..=0x5fff if self.exram_mode >= 2 => Some(self.exram[(addr - 0x5c00) as usize]),
+            0x8000..=0xffff => Some(match self.prg_rom_offset(addr) {
+                Ok(offset) => rom.prg_rom[offset % rom.prg_rom.len()],
+                Err(offset) => self.prg_ram[offset],
+            }),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, _rom: &Rom, addr: u16, data: u8) -> bool {
+        match addr {
+            0x8000..=0xffff => match self.prg_rom_offset(addr) {
+                Ok(_) => false,
+                Err(offset) => {
+                    if self.ram_writable() {
+                        self.prg_ram[offset] = data;
+                    }
+                    true
+                }
+            },
+            _ => self.write_register(addr, data),
+        }
+    }
+
+    fn ppu_read(&mut self, rom: &Rom, addr: u16) -> u8 {
+        self.chr_memory.read(rom, self.chr_offset(addr))
+    }
+
+    fn prg_ram_read(&mut self, addr: u16) -> Option<u8> {
+        Some(self.prg_ram[self.prg_ram_offset(addr)])
+    }
+
+    fn prg_ram_write(&mut self, addr: u16, data: u8) -> bool {
+        if self.ram_writable() {
+            let offset = self.prg_ram_offset(addr);
+            self.prg_ram[offset] = data;
+        }
+        true
+    }
+
+    fn ppu_write(&mut self, _rom: &Rom, addr: u16, data: u8) -> bool {
+        if addr < 0x2000 {
+            return self.chr_memory.write(self.chr_offset(addr), data);
+        }
+        // a nametable page on the board: ExRAM takes the write, the fill page doesn't
+        let slot = (addr as usize - 0x2000) / PAGE_SIZE % 4;
+        if self.nametables >> (slot * 2) & 0b11 == 2 && self.exram_nametable() {
+            self.exram[addr as usize % PAGE_SIZE] = data;
+            self.video_changed = true;
+        }
+        true
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match self.nametables {
+            0x00 => Mirroring::SINGLE_SCREEN_LOWER,
+            0x55 => Mirroring::SINGLE_SCREEN_UPPER,
+            0x44 => Mirroring::VERTICAL,
+            0x50 => Mirroring::HORIZONTAL,
+            layout => Mirroring::CUSTOM([
+                layout & 0b11,
+                layout >> 2 & 0b11,
+                layout >> 4 & 0b11,
+                layout >> 6,
+            ]),
+        })
+    }
+
+    fn cartridge_video(&self) -> Option<CartridgeVideo> {
+        let mut pages = if self.exram_nametable() {
+            self.exram.clone()
+        } else {
+            vec![0; PAGE_SIZE]
+        };
+        pages.extend_from_slice(&self.fill_page());
+        let split = (self.split[0] & 0x80 != 0 && self.exram_nametable()).then(|| Split {
+            right: self.split[0] & 0x40 != 0,
+            tile: self.split[0] & 0x1f,
+            scroll_y: self.split[1],
+            bank: self.split[2],
+        });
+        Some(CartridgeVideo {
+            pages,
+            extended_attributes: self.exram_mode == 1,
+            split,
+            chr_upper: self.chr_upper,
+        })
+    }
+
+    fn take_video_changed(&mut self) -> bool {
+        core::mem::replace(&mut self.video_changed, false)
+    }
+
+    fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn take_chr_switched(&mut self) -> bool {
+        core::mem::replace(&mut self.switched, false)
+    }
+
+    fn bank_register(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x5113..=0x5117 => Some((self.prg_banks[(addr - 0x5113) as usize] & 0x7f) as usize),
+            0x5120..=0x512b => Some(self.chr_banks[(addr - 0x5120) as usize] as usize),
+            _ => None,
+        }
+    }
+
+    // the board watches the PPU fetches: the first visible line starts the frame, every next
+    // one counts, vblank or the rendering off ends it
+    fn scanline(&mut self, line: usize, rendering: bool) {
+        if !rendering || line >= 240 {
+            self.in_frame = false;
+            return;
+        }
+        if !self.in_frame {
+            self.in_frame = true;
+            self.scanline = 0;
+            self.irq_pending = false;
+            return;
+        }
+        self.scanline = self.scanline.wrapping_add(1);
+        if self.scanline == self.irq_compare {
+            self.irq_pending = true;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+
+    fn power_on(&mut self) {
+        self.prg_mode = 3;
+        self.prg_banks[4] = 0xff;
+        self.irq_enabled = false;
+        self.irq_pending = false;
+        self.in_frame = false;
+    }
+
+    #[cfg(feature = "save-state")]
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![
+            self.prg_mode,
+            self.chr_mode,
+            self.ram_protect[0],
+            self.ram_protect[1],
+            self.exram_mode,
+            self.nametables,
+            self.fill_tile,
+            self.fill_attribute,
+        ];
+        state.extend_from_slice(&self.prg_banks);
+        for bank in self.chr_banks.iter() {
+            state.extend_from_slice(&bank.to_le_bytes());
+        }
+        state.extend_from_slice(&[self.chr_upper, self.chr_set_b as u8]);
+        state.extend_from_slice(&self.split);
+        state.extend_from_slice(&[
+            self.irq_compare,
+            self.irq_enabled as u8,
+            self.irq_pending as u8,
+            self.in_frame as u8,
+            self.scanline,
+            self.multiplicand,
+            self.multiplier,
+        ]);
+        state.extend_from_slice(&self.exram);
+        state.extend_from_slice(&self.prg_ram);
+        state.extend_from_slice(self.chr_memory.ram());
+        state
+    }
+
+    #[cfg(feature = "save-state")]
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let memory_len = EXRAM_SIZE + PRG_RAM_SIZE + self.chr_memory.ram().len();
+        if data.len() != STATE_LEN + memory_len {
+            return Err("corrupted MMC5 state".to_string());
+        }
+        let (registers, memory) = data.split_at(STATE_LEN);
+        self.prg_mode = registers[0];
+        self.chr_mode = registers[1];
+        self.ram_protect.copy_from_slice(&registers[2..4]);
+        self.exram_mode = registers[4];
+        self.nametables = registers[5];
+        self.fill_tile = registers[6];
+        self.fill_attribute = registers[7];
+        self.prg_banks.copy_from_slice(&registers[8..13]);
+        for (idx, bank) in self.chr_banks.iter_mut().enumerate() {
+            *bank = u16::from_le_bytes([registers[13 + idx * 2], registers[14 + idx * 2]]);
+        }
+        self.chr_upper = registers[37];
+        self.chr_set_b = registers[38] != 0;
+        self.split.copy_from_slice(&registers[39..42]);
+        self.irq_compare = registers[42];
+        self.irq_enabled = registers[43] != 0;
+        self.irq_pending = registers[44] != 0;
+        self.in_frame = registers[45] != 0;
+        self.scanline = registers[46];
+        self.multiplicand = registers[47];
+        self.multiplier = registers[48];
+        let (exram, memory) = memory.split_at(EXRAM_SIZE);
+        let (prg_ram, chr_ram) = memory.split_at(PRG_RAM_SIZE);
+        self.exram.copy_from_slice(exram);
+        self.prg_ram.copy_from_slice(prg_ram);
+        self.chr_memory.load_ram(chr_ram);
+        self.video_changed = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test_ines_rom;
+
+    #[test]
+    fn test_prg_modes() {
+        let rom = test_ines_rom::banked_rom(5, 16, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Mmc5::new(&rom);
+        // power on: 8KB banks, the last one at $E000
+        assert_eq!(mapper.cpu_read(&rom, 0xfffc), Some(15));
+
+        mapper.cpu_write(&rom, 0x5117, 0x85);
+        mapper.cpu_write(&rom, 0x5100, 0);
+        // 32KB: the 2 low bits of the bank are ignored
+        assert_eq!(mapper.cpu_read(&rom, 0x8000), Some(4));
+        assert_eq!(mapper.cpu_read(&rom, 0xffff), Some(7));
+
+        mapper.cpu_write(&rom, 0x5100, 1);
+        mapper.cpu_write(&rom, 0x5115, 0x83);
+        assert_eq!(mapper.cpu_read(&rom, 0x8000), Some(2));
+        assert_eq!(mapper.cpu_read(&rom, 0xa000), Some(3));
+        assert_eq!(mapper.cpu_read(&rom, 0xc000), Some(4));
+
+        mapper.cpu_write(&rom, 0x5100, 3);
+        mapper.cpu_write(&rom, 0x5114, 0x89);
+        mapper.cpu_write(&rom, 0x5116, 0x8a);
+        assert_eq!(mapper.cpu_read(&rom, 0x8000), Some(9));
+        assert_eq!(mapper.cpu_read(&rom, 0xa000), Some(3));
+        assert_eq!(mapper.cpu_read(&rom, 0xc000), Some(10));
+        assert_eq!(mapper.cpu_read(&rom, 0xe000), Some(5));
+        assert!(!mapper.cpu_write(&rom, 0x8000, 1));
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let rom = test_ines_rom::banked_rom(5, 16, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Mmc5::new(&rom);
+        mapper.cpu_write(&rom, 0x5114, 0x01);
+        assert!(mapper.cpu_write(&rom, 0x8000, 0x42));
+        assert_eq!(mapper.cpu_read(&rom, 0x8000), Some(0));
+
+        mapper.cpu_write(&rom, 0x5102, 0b10);
+        mapper.cpu_write(&rom, 0x5103, 0b01);
+        mapper.cpu_write(&rom, 0x8000, 0x42);
+        assert_eq!(mapper.cpu_read(&rom, 0x8000), Some(0x42));
+        assert_eq!(mapper.prg_ram[PRG_BANK], 0x42);
+        mapper.cpu_write(&rom, 0x5114, 0x80);
+        assert_eq!(mapper.cpu_read(&rom, 0x8000), Some(0));
+
+        // $6000-$7FFF: the $5113 bank of the same RAM
+        mapper.cpu_write(&rom, 0x5113, 0x01);
+        assert_eq!(mapper.prg_ram_read(0x6000), Some(0x42));
+        mapper.cpu_write(&rom, 0x5113, 0x07);
+        assert!(mapper.prg_ram_write(0x7fff, 0x43));
+        assert_eq!(mapper.prg_ram[7 * PRG_BANK + 0x1fff], 0x43);
+        mapper.cpu_write(&rom, 0x5102, 0);
+        assert!(mapper.prg_ram_write(0x7fff, 0x44));
+        assert_eq!(mapper.prg_ram_read(0x7fff), Some(0x43));
+        assert_eq!(
+            mapper.battery_ram().map(|ram| ram.len()),
+            Some(PRG_RAM_SIZE)
+        );
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let rom = test_ines_rom::banked_rom(5, 16, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Mmc5::new(&rom);
+        mapper.cpu_write(&rom, 0x5101, 3);
+        mapper.cpu_write(&rom, 0x5120, 7);
+        mapper.cpu_write(&rom, 0x5127, 9);
+        assert!(mapper.take_chr_switched());
+        assert_eq!(mapper.ppu_read(&rom, 0x0000), 7);
+        assert_eq!(mapper.ppu_read(&rom, 0x1c00), 9);
+
+        mapper.cpu_write(&rom, 0x5101, 1);
+        mapper.cpu_write(&rom, 0x5123, 2);
+        // 4KB banks: 1KB banks 8..11
+        assert_eq!(mapper.ppu_read(&rom, 0x0c00), 11);
+        assert_eq!(mapper.ppu_read(&rom, 0x1000), 36);
+
+        // set B, repeated in both pattern tables
+        mapper.cpu_write(&rom, 0x5101, 2);
+        mapper.cpu_write(&rom, 0x5130, 0);
+        mapper.cpu_write(&rom, 0x512b, 5);
+        assert_eq!(mapper.ppu_read(&rom, 0x0800), 10);
+        assert_eq!(mapper.ppu_read(&rom, 0x1800), 10);
+    }
+
+    #[test]
+    fn test_exram_and_multiplier() {
+        let rom = test_ines_rom::banked_rom(5, 16, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Mmc5::new(&rom);
+        mapper.cpu_write(&rom, 0x5104, 2);
+        mapper.cpu_write(&rom, 0x5c10, 0x42);
+        assert_eq!(mapper.cpu_read(&rom, 0x5c10), Some(0x42));
+        mapper.cpu_write(&rom, 0x5104, 3);
+        mapper.cpu_write(&rom, 0x5c10, 0x43);
+        assert_eq!(mapper.cpu_read(&rom, 0x5c10), Some(0x42));
+        mapper.cpu_write(&rom, 0x5104, 0);
+        assert_eq!(mapper.cpu_read(&rom, 0x5c10), None);
+
+        mapper.cpu_write(&rom, 0x5205, 200);
+        mapper.cpu_write(&rom, 0x5206, 100);
+        assert_eq!(mapper.cpu_read(&rom, 0x5205), Some(0x20));
+        assert_eq!(mapper.cpu_read(&rom, 0x5206), Some(0x4e));
+        assert!(!mapper.cpu_write(&rom, 0x4800, 1));
+    }
+
+    #[test]
+    fn test_nametables() {
+        let rom = test_ines_rom::banked_rom(5, 16, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Mmc5::new(&rom);
+        mapper.cpu_write(&rom, 0x5105, 0x44);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::VERTICAL));
+        mapper.cpu_write(&rom, 0x5105, 0x50);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::HORIZONTAL));
+        mapper.cpu_write(&rom, 0x5105, 0xe4);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::CUSTOM([0, 1, 2, 3])));
+
+        // ExRAM takes the PPU writes in modes 0 and 1, the fill page never
+        assert!(mapper.take_video_changed());
+        assert!(mapper.ppu_write(&rom, 0x2805, 0x42));
+        assert!(mapper.ppu_write(&rom, 0x2c05, 0x43));
+        assert_eq!(mapper.exram[5], 0x42);
+        assert!(mapper.take_video_changed());
+        mapper.cpu_write(&rom, 0x5104, 2);
+        mapper.take_video_changed();
+        mapper.ppu_write(&rom, 0x2806, 0x42);
+        assert_eq!(mapper.exram[6], 0);
+        assert!(!mapper.take_video_changed());
+    }
+
+    #[test]
+    fn test_cartridge_video() {
+        let rom = test_ines_rom::banked_rom(5, 16, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Mmc5::new(&rom);
+        mapper.cpu_write(&rom, 0x5104, 1);
+        mapper.cpu_write(&rom, 0x5c00, 0x42);
+        mapper.cpu_write(&rom, 0x5106, 0x17);
+        mapper.cpu_write(&rom, 0x5107, 2);
+        mapper.cpu_write(&rom, 0x5130, 1);
+        let video = mapper.cartridge_video().unwrap();
+        assert_eq!(video.page(2)[0], 0x42);
+        assert_eq!(video.page(3)[0x3bf], 0x17);
+        assert_eq!(video.page(3)[0x3c0], 0xaa);
+        assert!(video.extended_attributes);
+        assert_eq!(video.chr_upper, 1);
+        assert_eq!(video.split, None);
+
+        mapper.cpu_write(&rom, 0x5200, 0xc3);
+        mapper.cpu_write(&rom, 0x5201, 8);
+        mapper.cpu_write(&rom, 0x5202, 5);
+        let split = Split {
+            right: true,
+            tile: 3,
+            scroll_y: 8,
+            bank: 5,
+        };
+        assert_eq!(mapper.cartridge_video().unwrap().split, Some(split));
+
+        // CPU RAM: no nametable, no split
+        mapper.cpu_write(&rom, 0x5104, 2);
+        let video = mapper.cartridge_video().unwrap();
+        assert_eq!(video.page(2)[0], 0);
+        assert_eq!(video.split, None);
+        assert!(!video.extended_attributes);
+    }
+
+    #[test]
+    fn test_nametables_on_the_ppu() {
+        use crate::bus::{Bus, CpuBus};
+        use crate::ppu::ppu::NesPPU;
+
+        let mut rom = test_ines_rom::banked_rom(5, 16, PRG_BANK, 64, CHR_BANK);
+        rom.mapper = 5;
+        let mut bus = Bus::<NesPPU>::new(rom);
+        // $2000 - vram, $2400 - ExRAM, $2800 - fill, $2C00 - vram
+        bus.write(0x5105, 0b00_11_10_00);
+        bus.write(0x5106, 0x17);
+        bus.write(0x5c01, 0x42);
+        assert_eq!(bus.ppu().read_nametable(0x2401), 0x42);
+        assert_eq!(bus.ppu().read_nametable(0x2801), 0x17);
+
+        bus.write(0x2006, 0x24);
+        bus.write(0x2006, 0x02);
+        bus.write(0x2007, 0x43);
+        assert_eq!(bus.read(0x5c02), 0);
+        bus.write(0x5104, 2);
+        assert_eq!(bus.read(0x5c02), 0x43);
+        // ExRAM is CPU RAM now, the PPU sees zeros
+        assert_eq!(bus.ppu().read_nametable(0x2402), 0);
+        assert!(bus.take_error().is_none());
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let rom = test_ines_rom::banked_rom(5, 16, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Mmc5::new(&rom);
+        mapper.cpu_write(&rom, 0x5203, 3);
+        mapper.cpu_write(&rom, 0x5204, 0x80);
+        for line in 0..3 {
+            mapper.scanline(line, true);
+            assert!(!mapper.irq());
+        }
+        assert_eq!(mapper.cpu_read(&rom, 0x5204), Some(0x40));
+        mapper.scanline(3, true);
+        assert!(mapper.irq());
+        assert_eq!(mapper.cpu_read(&rom, 0x5204), Some(0xc0));
+        assert!(!mapper.irq());
+
+        mapper.scanline(240, true);
+        assert_eq!(mapper.cpu_read(&rom, 0x5204), Some(0));
+        // rendering off: no counting
+        for line in 0..10 {
+            mapper.scanline(line, false);
+        }
+        assert!(!mapper.irq());
+    }
+
+    #[test]
+    fn test_scanlines_from_the_ppu() {
+        use crate::bus::Bus;
+        use crate::ppu::ppu::NesPPU;
+
+        let mut rom = test_ines_rom::banked_rom(5, 16, PRG_BANK, 64, CHR_BANK);
+        rom.mapper = 5;
+        let mut bus = Bus::<NesPPU>::new(rom);
+        bus.write(0x2001, 0b0000_1000);
+        bus.write(0x5203, 10);
+        bus.write(0x5204, 0x80);
+        // a bit more than a scanline per tick
+        for _ in 0..10 {
+            bus.tick(114);
+        }
+        assert!(!bus.poll_irq_status());
+        assert_eq!(bus.read(0x5204), 0x40);
+        bus.tick(114);
+        // the cpu sees the IRQ line, reading the status acknowledges it
+        assert!(bus.poll_irq_status());
+        assert_eq!(bus.read(0x5204), 0xc0);
+        bus.tick(1);
+        assert!(!bus.poll_irq_status());
+    }
+
+    #[cfg(feature = "save-state")]
+    #[test]
+    fn test_state() {
+        let rom = test_ines_rom::banked_rom(5, 16, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Mmc5::new(&rom);
+        mapper.cpu_write(&rom, 0x5105, 0x44);
+        mapper.cpu_write(&rom, 0x5130, 1);
+        mapper.cpu_write(&rom, 0x5127, 2);
+        mapper.cpu_write(&rom, 0x5104, 2);
+        mapper.cpu_write(&rom, 0x5fff, 0x42);
+        let state = mapper.save_state();
+
+        let mut loaded = Mmc5::new(&rom);
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.save_state(), state);
+        assert_eq!(loaded.chr_banks[7], 0x102);
+        assert_eq!(loaded.mirroring(), Some(Mirroring::VERTICAL));
+        assert_eq!(loaded.cpu_read(&rom, 0x5fff), Some(0x42));
+        assert!(loaded.load_state(&state[1..]).is_err());
+    }
+}
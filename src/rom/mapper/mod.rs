@@ -0,0 +1,155 @@
+// Cartridge boards: the mapper sits between the consoles' buses and the rom chips, switches
+// banks and may add registers, RAM and interrupts. https://wiki.nesdev.com/w/index.php/Mapper
+//
+// The rom data stays in `Rom`, a mapper keeps its registers (and its own RAM) and maps the
+// addresses of both buses onto it:
+// - CPU: $4020-$5FFF and $8000-$FFFF. $6000-$7FFF is the 8KB of PRG RAM the bus gives every rom,
+//   unless the board puts something else there (`prg_ram_read`/`prg_ram_write`);
+// - PPU: the pattern tables, $0000-$1FFF. The PPU draws from an 8KB copy of them, the bus
+//   fetches it again when `take_chr_switched` says the banks changed. Carts without CHR ROM
+//   have CHR RAM, the board keeps it (see `chr::Chr`). Nametable pages and background
+//   features on the board go to the PPU the same way (`cartridge_video`).
+//
+// A new board implements `Mapper` and gets a line in `MAPPERS`, the bus doesn't change.
+mod axrom;
+mod bandai;
+mod chr;
+mod cnrom;
+mod gxrom;
+mod mmc5;
+mod nrom;
+mod vrc6;
+
+use super::eeprom::Eeprom;
+use super::{Mirroring, Rom};
+use crate::audio::ExpansionAudio;
+use crate::ppu::cartridge::CartridgeVideo;
+use crate::prelude::*;
+pub use axrom::Axrom;
+pub use bandai::Bandai;
+pub use cnrom::Cnrom;
+pub use gxrom::Gxrom;
+pub use mmc5::Mmc5;
+pub use nrom::Nrom;
+pub use vrc6::Vrc6;
+
+pub trait Mapper: Send {
+    /// None - nothing drives the data bus (open bus)
+    fn cpu_read(&mut self, rom: &Rom, addr: u16) -> Option<u8>;
+    /// false - the board ignores the write, e.g. ROM without registers behind it
+    fn cpu_write(&mut self, rom: &Rom, addr: u16, data: u8) -> bool;
+    /// $6000-$7FFF on the boards with registers or a serial EEPROM there, None - the PRG RAM
+    fn prg_ram_read(&mut self, _addr: u16) -> Option<u8> {
+        None
+    }
+    /// true - the board took the write, the PRG RAM is left as it is
+    fn prg_ram_write(&mut self, _addr: u16, _data: u8) -> bool {
+        false
+    }
+    fn ppu_read(&mut self, rom: &Rom, addr: u16) -> u8;
+    /// false - CHR ROM. $2000-$2FFF - a nametable page on the board (`Mirroring::CUSTOM`)
+    fn ppu_write(&mut self, rom: &Rom, addr: u16, data: u8) -> bool;
+    /// Nametable layout set by the board, None - the solder pads (the rom header)
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+    /// The PPU banks were switched since the last call
+    fn take_chr_switched(&mut self) -> bool {
+        false
+    }
+    /// Nametable pages, extended attributes and the split of the board (MMC5)
+    fn cartridge_video(&self) -> Option<CartridgeVideo> {
+        None
+    }
+    /// `cartridge_video` changed since the last call
+    fn take_video_changed(&mut self) -> bool {
+        false
+    }
+    /// The bank the register at `addr` selects, None - not a bank register. The bus sends
+    /// `Event::MapperBankSwitched` with it after the register writes
+    fn bank_register(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+    /// Every CPU tick, for the boards counting cycles
+    fn tick(&mut self, _cycles: u16) {}
+    /// The PPU moved to `line`, for the boards counting scanlines. `rendering` - the
+    /// background or the sprites are on
+    fn scanline(&mut self, _line: usize, _rendering: bool) {}
+    /// IRQ line state, sampled by the bus on every tick
+    fn irq(&self) -> bool {
+        false
+    }
+    /// Sound channels on the board
+    // todo: not mixed, there is no APU to mix them with
+    fn expansion_audio(&mut self) -> Option<&mut dyn ExpansionAudio> {
+        None
+    }
+    /// The battery RAM when the board has more than the 8KB of the bus (MMC5)
+    fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+    /// Save memory of the boards without battery RAM, the frontend persists it
+    fn eeprom(&mut self) -> Option<&mut Eeprom> {
+        None
+    }
+    /// Power on state of the registers, the RAM is kept (battery backed on some boards)
+    fn power_on(&mut self) {}
+    /// Registers and RAM, the rom data is not included
+    #[cfg(feature = "save-state")]
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    #[cfg(feature = "save-state")]
+    fn load_state(&mut self, _data: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+type Constructor = fn(&Rom) -> Box<dyn Mapper>;
+
+// iNES mapper number -> board
+const MAPPERS: &[(u8, Constructor)] = &[
+    (0, |rom| Box::new(Nrom::new(rom))),
+    (3, |rom| Box::new(Cnrom::new(rom))),
+    (5, |rom| Box::new(Mmc5::new(rom))),
+    (7, |rom| Box::new(Axrom::new(rom))),
+    (11, |rom| Box::new(Gxrom::new(rom))),
+    (16, |rom| Box::new(Bandai::new(rom))),
+    (24, |rom| Box::new(Vrc6::new(rom))),
+    (26, |rom| Box::new(Vrc6::new(rom))),
+    (66, |rom| Box::new(Gxrom::new(rom))),
+    (159, |rom| Box::new(Bandai::new(rom))),
+];
+
+/// The board of `rom.mapper`. The ones not implemented yet run as NROM: the game starts, but
+/// likely breaks on the first bank switch
+pub fn create(rom: &Rom) -> Box<dyn Mapper> {
+    match MAPPERS.iter().find(|(number, _)| *number == rom.mapper) {
+        Some((_, new)) => new(rom),
+        None => Box::new(Nrom::new(rom)),
+    }
+}
+
+pub fn is_supported(mapper: u8) -> bool {
+    MAPPERS.iter().any(|(number, _)| *number == mapper)
+}
+
+/// The pattern tables as the PPU sees them now
+pub fn chr_window(mapper: &mut dyn Mapper, rom: &Rom) -> Vec<u8> {
+    (0..0x2000).map(|addr| mapper.ppu_read(rom, addr)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test_ines_rom;
+
+    #[test]
+    fn test_registry() {
+        assert!(is_supported(0));
+        assert!(!is_supported(255));
+        let mut rom = test_ines_rom::test_rom();
+        rom.mapper = 255;
+        assert_eq!(create(&rom).cpu_read(&rom, 0x8000), Some(rom.prg_rom[0]));
+    }
+}
@@ -0,0 +1,79 @@
+// 16 or 32KB of PRG ROM at $8000 (16KB is mirrored at $C000), 8KB of CHR ROM or RAM, no
+// registers. https://wiki.nesdev.com/w/index.php/NROM
+use super::chr::Chr;
+use super::Mapper;
+use crate::rom::Rom;
+
+pub struct Nrom {
+    chr: Chr,
+}
+
+impl Nrom {
+    pub fn new(rom: &Rom) -> Self {
+        Nrom { chr: Chr::new(rom) }
+    }
+}
+
+/// $8000-$FFFF of the boards without PRG banking
+pub(super) fn fixed_prg(rom: &Rom, addr: u16) -> Option<u8> {
+    match addr {
+        0x8000..=0xffff => {
+            let offset = (addr - 0x8000) as usize % rom.prg_rom.len();
+            Some(rom.prg_rom[offset])
+        }
+        _ => None,
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&mut self, rom: &Rom, addr: u16) -> Option<u8> {
+        fixed_prg(rom, addr)
+    }
+
+    fn cpu_write(&mut self, _rom: &Rom, _addr: u16, _data: u8) -> bool {
+        false
+    }
+
+    fn ppu_read(&mut self, rom: &Rom, addr: u16) -> u8 {
+        self.chr.read(rom, addr as usize)
+    }
+
+    fn ppu_write(&mut self, _rom: &Rom, addr: u16, data: u8) -> bool {
+        self.chr.write(addr as usize, data)
+    }
+
+    #[cfg(feature = "save-state")]
+    fn save_state(&self) -> Vec<u8> {
+        self.chr.ram().to_vec()
+    }
+
+    #[cfg(feature = "save-state")]
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        if self.chr.load_ram(data) {
+            Ok(())
+        } else {
+            Err("corrupted NROM state".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::mapper::chr_window;
+    use crate::rom::test_ines_rom;
+
+    #[test]
+    fn test_nrom() {
+        let mut rom = test_ines_rom::test_rom();
+        rom.prg_rom = (0..0x4000).map(|i| (i >> 8) as u8).collect();
+        rom.chr_rom[0x1fff] = 0x42;
+        let mut mapper = Nrom::new(&rom);
+        assert_eq!(mapper.cpu_read(&rom, 0x8100), Some(0x01));
+        assert_eq!(mapper.cpu_read(&rom, 0xc100), Some(0x01));
+        assert_eq!(mapper.cpu_read(&rom, 0x5000), None);
+        assert!(!mapper.cpu_write(&rom, 0x8000, 1));
+        assert_eq!(chr_window(&mut mapper, &rom)[0x1fff], 0x42);
+        assert!(!mapper.ppu_write(&rom, 0, 1));
+    }
+}
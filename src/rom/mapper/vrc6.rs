@@ -0,0 +1,342 @@
+// Konami VRC6: a 16KB and an 8KB PRG bank, 1KB CHR banks, a CPU cycle / scanline IRQ counter
+// and three extra sound channels (Akumajou Densetsu, Madara, Esper Dream 2).
+// https://wiki.nesdev.com/w/index.php/VRC6
+//
+// Mapper 24 is VRC6a, 26 is VRC6b: the same chip with the A0 and A1 lines swapped.
+// todo: the CHR modes of $B003 other than 0 (2KB and mixed banks, nametables from CHR ROM)
+// todo: the sound channels are not heard, there is no APU to mix `expansion_audio` with
+use super::chr::Chr;
+use super::Mapper;
+use crate::audio::vrc6::Vrc6Audio;
+use crate::audio::ExpansionAudio;
+use crate::rom::{Mirroring, Rom};
+
+const PRG_BANK: usize = 0x2000;
+const CHR_BANK: usize = 0x400;
+// PPU dots per scanline, the prescaler takes 3 off per CPU cycle
+const PRESCALER: i16 = 341;
+// register bytes in a save state, followed by the CHR RAM
+#[cfg(feature = "save-state")]
+const REGISTERS_LEN: usize = 17;
+
+pub struct Vrc6 {
+    swapped_lines: bool,
+    // $8000, 16KB units
+    prg_16k: u8,
+    // $C000, 8KB units
+    prg_8k: u8,
+    // $D000-$E003
+    chr: [u8; 8],
+    // $B003: 2-3 - mirroring, 7 - PRG RAM enable
+    control: u8,
+    irq_latch: u8,
+    // $F001: 0 - enable after acknowledge, 1 - enable, 2 - CPU cycle mode
+    irq_control: u8,
+    irq_counter: u8,
+    prescaler: i16,
+    irq_pending: bool,
+    switched: bool,
+    chr_memory: Chr,
+    audio: Vrc6Audio,
+}
+
+impl Vrc6 {
+    pub fn new(rom: &Rom) -> Self {
+        Vrc6 {
+            swapped_lines: rom.mapper == 26,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr: [0; 8],
+            control: 0,
+            irq_latch: 0,
+            irq_control: 0,
+            irq_counter: 0,
+            prescaler: PRESCALER,
+            irq_pending: false,
+            switched: false,
+            chr_memory: Chr::new(rom),
+            audio: Vrc6Audio::new(),
+        }
+    }
+
+    // VRC6a addresses for both chips
+    fn register(&self, addr: u16) -> u16 {
+        if self.swapped_lines {
+            addr & 0xf000 | (addr & 0x01) << 1 | (addr & 0x02) >> 1
+        } else {
+            addr & 0xf003
+        }
+    }
+
+    fn clock_irq(&mut self) {
+        if self.irq_counter == 0xff {
+            self.irq_counter = self.irq_latch;
+            self.irq_pending = true;
+        } else {
+            self.irq_counter += 1;
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_read(&mut self, rom: &Rom, addr: u16) -> Option<u8> {
+        let offset = match addr {
+            0x8000..=0xbfff => {
+                (self.prg_16k & 0x0f) as usize * 2 * PRG_BANK + (addr - 0x8000) as usize
+            }
+            0xc000..=0xdfff => (self.prg_8k & 0x1f) as usize * PRG_BANK + (addr - 0xc000) as usize,
+            0xe000..=0xffff => rom.prg_rom.len() - PRG_BANK + (addr - 0xe000) as usize,
+            _ => return None,
+        };
+        Some(rom.prg_rom[offset % rom.prg_rom.len()])
+    }
+
+    fn cpu_write(&mut self, _rom: &Rom, addr: u16, data: u8) -> bool {
+        if addr < 0x8000 {
+            return false;
+        }
+        match self.register(addr) {
+            0x8000..=0x8003 => self.prg_16k = data,
+            reg @ 0x9000..=0xb002 => self.audio.write(reg, data),
+            0xb003 => self.control = data,
+            0xc000..=0xc003 => self.prg_8k = data,
+            reg @ 0xd000..=0xd003 | reg @ 0xe000..=0xe003 => {
+                let idx = (reg & 0x03) as usize + if reg >= 0xe000 { 4 } else { 0 };
+                self.chr[idx] = data;
+                self.switched = true;
+            }
+            0xf000 => self.irq_latch = data,
+            0xf001 => {
+                self.irq_control = data & 0x07;
+                if data & 0x02 != 0 {
+                    self.irq_counter = self.irq_latch;
+                    self.prescaler = PRESCALER;
+                }
+                self.irq_pending = false;
+            }
+            0xf002 => {
+                self.irq_pending = false;
+                // the enable bit gets the "enable after acknowledge" one
+                self.irq_control = self.irq_control & !0x02 | (self.irq_control & 0x01) << 1;
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn ppu_read(&mut self, rom: &Rom, addr: u16) -> u8 {
+        let bank = self.chr[addr as usize / CHR_BANK] as usize;
+        self.chr_memory
+            .read(rom, bank * CHR_BANK + addr as usize % CHR_BANK)
+    }
+
+    fn ppu_write(&mut self, _rom: &Rom, addr: u16, data: u8) -> bool {
+        let bank = self.chr[addr as usize / CHR_BANK] as usize;
+        self.chr_memory
+            .write(bank * CHR_BANK + addr as usize % CHR_BANK, data)
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match (self.control >> 2) & 0b11 {
+            0 => Mirroring::VERTICAL,
+            1 => Mirroring::HORIZONTAL,
+            2 => Mirroring::SINGLE_SCREEN_LOWER,
+            _ => Mirroring::SINGLE_SCREEN_UPPER,
+        })
+    }
+
+    fn take_chr_switched(&mut self) -> bool {
+        core::mem::replace(&mut self.switched, false)
+    }
+
+    fn bank_register(&self, addr: u16) -> Option<usize> {
+        match self.register(addr) {
+            0x8000..=0x8003 => Some((self.prg_16k & 0x0f) as usize),
+            0xc000..=0xc003 => Some((self.prg_8k & 0x1f) as usize),
+            reg @ 0xd000..=0xd003 | reg @ 0xe000..=0xe003 => {
+                Some(self.chr[(reg & 0x03) as usize + if reg >= 0xe000 { 4 } else { 0 }] as usize)
+            }
+            _ => None,
+        }
+    }
+
+    fn tick(&mut self, cycles: u16) {
+        if self.irq_control & 0x02 == 0 {
+            return;
+        }
+        for _ in 0..cycles {
+            if self.irq_control & 0x04 != 0 {
+                self.clock_irq();
+                continue;
+            }
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += PRESCALER;
+                self.clock_irq();
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn expansion_audio(&mut self) -> Option<&mut dyn ExpansionAudio> {
+        Some(&mut self.audio)
+    }
+
+    fn power_on(&mut self) {
+        self.irq_control = 0;
+        self.irq_pending = false;
+        self.audio = Vrc6Audio::new();
+    }
+
+    // the sound channels aren't saved, nothing plays them yet
+    #[cfg(feature = "save-state")]
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.prg_16k, self.prg_8k];
+        state.extend_from_slice(&self.chr);
+        state.extend_from_slice(&[
+            self.control,
+            self.irq_latch,
+            self.irq_control,
+            self.irq_counter,
+            self.irq_pending as u8,
+        ]);
+        state.extend_from_slice(&self.prescaler.to_le_bytes());
+        state.extend_from_slice(self.chr_memory.ram());
+        state
+    }
+
+    #[cfg(feature = "save-state")]
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() < REGISTERS_LEN {
+            return Err("corrupted VRC6 state".to_string());
+        }
+        let (registers, chr_ram) = data.split_at(REGISTERS_LEN);
+        match registers {
+            [prg_16k, prg_8k, chr @ .., control, latch, irq_control, counter, pending, p0, p1]
+                if self.chr_memory.load_ram(chr_ram) =>
+            {
+                self.prg_16k = *prg_16k;
+                self.prg_8k = *prg_8k;
+                self.chr.copy_from_slice(chr);
+                self.control = *control;
+                self.irq_latch = *latch;
+                self.irq_control = *irq_control;
+                self.irq_counter = *counter;
+                self.irq_pending = *pending != 0;
+                self.prescaler = i16::from_le_bytes([*p0, *p1]);
+                Ok(())
+            }
+            _ => Err("corrupted VRC6 state".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test_ines_rom;
+
+    #[test]
+    fn test_banks() {
+        let rom = test_ines_rom::banked_rom(24, 16, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Vrc6::new(&rom);
+        mapper.cpu_write(&rom, 0x8000, 3);
+        mapper.cpu_write(&rom, 0xc000, 9);
+        assert_eq!(mapper.cpu_read(&rom, 0x8000), Some(6));
+        assert_eq!(mapper.cpu_read(&rom, 0xbfff), Some(7));
+        assert_eq!(mapper.cpu_read(&rom, 0xc000), Some(9));
+        assert_eq!(mapper.cpu_read(&rom, 0xe000), Some(15));
+
+        mapper.cpu_write(&rom, 0xd001, 20);
+        mapper.cpu_write(&rom, 0xe003, 33);
+        assert!(mapper.take_chr_switched());
+        assert_eq!(mapper.ppu_read(&rom, 0x0400), 20);
+        assert_eq!(mapper.ppu_read(&rom, 0x1fff), 33);
+
+        mapper.cpu_write(&rom, 0xb003, 0b0000_0100);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::HORIZONTAL));
+        mapper.cpu_write(&rom, 0xb003, 0b0000_1100);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SINGLE_SCREEN_UPPER));
+        assert!(!mapper.cpu_write(&rom, 0x5000, 1));
+    }
+
+    #[test]
+    fn test_vrc6b_lines() {
+        let rom = test_ines_rom::banked_rom(26, 16, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Vrc6::new(&rom);
+        // $D001 on VRC6a
+        mapper.cpu_write(&rom, 0xd002, 20);
+        assert_eq!(mapper.ppu_read(&rom, 0x0400), 20);
+        // $B003 on VRC6a
+        mapper.cpu_write(&rom, 0xb003, 0b0000_0100);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::HORIZONTAL));
+    }
+
+    #[test]
+    fn test_irq_cycle_mode() {
+        let rom = test_ines_rom::banked_rom(24, 16, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Vrc6::new(&rom);
+        mapper.cpu_write(&rom, 0xf000, 0xfb);
+        mapper.cpu_write(&rom, 0xf001, 0b111);
+        mapper.tick(4);
+        assert!(!mapper.irq());
+        mapper.tick(1);
+        assert!(mapper.irq());
+
+        // acknowledge, the counter runs on: reloaded with the latch
+        mapper.cpu_write(&rom, 0xf002, 0);
+        assert!(!mapper.irq());
+        mapper.tick(5);
+        assert!(mapper.irq());
+
+        mapper.cpu_write(&rom, 0xf001, 0);
+        mapper.tick(100);
+        assert!(!mapper.irq());
+    }
+
+    #[test]
+    fn test_irq_scanline_mode() {
+        let rom = test_ines_rom::banked_rom(24, 16, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Vrc6::new(&rom);
+        mapper.cpu_write(&rom, 0xf000, 0xfe);
+        mapper.cpu_write(&rom, 0xf001, 0b010);
+        // 2 scanlines: 2 * 341 / 3
+        mapper.tick(227);
+        assert!(!mapper.irq());
+        mapper.tick(1);
+        assert!(mapper.irq());
+    }
+
+    #[test]
+    fn test_expansion_audio() {
+        let rom = test_ines_rom::banked_rom(24, 16, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Vrc6::new(&rom);
+        mapper.cpu_write(&rom, 0x9000, 0x8f);
+        mapper.cpu_write(&rom, 0x9002, 0x80);
+        let audio = mapper.expansion_audio().unwrap();
+        audio.tick();
+        assert_eq!(audio.output(), 15.0 / 61.0);
+    }
+
+    #[cfg(feature = "save-state")]
+    #[test]
+    fn test_state() {
+        let rom = test_ines_rom::banked_rom(24, 16, PRG_BANK, 64, CHR_BANK);
+        let mut mapper = Vrc6::new(&rom);
+        mapper.cpu_write(&rom, 0x8000, 3);
+        mapper.cpu_write(&rom, 0xe002, 7);
+        mapper.cpu_write(&rom, 0xf000, 0x10);
+        mapper.cpu_write(&rom, 0xf001, 0b010);
+        mapper.tick(50);
+        let state = mapper.save_state();
+
+        let mut loaded = Vrc6::new(&rom);
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.save_state(), state);
+        assert_eq!(loaded.ppu_read(&rom, 0x1800), 7);
+        assert!(loaded.load_state(&state[1..]).is_err());
+    }
+}
@@ -3,6 +3,17 @@
 //
 extern crate nom;
 
+pub mod battery;
+#[cfg(feature = "std")]
+pub mod db;
+pub mod eeprom;
+pub mod header;
+pub mod mapper;
+#[cfg(feature = "std")]
+pub mod settings;
+
+use crate::error::{RomError, RustnessError};
+use crate::prelude::*;
 use nom::{
     bytes::complete::tag, cond, error::make_error, error::ErrorKind, number::complete::be_u8, take,
     Err, IResult,
@@ -13,10 +24,18 @@ const PRG_ROM_PAGE_SIZE: usize = 16384;
 const CHR_ROM_PAGE_SIZE: usize = 8192;
 const PRG_RAM_PAGE_SIZE: usize = 8192;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[allow(non_camel_case_types)]
 pub enum Mirroring {
     VERTICAL,
     HORIZONTAL,
+    /// all 4 nametables show the first 1KB of vram (mapper controlled, e.g. AxROM)
+    SINGLE_SCREEN_LOWER,
+    /// all 4 nametables show the second 1KB of vram
+    SINGLE_SCREEN_UPPER,
+    /// the 1KB page every nametable shows, picked by the board (MMC5): 0 and 1 - the vram
+    /// halves, 2 and 3 - the pages on the board, see `ppu::cartridge`
+    CUSTOM([u8; 4]),
 }
 
 #[derive(Debug)]
@@ -24,6 +43,8 @@ pub struct Rom {
     pub trainer: Option<Vec<u8>>,
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
+    /// 8KB of CHR RAM on the carts without CHR ROM, the mapper allocates it. 0 otherwise
+    pub chr_ram: usize,
     pub mapper: u8,
     pub tv_format: TVFormat,
     pub ram_size: usize,
@@ -84,6 +105,10 @@ impl Rom {
     fn _load(input: &[u8]) -> IResult<&[u8], Rom> {
         let (input, _) = tag(MAGIC)(input)?;
         let (input, len_prg_rom) = be_u8(input)?;
+        // nothing to run, the bus expects at least one bank
+        if len_prg_rom == 0 {
+            return Err(Err::Failure(make_error(input, ErrorKind::Verify)));
+        }
         let (input, len_chr_rom) = be_u8(input)?;
         let (input, _byte6) = be_u8(input)?;
 
@@ -115,6 +140,11 @@ impl Rom {
                 trainer: trainer.map(|t| t.to_vec()),
                 prg_rom: prg_rom.to_vec(),
                 chr_rom: chr_rom.to_vec(),
+                chr_ram: if len_chr_rom == 0 {
+                    CHR_ROM_PAGE_SIZE
+                } else {
+                    0
+                },
                 mapper: mapper,
                 tv_format: (if pal == 1 {
                     TVFormat::PAL
@@ -127,17 +157,44 @@ impl Rom {
         ))
     }
 
-    pub fn load(input: &[u8]) -> Result<Rom, &str> {
-        match Rom::_load(input) {
-            IResult::Ok((_, rom)) => Result::Ok(rom),
-            IResult::Err(nom::Err::Error((_, _kind))) => Result::Err("failed to read file"),
+    /// CRC32 of PRG+CHR data (without iNES header), used to identify the game
+    pub fn crc32(&self) -> u32 {
+        crc32_update(crc32_update(0, &self.prg_rom), &self.chr_rom)
+    }
+
+    pub fn load(input: &[u8]) -> Result<Rom, RustnessError> {
+        let error = match Rom::_load(input) {
+            IResult::Ok((_, rom)) => return Result::Ok(rom),
             IResult::Err(nom::Err::Failure((_, kind))) if kind == ErrorKind::OneOf => {
-                Result::Err("NES2.0 format is not supported")
+                RomError::Nes2NotSupported
             }
-            IResult::Err(nom::Err::Failure((_, _kind))) => Result::Err("failed to read file"),
-            IResult::Err(nom::Err::Incomplete(_)) => Result::Err("Unexpected end of file"),
+            IResult::Err(nom::Err::Failure((_, ErrorKind::Verify))) => RomError::NoPrgRom,
+            IResult::Err(nom::Err::Error((_, _kind))) => RomError::NotINes,
+            IResult::Err(nom::Err::Failure((_, _kind))) => RomError::NotINes,
+            IResult::Err(nom::Err::Incomplete(_)) => RomError::UnexpectedEof,
+        };
+        Result::Err(error.into())
+    }
+}
+
+// https://en.wikipedia.org/wiki/Cyclic_redundancy_check (CRC-32/ISO-HDLC, the one zip uses)
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
         }
     }
+    !crc
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
 }
 
 #[cfg(test)]
@@ -183,6 +240,30 @@ pub mod test_ines_rom {
         Rom::load(&test_rom).unwrap()
     }
 
+    /// A rom for the bank switching tests: `prg_banks` banks of `prg_bank` bytes and
+    /// `chr_banks` of `chr_bank` bytes, every byte is its bank number. No CHR banks - 8KB of
+    /// CHR RAM
+    pub fn banked_rom(
+        mapper: u8,
+        prg_banks: usize,
+        prg_bank: usize,
+        chr_banks: usize,
+        chr_bank: usize,
+    ) -> Rom {
+        let mut rom = test_rom();
+        rom.mapper = mapper;
+        rom.prg_rom = (0..prg_banks * prg_bank)
+            .map(|i| (i / prg_bank) as u8)
+            .collect();
+        rom.chr_rom = (0..chr_banks * chr_bank)
+            .map(|i| (i / chr_bank) as u8)
+            .collect();
+        if chr_banks == 0 {
+            rom.chr_ram = CHR_ROM_PAGE_SIZE;
+        }
+        rom
+    }
+
     #[test]
     fn test() {
         let test_rom = create_rom(TestRom {
@@ -202,6 +283,34 @@ pub mod test_ines_rom {
         assert_eq!(rom.mapper, 3);
         assert_eq!(rom.ram_size, 0);
         assert_eq!(rom.rom_flags.bits, 0b0001);
+        assert_eq!(rom.chr_ram, 0);
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x00, 0x01, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+
+        let rom = Rom::load(&test_rom).unwrap();
+        assert!(rom.chr_rom.is_empty());
+        assert_eq!(rom.chr_ram, CHR_ROM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32_update(crc32(b"1234"), b"56789"), 0xCBF43926);
+
+        let rom = test_rom();
+        let mut data = rom.prg_rom.clone();
+        data.extend(&rom.chr_rom);
+        assert_eq!(rom.crc32(), crc32(&data));
     }
 
     #[test]
@@ -218,7 +327,42 @@ pub mod test_ines_rom {
         let rom = Rom::load(&test_rom);
         match rom {
             Result::Ok(_) => assert!(false, "should not load rom"),
-            Result::Err(str) => assert_eq!(str, "Unexpected end of file"),
+            Result::Err(e) => assert_eq!(e, RustnessError::Rom(RomError::UnexpectedEof)),
+        }
+    }
+
+    #[test]
+    fn test_pathological_headers() {
+        let header = |prg, chr, flags| {
+            vec![0x4E, 0x45, 0x53, 0x1A, prg, chr, flags, 00, 00, 00, 00, 00, 00, 00, 00, 00]
+        };
+        // more pages than data
+        let mut data = header(0xff, 0xff, 0);
+        data.extend(vec![1; PRG_ROM_PAGE_SIZE]);
+        assert_eq!(Rom::load(&data).unwrap_err(), RustnessError::Rom(RomError::UnexpectedEof));
+
+        // trainer flag without a trainer
+        let data = header(1, 0, 0b100);
+        assert_eq!(Rom::load(&data).unwrap_err(), RustnessError::Rom(RomError::UnexpectedEof));
+
+        let mut data = header(0, 1, 0);
+        data.extend(vec![1; CHR_ROM_PAGE_SIZE]);
+        assert_eq!(Rom::load(&data).unwrap_err(), RustnessError::Rom(RomError::NoPrgRom));
+    }
+
+    #[test]
+    fn test_truncated_rom_is_an_error() {
+        let rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x35, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: Some(vec![3; 512]),
+            pgp_rom: vec![1; 1 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+        });
+        assert!(Rom::load(&rom).is_ok());
+        for len in (0..rom.len()).step_by(97) {
+            assert!(Rom::load(&rom[..len]).is_err(), "{} bytes", len);
         }
     }
 
@@ -235,7 +379,7 @@ pub mod test_ines_rom {
         let rom = Rom::load(&test_rom);
         match rom {
             Result::Ok(_) => assert!(false, "should not load rom"),
-            Result::Err(str) => assert_eq!(str, "NES2.0 format is not supported"),
+            Result::Err(e) => assert_eq!(e.to_string(), "rom: NES2.0 format is not supported"),
         }
     }
 }
@@ -0,0 +1,146 @@
+// Per-game settings keyed by CRC32 of PRG+CHR data, same as the game database: options that
+// suit some games and not others.
+//
+// File format, one game per line:
+//   # Gradius: flicker off
+//   3337EC46 sprite_limit=off
+//
+// Settings:
+//   sprite_limit=on|off  8 sprites per scanline as on the hardware (on by default)
+use crate::emulator::Config;
+use crate::rom::Rom;
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Settings {
+    pub sprite_limit: Option<bool>,
+}
+
+impl Settings {
+    /// Overrides the options set for the game, the rest of `config` stays
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(sprite_limit) = self.sprite_limit {
+            config.sprite_limit = sprite_limit;
+        }
+    }
+}
+
+pub struct GameSettings {
+    games: HashMap<u32, Settings>,
+}
+
+fn parse_switch(value: &str) -> Option<bool> {
+    match value {
+        "on" | "true" => Some(true),
+        "off" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+impl GameSettings {
+    pub fn new() -> Self {
+        GameSettings {
+            games: HashMap::new(),
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<GameSettings, String> {
+        let mut settings = GameSettings::new();
+        for (line_num, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let crc = parts.next().unwrap_or("");
+            let crc = u32::from_str_radix(crc, 16)
+                .map_err(|_| format!("line {}: bad crc32 '{}'", line_num + 1, crc))?;
+            let game = settings.games.entry(crc).or_default();
+            for setting in parts {
+                let mut key_value = setting.splitn(2, '=');
+                let key = key_value.next().unwrap_or("");
+                let value = key_value.next().unwrap_or("");
+                match key {
+                    "sprite_limit" => {
+                        game.sprite_limit = Some(parse_switch(value).ok_or_else(|| {
+                            format!("line {}: expected on or off, got '{}'", line_num + 1, value)
+                        })?)
+                    }
+                    _ => return Err(format!("line {}: unknown setting '{}'", line_num + 1, key)),
+                }
+            }
+        }
+        Ok(settings)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load(path: &Path) -> Result<GameSettings, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        GameSettings::parse(&content)
+    }
+
+    pub fn insert(&mut self, crc32: u32, settings: Settings) {
+        self.games.insert(crc32, settings);
+    }
+
+    /// Settings of the game, all unset for unknown games
+    pub fn lookup(&self, rom: &Rom) -> Settings {
+        self.games.get(&rom.crc32()).copied().unwrap_or_default()
+    }
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        GameSettings::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::test_ines_rom;
+
+    #[test]
+    fn test_parse() {
+        let settings =
+            GameSettings::parse("# comment\n\n3337ec46 sprite_limit=off\nDEADBEEF\n").unwrap();
+        assert_eq!(settings.games[&0x3337ec46].sprite_limit, Some(false));
+        assert_eq!(settings.games[&0xdeadbeef], Settings::default());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            GameSettings::parse("3337ec46 sprite_limit=maybe").err(),
+            Some(String::from("line 1: expected on or off, got 'maybe'"))
+        );
+        assert_eq!(
+            GameSettings::parse("\n3337ec46 overclock=on").err(),
+            Some(String::from("line 2: unknown setting 'overclock'"))
+        );
+        assert!(GameSettings::parse("zzz sprite_limit=on").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let rom = test_ines_rom::test_rom();
+        let mut settings = GameSettings::new();
+        let mut config = Config::default();
+        settings.lookup(&rom).apply(&mut config);
+        assert!(config.sprite_limit);
+
+        settings.insert(
+            rom.crc32(),
+            Settings {
+                sprite_limit: Some(false),
+            },
+        );
+        settings.lookup(&rom).apply(&mut config);
+        assert!(!config.sprite_limit);
+    }
+}
@@ -0,0 +1,98 @@
+// Run-ahead: hides the input lag games have on their own. Most games read the controllers in
+// vblank and show the result a frame (or more) later; run-ahead emulates that many frames past
+// the current one with the same buttons held, shows the picture of the last one and goes back.
+//
+//   let run_ahead = RunAhead::new(1);
+//   loop {
+//       let picture = run_ahead.run_frame(&mut emulator, &Inputs::new(keyboard.buttons()))?;
+//   }
+//
+// The button presses show up `frames` frames earlier, at the cost of emulating `frames + 1`
+// frames per displayed one. Going further than the game's own lag skips frames of the response.
+// The speculative frames are thrown away: the machine state is the one of the real frames only,
+// but event subscribers and `frame_count` see the speculative frames too.
+use crate::emulator::{Emulator, Inputs};
+use crate::screen::frame::Frame;
+
+pub struct RunAhead {
+    frames: usize,
+}
+
+impl RunAhead {
+    /// `frames`: how many frames to run ahead, 0 is a normal run
+    pub fn new(frames: usize) -> Self {
+        RunAhead { frames }
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Runs the next frame and returns the picture `frames` frames ahead of it
+    pub fn run_frame<'a>(
+        &self,
+        emulator: &'a mut Emulator,
+        inputs: &Inputs,
+    ) -> Result<&'a Frame, String> {
+        emulator.run_frame(inputs).map_err(|e| e.to_string())?;
+        if self.frames == 0 {
+            return Ok(emulator.frame());
+        }
+        let snapshot = emulator.snapshot();
+        for _ in 0..self.frames {
+            emulator.run_frame(inputs).map_err(|e| e.to_string())?;
+        }
+        // the frame buffer is not a part of the state, it keeps the picture from ahead
+        emulator.restore(&snapshot)?;
+        Ok(emulator.frame())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::cpu::CPU;
+    use crate::input::JoypadButton;
+    use crate::rom::test_ines_rom;
+
+    // the backdrop color changes every frame
+    fn emulator() -> Emulator {
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x0600)
+            .build();
+        // wait for vblank; INC $10; $3F00-$3F03 = $10; PPUMASK = background on
+        let program = CPU::transform(
+            "ad 02 20 10 fb e6 10 \
+             a9 3f 8d 06 20 a9 00 8d 06 20 \
+             a5 10 8d 07 20 8d 07 20 8d 07 20 8d 07 20 \
+             a9 08 8d 01 20 4c 00 06",
+        );
+        for (idx, byte) in program.iter().enumerate() {
+            emulator.cpu_mut().bus.write(0x0600 + idx as u16, *byte);
+        }
+        emulator
+    }
+
+    #[test]
+    fn test_run_ahead() {
+        let inputs = Inputs::new(JoypadButton::empty());
+        let mut normal = emulator();
+        let mut ahead = emulator();
+        let run_ahead = RunAhead::new(1);
+
+        normal.run_frame(&inputs).unwrap();
+        for _ in 0..5 {
+            let picture = run_ahead.run_frame(&mut ahead, &inputs).unwrap().clone();
+            let current = normal.frame().clone();
+            normal.run_frame(&inputs).unwrap();
+            assert!(picture == *normal.frame());
+            assert!(current != *normal.frame());
+        }
+        // the speculative frames left no trace
+        let mut reference = emulator();
+        for _ in 0..5 {
+            reference.run_frame(&inputs).unwrap();
+        }
+        assert_eq!(ahead.snapshot(), reference.snapshot());
+    }
+}
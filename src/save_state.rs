@@ -0,0 +1,428 @@
+// Save state slots, stored next to the rom: game.nes -> game.state1 .. game.state10
+// and the auto-resume state (game.resume), saved on exit and offered on the next launch.
+//
+// File format: "RNSS", format version (u16 LE), crc32 of the rom prg+chr data (u32 LE),
+// then the bincode encoded machine state (see `CPU::save_state`), compressed with `compress`
+// (a zero-run codec of its own rather than zstd, see "State compression" below)
+//
+// Snapshots (`CPU::snapshot`/`CPU::restore`) are the same state uncompressed, in memory: meant
+// to be taken every frame by tools that branch execution (TAS search, AI training, run-ahead).
+// `Snapshot::to_bytes`/`from_bytes` convert them to and from the file format, for embedders
+// that keep states in their own storage (browser storage, libretro serialization buffers):
+//
+//   let data = emulator.snapshot().to_bytes();
+//   emulator.restore(&Snapshot::from_bytes(&data)?)?;
+//
+// Determinism: restoring a snapshot and feeding the same input at the same points gives exactly
+// the same execution (registers, memory, PPU state, cycle counts and rendered frames).
+// The emulation core doesn't use the wall clock, randomness, threads or HashMap iteration order.
+// Two runs of the same rom with the same input give the same `Frame::hash` sequence. Caveats:
+// - input is set by the frontend once per frame (at vblank, see `Bus::poll_frame_complete`);
+//   it's not part of the state and has to be replayed by the caller the same way;
+// - the frame buffer is not part of the state: after a restore in the middle of a frame the
+//   scanlines above the current one keep the old picture until the next frame;
+// - event subscribers and the rom itself are not included, snapshots can only be restored into
+//   a machine created with the same rom.
+#[cfg(feature = "std")]
+use crate::bus::CpuBus;
+#[cfg(feature = "std")]
+use crate::cpu::cpu::CPU;
+use std::convert::TryInto;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+pub const SLOTS: u8 = 10;
+
+const MAGIC: &[u8; 4] = b"RNSS";
+/// Has to be bumped on any change of the serialized state (cpu, bus, ppu, controllers)
+pub const VERSION: u16 = 8;
+const HEADER_LEN: usize = 10;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Snapshot {
+    pub(crate) rom_crc32: u32,
+    pub(crate) state: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Same bytes as `CPU::save_state`: the header and the compressed state
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Header::new(self.rom_crc32).to_bytes();
+        data.extend(compress(&self.state));
+        data
+    }
+
+    /// Reads `to_bytes` output or a save state file, the rom is checked on restore
+    pub fn from_bytes(data: &[u8]) -> Result<Snapshot, String> {
+        let (header, data) = Header::parse(data)?;
+        header.check_version()?;
+        Ok(Snapshot {
+            rom_crc32: header.rom_crc32,
+            state: decompress(data)?,
+        })
+    }
+
+    /// Checks that the snapshot was taken with this rom
+    pub fn validate(&self, rom_crc32: u32) -> Result<(), String> {
+        Header::new(self.rom_crc32).validate(rom_crc32)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Header {
+    pub version: u16,
+    pub rom_crc32: u32,
+}
+
+impl Header {
+    pub fn new(rom_crc32: u32) -> Self {
+        Header {
+            version: VERSION,
+            rom_crc32,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&self.version.to_le_bytes());
+        data.extend_from_slice(&self.rom_crc32.to_le_bytes());
+        data
+    }
+
+    /// The header and the state that follows it
+    pub fn parse(data: &[u8]) -> Result<(Header, &[u8]), String> {
+        if data.len() < HEADER_LEN || &data[0..4] != MAGIC {
+            return Err("not a save state file".to_string());
+        }
+        let header = Header {
+            version: u16::from_le_bytes(data[4..6].try_into().unwrap()),
+            rom_crc32: u32::from_le_bytes(data[6..10].try_into().unwrap()),
+        };
+        Ok((header, &data[HEADER_LEN..]))
+    }
+
+    pub fn check_version(&self) -> Result<(), String> {
+        if self.version != VERSION {
+            return Err(format!(
+                "save state format version {} is not supported (expected {}): it was made by a different version of the emulator",
+                self.version, VERSION
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that the state can be loaded into this version of the emulator with this rom
+    pub fn validate(&self, rom_crc32: u32) -> Result<(), String> {
+        self.check_version()?;
+        if self.rom_crc32 != rom_crc32 {
+            return Err(format!(
+                "save state was made with a different rom (crc32: {:08X}, loaded rom crc32: {:08X})",
+                self.rom_crc32, rom_crc32
+            ));
+        }
+        Ok(())
+    }
+}
+
+// State compression. A state is mostly memory, and most of it doesn't change from one frame to
+// the next: the state is XORed with a base state (zeroes where nothing changed) and stored as
+// runs of zeroes and of literal bytes. Lengths are LEB128 varints:
+//   <state length> (<zero run> <literal run> <literal bytes>)*
+// Files use no base (all zeroes): RAM, VRAM and PRG RAM have long zero stretches. Rewind stores
+// every state as the delta from the next one, a frame apart that's a few hundred bytes.
+// It takes the place of zstd: a general purpose compressor would be a C dependency of the
+// library (zstd-sys) for states that are mostly zeroes anyway. Files made with it are not zstd
+// frames, the format version would have to be bumped to switch.
+
+// states are ~15KB, a corrupted length shouldn't allocate gigabytes
+const MAX_STATE_LEN: usize = 16 << 20;
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Result<usize, String> {
+    let mut value = 0usize;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos).ok_or("truncated compressed state")?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(String::from("corrupted compressed state"))
+}
+
+/// `state` as the difference from `base`, `delta_decode` with the same base gives it back
+pub fn delta_encode(base: &[u8], state: &[u8]) -> Vec<u8> {
+    let diff = |idx: usize| state[idx] ^ base.get(idx).copied().unwrap_or(0);
+    let mut out = Vec::new();
+    write_varint(&mut out, state.len());
+    let mut pos = 0;
+    while pos < state.len() {
+        let zeros_start = pos;
+        while pos < state.len() && diff(pos) == 0 {
+            pos += 1;
+        }
+        let literal_start = pos;
+        // a single zero between literals is cheaper inline than as a new run
+        while pos < state.len() && (diff(pos) != 0 || (pos + 1 < state.len() && diff(pos + 1) != 0))
+        {
+            pos += 1;
+        }
+        write_varint(&mut out, literal_start - zeros_start);
+        write_varint(&mut out, pos - literal_start);
+        out.extend((literal_start..pos).map(diff));
+    }
+    out
+}
+
+pub fn delta_decode(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos)?;
+    if len > MAX_STATE_LEN {
+        return Err(String::from("corrupted compressed state"));
+    }
+    let mut state = Vec::with_capacity(len);
+    while state.len() < len {
+        let zeros = read_varint(delta, &mut pos)?;
+        let literals = read_varint(delta, &mut pos)?;
+        let literal_end = pos.checked_add(literals).filter(|end| *end <= delta.len());
+        let literal_end = literal_end.ok_or("truncated compressed state")?;
+        state
+            .len()
+            .checked_add(zeros)
+            .and_then(|end| end.checked_add(literals))
+            .filter(|end| *end <= len)
+            .ok_or("corrupted compressed state")?;
+        for _ in 0..zeros {
+            state.push(base.get(state.len()).copied().unwrap_or(0));
+        }
+        for byte in &delta[pos..literal_end] {
+            state.push(byte ^ base.get(state.len()).copied().unwrap_or(0));
+        }
+        pos = literal_end;
+    }
+    Ok(state)
+}
+
+/// Compressed state for the files
+pub fn compress(state: &[u8]) -> Vec<u8> {
+    delta_encode(&[], state)
+}
+
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    delta_decode(&[], data)
+}
+
+#[cfg(feature = "std")]
+pub fn slot_path(rom_path: &Path, slot: u8) -> PathBuf {
+    rom_path.with_extension(format!("state{}", slot))
+}
+
+#[cfg(feature = "std")]
+pub fn save_slot<B: CpuBus + ?Sized>(
+    cpu: &CPU<B>,
+    rom_path: &Path,
+    slot: u8,
+) -> Result<PathBuf, String> {
+    let path = slot_path(rom_path, slot);
+    let state = cpu.save_state()?;
+    fs::write(&path, state).map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+#[cfg(feature = "std")]
+pub fn load_slot<B: CpuBus + ?Sized>(
+    cpu: &mut CPU<B>,
+    rom_path: &Path,
+    slot: u8,
+) -> Result<PathBuf, String> {
+    let path = slot_path(rom_path, slot);
+    let state = fs::read(&path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    cpu.load_state(&state)?;
+    Ok(path)
+}
+
+#[cfg(feature = "std")]
+pub fn resume_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("resume")
+}
+
+#[cfg(feature = "std")]
+pub fn save_resume<B: CpuBus + ?Sized>(cpu: &CPU<B>, rom_path: &Path) -> Result<PathBuf, String> {
+    let path = resume_path(rom_path);
+    let state = cpu.save_state()?;
+    fs::write(&path, state).map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+/// State saved on the last exit, None if there is none for this rom: the file next to the rom
+/// could belong to a different dump or a patched version of the game
+#[cfg(feature = "std")]
+pub fn read_resume(rom_path: &Path, rom_crc32: u32) -> Result<Option<Vec<u8>>, String> {
+    let path = resume_path(rom_path);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data = fs::read(&path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let (header, _) = Header::parse(&data)?;
+    if header.rom_crc32 != rom_crc32 {
+        return Ok(None);
+    }
+    Ok(Some(data))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::MockBus;
+    use crate::cpu::cpu::CPU;
+    use crate::emulator::Emulator;
+    use crate::rom::test_ines_rom;
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_slot_path() {
+        assert_eq!(
+            slot_path(Path::new("roms/game.nes"), 1),
+            PathBuf::from("roms/game.state1")
+        );
+        assert_eq!(
+            slot_path(Path::new("game"), 10),
+            PathBuf::from("game.state10")
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_save_load_slot() {
+        let rom_path = std::env::temp_dir().join("rustness_test_save_load_slot.nes");
+        let mut cpu = CPU::new(Box::from(MockBus::new()));
+        cpu.program_counter = 0x1234;
+        cpu.bus.write(0x10, 0x42);
+
+        let path = save_slot(&cpu, &rom_path, 3).unwrap();
+        cpu.program_counter = 0;
+        cpu.bus.write(0x10, 0);
+
+        assert_eq!(load_slot(&mut cpu, &rom_path, 3), Ok(path.clone()));
+        assert_eq!(cpu.program_counter, 0x1234);
+        assert_eq!(cpu.bus.read(0x10), 0x42);
+        assert!(load_slot(&mut cpu, &rom_path, 4).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_header() {
+        let mut cpu = CPU::new(Box::from(MockBus::new()));
+        let mut state = cpu.save_state().unwrap();
+        // MockBus has no rom
+        let (header, _) = Header::parse(&state).unwrap();
+        assert_eq!(header, Header::new(0));
+        assert_eq!(&state[0..6], b"RNSS\x08\x00");
+
+        assert_eq!(
+            cpu.load_state(&state[..8]),
+            Err("not a save state file".to_string())
+        );
+        assert_eq!(
+            cpu.load_state(&[0; 64]),
+            Err("not a save state file".to_string())
+        );
+
+        state[6..10].copy_from_slice(&0x1234abcdu32.to_le_bytes());
+        assert_eq!(
+            cpu.load_state(&state),
+            Err("save state was made with a different rom (crc32: 1234ABCD, loaded rom crc32: 00000000)".to_string())
+        );
+
+        state[4] = 0x09;
+        assert!(cpu
+            .load_state(&state)
+            .unwrap_err()
+            .starts_with("save state format version 9 is not supported (expected 8)"));
+    }
+
+    #[test]
+    fn test_snapshot_bytes() {
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom()).build();
+        emulator.cpu_mut().bus.write(0x10, 0x42);
+        let data = emulator.snapshot().to_bytes();
+        assert_eq!(data, emulator.cpu().save_state().unwrap());
+
+        let snapshot = Snapshot::from_bytes(&data).unwrap();
+        assert_eq!(snapshot, emulator.snapshot());
+        emulator.cpu_mut().bus.write(0x10, 0);
+        emulator.restore(&snapshot).unwrap();
+        assert_eq!(emulator.cpu_mut().bus.read(0x10), 0x42);
+
+        // a different rom
+        let mut cpu = CPU::new(Box::from(MockBus::new()));
+        assert!(cpu.restore(&snapshot).is_err());
+        assert!(Snapshot::from_bytes(&data[..20]).is_err());
+    }
+
+    #[test]
+    fn test_compression() {
+        let base: Vec<u8> = (0..1000).map(|idx| (idx * 7) as u8).collect();
+        let mut state = base.clone();
+        state[10] ^= 1;
+        state[12] ^= 2;
+        state[500..510].copy_from_slice(&[0; 10]);
+        state.extend_from_slice(&[1, 2, 3]);
+
+        let delta = delta_encode(&base, &state);
+        assert!(delta.len() < 30, "{}", delta.len());
+        assert_eq!(delta_decode(&base, &delta), Ok(state.clone()));
+        // shorter than the base
+        let delta = delta_encode(&state, &base);
+        assert_eq!(delta_decode(&state, &delta), Ok(base.clone()));
+
+        let mut zeros = vec![0; 10_000];
+        zeros[5000] = 0x42;
+        // length, 5000 zeros, 1 literal and the byte, 4999 zeros, 0 literals
+        assert_eq!(compress(&zeros).len(), 2 + 2 + 1 + 1 + 2 + 1);
+        assert_eq!(decompress(&compress(&zeros)), Ok(zeros));
+        assert_eq!(
+            decompress(&[]),
+            Err(String::from("truncated compressed state"))
+        );
+        assert!(decompress(&compress(&base)[..100]).is_err());
+        // length 10, a zero run of usize::MAX
+        let mut huge = vec![10];
+        write_varint(&mut huge, usize::MAX);
+        huge.push(0);
+        assert_eq!(
+            decompress(&huge),
+            Err(String::from("corrupted compressed state"))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_resume() {
+        let rom_path = std::env::temp_dir().join("rustness_test_resume.nes");
+        let mut cpu = CPU::new(Box::from(MockBus::new()));
+        cpu.program_counter = 0x8057;
+        assert_eq!(read_resume(&rom_path, 0), Ok(None));
+
+        let path = save_resume(&cpu, &rom_path).unwrap();
+        assert_eq!(path, rom_path.with_extension("resume"));
+        // different rom at the same path
+        assert_eq!(read_resume(&rom_path, 0x1234abcd), Ok(None));
+
+        let state = read_resume(&rom_path, 0).unwrap().unwrap();
+        cpu.program_counter = 0;
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.program_counter, 0x8057);
+        fs::remove_file(path).unwrap();
+    }
+}
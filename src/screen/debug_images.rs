@@ -0,0 +1,163 @@
+// PPU memory as pictures, for debugging and documentation (`rustness dump`):
+//   pattern tables  256x128: $0000 and $1000 side by side, 16x16 tiles each
+//   nametables      512x480: $2000 $2400 / $2800 $2C00 as the CPU sees them (through the
+//                   mirroring), the scroll position isn't marked
+//   palettes        128x32: background palettes on the top row, sprite palettes below,
+//                   an 8x16 swatch per color
+use super::png;
+use super::tile;
+use crate::ppu::ppu::NesPPU;
+
+// palettes: swatch size, 16 colors per row
+const SWATCH_WIDTH: usize = 8;
+const SWATCH_HEIGHT: usize = 16;
+
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// RGB24
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    fn new(width: usize, height: usize) -> Self {
+        Image {
+            width,
+            height,
+            data: vec![0; width * height * 3],
+        }
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, (r, g, b): (u8, u8, u8)) {
+        let base = (y * self.width + x) * 3;
+        self.data[base..base + 3].copy_from_slice(&[r, g, b]);
+    }
+
+    fn draw_tile(&mut self, x: usize, y: usize, tile: &[u8], palette: &[(u8, u8, u8); 4]) {
+        for row_idx in 0..8 {
+            let row = tile::decode_row(tile[row_idx], tile[row_idx + 8]);
+            for col in 0..8 {
+                let color = palette[tile::pixel(row, col) as usize];
+                self.set_pixel(x + col, y + row_idx, color);
+            }
+        }
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        png::encode(self.width, self.height, &self.data)
+    }
+}
+
+/// Both pattern tables drawn with one of the 8 palettes (0-3 background, 4-7 sprites)
+pub fn pattern_tables(ppu: &NesPPU, palette: usize) -> Image {
+    let mut image = Image::new(256, 128);
+    let palette = &ppu.rgb_palettes[palette];
+    for table in 0..2 {
+        for idx in 0..256 {
+            let addr = table * 0x1000 + idx * 16;
+            if addr + 16 > ppu.chr_rom.len() {
+                break;
+            }
+            let tile = &ppu.chr_rom[addr..addr + 16];
+            image.draw_tile(table * 128 + idx % 16 * 8, idx / 16 * 8, tile, palette);
+        }
+    }
+    image
+}
+
+pub fn nametables(ppu: &NesPPU) -> Image {
+    let mut image = Image::new(512, 480);
+    let bank = ppu.ctrl.bknd_pattern_addr() as usize;
+    for nametable in 0..4u16 {
+        let base = 0x2000 + nametable * 0x400;
+        let byte = |addr: u16| ppu.read_nametable(addr);
+        let (left, top) = (nametable as usize % 2 * 256, nametable as usize / 2 * 240);
+        for idx in 0..960u16 {
+            let (column, row) = (idx as usize % 32, idx as usize / 32);
+            let attr = byte(base + 0x3c0 + (row / 4 * 8 + column / 4) as u16);
+            let shift = (row % 4 / 2 * 2 + column % 4 / 2) * 2;
+            let palette = &ppu.rgb_palettes[(attr >> shift & 0b11) as usize];
+            let addr = bank + byte(base + idx) as usize * 16;
+            // blank past the end of a small CHR
+            let tile = ppu.chr_rom.get(addr..addr + 16).unwrap_or(&[0; 16]);
+            image.draw_tile(left + column * 8, top + row * 8, tile, palette);
+        }
+    }
+    image
+}
+
+pub fn palettes(ppu: &NesPPU) -> Image {
+    let mut image = Image::new(16 * SWATCH_WIDTH, 2 * SWATCH_HEIGHT);
+    for (idx, palette) in ppu.rgb_palettes.iter().enumerate() {
+        for (color_idx, color) in palette.iter().enumerate() {
+            let left = (idx % 4 * 4 + color_idx) * SWATCH_WIDTH;
+            let top = idx / 4 * SWATCH_HEIGHT;
+            for y in top..top + SWATCH_HEIGHT {
+                for x in left..left + SWATCH_WIDTH {
+                    image.set_pixel(x, y, *color);
+                }
+            }
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::Mirroring;
+    use crate::screen::palette::SYSTEM_PALETTE;
+
+    #[test]
+    fn test_pattern_tables() {
+        let mut chr = vec![0; 0x2000];
+        // tile 1 of the second table: color 3 on the top row
+        chr[0x1010] = 0xff;
+        chr[0x1018] = 0xff;
+        let mut ppu = NesPPU::new(chr, Mirroring::HORIZONTAL);
+        ppu.palette_table[..4].copy_from_slice(&[0x0f, 0x01, 0x21, 0x31]);
+        ppu.resolve_palettes();
+
+        let image = pattern_tables(&ppu, 0);
+        let pixel = |x: usize, y: usize| {
+            let base = (y * image.width + x) * 3;
+            (image.data[base], image.data[base + 1], image.data[base + 2])
+        };
+        assert_eq!(pixel(136, 0), SYSTEM_PALETTE[0x31]);
+        assert_eq!(pixel(136, 1), SYSTEM_PALETTE[0x0f]);
+        assert_eq!(pixel(8, 0), SYSTEM_PALETTE[0x0f]);
+    }
+
+    #[test]
+    fn test_nametables_follow_mirroring() {
+        let mut chr = vec![0; 0x2000];
+        chr[16..24].copy_from_slice(&[0xff; 8]);
+        let mut ppu = NesPPU::new(chr, Mirroring::VERTICAL);
+        ppu.palette_table[..2].copy_from_slice(&[0x0f, 0x16]);
+        ppu.resolve_palettes();
+        // $2000 tile 0 and its mirror at $2800
+        ppu.vram[0] = 1;
+
+        let image = nametables(&ppu);
+        let pixel = |x: usize, y: usize| {
+            let base = (y * image.width + x) * 3;
+            (image.data[base], image.data[base + 1], image.data[base + 2])
+        };
+        assert_eq!(pixel(0, 0), SYSTEM_PALETTE[0x16]);
+        assert_eq!(pixel(0, 240), SYSTEM_PALETTE[0x16]);
+        assert_eq!(pixel(256, 0), SYSTEM_PALETTE[0x0f]);
+    }
+
+    #[test]
+    fn test_palettes() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.palette_table[0x13] = 0x2a;
+        ppu.resolve_palettes();
+        let image = palettes(&ppu);
+        assert_eq!((image.width, image.height), (128, 32));
+        // sprite palette 0, color 3
+        let base = (SWATCH_HEIGHT * image.width + 3 * SWATCH_WIDTH) * 3;
+        let (r, g, b) = SYSTEM_PALETTE[0x2a];
+        assert_eq!(&image.data[base..base + 3], &[r, g, b]);
+    }
+}
@@ -1,10 +1,20 @@
+use crate::prelude::*;
+
+/// Destination of the rendered picture (texture, canvas, ...): gets the PPU frame buffer
+/// directly, without an intermediate `Frame` copy.
+/// `data` is RGB24, `Frame::HIGHT` rows of `pitch` bytes
+pub trait PixelSink {
+    fn blit(&mut self, data: &[u8], pitch: usize);
+}
+
+#[derive(Clone, PartialEq)]
 pub struct Frame {
     pub data: Vec<u8>,
 }
 
 impl Frame {
-    const WIDTH: usize = 256;
-    const HIGHT: usize = 240;
+    pub const WIDTH: usize = 256;
+    pub const HIGHT: usize = 240;
 
     pub fn new() -> Self {
         Frame {
@@ -23,6 +33,57 @@ impl Frame {
     }
 
     pub fn clear(&mut self) {
-        self.data = vec![0; (Frame::WIDTH) * (Frame::HIGHT) * 3];
+        self.data.iter_mut().for_each(|byte| *byte = 0);
+    }
+
+    /// FNV-1a of the pixel data: stable across runs, platforms and compiler versions
+    /// (unlike `std::hash`), for replays, netplay desync checks and regression tests
+    pub fn hash(&self) -> u64 {
+        // http://www.isthe.com/chongo/tech/comp/fnv/index.html
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in self.data.iter() {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+}
+
+impl PixelSink for Frame {
+    fn blit(&mut self, data: &[u8], pitch: usize) {
+        let width = Frame::WIDTH * 3;
+        for (dst, src) in self.data.chunks_mut(width).zip(data.chunks(pitch)) {
+            dst.copy_from_slice(&src[..width]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hash() {
+        let mut frame = Frame::new();
+        let empty = frame.hash();
+        assert_eq!(empty, Frame::new().hash());
+
+        frame.set_pixel(10, 20, (1, 2, 3));
+        assert_ne!(frame.hash(), empty);
+        frame.set_pixel(10, 20, (0, 0, 0));
+        assert_eq!(frame.hash(), empty);
+    }
+
+    #[test]
+    fn test_blit_with_padding() {
+        let pitch = Frame::WIDTH * 3 + 4;
+        let mut data = vec![0; pitch * Frame::HIGHT];
+        data[pitch + 3..pitch + 6].copy_from_slice(&[1, 2, 3]);
+        let mut frame = Frame::new();
+        frame.blit(&data, pitch);
+
+        let mut expected = Frame::new();
+        expected.set_pixel(1, 1, (1, 2, 3));
+        assert!(frame == expected);
     }
 }
@@ -0,0 +1,149 @@
+// Replay ghost: a recorded movie runs on a second machine alongside the live game and is drawn
+// over the live picture, translucent, so speedrunners can race their previous attempts:
+// - a box at the ghost player position, read from the ghost machine RAM (the addresses are game
+//   specific, e.g. Super Mario Bros. keeps the player screen x at $0086 and y at $00CE);
+// - the buttons the movie holds in the current frame, bottom left, in the OSD font.
+use super::frame::Frame;
+use super::osd::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::emulator::Emulator;
+use crate::error::RustnessError;
+use crate::movie::Movie;
+
+const COLOR: (u8, u8, u8) = (0x40, 0xc0, 0xff);
+// player sized box
+const WIDTH: usize = 16;
+const HEIGHT: usize = 16;
+const LEFT: usize = 16;
+const BOTTOM: usize = Frame::HIGHT - 16;
+// same order as in the movie files
+const BUTTONS: &str = "RLDUTSBA";
+
+// half way between the picture and the ghost color
+fn blend(frame: &mut Frame, x: usize, y: usize) {
+    if x >= Frame::WIDTH || y >= Frame::HIGHT {
+        return;
+    }
+    let base = (y * Frame::WIDTH + x) * 3;
+    let pixel = &mut frame.data[base..base + 3];
+    pixel[0] = ((pixel[0] as u16 + COLOR.0 as u16) / 2) as u8;
+    pixel[1] = ((pixel[1] as u16 + COLOR.1 as u16) / 2) as u8;
+    pixel[2] = ((pixel[2] as u16 + COLOR.2 as u16) / 2) as u8;
+}
+
+pub struct Ghost {
+    emulator: Emulator,
+    movie: Movie,
+    frame: usize,
+    // RAM addresses of the player screen x and y
+    position: Option<(u16, u16)>,
+}
+
+impl Ghost {
+    /// `emulator` has to be set up the same way as the live one: same rom, `RamInit`, start state
+    pub fn new(emulator: Emulator, movie: Movie) -> Self {
+        Ghost {
+            emulator,
+            movie,
+            frame: 0,
+            position: None,
+        }
+    }
+
+    pub fn position(mut self, x_addr: u16, y_addr: u16) -> Self {
+        self.position = Some((x_addr, y_addr));
+        self
+    }
+
+    /// The movie is over, the ghost stands still
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+
+    /// Runs the ghost machine one frame, call it once per live frame
+    pub fn advance(&mut self) -> Result<(), RustnessError> {
+        if self.is_finished() {
+            return Ok(());
+        }
+        self.emulator.run_frame(&self.movie.inputs(self.frame))?;
+        self.frame += 1;
+        Ok(())
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame) {
+        if let Some((x_addr, y_addr)) = self.position {
+            let bus = &mut self.emulator.cpu_mut().bus;
+            let (x, y) = (bus.read(x_addr) as usize, bus.read(y_addr) as usize);
+            for dy in 0..HEIGHT {
+                for dx in 0..WIDTH {
+                    blend(frame, x + dx, y + dy);
+                }
+            }
+        }
+
+        if self.frame == 0 {
+            return;
+        }
+        let held = self.movie.inputs(self.frame - 1).joypad1.bits();
+        let top = BOTTOM - GLYPH_HEIGHT;
+        for (idx, c) in BUTTONS.chars().enumerate() {
+            if held & (0x80 >> idx) == 0 {
+                continue;
+            }
+            let left = LEFT + idx * (GLYPH_WIDTH + 1);
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if bits & (0b100 >> column) != 0 {
+                        blend(frame, left + column, top + row);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::cpu::CPU;
+    use crate::emulator::Inputs;
+    use crate::input::JoypadButton;
+    use crate::rom::test_ines_rom;
+
+    #[test]
+    fn test_ghost() {
+        let mut emulator = Emulator::builder(test_ines_rom::test_rom())
+            .start_pc(0x0600)
+            .build();
+        // loop: INC $10; JMP loop - the player moves on its own
+        for (idx, byte) in CPU::transform("e6 10 4c 00 06").iter().enumerate() {
+            emulator.cpu_mut().bus.write(0x0600 + idx as u16, *byte);
+        }
+        let mut movie = Movie::new();
+        movie.frames.push(Inputs::new(JoypadButton::RIGHT));
+        let mut ghost = Ghost::new(emulator, movie).position(0x10, 0x11);
+
+        let mut frame = Frame::new();
+        ghost.draw(&mut frame);
+        // at (0, 0) before the first frame
+        assert_eq!(&frame.data[0..3], &[0x20, 0x60, 0x7f]);
+        assert_eq!(&frame.data[WIDTH * 3..WIDTH * 3 + 3], &[0, 0, 0]);
+
+        ghost.advance().unwrap();
+        assert!(ghost.is_finished());
+        let x = ghost.emulator.cpu().bus.ram[0x10] as usize;
+        let mut frame = Frame::new();
+        ghost.draw(&mut frame);
+        let pixel = |x: usize, y: usize| {
+            let base = (y * Frame::WIDTH + x) * 3;
+            frame.data[base..base + 3].to_vec()
+        };
+        assert_eq!(pixel(x, 0), vec![0x20, 0x60, 0x7f]);
+        // 'R' is held: the top row of the glyph is 110
+        assert_eq!(pixel(LEFT, BOTTOM - GLYPH_HEIGHT), vec![0x20, 0x60, 0x7f]);
+        assert_eq!(pixel(LEFT + 2, BOTTOM - GLYPH_HEIGHT), vec![0, 0, 0]);
+
+        // the movie is over
+        ghost.advance().unwrap();
+        assert_eq!(ghost.emulator.cpu().bus.ram[0x10] as usize, x);
+    }
+}
@@ -0,0 +1,137 @@
+// 8-bit output: one NES color index per pixel plus the 64 color table to map them with, a third
+// of the RGB frame size. For consumers that do the palette lookup themselves (WASM canvas
+// shaders, netplay, recordings):
+//
+//   let mut frame = IndexedFrame::new();
+//   emulator.run_frame_indexed(&inputs, &mut frame)?;
+//   send(&frame.pixels, &frame.palette);
+//
+// The PPU draws RGB, the indices are recovered from it. Colors the system palette has more than
+// once (the blacks) get the first index, the picture is the same either way.
+use crate::screen::frame::{Frame, PixelSink};
+use crate::screen::palette::SYSTEM_PALETTE;
+use std::collections::HashMap;
+
+pub struct IndexedFrame {
+    /// `Frame::WIDTH * Frame::HIGHT` indices into `palette`
+    pub pixels: Vec<u8>,
+    pub palette: [(u8, u8, u8); 64],
+    lookup: HashMap<(u8, u8, u8), u8>,
+}
+
+impl IndexedFrame {
+    pub fn new() -> Self {
+        IndexedFrame::with_palette(SYSTEM_PALETTE)
+    }
+
+    pub fn with_palette(palette: [(u8, u8, u8); 64]) -> Self {
+        let mut frame = IndexedFrame {
+            pixels: vec![0; Frame::WIDTH * Frame::HIGHT],
+            palette,
+            lookup: HashMap::new(),
+        };
+        frame.index_palette();
+        frame
+    }
+
+    /// Following blits are indexed against `palette`
+    pub fn set_palette(&mut self, palette: [(u8, u8, u8); 64]) {
+        if self.palette != palette {
+            self.palette = palette;
+            self.index_palette();
+        }
+    }
+
+    fn index_palette(&mut self) {
+        self.lookup.clear();
+        for (idx, rgb) in self.palette.iter().enumerate() {
+            self.lookup.entry(*rgb).or_insert(idx as u8);
+        }
+    }
+
+    // colors outside of the palette (drawn by something other than the PPU) get the closest one
+    fn index(&mut self, rgb: (u8, u8, u8)) -> u8 {
+        if let Some(idx) = self.lookup.get(&rgb) {
+            return *idx;
+        }
+        let distance = |other: &(u8, u8, u8)| {
+            let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+            d(rgb.0, other.0) + d(rgb.1, other.1) + d(rgb.2, other.2)
+        };
+        let idx = (0..self.palette.len())
+            .min_by_key(|idx| distance(&self.palette[*idx]))
+            .unwrap() as u8;
+        self.lookup.insert(rgb, idx);
+        idx
+    }
+
+    /// Back to RGB24
+    pub fn to_frame(&self) -> Frame {
+        let mut frame = Frame::new();
+        for (rgb, idx) in frame.data.chunks_mut(3).zip(self.pixels.iter()) {
+            let color = self.palette[*idx as usize];
+            rgb.copy_from_slice(&[color.0, color.1, color.2]);
+        }
+        frame
+    }
+}
+
+impl Default for IndexedFrame {
+    fn default() -> Self {
+        IndexedFrame::new()
+    }
+}
+
+impl PixelSink for IndexedFrame {
+    fn blit(&mut self, data: &[u8], pitch: usize) {
+        let mut last = None;
+        for y in 0..Frame::HIGHT {
+            let row = &data[y * pitch..y * pitch + Frame::WIDTH * 3];
+            for (x, rgb) in row.chunks(3).enumerate() {
+                let rgb = (rgb[0], rgb[1], rgb[2]);
+                // runs of the same color are the common case
+                let idx = match last {
+                    Some((color, idx)) if color == rgb => idx,
+                    _ => self.index(rgb),
+                };
+                last = Some((rgb, idx));
+                self.pixels[y * Frame::WIDTH + x] = idx;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut frame = Frame::new();
+        for x in 0..64 {
+            frame.set_pixel(x, 3, SYSTEM_PALETTE[x]);
+        }
+        let mut indexed = IndexedFrame::new();
+        indexed.blit(&frame.data, Frame::WIDTH * 3);
+        assert!(indexed.to_frame() == frame);
+        assert_eq!(indexed.pixels[3 * Frame::WIDTH + 0x21], 0x21);
+    }
+
+    #[test]
+    fn test_unknown_colors() {
+        let mut palette = [(0, 0, 0); 64];
+        palette[1] = (200, 0, 0);
+        palette[2] = (0, 0, 200);
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 0, (190, 10, 10));
+        frame.set_pixel(1, 0, (0, 0, 200));
+        let mut indexed = IndexedFrame::with_palette(palette);
+        indexed.blit(&frame.data, Frame::WIDTH * 3);
+        assert_eq!(&indexed.pixels[..3], &[1, 2, 0]);
+
+        palette[2] = (0, 200, 0);
+        indexed.set_palette(palette);
+        indexed.blit(&frame.data, Frame::WIDTH * 3);
+        assert_eq!(&indexed.pixels[..3], &[1, 0, 0]);
+    }
+}
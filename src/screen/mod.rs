@@ -1,3 +1,18 @@
+#[cfg(feature = "std")]
+pub mod debug_images;
 pub mod frame;
+#[cfg(feature = "std")]
+pub mod ghost;
+#[cfg(feature = "std")]
+pub mod indexed;
+#[cfg(feature = "std")]
+pub mod osd;
+#[cfg(feature = "std")]
+pub mod overscan;
 pub mod palette;
+#[cfg(feature = "std")]
+pub mod phosphor;
+#[cfg(feature = "std")]
+pub mod png;
 pub mod render;
+pub mod tile;
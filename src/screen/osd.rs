@@ -0,0 +1,165 @@
+// On-screen display: a short text message drawn over the picture for a number of frames
+// (e.g. "STATE 1 SAVED"). Uses a tiny 3x5 font, lowercase is shown as uppercase.
+use super::frame::Frame;
+
+pub(super) const GLYPH_WIDTH: usize = 3;
+pub(super) const GLYPH_HEIGHT: usize = 5;
+const SCALE: usize = 2;
+// inside the NTSC overscan and cropped sides
+const LEFT: usize = 16;
+const TOP: usize = 16;
+
+const TEXT: (u8, u8, u8) = (0xff, 0xff, 0xff);
+const BACKGROUND: (u8, u8, u8) = (0x00, 0x00, 0x00);
+
+// 5 rows, 3 bits per row, the highest bit is the leftmost pixel
+pub(super) fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+pub struct Osd {
+    message: String,
+    frames_left: usize,
+}
+
+impl Osd {
+    pub fn new() -> Self {
+        Osd {
+            message: String::new(),
+            frames_left: 0,
+        }
+    }
+
+    /// Replaces the current message
+    pub fn show(&mut self, message: &str, frames: usize) {
+        self.message = message.to_string();
+        self.frames_left = frames;
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.frames_left > 0
+    }
+
+    /// Draws the message (if any) and counts down one frame
+    pub fn draw(&mut self, frame: &mut Frame) {
+        if !self.is_visible() {
+            return;
+        }
+        self.frames_left -= 1;
+
+        let columns = (Frame::WIDTH - 2 * LEFT) / ((GLYPH_WIDTH + 1) * SCALE);
+        // drawn every frame, the message is walked twice rather than collected
+        let text = || self.message.chars().take(columns);
+        // one pixel (scaled) border around the text
+        let width = (text().count() * (GLYPH_WIDTH + 1) + 1) * SCALE;
+        let height = (GLYPH_HEIGHT + 2) * SCALE;
+        for y in TOP..TOP + height {
+            for x in LEFT..LEFT + width {
+                frame.set_pixel(x, y, BACKGROUND);
+            }
+        }
+
+        for (idx, c) in text().enumerate() {
+            let left = LEFT + (idx * (GLYPH_WIDTH + 1) + 1) * SCALE;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if bits & (0b100 >> column) == 0 {
+                        continue;
+                    }
+                    for dy in 0..SCALE {
+                        for dx in 0..SCALE {
+                            frame.set_pixel(
+                                left + column * SCALE + dx,
+                                TOP + (row + 1) * SCALE + dy,
+                                TEXT,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for Osd {
+    fn default() -> Self {
+        Osd::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pixel(frame: &Frame, x: usize, y: usize) -> (u8, u8, u8) {
+        let base = y * 3 * Frame::WIDTH + x * 3;
+        (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+    }
+
+    #[test]
+    fn test_osd_message() {
+        let mut frame = Frame::new();
+        for pixel in frame.data.iter_mut() {
+            *pixel = 0x80;
+        }
+        let mut osd = Osd::new();
+        osd.draw(&mut frame);
+        assert_eq!(pixel(&frame, LEFT, TOP), (0x80, 0x80, 0x80));
+
+        osd.show("1", 2);
+        osd.draw(&mut frame);
+        assert_eq!(pixel(&frame, LEFT, TOP), BACKGROUND);
+        // top row of '1' is 010
+        assert_eq!(pixel(&frame, LEFT + SCALE, TOP + SCALE), BACKGROUND);
+        assert_eq!(pixel(&frame, LEFT + 2 * SCALE, TOP + SCALE), TEXT);
+        assert_eq!(pixel(&frame, LEFT + 2 * SCALE + 1, TOP + SCALE + 1), TEXT);
+        assert!(osd.is_visible());
+
+        osd.draw(&mut frame);
+        assert!(!osd.is_visible());
+    }
+}
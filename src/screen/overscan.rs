@@ -0,0 +1,139 @@
+// https://wiki.nesdev.com/w/index.php/Overscan
+//
+// CRT TVs didn't show the whole 256x240 picture: roughly 8 lines at the top and at the bottom
+// were hidden behind the bezel. Games relied on that and often left garbage there
+// (e.g. the nametable seam while scrolling vertically).
+use super::frame::Frame;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Overscan {
+    pub const NONE: Overscan = Overscan {
+        top: 0,
+        bottom: 0,
+        left: 0,
+        right: 0,
+    };
+
+    /// top/bottom 8 lines, the way most NTSC TV sets displayed it
+    pub const NTSC: Overscan = Overscan {
+        top: 8,
+        bottom: 8,
+        left: 0,
+        right: 0,
+    };
+
+    pub fn new(top: usize, bottom: usize, left: usize, right: usize) -> Self {
+        assert!(
+            top + bottom < Frame::HIGHT,
+            "overscan crops the whole frame"
+        );
+        assert!(
+            left + right < Frame::WIDTH,
+            "overscan crops the whole frame"
+        );
+        Overscan {
+            top,
+            bottom,
+            left,
+            right,
+        }
+    }
+
+    pub fn with_sides(self, pixels: usize) -> Self {
+        Overscan::new(self.top, self.bottom, pixels, pixels)
+    }
+
+    pub fn width(&self) -> usize {
+        Frame::WIDTH - self.left - self.right
+    }
+
+    pub fn height(&self) -> usize {
+        Frame::HIGHT - self.top - self.bottom
+    }
+
+    /// RGB24 data of the visible area, row by row
+    pub fn crop(&self, frame: &Frame) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.width() * self.height() * 3);
+        self.crop_into(frame, &mut result);
+        result
+    }
+
+    /// Same as `crop`, reusing `out` (cleared first) to avoid an allocation per frame
+    pub fn crop_into(&self, frame: &Frame, out: &mut Vec<u8>) {
+        out.clear();
+        for y in self.top..(Frame::HIGHT - self.bottom) {
+            let row = y * Frame::WIDTH * 3;
+            out.extend_from_slice(
+                &frame.data[row + self.left * 3..row + (Frame::WIDTH - self.right) * 3],
+            );
+        }
+    }
+}
+
+impl Default for Overscan {
+    fn default() -> Self {
+        Overscan::NONE
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_no_overscan_keeps_frame() {
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 0, (1, 2, 3));
+        frame.set_pixel(255, 239, (4, 5, 6));
+
+        assert_eq!(Overscan::NONE.crop(&frame), frame.data);
+    }
+
+    #[test]
+    fn test_ntsc_crops_top_and_bottom() {
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 7, (1, 1, 1)); //hidden
+        frame.set_pixel(0, 8, (2, 2, 2));
+        frame.set_pixel(255, 231, (3, 3, 3));
+        frame.set_pixel(255, 232, (4, 4, 4)); //hidden
+
+        let cropped = Overscan::NTSC.crop(&frame);
+        assert_eq!(cropped.len(), 256 * 224 * 3);
+        assert_eq!(&cropped[0..3], &[2, 2, 2]);
+        assert_eq!(&cropped[cropped.len() - 3..], &[3, 3, 3]);
+    }
+
+    #[test]
+    fn test_crop_sides() {
+        let mut frame = Frame::new();
+        frame.set_pixel(8, 8, (1, 2, 3));
+        frame.set_pixel(247, 8, (4, 5, 6));
+
+        let overscan = Overscan::NTSC.with_sides(8);
+        let cropped = overscan.crop(&frame);
+        assert_eq!(overscan.width(), 240);
+        assert_eq!(cropped.len(), 240 * 224 * 3);
+        assert_eq!(&cropped[0..3], &[1, 2, 3]);
+        assert_eq!(&cropped[239 * 3..240 * 3], &[4, 5, 6]);
+    }
+
+    #[test]
+    fn test_crop_into_reuses_buffer() {
+        let mut frame = Frame::new();
+        let mut out = Vec::new();
+        Overscan::NTSC.crop_into(&frame, &mut out);
+        let buffer = out.as_ptr();
+
+        frame.set_pixel(0, 8, (1, 2, 3));
+        Overscan::NTSC.crop_into(&frame, &mut out);
+        assert_eq!(out.as_ptr(), buffer);
+        assert_eq!(out, Overscan::NTSC.crop(&frame));
+    }
+}
@@ -1,5 +1,7 @@
-#[rustfmt::skip]
+use crate::prelude::*;
+use core::str::FromStr;
 
+#[rustfmt::skip]
 pub static SYSTEM_PALETTE: [(u8,u8,u8); 64] = [
     (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96), (0xA1, 0x00, 0x5E), 
     (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00), (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00), 
@@ -15,3 +17,142 @@ pub static SYSTEM_PALETTE: [(u8,u8,u8); 64] = [
     (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA), 
     (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11),
     ];
+
+/// .pal file: 64 RGB triplets, files with emphasis variants (512 colors) use only the first 64
+pub fn from_pal(data: &[u8]) -> Result<[(u8, u8, u8); 64], String> {
+    if data.len() < 64 * 3 {
+        return Err(format!(
+            "palette file should have at least 192 bytes, got {}",
+            data.len()
+        ));
+    }
+    let mut palette = [(0, 0, 0); 64];
+    for (idx, color) in palette.iter_mut().enumerate() {
+        *color = (data[idx * 3], data[idx * 3 + 1], data[idx * 3 + 2]);
+    }
+    Ok(palette)
+}
+
+/// Palettes for color-vision deficiency: the system palette daltonized, the colors a
+/// deuteranope/protanope confuses (reds and greens) are moved to distinguishable ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorVision {
+    Normal,
+    Deuteranopia,
+    Protanopia,
+}
+
+impl ColorVision {
+    pub const ALL: [ColorVision; 3] = [
+        ColorVision::Normal,
+        ColorVision::Deuteranopia,
+        ColorVision::Protanopia,
+    ];
+
+    pub fn palette(&self) -> [(u8, u8, u8); 64] {
+        let mut palette = SYSTEM_PALETTE;
+        if *self != ColorVision::Normal {
+            for color in palette.iter_mut() {
+                *color = self.daltonize(*color);
+            }
+        }
+        palette
+    }
+
+    /// The next one, for a hotkey cycling through them
+    pub fn next(&self) -> ColorVision {
+        let idx = ColorVision::ALL.iter().position(|v| v == self).unwrap();
+        ColorVision::ALL[(idx + 1) % ColorVision::ALL.len()]
+    }
+
+    // Fidaner, Lin, Ozguven: simulate the deficiency in LMS space, then shift the lost
+    // information (the difference from the original) to the channels still seen.
+    // http://www.daltonize.org/
+    fn daltonize(&self, (r, g, b): (u8, u8, u8)) -> (u8, u8, u8) {
+        let (r, g, b) = (r as f32, g as f32, b as f32);
+        let l = 17.8824 * r + 43.5161 * g + 4.11935 * b;
+        let m = 3.45565 * r + 27.1554 * g + 3.86714 * b;
+        let s = 0.0299566 * r + 0.184309 * g + 1.46709 * b;
+        let (l, m) = match self {
+            ColorVision::Normal => (l, m),
+            ColorVision::Protanopia => (2.02344 * m - 2.52581 * s, m),
+            ColorVision::Deuteranopia => (l, 0.494207 * l + 1.24827 * s),
+        };
+        let seen_r = 0.080_944_45 * l - 0.130_504_41 * m + 0.116_721_07 * s;
+        let seen_g = -0.010_248_534 * l + 0.054_019_33 * m - 0.113_614_71 * s;
+        let seen_b = -0.000_365_297 * l - 0.004_121_614_7 * m + 0.693_511_4 * s;
+
+        let (err_r, err_g, err_b) = (r - seen_r, g - seen_g, b - seen_b);
+        let clamp = |v: f32| v.round().clamp(0.0, 255.0) as u8;
+        (
+            clamp(r),
+            clamp(g + 0.7 * err_r + err_g),
+            clamp(b + 0.7 * err_r + err_b),
+        )
+    }
+}
+
+impl FromStr for ColorVision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ColorVision, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "normal" => Ok(ColorVision::Normal),
+            "deuteranopia" => Ok(ColorVision::Deuteranopia),
+            "protanopia" => Ok(ColorVision::Protanopia),
+            _ => Err(format!(
+                "unknown color vision '{}', expected normal, deuteranopia or protanopia",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_pal() {
+        let data: Vec<u8> = (0..=255u8).cycle().take(512 * 3).collect();
+        let palette = from_pal(&data).unwrap();
+        assert_eq!(palette[0], (0, 1, 2));
+        assert_eq!(palette[63], (189, 190, 191));
+        assert!(from_pal(&data[..191]).is_err());
+    }
+
+    #[test]
+    fn test_color_vision() {
+        assert_eq!(ColorVision::Normal.palette(), SYSTEM_PALETTE);
+        for vision in [ColorVision::Deuteranopia, ColorVision::Protanopia].iter() {
+            let palette = vision.palette();
+            // grays stay (almost) the same
+            for &gray in [0x00, 0x10, 0x20, 0x2d].iter() {
+                let (r, g, b) = palette[gray];
+                let original = SYSTEM_PALETTE[gray];
+                assert!((r as i16 - original.0 as i16).abs() <= 2, "{:?}", vision);
+                assert!((g as i16 - original.1 as i16).abs() <= 2, "{:?}", vision);
+                assert!((b as i16 - original.2 as i16).abs() <= 2, "{:?}", vision);
+            }
+            // the red gets a blue component, the green doesn't: they don't look the same anymore
+            let (red, green) = (palette[0x16], palette[0x2a]);
+            assert!(red.2 > SYSTEM_PALETTE[0x16].2 + 0x40, "{:?}", vision);
+            assert!(green.2 <= SYSTEM_PALETTE[0x2a].2, "{:?}", vision);
+        }
+        assert_ne!(
+            ColorVision::Deuteranopia.palette(),
+            ColorVision::Protanopia.palette()
+        );
+    }
+
+    #[test]
+    fn test_color_vision_cycle() {
+        assert_eq!("Protanopia".parse(), Ok(ColorVision::Protanopia));
+        assert!("tritanopia".parse::<ColorVision>().is_err());
+        let mut vision = ColorVision::Normal;
+        for _ in 0..3 {
+            vision = vision.next();
+        }
+        assert_eq!(vision, ColorVision::Normal);
+    }
+}
@@ -0,0 +1,113 @@
+// CRT phosphor persistence: a pixel fades into its new color over a few frames instead of
+// switching right away. Games flickered sprites every other frame on purpose for transparency
+// (shadows, water, ghosts) or because of the 8 sprites per scanline limit; a CRT smoothed that
+// into a steady translucent picture, an LCD shows the flicker as it is.
+//
+//   let mut phosphor = Phosphor::new(0.5);
+//   bus.ppu().blit(&mut frame);
+//   phosphor.apply(&mut frame);
+use super::frame::Frame;
+
+pub struct Phosphor {
+    // share of the previous picture in 1/256s
+    weight: u16,
+    previous: Option<Frame>,
+}
+
+impl Phosphor {
+    /// `decay` is the share of the previous picture kept in every frame: 0.0 - off, 0.5 - a
+    /// pixel flickering every other frame shows at 1/3..2/3 of its brightness
+    pub fn new(decay: f32) -> Self {
+        Phosphor {
+            weight: (decay.clamp(0.0, 1.0) * 256.0).min(255.0) as u16,
+            previous: None,
+        }
+    }
+
+    /// Blends `frame` with the previous result, in place
+    pub fn apply(&mut self, frame: &mut Frame) {
+        if self.weight == 0 {
+            return;
+        }
+        let previous = match self.previous.as_mut() {
+            Some(previous) => previous,
+            None => {
+                self.previous = Some(frame.clone());
+                return;
+            }
+        };
+        let weight = self.weight;
+        for (pixel, old) in frame.data.iter_mut().zip(previous.data.iter_mut()) {
+            let mixed = (*pixel as u16 * (256 - weight) + *old as u16 * weight + 128) >> 8;
+            // rounding alone never reaches the new color
+            let mixed = match mixed as u8 {
+                same if same == *old && *pixel != *old => {
+                    if *pixel > *old {
+                        same + 1
+                    } else {
+                        same - 1
+                    }
+                }
+                mixed => mixed,
+            };
+            *pixel = mixed;
+            *old = mixed;
+        }
+    }
+
+    /// The next frame is shown as it is, e.g. after loading a state
+    pub fn reset(&mut self) {
+        self.previous = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(rgb: (u8, u8, u8)) -> Frame {
+        let mut frame = Frame::new();
+        frame.set_pixel(5, 5, rgb);
+        frame
+    }
+
+    #[test]
+    fn test_flicker() {
+        let mut phosphor = Phosphor::new(0.5);
+        let mut shown = vec![];
+        for n in 0..20 {
+            let mut picture = frame(if n % 2 == 0 { (240, 120, 0) } else { (0, 0, 0) });
+            phosphor.apply(&mut picture);
+            shown.push(picture.data[(5 * Frame::WIDTH + 5) * 3]);
+        }
+        assert_eq!(shown[0], 240);
+        // settles at about 2/3 and 1/3 of the brightness
+        assert_eq!(&shown[18..], &[161, 81]);
+    }
+
+    #[test]
+    fn test_fades_completely() {
+        let mut phosphor = Phosphor::new(0.9);
+        phosphor.apply(&mut frame((255, 255, 255)));
+        let mut picture = Frame::new();
+        for _ in 0..200 {
+            picture.clear();
+            phosphor.apply(&mut picture);
+        }
+        assert!(picture == Frame::new());
+
+        phosphor.reset();
+        let mut picture = frame((255, 0, 0));
+        phosphor.apply(&mut picture);
+        assert!(picture == frame((255, 0, 0)));
+    }
+
+    #[test]
+    fn test_off() {
+        let mut phosphor = Phosphor::new(0.0);
+        phosphor.apply(&mut frame((255, 255, 255)));
+        let mut picture = Frame::new();
+        phosphor.apply(&mut picture);
+        assert!(picture == Frame::new());
+    }
+}
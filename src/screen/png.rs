@@ -0,0 +1,100 @@
+// Minimal PNG encoder for debug images and screenshots: 8-bit RGB, no filtering, the image data
+// in stored (uncompressed) deflate blocks. The files are bigger than they could be, any viewer
+// opens them. https://www.w3.org/TR/PNG/
+use crate::rom::crc32_update;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+// the largest stored deflate block
+const BLOCK: usize = 0xffff;
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32_update(crc32_update(0, kind), data);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+// zlib stream of stored blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        out.push(last as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// PNG file of an RGB24 image, `rgb` holds `height` rows of `width * 3` bytes
+pub fn encode(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(rgb.len(), width * height * 3, "image size doesn't match");
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGB, deflate, no filter, no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    // every row starts with its filter type, 0 - none
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for row in rgb.chunks(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut out = SIGNATURE.to_vec();
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let png = encode(2, 1, &[255, 0, 0, 0, 0, 255]);
+        assert_eq!(&png[..8], &SIGNATURE);
+        assert_eq!(&png[8..16], &[0, 0, 0, 13, b'I', b'H', b'D', b'R']);
+        assert_eq!(&png[16..29], &[0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 0]);
+        // crc32 of the chunk type and data, as zlib computes it
+        assert_eq!(&png[29..33], &[0x7b, 0x40, 0xe8, 0xdd]);
+        // IEND and its well-known crc
+        assert_eq!(
+            &png[png.len() - 12..],
+            &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]
+        );
+    }
+
+    #[test]
+    fn test_zlib_stored() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+
+        let data: Vec<u8> = (0..BLOCK + 10).map(|idx| idx as u8).collect();
+        let zlib = zlib_stored(&data);
+        // two blocks: 5 byte headers
+        assert_eq!(zlib.len(), 2 + 5 + BLOCK + 5 + 10 + 4);
+        assert_eq!(&zlib[2..7], &[0, 0xff, 0xff, 0, 0]);
+        assert_eq!(&zlib[7 + BLOCK..12 + BLOCK], &[1, 10, 0, 0xf5, 0xff]);
+        assert_eq!(&zlib[12 + BLOCK..12 + BLOCK + 10], &data[BLOCK..]);
+    }
+}
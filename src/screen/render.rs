@@ -1,40 +1,23 @@
 use super::frame::Frame;
-use crate::screen::palette;
+use super::tile;
+use crate::ppu::cartridge::Split;
 use crate::ppu::ppu::NesPPU;
 use crate::rom::Mirroring;
 
-fn bg_pallette(ppu: &NesPPU, attribute_table: &[u8], tile_column: usize, tile_row: usize) -> [u8; 4] {
+fn bg_pallette(attribute_table: &[u8], tile_column: usize, tile_row: usize) -> u8 {
     let attr_table_idx = tile_row / 4 * 8 + tile_column / 4;
     let attr_byte = attribute_table[attr_table_idx]; 
 
-    let pallet_idx = match (tile_column % 4 / 2, tile_row % 4 / 2) {
+    match (tile_column % 4 / 2, tile_row % 4 / 2) {
         (0, 0) => attr_byte & 0b11,
         (1, 0) => (attr_byte >> 2) & 0b11,
         (0, 1) => (attr_byte >> 4) & 0b11,
         (1, 1) => (attr_byte >> 6) & 0b11,
         (_, _) => panic!("should not happen"),
-    };
-
-    let pallete_start: usize = 1 + (pallet_idx as usize) * 4;
-    [
-        ppu.palette_table[0],
-        ppu.palette_table[pallete_start],
-        ppu.palette_table[pallete_start + 1],
-        ppu.palette_table[pallete_start + 2],
-    ]
-}
-
-
-fn sprite_palette(ppu: &NesPPU, pallete_idx: u8) -> [u8; 4] {
-    let start = 0x11 + (pallete_idx * 4) as usize;
-    [
-        0,
-        ppu.palette_table[start],
-        ppu.palette_table[start + 1],
-        ppu.palette_table[start + 2],
-    ]
+    }
 }
 
+#[derive(Clone, Copy)]
 struct Rect {
     x1: usize,
     y1: usize,
@@ -64,23 +47,14 @@ fn render_name_table(ppu: &NesPPU, frame: &mut Frame, name_table: &[u8],
         let tile_row = i / 32;
         let tile_idx = name_table[i] as u16;
         let tile = &ppu.chr_rom[(bank + tile_idx * 16) as usize..=(bank + tile_idx * 16 + 15) as usize];
-        let palette = bg_pallette(ppu, attribute_table, tile_column, tile_row);
+        let palette = &ppu.rgb_palettes[bg_pallette(attribute_table, tile_column, tile_row) as usize];
 
         for y in 0..=7 {
-            let mut upper = tile[y];
-            let mut lower = tile[y + 8];
+            let row = tile::decode_row(tile[y], tile[y + 8]);
 
             for x in (0..=7).rev() {
-                let value = (1 & lower) << 1 | (1 & upper);
-                upper = upper >> 1;
-                lower = lower >> 1;
-                let rgb = match value {
-                    0 => palette::SYSTEM_PALETTE[ppu.palette_table[0] as usize],
-                    1 => palette::SYSTEM_PALETTE[palette[1] as usize],
-                    2 => palette::SYSTEM_PALETTE[palette[2] as usize],
-                    3 => palette::SYSTEM_PALETTE[palette[3] as usize],
-                    _ => panic!("can't be"),
-                };
+                let value = tile::pixel(row, x);
+                let rgb = palette[value as usize];
                 let pixel_x = tile_column * 8 + x;
                 let pixel_y = tile_row * 8 + y;
 
@@ -103,6 +77,12 @@ pub fn render(ppu: &NesPPU, frame: &mut Frame) {
         (Mirroring::VERTICAL, 0x2400) | (Mirroring::VERTICAL, 0x2C00) | (Mirroring::HORIZONTAL, 0x2800) | (Mirroring::HORIZONTAL, 0x2C00) => {
             ( &ppu.vram[0x400..0x800], &ppu.vram[0..0x400])
         }
+        (Mirroring::SINGLE_SCREEN_LOWER, _) => (&ppu.vram[0..0x400], &ppu.vram[0..0x400]),
+        (Mirroring::SINGLE_SCREEN_UPPER, _) => (&ppu.vram[0x400..0x800], &ppu.vram[0x400..0x800]),
+        (Mirroring::CUSTOM(pages), _) => {
+            let (main, second) = custom_nametables(ppu, pages);
+            (ppu.nametable(main), ppu.nametable(second))
+        }
         (_,_) => {
             panic!("Not supported mirroring type {:?}", ppu.mirroring);
         }
@@ -130,121 +110,269 @@ pub fn render(ppu: &NesPPU, frame: &mut Frame) {
     render_sprites(ppu, frame);
 }
 
-pub fn render_bg_scanline(ppu: &NesPPU, scanline: usize, frame: &mut Frame) {
+/// A nametable row drawn on a background scanline, a scrolled scanline is made of two
+#[derive(Clone, Copy)]
+struct Span {
+    nametable_idx: usize,
+    // line in the nametable
+    scanline: usize,
+    view_port: Rect,
+    shift_x: isize,
+    shift_y: isize,
+}
+
+/// What the PPU fetches for a tile of a scanline: both pattern planes and the palette
+#[derive(Clone, Copy, Default)]
+struct TileFetch {
+    plane0: u8,
+    plane1: u8,
+    palette: u8,
+}
+
+/// Everything needed to draw a background scanline without access to the PPU
+#[derive(Clone, Copy)]
+pub struct ScanlineFetch {
+    spans: [Option<(Span, [TileFetch; 32])>; 2],
+    palettes: [[(u8, u8, u8); 4]; 8],
+}
+
+/// Sprite attributes with the pattern data, to draw sprites without access to the PPU
+#[derive(Clone, Copy)]
+pub struct SpriteFetch {
+    oam: [u8; 4],
+    tile: [u8; 16],
+    // 8 or 16 (8x16 sprites), for the sprite limit
+    height: u8,
+}
+
+// the page of the nametable in `PPUCTRL` and of its neighbour the picture scrolls into
+fn custom_nametables(ppu: &NesPPU, pages: &[u8; 4]) -> (usize, usize) {
+    let slot = ((ppu.ctrl.nametable_addr() - 0x2000) / 0x400) as usize;
+    let neighbour = if ppu.scroll.scroll_y == 0 { slot ^ 1 } else { slot ^ 2 };
+    (pages[slot] as usize, pages[neighbour] as usize)
+}
+
+fn bg_spans(ppu: &NesPPU, scanline: usize) -> [Option<Span>; 2] {
     let scroll_x = (ppu.scroll.scroll_x) as usize;
     let scroll_y = (ppu.scroll.scroll_y) as usize;
 
+    // nametables as indexes in vram
     let (main_nametable, second_nametable) = match (&ppu.mirroring, ppu.ctrl.nametable_addr()) {
         (Mirroring::VERTICAL, 0x2000) | (Mirroring::VERTICAL, 0x2800) | (Mirroring::HORIZONTAL, 0x2000) | (Mirroring::HORIZONTAL, 0x2400) => {
-            (&ppu.vram[0..0x400], &ppu.vram[0x400..0x800])
+            (0, 1)
         }
         (Mirroring::VERTICAL, 0x2400) | (Mirroring::VERTICAL, 0x2C00) | (Mirroring::HORIZONTAL, 0x2800) | (Mirroring::HORIZONTAL, 0x2C00) => {
-            ( &ppu.vram[0x400..0x800], &ppu.vram[0..0x400])
+            (1, 0)
         }
+        (Mirroring::SINGLE_SCREEN_LOWER, _) => (0, 0),
+        (Mirroring::SINGLE_SCREEN_UPPER, _) => (1, 1),
+        (Mirroring::CUSTOM(pages), _) => custom_nametables(ppu, pages),
         (_,_) => {
             panic!("Not supported mirroring type {:?}", ppu.mirroring);
         }
     };
+    let span = |nametable_idx, scanline, view_port, shift_x, shift_y| {
+        Some(Span { nametable_idx, scanline, view_port, shift_x, shift_y })
+    };
 
-
-    if(scroll_y == 0) {
-        render_name_table_scanline(ppu, frame, scanline, main_nametable, 
-            Rect::new(scroll_x,scroll_y,256, 240), 
-            -(scroll_x as isize), -(scroll_y as isize));
-
-        render_name_table_scanline(ppu, frame, scanline, second_nametable, 
-            Rect::new(0,0,scroll_x, 240), 
-            (256 - scroll_x as isize), 0);
+    if scroll_y == 0 {
+        [
+            span(main_nametable, scanline, Rect::new(scroll_x, scroll_y, 256, 240), -(scroll_x as isize), -(scroll_y as isize)),
+            span(second_nametable, scanline, Rect::new(0, 0, scroll_x, 240), 256 - scroll_x as isize, 0),
+        ]
+    } else if scanline + scroll_y > 240 {
+        [span(second_nametable, scanline + scroll_y - 240, Rect::new(0, 0, 256, 240), 0, (239 - scroll_y) as isize), None]
     } else {
-        if(scanline + scroll_y > 240) {
-            render_name_table_scanline(ppu, frame, scanline + scroll_y - 240, second_nametable, 
-                Rect::new(0,0,256, 240), 
-                0, (239 - scroll_y) as isize)
-        } else {
-            render_name_table_scanline(ppu, frame, scroll_y + scanline, main_nametable, 
-                Rect::new(0,0,256, 240), 
-                0, -(scroll_y as isize))
+        [span(main_nametable, scroll_y + scanline, Rect::new(0, 0, 256, 240), 0, -(scroll_y as isize)), None]
+    }
+}
 
+fn fetch_tile(ppu: &NesPPU, span: &Span, tile_column: usize) -> TileFetch {
+    let cartridge = ppu.cartridge_video();
+    if let Some(split) = cartridge.and_then(|cartridge| cartridge.split) {
+        // the fetch number: where the tile lands on the screen, rounded up past the fine scroll
+        let fetch = (span.shift_x + (tile_column * 8) as isize + 7).div_euclid(8) as usize;
+        if split.covers(fetch) {
+            let screen_y = (span.shift_y + span.scanline as isize) as usize;
+            return fetch_split_tile(ppu, &split, fetch % 32, screen_y);
         }
     }
-}
 
-fn render_name_table_scanline(ppu: &NesPPU, frame: &mut Frame, scanline: usize, name_table: &[u8], view_port: Rect, shift_x: isize, shift_y: isize) {
     let bank = ppu.ctrl.bknd_pattern_addr();
-
+    let name_table = ppu.nametable(span.nametable_idx);
     let attribute_table = &name_table[0x3c0.. 0x400];
+    let tile_row = span.scanline / 8;
+
+    let tile_offset = tile_row * 32 + tile_column;
+    let tile_idx = name_table[tile_offset] as u16;
+    let y = span.scanline % 8;
+    match cartridge {
+        // the ExRAM byte of the tile: its 4KB bank and palette
+        Some(cartridge) if cartridge.extended_attributes => {
+            let attribute = cartridge.page(2)[tile_offset];
+            let bank = (cartridge.chr_upper as usize) << 6 | (attribute & 0x3f) as usize;
+            let tile = &ppu.chr_bank(bank)[tile_idx as usize * 16..];
+            TileFetch {
+                plane0: tile[y],
+                plane1: tile[y + 8],
+                palette: attribute >> 6,
+            }
+        }
+        _ => {
+            let tile = &ppu.chr_rom[(bank + tile_idx * 16) as usize..=(bank + tile_idx * 16 + 15) as usize];
+            TileFetch {
+                plane0: tile[y],
+                plane1: tile[y + 8],
+                palette: bg_pallette(attribute_table, tile_column, tile_row),
+            }
+        }
+    }
+}
 
-    let tile_row = scanline / 8;
+// MMC5 vertical split: ExRAM as the nametable, scrolled by the split's own scroll
+fn fetch_split_tile(ppu: &NesPPU, split: &Split, tile_column: usize, screen_y: usize) -> TileFetch {
+    let exram = ppu.nametable(2);
+    let line = (split.scroll_y as usize + screen_y) % 240;
+    let tile_row = line / 8;
+    let tile_idx = exram[tile_row * 32 + tile_column] as usize;
+    let tile = &ppu.chr_bank(split.bank as usize)[tile_idx * 16..];
+    TileFetch {
+        plane0: tile[line % 8],
+        plane1: tile[line % 8 + 8],
+        palette: bg_pallette(&exram[0x3c0..], tile_column, tile_row),
+    }
+}
 
-    for tile_column in 0..32usize {
+fn draw_tile(frame: &mut Frame, palettes: &[[(u8, u8, u8); 4]; 8], span: &Span, tile_column: usize, fetch: TileFetch) {
+    let view_port = &span.view_port;
+    let palette = &palettes[fetch.palette as usize];
+    let row = tile::decode_row(fetch.plane0, fetch.plane1);
+    let pixel_y = span.scanline;
+
+    // the whole row is visible: 8 pixels at once
+    let (first_x, screen_x, screen_y) = (tile_column * 8, span.shift_x + (tile_column * 8) as isize, span.shift_y + pixel_y as isize);
+    if first_x >= view_port.x1 && first_x + 8 <= view_port.x2 && pixel_y >= view_port.y1 && pixel_y < view_port.y2
+        && screen_x >= 0 && screen_x + 8 <= Frame::WIDTH as isize && screen_y >= 0 && screen_y < Frame::HIGHT as isize {
+        let base = (screen_y as usize * Frame::WIDTH + screen_x as usize) * 3;
+        tile::map_row(row, palette, &mut frame.data[base..base + 24]);
+        return;
+    }
 
-        let tile_idx = name_table[tile_row * 32 + tile_column] as u16;
-        let tile = &ppu.chr_rom[(bank + tile_idx * 16) as usize..=(bank + tile_idx * 16 + 15) as usize];
-        let palette = bg_pallette(ppu, attribute_table, tile_column, tile_row);
-
-        let y = scanline % 8;
-        let mut upper = tile[y];
-        let mut lower = tile[y + 8];
-
-        for x in (0..=7).rev() {
-            let value = (1 & lower) << 1 | (1 & upper);
-            upper = upper >> 1;
-            lower = lower >> 1;
-            let rgb = match value {
-                0 => palette::SYSTEM_PALETTE[ppu.palette_table[0] as usize],
-                1 => palette::SYSTEM_PALETTE[palette[1] as usize],
-                2 => palette::SYSTEM_PALETTE[palette[2] as usize],
-                3 => palette::SYSTEM_PALETTE[palette[3] as usize],
-                _ => panic!("can't be"),
-            };
-            let pixel_x = tile_column * 8 + x;
-            let pixel_y = tile_row * 8 + y;
-
-            if pixel_x >= view_port.x1 && pixel_x < view_port.x2 && pixel_y >= view_port.y1 && pixel_y < view_port.y2 {
-                frame.set_pixel((shift_x + pixel_x as isize) as usize, (shift_y + pixel_y as isize) as usize, rgb);
+    for x in (0..=7).rev() {
+        let value = tile::pixel(row, x);
+        let rgb = palette[value as usize];
+        let pixel_x = tile_column * 8 + x;
+
+        if pixel_x >= view_port.x1 && pixel_x < view_port.x2 && pixel_y >= view_port.y1 && pixel_y < view_port.y2 {
+            frame.set_pixel((span.shift_x + pixel_x as isize) as usize, (span.shift_y + pixel_y as isize) as usize, rgb);
+        }
+    }
+}
+
+pub fn render_bg_scanline(ppu: &NesPPU, scanline: usize, frame: &mut Frame) {
+    let redraw_all = ppu.cartridge_video().is_some_and(|cartridge| cartridge.redraws_all());
+    for span in bg_spans(ppu, scanline).iter().flatten() {
+        let tile_row = span.scanline / 8;
+        for tile_column in 0..32usize {
+            // unchanged tiles are already in the frame buffer
+            let screen_x = span.shift_x + (tile_column * 8) as isize;
+            let screen_y = span.shift_y + span.scanline as isize;
+            let dirty_idx = span.nametable_idx * 960 + tile_row * 32 + tile_column;
+            if tile_row < 30 && !redraw_all && !ppu.dirty_tiles.needs_redraw(dirty_idx, screen_x, screen_y) {
+                continue;
             }
+            draw_tile(frame, &ppu.rgb_palettes, span, tile_column, fetch_tile(ppu, span, tile_column));
         }
     }
+}
 
+/// The PPU side of the background rendering, `draw_scanline` can run anywhere
+pub fn fetch_scanline(ppu: &NesPPU, scanline: usize) -> ScanlineFetch {
+    let mut spans = [None, None];
+    for (fetched, span) in spans.iter_mut().zip(bg_spans(ppu, scanline).iter()) {
+        if let Some(span) = span {
+            let mut tiles = [TileFetch::default(); 32];
+            for (tile_column, tile) in tiles.iter_mut().enumerate() {
+                *tile = fetch_tile(ppu, span, tile_column);
+            }
+            *fetched = Some((*span, tiles));
+        }
+    }
+    ScanlineFetch { spans, palettes: ppu.rgb_palettes }
 }
 
+pub fn draw_scanline(frame: &mut Frame, fetch: &ScanlineFetch) {
+    for (span, tiles) in fetch.spans.iter().flatten() {
+        for (tile_column, tile) in tiles.iter().enumerate() {
+            draw_tile(frame, &fetch.palettes, span, tile_column, *tile);
+        }
+    }
+}
 
 pub fn render_sprites(ppu:&NesPPU, frame: &mut Frame) {
-    for i in (0..ppu.oam_data.len()).step_by(4).rev() {
-        let tile_idx = ppu.oam_data[i + 1] as u16;
-        let tile_x = ppu.oam_data[i + 3] as usize;
-        let tile_y = ppu.oam_data[i] as usize;
-
-        let flip_vertical = if ppu.oam_data[i + 2] >> 7 & 1 == 1 {
-            true
-        } else {
-            false
-        };
-        let flip_horizontal = if ppu.oam_data[i + 2] >> 6 & 1 == 1 {
-            true
-        } else {
-            false
-        };
-        let pallette_idx = ppu.oam_data[i + 2] & 0b11;
-        let sprite_palette = sprite_palette(ppu, pallette_idx);
-        let bank: u16 = ppu.ctrl.sprt_pattern_addr();
-
-        let tile =
-            &ppu.chr_rom[(bank + tile_idx * 16) as usize..=(bank + tile_idx * 16 + 15) as usize];
+    draw_sprites(frame, &ppu.rgb_palettes, &fetch_sprites(ppu), ppu.sprite_limit());
+}
+
+pub fn fetch_sprites(ppu: &NesPPU) -> [SpriteFetch; 64] {
+    let bank: u16 = ppu.ctrl.sprt_pattern_addr();
+    let height = ppu.ctrl.sprite_size();
+    let mut sprites = [SpriteFetch { oam: [0; 4], tile: [0; 16], height }; 64];
+    for (sprite, oam) in sprites.iter_mut().zip(ppu.oam_data.chunks(4)) {
+        let tile_idx = oam[1] as u16;
+        sprite.oam.copy_from_slice(oam);
+        sprite.tile.copy_from_slice(&ppu.chr_rom[(bank + tile_idx * 16) as usize..=(bank + tile_idx * 16 + 15) as usize]);
+    }
+    sprites
+}
+
+/// The PPU fetches only the first 8 sprites (in OAM order) that fall on a scanline
+pub const SPRITES_PER_LINE: usize = 8;
+
+// rows of every sprite that made it into the 8 sprites of their scanline, bit n - row n of the
+// sprite. 8x16 sprites take 16 lines of the limit
+fn visible_rows(sprites: &[SpriteFetch; 64]) -> [u16; 64] {
+    let mut per_line = [0usize; 256];
+    let mut rows = [0u16; 64];
+    for (sprite, rows) in sprites.iter().zip(rows.iter_mut()) {
+        let top = sprite.oam[0] as usize;
+        for y in 0..sprite.height as usize {
+            if let Some(count) = per_line.get_mut(top + y) {
+                if *count < SPRITES_PER_LINE {
+                    *count += 1;
+                    *rows |= 1 << y;
+                }
+            }
+        }
+    }
+    rows
+}
+
+/// `limit`: only 8 sprites per scanline are drawn, as on the hardware (the flicker included)
+pub fn draw_sprites(frame: &mut Frame, palettes: &[[(u8, u8, u8); 4]; 8], sprites: &[SpriteFetch; 64], limit: bool) {
+    let visible_rows = if limit { visible_rows(sprites) } else { [0xffff; 64] };
+    for (sprite, &visible) in sprites.iter().zip(visible_rows.iter()).rev() {
+        let tile_x = sprite.oam[3] as usize;
+        let tile_y = sprite.oam[0] as usize;
+
+        let flip_vertical = sprite.oam[2] >> 7 & 1 == 1;
+        let flip_horizontal = sprite.oam[2] >> 6 & 1 == 1;
+        let pallette_idx = sprite.oam[2] & 0b11;
+        let sprite_palette = &palettes[4 + pallette_idx as usize];
+
+        let tile = &sprite.tile;
 
         for y in 0..=7 {
-            let mut upper = tile[y];
-            let mut lower = tile[y + 8];
+            // dropped by the sprite limit on this scanline
+            let line = if flip_vertical { 7 - y } else { y };
+            if visible >> line & 1 == 0 {
+                continue;
+            }
+            let row = tile::decode_row(tile[y], tile[y + 8]);
             'ololo: for x in (0..=7).rev() {
-                let value = (1 & lower) << 1 | (1 & upper);
-                upper = upper >> 1;
-                lower = lower >> 1;
+                let value = tile::pixel(row, x);
                 let rgb = match value {
                     0 => continue 'ololo, // skip coloring the pixel
-                    1 => palette::SYSTEM_PALETTE[sprite_palette[1] as usize],
-                    2 => palette::SYSTEM_PALETTE[sprite_palette[2] as usize],
-                    3 => palette::SYSTEM_PALETTE[sprite_palette[3] as usize],
-                    _ => panic!("can't be"),
+                    _ => sprite_palette[value as usize],
                 };
                 let (pixel_x, pixel_y) = match (flip_horizontal, flip_vertical) {
                     (false, false) => {
@@ -265,4 +393,52 @@ pub fn render_sprites(ppu:&NesPPU, frame: &mut Frame) {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ppu::cartridge::CartridgeVideo;
+    use crate::ppu::ppu::PPU;
+
+    // MMC5 like: 4KB banks filled with their number, the fill page shows tile 5
+    fn mmc5_ppu(extended_attributes: bool, split: Option<Split>) -> NesPPU {
+        let mut chr = vec![0; 0x2000];
+        chr[5 * 16] = 0xff;
+        let mut ppu = NesPPU::new(chr, Mirroring::CUSTOM([3, 3, 3, 3]));
+        ppu.set_chr_banks((0..4u8).flat_map(|bank| vec![bank; 0x1000]).collect());
+        // ExRAM: tile 0b11_000011 (bank 3, palette 3 as an extended attribute), palette 3
+        let mut pages = vec![0b11_000011; 0x3c0];
+        pages.extend_from_slice(&[0xff; 0x40]);
+        pages.extend_from_slice(&[5; 0x3c0]);
+        pages.extend_from_slice(&[0b1010_1010; 0x40]);
+        ppu.set_cartridge_video(Some(CartridgeVideo { pages, extended_attributes, split, chr_upper: 0 }));
+        ppu
+    }
+
+    fn planes(fetch: &ScanlineFetch) -> Vec<(u8, u8)> {
+        let (_, tiles) = fetch.spans[0].as_ref().unwrap();
+        tiles.iter().map(|tile| (tile.plane0, tile.palette)).collect()
+    }
+
+    #[test]
+    fn test_fill_page() {
+        let ppu = mmc5_ppu(false, None);
+        assert_eq!(planes(&fetch_scanline(&ppu, 0)), vec![(0xff, 2); 32]);
+    }
+
+    #[test]
+    fn test_extended_attributes() {
+        let ppu = mmc5_ppu(true, None);
+        assert_eq!(planes(&fetch_scanline(&ppu, 0)), vec![(3, 3); 32]);
+    }
+
+    #[test]
+    fn test_split() {
+        let split = Split { right: true, tile: 16, scroll_y: 0, bank: 1 };
+        let ppu = mmc5_ppu(false, Some(split));
+        let tiles = planes(&fetch_scanline(&ppu, 0));
+        assert_eq!(tiles[..16], [(0xff, 2); 16]);
+        assert_eq!(tiles[16..], [(1, 3); 16]);
+    }
+}
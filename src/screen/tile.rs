@@ -0,0 +1,112 @@
+// Tile rows come as two bit planes: the pixel color index is (plane1 bit << 1) | plane0 bit.
+// Expanding them bit by bit for every pixel is the hot spot of rendering,
+// the table does it for all 8 pixels of the row at once.
+
+// plane byte with its bits spread out to the even bits: bit n goes to bit 2n
+const SPREAD_BITS: [u16; 256] = spread_bits();
+
+const fn spread_bits() -> [u16; 256] {
+    let mut table = [0u16; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut bit = 0;
+        while bit < 8 {
+            table[byte] |= ((byte as u16 >> bit) & 1) << (2 * bit);
+            bit += 1;
+        }
+        byte += 1;
+    }
+    table
+}
+
+/// 8 pixels (2-bit color indexes) of a tile row, the leftmost in the highest bits
+#[inline]
+pub fn decode_row(plane0: u8, plane1: u8) -> u16 {
+    SPREAD_BITS[plane1 as usize] << 1 | SPREAD_BITS[plane0 as usize]
+}
+
+/// Color index of pixel `x` (0 - leftmost) of a decoded row
+#[inline]
+pub fn pixel(row: u16, x: usize) -> u8 {
+    (row >> (14 - 2 * x)) as u8 & 0b11
+}
+
+/// Palette-mapped pixels of a decoded row: 8 RGB24 pixels into `out[..24]`.
+/// SSSE3 with the `simd` feature (when the cpu has it), scalar code otherwise
+pub fn map_row(row: u16, palette: &[(u8, u8, u8); 4], out: &mut [u8]) {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("ssse3") {
+            assert!(out.len() >= 24);
+            // safe: the cpu supports ssse3, `out` has room for 24 bytes
+            unsafe { map_row_ssse3(row, palette, out) };
+            return;
+        }
+    }
+    map_row_scalar(row, palette, out);
+}
+
+pub fn map_row_scalar(row: u16, palette: &[(u8, u8, u8); 4], out: &mut [u8]) {
+    for (x, rgb) in out[..24].chunks_exact_mut(3).enumerate() {
+        let color = palette[pixel(row, x) as usize];
+        rgb.copy_from_slice(&[color.0, color.1, color.2]);
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+#[target_feature(enable = "ssse3")]
+unsafe fn map_row_ssse3(row: u16, palette: &[(u8, u8, u8); 4], out: &mut [u8]) {
+    use std::arch::x86_64::*;
+
+    let mut colors = [0u8; 16];
+    for (rgb, color) in colors.chunks_exact_mut(3).zip(palette.iter()) {
+        rgb.copy_from_slice(&[color.0, color.1, color.2]);
+    }
+    let colors = _mm_loadu_si128(colors.as_ptr() as *const __m128i);
+
+    // 16 bit lane x: row << 2x, the pixel ends up in the top 2 bits
+    let shifts = _mm_setr_epi16(1, 1 << 2, 1 << 4, 1 << 6, 1 << 8, 1 << 10, 1 << 12, 1 << 14);
+    let pixels = _mm_srli_epi16(_mm_mullo_epi16(_mm_set1_epi16(row as i16), shifts), 14);
+    // offset of the pixel color in `colors`, as bytes
+    let offsets = _mm_add_epi16(pixels, _mm_add_epi16(pixels, pixels));
+    let offsets = _mm_packus_epi16(offsets, _mm_setzero_si128());
+
+    // every pixel repeated for r, g, b: 16 bytes, then the remaining 8
+    let lo = _mm_shuffle_epi8(offsets, _mm_setr_epi8(0, 0, 0, 1, 1, 1, 2, 2, 2, 3, 3, 3, 4, 4, 4, 5));
+    let lo = _mm_add_epi8(lo, _mm_setr_epi8(0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0));
+    let hi = _mm_shuffle_epi8(offsets, _mm_setr_epi8(5, 5, 6, 6, 6, 7, 7, 7, 0, 0, 0, 0, 0, 0, 0, 0));
+    let hi = _mm_add_epi8(hi, _mm_setr_epi8(1, 2, 0, 1, 2, 0, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0));
+
+    _mm_storeu_si128(out.as_mut_ptr() as *mut __m128i, _mm_shuffle_epi8(colors, lo));
+    _mm_storel_epi64(out[16..].as_mut_ptr() as *mut __m128i, _mm_shuffle_epi8(colors, hi));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_row() {
+        // plane0 0b1100_0101, plane1 0b1010_0110
+        let row = decode_row(0xc5, 0xa6);
+        let pixels: Vec<u8> = (0..8).map(|x| pixel(row, x)).collect();
+        assert_eq!(pixels, vec![3, 1, 2, 0, 0, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_map_row() {
+        let palette = [(1, 2, 3), (4, 5, 6), (7, 8, 9), (10, 11, 12)];
+        for (plane0, plane1) in [(0xc5, 0xa6), (0, 0), (0xff, 0xff), (0x0f, 0x3c)].iter() {
+            let row = decode_row(*plane0, *plane1);
+            let mut out = [0; 24];
+            map_row(row, &palette, &mut out);
+            let expected: Vec<u8> = (0..8)
+                .flat_map(|x| {
+                    let (r, g, b) = palette[pixel(row, x) as usize];
+                    vec![r, g, b]
+                })
+                .collect();
+            assert_eq!(out.to_vec(), expected);
+        }
+    }
+}
@@ -0,0 +1,262 @@
+// A bare 6502 with 64KB of RAM and memory-mapped devices, no NES hardware: runs easy6502-style
+// programs (https://skilldrick.github.io/easy6502/), like the snake game in snake/.
+//
+//   let key = Rc::new(Cell::new(0));
+//   let key_rc = key.clone();
+//   let mut machine = SimpleMachine::new()
+//       .random(0xfe, seed)
+//       .input(0xff, move || key_rc.get())
+//       .load(&program, 0x0600);
+//   machine.run(|cpu| draw(&cpu.bus.memory()[0x200..0x600]));
+//
+// Devices take a range of addresses: reads of an input or a random register return the device
+// value, writes to them are dropped. Output devices see the writes, the memory keeps them as well
+// (reading the screen memory back works). The program stops on BRK: the BRK vector is empty
+// unless the program sets it up.
+use crate::bus::{BusTrace, CpuBus};
+use crate::cpu::cpu::CPU;
+use crate::cpu::mem::Mem;
+use crate::prelude::*;
+use core::ops::RangeInclusive;
+
+// BRK with an empty vector moves the pc here, see `CPU::interpret_fn`
+const HALT: u16 = 0xffff;
+
+enum Device {
+    Input(Box<dyn FnMut() -> u8>),
+    Output(Box<dyn FnMut(u16, u8)>),
+    // xorshift32 state
+    Random(u32),
+}
+
+pub struct SimpleBus {
+    memory: Vec<u8>,
+    devices: Vec<(RangeInclusive<u16>, Device)>,
+    cycles: usize,
+}
+
+impl SimpleBus {
+    fn new() -> Self {
+        SimpleBus {
+            memory: vec![0; 0x10000],
+            devices: vec![],
+            cycles: 0,
+        }
+    }
+
+    /// The whole address space as the program wrote it, device reads are not triggered
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    fn device(&mut self, pos: u16) -> Option<&mut Device> {
+        self.devices
+            .iter_mut()
+            .find(|(range, _)| range.contains(&pos))
+            .map(|(_, device)| device)
+    }
+}
+
+impl Mem for SimpleBus {
+    fn write(&mut self, pos: u16, data: u8) {
+        match self.device(pos) {
+            Some(Device::Input(_)) | Some(Device::Random(_)) => return,
+            Some(Device::Output(write)) => write(pos, data),
+            None => {}
+        }
+        self.memory[pos as usize] = data;
+    }
+
+    fn read(&mut self, pos: u16) -> u8 {
+        match self.device(pos) {
+            Some(Device::Input(read)) => read(),
+            Some(Device::Random(state)) => {
+                *state ^= *state << 13;
+                *state ^= *state >> 17;
+                *state ^= *state << 5;
+                *state as u8
+            }
+            Some(Device::Output(_)) | None => self.memory[pos as usize],
+        }
+    }
+}
+
+impl CpuBus for SimpleBus {
+    fn poll_nmi_status(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn poll_irq_status(&mut self) -> bool {
+        false
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.cycles += cycles as usize;
+    }
+
+    fn trace(&self) -> BusTrace {
+        BusTrace {
+            cpu_cycles: self.cycles,
+            ppu_cycles: 0,
+            ppu_scanline: 0,
+        }
+    }
+
+    // memory only, the devices are the caller's
+    #[cfg(feature = "save-state")]
+    fn save_state(&self) -> Result<Vec<u8>, String> {
+        bincode::serialize(&(&self.memory, self.cycles)).map_err(|e| e.to_string())
+    }
+
+    #[cfg(feature = "save-state")]
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let (memory, cycles): (Vec<u8>, usize) =
+            bincode::deserialize(data).map_err(|e| format!("corrupted bus state: {}", e))?;
+        if memory.len() != self.memory.len() {
+            return Err("corrupted bus state: wrong memory size".to_string());
+        }
+        self.memory = memory;
+        self.cycles = cycles;
+        Ok(())
+    }
+
+    // no rom
+    fn rom_crc32(&self) -> u32 {
+        0
+    }
+}
+
+pub struct SimpleMachine {
+    pub cpu: CPU<SimpleBus>,
+}
+
+impl SimpleMachine {
+    pub fn new() -> Self {
+        SimpleMachine {
+            cpu: CPU::with_bus(Box::new(SimpleBus::new())),
+        }
+    }
+
+    /// Register at `addr` the program reads a value from, e.g. the last key pressed
+    pub fn input<F: FnMut() -> u8 + 'static>(mut self, addr: u16, read: F) -> Self {
+        self.cpu
+            .bus
+            .devices
+            .push((addr..=addr, Device::Input(Box::new(read))));
+        self
+    }
+
+    /// `write` is called with the address and the value on every write into `range`
+    pub fn output<F: FnMut(u16, u8) + 'static>(
+        mut self,
+        range: RangeInclusive<u16>,
+        write: F,
+    ) -> Self {
+        self.cpu
+            .bus
+            .devices
+            .push((range, Device::Output(Box::new(write))));
+        self
+    }
+
+    /// Register giving a new pseudo-random byte on every read, the same sequence for the same seed
+    pub fn random(mut self, addr: u16, seed: u32) -> Self {
+        // xorshift is stuck at 0
+        let state = if seed == 0 { 0x6502 } else { seed };
+        self.cpu
+            .bus
+            .devices
+            .push((addr..=addr, Device::Random(state)));
+        self
+    }
+
+    /// Copies the program to `addr` and points the pc at it
+    pub fn load(mut self, program: &[u8], addr: u16) -> Self {
+        let start = addr as usize;
+        self.cpu.bus.memory[start..start + program.len()].copy_from_slice(program);
+        self.cpu.program_counter = addr;
+        self
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        self.cpu.bus.memory()
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.cpu.program_counter == HALT
+    }
+
+    /// Executes one instruction
+    pub fn step(&mut self) {
+        self.cpu.step();
+    }
+
+    /// Runs till BRK, `callback` is called before every instruction
+    pub fn run<F: FnMut(&mut CPU<SimpleBus>)>(&mut self, callback: F) {
+        self.cpu.interpret_fn(HALT as usize, callback);
+    }
+}
+
+impl Default for SimpleMachine {
+    fn default() -> Self {
+        SimpleMachine::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    #[test]
+    fn test_devices() {
+        let key = Rc::new(Cell::new(0x77));
+        let key_rc = key.clone();
+        let writes = Rc::new(RefCell::new(vec![]));
+        let writes_rc = writes.clone();
+        // LDA $FF; STA $0200; LDA $FE; STA $0201; LDA $FE; STA $0202; STA $FF; BRK
+        let program = CPU::transform("a5 ff 8d 00 02 a5 fe 8d 01 02 a5 fe 8d 02 02 85 ff 00");
+        let mut machine = SimpleMachine::new()
+            .random(0xfe, 42)
+            .input(0xff, move || key_rc.get())
+            .output(0x0200..=0x05ff, move |addr, data| {
+                writes_rc.borrow_mut().push((addr, data))
+            })
+            .load(&program, 0x0600);
+        let mut steps = 0;
+        machine.run(|_| steps += 1);
+        assert!(machine.is_halted());
+        assert_eq!(steps, 8);
+
+        let random = machine.memory()[0x201];
+        assert_eq!(
+            *writes.borrow(),
+            vec![
+                (0x200, 0x77),
+                (0x201, random),
+                (0x202, machine.memory()[0x202])
+            ]
+        );
+        assert_ne!(machine.memory()[0x201], machine.memory()[0x202]);
+        assert_eq!(machine.cpu.bus.read(0x200), 0x77);
+        // input registers are read-only
+        key.set(0x64);
+        assert_eq!(machine.cpu.bus.read(0xff), 0x64);
+        assert_eq!(machine.memory()[0xff], 0);
+    }
+
+    #[test]
+    fn test_random_seed() {
+        let mut first = SimpleMachine::new().random(0xfe, 1);
+        let mut second = SimpleMachine::new().random(0xfe, 1);
+        let sequence: Vec<u8> = (0..16).map(|_| first.cpu.bus.read(0xfe)).collect();
+        assert_eq!(
+            sequence,
+            (0..16)
+                .map(|_| second.cpu.bus.read(0xfe))
+                .collect::<Vec<_>>()
+        );
+        assert!(sequence.iter().any(|b| *b != sequence[0]));
+    }
+}
@@ -0,0 +1,169 @@
+// Debug symbols (labels) for disassembly, traces and breakpoints.
+//
+// Supported formats:
+//  * FCEUX name lists (*.nl): one label per line
+//      $C000#reset_handler#optional comment
+//      $0200/100#oam_buffer#    (array: address/size)
+//    http://fceux.com/web/help/NLFilesFormat.html
+//  * ca65/ld65 debug info (*.dbg, `ld65 --dbgfile`), only "sym" records are used:
+//      sym	id=3,name="reset_handler",addrsize=absolute,scope=0,def=12,ref=4,val=0xC000,seg=1,type=lab
+//    https://cc65.github.io/doc/debugging.html
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+pub struct Symbols {
+    names: HashMap<u16, String>,
+    addrs: HashMap<String, u16>,
+}
+
+fn parse_hex(s: &str) -> Option<u16> {
+    let s = s.trim();
+    let s = s
+        .strip_prefix('$')
+        .or_else(|| s.strip_prefix("0x"))
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    u16::from_str_radix(s, 16).ok()
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols {
+            names: HashMap::new(),
+            addrs: HashMap::new(),
+        }
+    }
+
+    pub fn parse_nl(content: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::new();
+        for (line_num, line) in content.lines().enumerate() {
+            let line = line.trim();
+            // lines starting with '\' continue the multi-line comment of the previous label
+            if line.is_empty() || line.starts_with('\\') {
+                continue;
+            }
+            let mut parts = line.splitn(3, '#');
+            let addr = parts.next().unwrap_or("");
+            let name = parts.next().unwrap_or("").trim();
+            let addr = addr.split('/').next().unwrap_or("");
+            let addr = parse_hex(addr)
+                .ok_or_else(|| format!("line {}: bad address '{}'", line_num + 1, addr))?;
+            if name.is_empty() {
+                return Err(format!("line {}: missing name", line_num + 1));
+            }
+            symbols.insert(addr, name);
+        }
+        Ok(symbols)
+    }
+
+    pub fn parse_dbg(content: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::new();
+        for (line_num, line) in content.lines().enumerate() {
+            let mut record = line.splitn(2, '\t');
+            if record.next() != Some("sym") {
+                continue;
+            }
+            let fields: HashMap<&str, &str> = record
+                .next()
+                .unwrap_or("")
+                .split(',')
+                .filter_map(|field| {
+                    let idx = field.find('=')?;
+                    Some((&field[..idx], &field[idx + 1..]))
+                })
+                .collect();
+            // "equ" symbols are constants, not addresses
+            if fields.get("type") != Some(&"lab") {
+                continue;
+            }
+            let name = fields
+                .get("name")
+                .map(|name| name.trim_matches('"'))
+                .ok_or_else(|| format!("line {}: missing name", line_num + 1))?;
+            let val = fields.get("val").unwrap_or(&"");
+            let addr = parse_hex(val)
+                .ok_or_else(|| format!("line {}: bad value '{}'", line_num + 1, val))?;
+            symbols.insert(addr, name);
+        }
+        Ok(symbols)
+    }
+
+    /// Format is picked by file extension: `.nl` or `.dbg`
+    #[cfg(feature = "std")]
+    pub fn load(path: &Path) -> Result<Symbols, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("nl") => Symbols::parse_nl(&content),
+            Some("dbg") => Symbols::parse_dbg(&content),
+            _ => Err(format!(
+                "unknown symbol file format {}, expected .nl or .dbg",
+                path.display()
+            )),
+        }
+    }
+
+    /// First name wins if several labels point to the same address
+    pub fn insert(&mut self, addr: u16, name: &str) {
+        self.names.entry(addr).or_insert_with(|| name.to_string());
+        self.addrs.insert(name.to_string(), addr);
+    }
+
+    pub fn name(&self, addr: u16) -> Option<&str> {
+        self.names.get(&addr).map(|n| n.as_str())
+    }
+
+    pub fn addr(&self, name: &str) -> Option<u16> {
+        self.addrs.get(name).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.names.iter().map(|(addr, name)| (*addr, name.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+impl Default for Symbols {
+    fn default() -> Self {
+        Symbols::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_nl() {
+        let symbols = Symbols::parse_nl(
+            "$C000#reset_handler#entry point\n\\ continues the comment\n$0200/100#oam_buffer#\n",
+        )
+        .unwrap();
+        assert_eq!(symbols.name(0xc000), Some("reset_handler"));
+        assert_eq!(symbols.addr("oam_buffer"), Some(0x0200));
+        assert_eq!(symbols.name(0x0201), None);
+
+        assert!(Symbols::parse_nl("C0Z0#bad#").is_err());
+        assert!(Symbols::parse_nl("$C000##").is_err());
+    }
+
+    #[test]
+    fn test_parse_dbg() {
+        let symbols = Symbols::parse_dbg(
+            "version\tmajor=2,minor=0\n\
+             sym\tid=0,name=\"reset_handler\",addrsize=absolute,scope=0,def=1,ref=3,val=0xC000,seg=0,type=lab\n\
+             sym\tid=1,name=\"PPU_CTRL\",addrsize=absolute,scope=0,def=2,val=0x2000,type=equ\n\
+             sym\tid=2,name=\"nmi\",addrsize=absolute,scope=0,def=4,val=0xC0A3,seg=0,type=lab\n",
+        )
+        .unwrap();
+        assert_eq!(symbols.addr("reset_handler"), Some(0xc000));
+        assert_eq!(symbols.name(0xc0a3), Some("nmi"));
+        assert_eq!(symbols.addr("PPU_CTRL"), None);
+    }
+}
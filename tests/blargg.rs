@@ -0,0 +1,350 @@
+// blargg's test roms (https://github.com/christopherpow/nes-test-roms), run headless.
+//
+// The newer ones report through PRG-RAM:
+//   $6000       status: $80 - running, $81 - reset requested, anything else - the result code
+//               (0 - passed)
+//   $6001-$6003 DE B0 61 once $6000 holds a valid status
+//   $6004       zero terminated message
+// The 2005 ones (sprite_hit_tests, sprite_overflow_tests) store the result code at $F8
+// (1 - passed) and print the message on screen. Some early builds only print on screen:
+// "Passed", or "Error <code>" under the message.
+//
+// The roms aren't in the repo (but cpu_dummy_reads), so the tests are ignored by default. Get nes-test-roms and run
+// them with the directory in BLARGG_ROMS (same layout, rom_singles builds: NROM, no mapper
+// needed), a missing rom fails the test:
+//
+//   BLARGG_ROMS=../nes-test-roms cargo test --test blargg -- --ignored
+use rustness::rom::Rom;
+use rustness::{Emulator, Inputs};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const STATUS: u16 = 0x6000;
+const SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+const MESSAGE: u16 = 0x6004;
+
+const LEGACY_RESULT: u16 = 0xf8;
+const LEGACY_PASSED: u8 = 1;
+
+const RUNNING: u8 = 0x80;
+const RESET_REQUESTED: u8 = 0x81;
+// the roms ask for a reset and expect it no sooner than 100ms later
+const RESET_DELAY_FRAMES: usize = 6;
+const MAX_FRAMES: usize = 60 * 60;
+
+#[derive(Clone, Copy)]
+enum Protocol {
+    PrgRam,
+    Legacy,
+    Screen,
+}
+
+#[derive(Clone, Copy)]
+enum Expect {
+    Pass,
+    // a recorded run of something not emulated yet: the rom has to report exactly this, update
+    // the expectation once the result changes
+    KnownFailure { code: u8, message: &'static str },
+}
+
+// the rom's verdict. Timeouts, faults and unreadable roms are errors, never an expected result
+enum Outcome {
+    Passed(String),
+    Failed { code: u8, message: String },
+}
+
+fn peek(emulator: &mut Emulator, addr: u16) -> u8 {
+    emulator.cpu_mut().bus.read(addr)
+}
+
+fn message(emulator: &mut Emulator) -> String {
+    let mut text = String::new();
+    for addr in MESSAGE..0x8000 {
+        match peek(emulator, addr) {
+            0 => break,
+            c => text.push(c as char),
+        }
+    }
+    text.trim().to_string()
+}
+
+// text printed into the first nametable, the roms use ASCII codes as tile numbers
+fn screen_text(emulator: &Emulator) -> String {
+    emulator.ppu().vram[..0x3c0]
+        .chunks(32)
+        .map(|row| {
+            row.iter()
+                .map(|&c| {
+                    if (0x20..0x7f).contains(&c) {
+                        c as char
+                    } else {
+                        ' '
+                    }
+                })
+                .collect::<String>()
+                .trim()
+                .to_string()
+        })
+        .filter(|row| !row.is_empty())
+        .collect::<Vec<_>>()
+        .join(" / ")
+}
+
+fn load(path: &Path) -> Result<Emulator, String> {
+    let data = fs::read(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let rom = Rom::load(&data).map_err(|e| e.to_string())?;
+    Ok(Emulator::builder(rom).build())
+}
+
+/// Runs the rom to completion
+fn run(path: &Path, protocol: Protocol) -> Result<Outcome, String> {
+    match protocol {
+        Protocol::PrgRam => run_prg_ram(path),
+        Protocol::Legacy => run_legacy(path),
+        Protocol::Screen => run_screen(path),
+    }
+}
+
+// the result code printed after "Error", the number ends the screen text
+fn screen_error(text: &str) -> Option<u8> {
+    let (_, code) = text.rsplit_once("Error ")?;
+    code.trim().parse().ok()
+}
+
+fn run_screen(path: &Path) -> Result<Outcome, String> {
+    let mut emulator = load(path)?;
+    let inputs = Inputs::default();
+    for frame in 0..MAX_FRAMES {
+        emulator
+            .run_frame(&inputs)
+            .map_err(|e| format!("frame {}: {}", frame, e))?;
+        let text = screen_text(&emulator);
+        if text.contains("Passed") {
+            return Ok(Outcome::Passed(text));
+        }
+        if let Some(code) = screen_error(&text) {
+            return Ok(Outcome::Failed {
+                code,
+                message: text,
+            });
+        }
+    }
+    Err(format!(
+        "no result after {} frames: {}",
+        MAX_FRAMES,
+        screen_text(&emulator)
+    ))
+}
+
+fn run_legacy(path: &Path) -> Result<Outcome, String> {
+    let mut emulator = load(path)?;
+    let inputs = Inputs::default();
+    for frame in 0..MAX_FRAMES {
+        emulator
+            .run_frame(&inputs)
+            .map_err(|e| format!("frame {}: {}", frame, e))?;
+        match peek(&mut emulator, LEGACY_RESULT) {
+            0 => {}
+            LEGACY_PASSED => return Ok(Outcome::Passed(screen_text(&emulator))),
+            code => {
+                return Ok(Outcome::Failed {
+                    code,
+                    message: screen_text(&emulator),
+                })
+            }
+        }
+    }
+    Err(format!(
+        "no result after {} frames: {}",
+        MAX_FRAMES,
+        screen_text(&emulator)
+    ))
+}
+
+fn run_prg_ram(path: &Path) -> Result<Outcome, String> {
+    let mut emulator = load(path)?;
+    let inputs = Inputs::default();
+
+    let mut reset_at = None;
+    for frame in 0..MAX_FRAMES {
+        emulator
+            .run_frame(&inputs)
+            .map_err(|e| format!("frame {}: {}", frame, e))?;
+
+        let signature = [
+            peek(&mut emulator, STATUS + 1),
+            peek(&mut emulator, STATUS + 2),
+            peek(&mut emulator, STATUS + 3),
+        ];
+        if signature != SIGNATURE {
+            continue;
+        }
+        match peek(&mut emulator, STATUS) {
+            RUNNING => {}
+            RESET_REQUESTED => match reset_at {
+                None => reset_at = Some(frame + RESET_DELAY_FRAMES),
+                Some(at) if at <= frame => {
+                    emulator.soft_reset();
+                    reset_at = None;
+                }
+                Some(_) => {}
+            },
+            0 => return Ok(Outcome::Passed(message(&mut emulator))),
+            code => {
+                return Ok(Outcome::Failed {
+                    code,
+                    message: message(&mut emulator),
+                })
+            }
+        }
+    }
+    Err(format!(
+        "no result after {} frames: {}",
+        MAX_FRAMES,
+        message(&mut emulator)
+    ))
+}
+
+fn roms_dir() -> PathBuf {
+    match env::var_os("BLARGG_ROMS") {
+        Some(dir) => PathBuf::from(dir),
+        None => panic!("BLARGG_ROMS is not set: the directory with nes-test-roms"),
+    }
+}
+
+fn check(rom: &str, protocol: Protocol, expect: Expect) {
+    check_path(&roms_dir().join(rom), protocol, expect);
+}
+
+fn check_path(path: &Path, protocol: Protocol, expect: Expect) {
+    assert!(path.exists(), "{} not found", path.display());
+    let outcome = match run(path, protocol) {
+        Ok(outcome) => outcome,
+        Err(error) => panic!("{}: {}", path.display(), error),
+    };
+    match (outcome, expect) {
+        (Outcome::Passed(message), Expect::Pass) => println!("{}: {}", path.display(), message),
+        (Outcome::Failed { code, message }, Expect::Pass) => {
+            panic!("{}: failed with code {}: {}", path.display(), code, message)
+        }
+        (
+            Outcome::Failed { code, message },
+            Expect::KnownFailure {
+                code: known,
+                message: text,
+            },
+        ) => {
+            assert!(
+                code == known && message == text,
+                "{}: failed with code {}: {}, the recorded run is code {}: {}",
+                path.display(),
+                code,
+                message,
+                known,
+                text
+            );
+            println!("{}: known failure {}: {}", path.display(), code, message)
+        }
+        (Outcome::Passed(_), Expect::KnownFailure { code, .. }) => panic!(
+            "{} passes now, it was a known failure (code {}): update the expectation",
+            path.display(),
+            code
+        ),
+    }
+}
+
+macro_rules! expect {
+    () => {
+        Expect::Pass
+    };
+    ($expect:expr) => {
+        $expect
+    };
+}
+
+macro_rules! blargg_tests {
+    ($protocol:expr; $($name:ident: $rom:expr $(=> $expect:expr)?,)*) => {
+        $(
+            #[test]
+            #[ignore]
+            fn $name() {
+                check($rom, $protocol, expect!($($expect)?));
+            }
+        )*
+    };
+}
+
+// Known failures are recorded runs: the code and the message the rom reported. A rom without
+// a recorded run is expected to pass.
+
+blargg_tests! {
+    Protocol::PrgRam;
+    instr_basics: "instr_test-v5/rom_singles/01-basics.nes",
+    instr_implied: "instr_test-v5/rom_singles/02-implied.nes",
+    instr_immediate: "instr_test-v5/rom_singles/03-immediate.nes",
+    instr_zero_page: "instr_test-v5/rom_singles/04-zero_page.nes",
+    instr_zp_xy: "instr_test-v5/rom_singles/05-zp_xy.nes",
+    instr_absolute: "instr_test-v5/rom_singles/06-absolute.nes",
+    instr_abs_xy: "instr_test-v5/rom_singles/07-abs_xy.nes",
+    instr_ind_x: "instr_test-v5/rom_singles/08-ind_x.nes",
+    instr_ind_y: "instr_test-v5/rom_singles/09-ind_y.nes",
+    instr_branches: "instr_test-v5/rom_singles/10-branches.nes",
+    instr_stack: "instr_test-v5/rom_singles/11-stack.nes",
+    instr_jmp_jsr: "instr_test-v5/rom_singles/12-jmp_jsr.nes",
+    instr_rts: "instr_test-v5/rom_singles/13-rts.nes",
+    instr_rti: "instr_test-v5/rom_singles/14-rti.nes",
+    instr_brk: "instr_test-v5/rom_singles/15-brk.nes",
+    instr_special: "instr_test-v5/rom_singles/16-special.nes",
+    apu_len_ctr: "apu_test/rom_singles/1-len_ctr.nes",
+    apu_len_table: "apu_test/rom_singles/2-len_table.nes",
+    apu_irq_flag: "apu_test/rom_singles/3-irq_flag.nes",
+}
+
+blargg_tests! {
+    Protocol::PrgRam;
+    ppu_vbl_basics: "ppu_vbl_nmi/rom_singles/01-vbl_basics.nes",
+    ppu_vbl_set_time: "ppu_vbl_nmi/rom_singles/02-vbl_set_time.nes",
+    ppu_vbl_clear_time: "ppu_vbl_nmi/rom_singles/03-vbl_clear_time.nes",
+    ppu_nmi_control: "ppu_vbl_nmi/rom_singles/04-nmi_control.nes",
+    ppu_nmi_timing: "ppu_vbl_nmi/rom_singles/05-nmi_timing.nes",
+    ppu_nmi_suppression: "ppu_vbl_nmi/rom_singles/06-suppression.nes",
+    ppu_nmi_on_timing: "ppu_vbl_nmi/rom_singles/07-nmi_on_timing.nes",
+    ppu_nmi_off_timing: "ppu_vbl_nmi/rom_singles/08-nmi_off_timing.nes",
+    ppu_even_odd_frames: "ppu_vbl_nmi/rom_singles/09-even_odd_frames.nes",
+    ppu_even_odd_timing: "ppu_vbl_nmi/rom_singles/10-even_odd_timing.nes",
+}
+
+blargg_tests! {
+    Protocol::Legacy;
+    sprite_hit_basics: "sprite_hit_tests_2005.10.05/01.basics.nes",
+    sprite_hit_alignment: "sprite_hit_tests_2005.10.05/02.alignment.nes",
+    sprite_hit_corners: "sprite_hit_tests_2005.10.05/03.corners.nes",
+    sprite_hit_flip: "sprite_hit_tests_2005.10.05/04.flip.nes",
+    sprite_hit_left_clip: "sprite_hit_tests_2005.10.05/05.left_clip.nes",
+    sprite_hit_right_edge: "sprite_hit_tests_2005.10.05/06.right_edge.nes",
+    sprite_hit_screen_bottom: "sprite_hit_tests_2005.10.05/07.screen_bottom.nes",
+    sprite_hit_double_height: "sprite_hit_tests_2005.10.05/08.double_height.nes",
+    sprite_hit_timing_basics: "sprite_hit_tests_2005.10.05/09.timing_basics.nes",
+    sprite_hit_timing_order: "sprite_hit_tests_2005.10.05/10.timing_order.nes",
+    sprite_hit_edge_timing: "sprite_hit_tests_2005.10.05/11.edge_timing.nes",
+    sprite_overflow_basics: "sprite_overflow_tests/1.Basics.nes",
+    sprite_overflow_details: "sprite_overflow_tests/2.Details.nes",
+    sprite_overflow_timing: "sprite_overflow_tests/3.Timing.nes",
+    sprite_overflow_obscure: "sprite_overflow_tests/4.Obscure.nes",
+    sprite_overflow_emulator: "sprite_overflow_tests/5.Emulator.nes",
+}
+
+// the rom in the repo, it predates the $6000 protocol. Recorded run: "Error 3" under LDA abs,x,
+// its dummy read isn't emulated
+#[test]
+fn cpu_dummy_reads() {
+    check_path(
+        Path::new("test_rom/cpu_dummy_reads.nes"),
+        Protocol::Screen,
+        Expect::KnownFailure {
+            code: 3,
+            message: "LDA abs,x / cpu_dummy_reads / Error 3",
+        },
+    );
+}
@@ -0,0 +1,171 @@
+// Screenshot regression tests: runs a rom for a number of frames and compares
+// `Frame::hash()` with the recorded one. A mismatch means the picture changed: if the change
+// is intended (e.g. a rendering fix), check the new picture and update the hash from the
+// failure message.
+use rustness::rom::Rom;
+use rustness::{Emulator, Inputs};
+use std::fs;
+
+const PRG_ROM_PAGE_SIZE: usize = 16384;
+const CHR_ROM_PAGE_SIZE: usize = 8192;
+
+/// Palette, a nametable full of different tiles, 64 sprites, horizontal scrolling by
+/// a pixel per frame (the second nametable is all tile 0)
+fn scrolling_rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    let reset = [
+        0x78,             // SEI
+        0xd8,             // CLD
+        0xa2, 0xff,       // LDX #$FF
+        0x9a,             // TXS
+        0x2c, 0x02, 0x20, // vbl1: BIT $2002
+        0x10, 0xfb,       // BPL vbl1
+        0x2c, 0x02, 0x20, // vbl2: BIT $2002
+        0x10, 0xfb,       // BPL vbl2
+        0xa9, 0x3f,       // LDA #$3F
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa9, 0x00,       // LDA #$00
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa2, 0x00,       // LDX #0
+        0xbd, 0x00, 0x90, // palette: LDA $9000,X
+        0x8d, 0x07, 0x20, // STA $2007
+        0xe8,             // INX
+        0xe0, 0x20,       // CPX #$20
+        0xd0, 0xf5,       // BNE palette
+        0xa9, 0x20,       // LDA #$20
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa9, 0x00,       // LDA #$00
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa0, 0x04,       // LDY #4
+        0xa2, 0x00,       // LDX #0
+        0x8a,             // nametable: TXA
+        0x8d, 0x07, 0x20, // STA $2007
+        0xe8,             // INX
+        0xd0, 0xf9,       // BNE nametable
+        0x88,             // DEY
+        0xd0, 0xf6,       // BNE nametable
+        0xa2, 0x00,       // LDX #0
+        0xbd, 0x00, 0x91, // sprites: LDA $9100,X
+        0x9d, 0x00, 0x02, // STA $0200,X
+        0xe8,             // INX
+        0xd0, 0xf7,       // BNE sprites
+        0xa9, 0x00,       // LDA #0
+        0x8d, 0x03, 0x20, // STA $2003
+        0xa9, 0x02,       // LDA #2
+        0x8d, 0x14, 0x40, // STA $4014
+        0xa9, 0x00,       // LDA #0
+        0x8d, 0x05, 0x20, // STA $2005
+        0x8d, 0x05, 0x20, // STA $2005
+        0xa9, 0x80,       // LDA #$80
+        0x8d, 0x00, 0x20, // STA $2000
+        0xa9, 0x1e,       // LDA #$1E
+        0x8d, 0x01, 0x20, // STA $2001
+        0x4c, 0x65, 0x80, // loop: JMP loop
+    ];
+    #[rustfmt::skip]
+    let nmi = [
+        0xe6, 0x10,       // INC $10
+        0xa5, 0x10,       // LDA $10
+        0x8d, 0x05, 0x20, // STA $2005
+        0xa9, 0x00,       // LDA #0
+        0x8d, 0x05, 0x20, // STA $2005
+        0xa9, 0x80,       // LDA #$80
+        0x8d, 0x00, 0x20, // STA $2000
+        0x40,             // RTI
+    ];
+    let mut prg = vec![0xea; 2 * PRG_ROM_PAGE_SIZE];
+    prg[..reset.len()].copy_from_slice(&reset);
+    prg[0x70..0x70 + nmi.len()].copy_from_slice(&nmi);
+    for i in 0..0x20 {
+        prg[0x1000 + i] = ((i * 5 + 1) & 0x3f) as u8;
+    }
+    for i in 0..64 {
+        let flips = if i % 3 == 0 { 0x40 } else { 0 } | if i % 5 == 0 { 0x80 } else { 0 };
+        prg[0x1100 + i * 4..0x1100 + i * 4 + 4].copy_from_slice(&[
+            ((i * 13) % 200 + 8) as u8,
+            i as u8,
+            (i % 4) as u8 | flips,
+            ((i * 37) % 240) as u8,
+        ]);
+    }
+    // NMI, RESET, IRQ vectors
+    prg[0x7ffa..].copy_from_slice(&[0x70, 0x80, 0x00, 0x80, 0x70, 0x80]);
+
+    let mut image = vec![0x4e, 0x45, 0x53, 0x1a, 2, 1, 0x01];
+    image.resize(16, 0);
+    image.extend_from_slice(&prg);
+    image.extend((0..CHR_ROM_PAGE_SIZE).map(|i| ((i * 37) ^ (i >> 3)) as u8));
+    image
+}
+
+/// hashes of the frames with the given numbers (1-based)
+fn frame_hashes(emulator: &mut Emulator, frames: &[usize]) -> Vec<u64> {
+    let inputs = Inputs::default();
+    let mut hashes = Vec::new();
+    for frame in 1..=*frames.iter().max().unwrap() {
+        let hash = emulator.run_frame(&inputs).unwrap().hash();
+        if frames.contains(&frame) {
+            hashes.push(hash);
+        }
+    }
+    hashes
+}
+
+fn check(name: &str, emulator: &mut Emulator, expected: &[(usize, u64)]) {
+    let frames: Vec<usize> = expected.iter().map(|(frame, _)| *frame).collect();
+    let actual: Vec<(usize, u64)> = frames
+        .iter()
+        .cloned()
+        .zip(frame_hashes(emulator, &frames))
+        .collect();
+    let listing: Vec<String> = actual
+        .iter()
+        .map(|(frame, hash)| format!("({}, {:#018x})", frame, hash))
+        .collect();
+    assert_eq!(
+        actual,
+        expected,
+        "{}: the picture changed, (frame, hash): [{}]",
+        name,
+        listing.join(", ")
+    );
+}
+
+#[test]
+fn scrolling_rom_frames() {
+    let rom = Rom::load(&scrolling_rom()).unwrap();
+    check(
+        "scrolling rom",
+        &mut Emulator::builder(rom).build(),
+        &[
+            (3, 0xb126713ac18adcc8),
+            (10, 0x7806944a235bf669),
+            (60, 0xc351de2e46706851),
+        ],
+    );
+}
+
+#[test]
+fn scrolling_rom_frames_on_render_thread() {
+    let rom = Rom::load(&scrolling_rom()).unwrap();
+    check(
+        "scrolling rom, render thread",
+        &mut Emulator::builder(rom).render_thread(true).build(),
+        &[
+            (3, 0xb126713ac18adcc8),
+            (10, 0x7806944a235bf669),
+            (60, 0xc351de2e46706851),
+        ],
+    );
+}
+
+#[test]
+fn cpu_dummy_reads_frames() {
+    // prints the result ("Error 3") on screen and stops
+    let rom = Rom::load(&fs::read("test_rom/cpu_dummy_reads.nes").unwrap()).unwrap();
+    check(
+        "cpu_dummy_reads",
+        &mut Emulator::builder(rom).build(),
+        &[(10, 0x291c50bd91680025), (60, 0x291c50bd91680025)],
+    );
+}
@@ -0,0 +1,38 @@
+// nestest (http://www.qmtpro.com/~nes/misc/nestest.nes) in automation mode: runs from $C000
+// without a PPU picture and checks every executed instruction against the reference log
+// (http://www.qmtpro.com/~nes/misc/nestest.log), same as `rustness --golden=nestest.log`.
+//
+// Neither file is in the repo, the test is ignored by default. Put them into test_rom/ and run
+//
+//   cargo test --test nestest -- --ignored
+use rustness::cpu::trace;
+use rustness::debugger::golden_log::GoldenLog;
+use rustness::rom::Rom;
+use rustness::Emulator;
+use std::fs;
+use std::path::Path;
+
+const ROM: &str = "test_rom/nestest.nes";
+const LOG: &str = "test_rom/nestest.log";
+
+#[test]
+#[ignore]
+fn nestest_matches_golden_log() {
+    assert!(Path::new(ROM).exists(), "{} not found", ROM);
+    assert!(Path::new(LOG).exists(), "{} not found", LOG);
+    let rom = Rom::load(&fs::read(ROM).unwrap()).unwrap();
+    let mut golden = GoldenLog::load(Path::new(LOG)).unwrap();
+    let mut emulator = Emulator::builder(rom).start_pc(0xc000).build();
+    let cpu = emulator.cpu_mut();
+
+    while !golden.is_finished() {
+        if let Err(mismatch) = golden.check(&trace(cpu)) {
+            panic!("{}", mismatch);
+        }
+        cpu.step();
+    }
+
+    // error codes of the official and unofficial opcode tests, 0 - all passed
+    assert_eq!(cpu.bus.read(0x02), 0, "official opcodes failed");
+    assert_eq!(cpu.bus.read(0x03), 0, "unofficial opcodes failed");
+}